@@ -318,87 +318,814 @@ pub mod final_project {
     }
 
     /// Basic implementation of a web server capable of handling multiple clients at the same time
-    /// without the risk of DOS. The website has two valid roots:
+    /// with a fixed number of threads. The routes are defined with a [`Router`], the default one
+    /// ([`default_router`]) has these routes:
     ///
-    /// - `/`: Shows a static HTML webpage located on `./html/hello.html`.
-    /// - `/sleep`: First sleeps the thread for two seconds and displays the same website as root (`\`).
-    /// - `others`: Displays an error HTML website located on `./html/404.html`.
+    /// - `/`: Shows the HTML webpage rendered from the template `./templates/hello.html`, with
+    ///   the uptime of the server.
+    /// - `/sleep`: First sleeps the thread for five seconds and displays the same website as root (`\`).
+    /// - `/metrics`: Shows the metrics of the server in the Prometheus text format.
+    /// - `/api/pi`: With the `json` feature, calculates PI with [`pi::calculate_pi_on`] and
+    ///   returns it as JSON, e.g. `/api/pi?threads=8&iterations=1000000`.
+    /// - `/api/pi/progress`: With the `json` feature, the same calculation streaming its progress
+    ///   as Server-Sent Events, followed by the result.
+    /// - `/ws/echo`: WebSocket endpoint sending back every text and binary message it receives.
+    /// - `others`: Serves the files of the `./html` directory, or displays an error HTML website
+    ///   rendered from `./templates/404.html`, with the requested path, if the file does not
+    ///   exist.
+    ///
+    /// Requests are parsed by [`Request::read_from`], so any valid HTTP/1.x request is accepted.
+    /// Malformed requests are answered with the matching `4xx`/`5xx` status. Connections are
+    /// persistent, so a client can send multiple requests through the same connection.
+    ///
+    /// To bound the memory used under load, the requests have size limits, slow clients are
+    /// disconnected after the read and write timeouts, and the connections accepted while too
+    /// many others are waiting for a thread are answered with `503 Service Unavailable`.
+    ///
+    /// By default every connection is served by a thread of the pool. With
+    /// `backend = "event_loop"` the connections are waited on with epoll and the threads are only
+    /// used to handle the requests, so idle and slow clients do not keep them busy.
+    ///
+    /// Built with the `tls` feature, the server speaks HTTPS when the `[tls]` section of the
+    /// configuration sets the certificate and key files.
+    ///
+    /// The address, port, number of threads and the rest of options are read from the
+    /// [`CONFIG_FILE`] and the `WEB_SERVER_*` environment variables, see [`config::Config`].
     ///
     /// # Example
     ///
     /// To run the start the server run the following command on the `main()` function.
     ///
     /// ```rust
-    /// run_server()
+    /// run_server(default_handler)
     /// ```
     pub mod web_server {
-        use std::fs;
-        use std::io::prelude::*;
-        use std::net::TcpListener;
-        use std::net::TcpStream;
+        /// Case-insensitive collection of header fields used by requests and responses.
+        pub mod headers;
+        /// Parsing of HTTP/1.x requests from a stream.
+        pub mod request;
+        /// Status codes and responses that can be written back to the client.
+        pub mod response;
+        /// Response bodies: bytes, files and streams, and the chunked transfer coding.
+        pub mod body;
+        /// Dispatching of requests to handlers by method and path.
+        pub mod router;
+        /// Handler serving the files of a directory.
+        pub mod static_files;
+        /// Validators, conditional requests and byte ranges of static representations.
+        pub mod conditional;
+        /// Percent-encoding and HTML escaping helpers.
+        pub mod encoding;
+        /// Persistent connections serving multiple requests.
+        pub mod connection;
+        /// Connections handed over to another protocol after `101 Switching Protocols`.
+        pub mod upgrade;
+        /// WebSocket connections (RFC 6455): handshake, frames and messages.
+        pub mod websocket;
+        /// Server-Sent Events: `text/event-stream` responses pushing events to the client.
+        pub mod sse;
+        /// Server accepting connections, with support for graceful shutdown.
+        pub mod server;
+        /// Event loop waiting on all the connections with epoll, an alternative to a thread per
+        /// connection.
+        pub mod event_loop;
+        /// HTTPS with rustls, available with the `tls` feature.
+        pub mod tls;
+        /// Compression of the responses negotiated with `Accept-Encoding`.
+        pub mod compression;
+        /// Server configuration loaded from files and environment variables.
+        pub mod config;
+        /// Middlewares wrapping the handlers: request IDs, timing and panic catching.
+        pub mod middleware;
+        /// Access log in the Common Log Format or as JSON lines.
+        pub mod access_log;
+        /// Counters and histograms of the server in the Prometheus text format.
+        pub mod metrics;
+        /// Calendar dates, their formatting and parsing, for headers and logs.
+        pub mod date;
+        /// Templates of the HTML pages, with variables, conditionals, loops and includes.
+        pub mod template;
+        /// Virtual hosts: sites chosen by the `Host` header of the requests.
+        pub mod vhost;
+        /// Reverse proxy forwarding requests to other HTTP servers.
+        pub mod proxy;
+        /// Forms sent in the body of the requests, with the uploaded files.
+        pub mod form;
+        /// Cookies sent by the clients and set by the responses.
+        pub mod cookie;
+        /// Sessions of the clients, identified by a signed cookie and kept in a store.
+        pub mod session;
+        /// Rate limiting of the requests of every client IP address.
+        pub mod rate_limit;
+        /// Cross-Origin Resource Sharing for the paths of a router that opt in.
+        pub mod cors;
+        /// JSON request and response bodies, available with the `json` feature.
+        #[cfg(feature = "json")]
+        pub mod json;
+        /// Server started on an ephemeral port and HTTP client, for the integration tests.
+        #[cfg(test)]
+        pub mod testing;
+
+        use config::{Config, ENV_PREFIX};
+        use access_log::AccessLog;
+        use compression::Compression;
+        use cors::Cors;
+        #[cfg(feature = "json")]
+        use json::Json;
+        use metrics::Metrics;
+        use middleware::{CatchPanic, Pipeline, RequestId, ResponseTime};
+        use proxy::Proxy;
+        use rate_limit::RateLimiter;
+        use request::Request;
+        #[cfg(feature = "json")]
+        use response::Response;
+        use response::StatusCode;
+        use router::{Handler, Router};
+        use server::{ServerBuilder, ServerError};
+        use static_files::StaticFiles;
+        use std::env;
+        use std::path::Path;
+        use std::sync::Arc;
         use std::thread;
-        use std::time::Duration;
-        use threadpool::ThreadPool;
+        use std::time::{Duration, Instant};
+        #[cfg(feature = "json")]
+        use sse::{Event, EventSender, EventStream};
+        #[cfg(feature = "json")]
+        use std::collections::HashMap;
+        #[cfg(feature = "json")]
+        use std::mem;
+        #[cfg(feature = "json")]
+        use std::sync::atomic::{AtomicU64, Ordering};
+        #[cfg(feature = "json")]
+        use std::sync::Mutex;
+        #[cfg(feature = "json")]
+        use super::thread_pool::ThreadPool;
+        use template::{Context, Templates};
+        use vhost::VirtualHosts;
+        use websocket::{Message, WebSocket};
+
+        /// Configuration file loaded by [`run_server`] if it exists. Another file can be used
+        /// by setting the `WEB_SERVER_CONFIG` environment variable.
+        pub const CONFIG_FILE: &str = "web_server.toml";
+
+        /// Function is renamed from main.rs. Loads the configuration, creates the router with
+        /// it and runs the server until the process receives `Ctrl+C`, then waits for the
+        /// in-flight requests before returning.
+        pub fn run_server<F, H>(handler: F) -> Result<(), ServerError>
+        where
+            F: FnOnce(&Config, &Arc<Metrics>) -> H,
+            H: Handler,
+        {
+            let mut builder = ServerBuilder::new();
+            match env::var(format!("{}CONFIG", ENV_PREFIX)) {
+                Ok(path) => builder = builder.config_file(path)?,
+                Err(_) if Path::new(CONFIG_FILE).is_file() => {
+                    builder = builder.config_file(CONFIG_FILE)?
+                }
+                Err(_) => {}
+            }
+            let builder = builder.env()?;
 
-        /// Function is renamed from main.rs
-        pub fn run_server() {
-            let listener =
-                TcpListener::bind("127.0.0.1:7878").expect("Could not bind to port: 7878");
-            let pool = ThreadPool::new(4);
+            let metrics = Arc::new(Metrics::new());
+            let handler = handler(builder.config(), &metrics);
+            let server = builder.metrics(metrics).build(handler)?;
 
-            for stream in listener.incoming() {
-                let stream = match stream {
-                    Ok(stream) => stream,
-                    Err(e) => {
-                        eprintln!("Stream error: {}", e);
-                        continue;
-                    }
+            if let Err(err) = server.handle().stop_on_signals() {
+                eprintln!("Could not set the signal handler: {}", err);
+            }
+
+            println!("Listening on {}://{}", server.scheme(), server.local_addr());
+            server.run();
+            Ok(())
+        }
+
+        /// Handler used by `main`: the [`default_router`] serving the configured document root
+        /// and the metrics on `/metrics`, wrapped by the middlewares that log the requests,
+        /// record their metrics, limit their rate, identify them, time them, compress them and
+        /// turn the panics into `500 Internal Server Error`.
+        ///
+        /// Every site of [`Config::hosts`] gets its own [`default_router`] serving its document
+        /// root, and the document root of the configuration serves the other hosts.
+        pub fn default_handler(config: &Config, metrics: &Arc<Metrics>) -> Pipeline {
+            let router = default_router(config).get("/metrics", Arc::clone(metrics));
+            let mut hosts = VirtualHosts::new().unknown_hosts(config.unknown_hosts);
+            for (name, document_root) in &config.hosts {
+                let site = Config {
+                    document_root: document_root.clone(),
+                    ..config.clone()
                 };
+                hosts = hosts.host(name, default_router(&site));
+            }
 
-                pool.execute(|| {
-                    handle_connection(stream);
+            let mut pipeline = Pipeline::new(hosts.fallback(router));
+            if let Some(format) = config.access_log {
+                pipeline = pipeline.with(AccessLog::stdout(format));
+            }
+            pipeline = pipeline.with(Arc::clone(metrics));
+            if let Some(rate) = config.rate_limit_rate {
+                let mut limiter = RateLimiter::new(rate)
+                    .allow_localhost(config.rate_limit_allow_localhost);
+                if let Some(burst) = config.rate_limit_burst {
+                    limiter = limiter.burst(burst);
+                }
+                for &address in &config.rate_limit_allow {
+                    limiter = limiter.allow(address);
+                }
+                pipeline = pipeline.with(limiter);
+            }
+            pipeline = pipeline
+                .with(CatchPanic)
+                .with(RequestId::new())
+                .with(ResponseTime);
+
+            if config.compression {
+                pipeline = pipeline
+                    .with(Compression::new().with_min_size(config.compression_min_size));
+            }
+            pipeline
+        }
+
+        /// Router with the routes of the book: the index page rendered from the `hello.html`
+        /// template, `/sleep`, the WebSocket echo and the files of the document root. Missing
+        /// files get the `404.html` template. The prefixes of [`Config::proxies`] are forwarded
+        /// to their upstreams, and the paths of [`Config::cors_paths`] opt in to CORS.
+        pub fn default_router(config: &Config) -> Router {
+            let templates = Arc::new(
+                Templates::new(&config.templates_dir).with_reload(config.templates_reload),
+            );
+            let started = Instant::now();
+            let index = Arc::new({
+                let templates = Arc::clone(&templates);
+                move || {
+                    let context = Context::new()
+                        .with("title", "Hello!")
+                        .with("uptime", format_uptime(started.elapsed()));
+                    templates.response(StatusCode::OK, "hello.html", &context)
+                }
+            });
+
+            let files = StaticFiles::new(&config.document_root);
+            let not_found = move |request: &mut Request| {
+                let response = files.handle(request);
+                if response.status != StatusCode::NOT_FOUND {
+                    return response;
+                }
+                let context = Context::new()
+                    .with("title", "Not Found")
+                    .with("path", &request.path);
+                templates.response(StatusCode::NOT_FOUND, "404.html", &context)
+            };
+
+            let sleep_index = Arc::clone(&index);
+            let router = Router::new()
+                .get("/", move |_: &mut Request| index())
+                .get("/sleep", move |_: &mut Request| {
+                    thread::sleep(Duration::from_secs(5));
+                    sleep_index()
                 })
+                .get("/ws/echo", websocket::handler(echo));
+            #[cfg(feature = "json")]
+            let router = {
+                // All the calculations share the threads of one pool, one per CPU.
+                let cpus = thread::available_parallelism().map_or(4, |cpus| cpus.get());
+                let pool = Arc::new(ThreadPool::new(cpus));
+                let progress_pool = Arc::clone(&pool);
+                let calculations = PiCalculations::default();
+                router
+                    .get("/api/pi", move |request: &mut Request| api_pi(request, &pool))
+                    .get("/api/pi/progress", move |request: &mut Request| {
+                        api_pi_progress(request, &progress_pool, &calculations)
+                    })
+            };
+
+            let mut router = router.mount("/", not_found);
+            for (prefix, upstreams) in &config.proxies {
+                let mut proxy = Proxy::new(upstreams).timeout(config.proxy_timeout);
+                if let Some(path) = &config.proxy_health_check {
+                    proxy = proxy.health_check(path, config.proxy_health_interval);
+                }
+                router = router.mount(prefix, proxy);
             }
 
-            println!("Shutting down.");
+            let mut cors = Cors::new()
+                .allow_methods(config.cors_methods.iter().copied())
+                .allow_headers(&config.cors_headers)
+                .expose_headers(&config.cors_expose_headers)
+                .allow_credentials(config.cors_credentials);
+            for origin in &config.cors_origins {
+                cors = cors.allow_origin(origin);
+            }
+            if let Some(max_age) = config.cors_max_age {
+                cors = cors.max_age(max_age);
+            }
+            for path in &config.cors_paths {
+                router = router.cors(path, cors.clone());
+            }
+            router
         }
 
-        fn handle_connection(mut stream: TcpStream) {
-            let mut buffer = [0; 1024];
-            if let Err(err) = stream.read(&mut buffer) {
-                eprintln!("Error reading from stream: {}", err);
-                return;
+        /// Sends back the text and binary messages of a WebSocket connection until it is closed.
+        fn echo(mut socket: WebSocket, _: Request) {
+            loop {
+                let result = match socket.recv() {
+                    Ok(Message::Text(text)) => socket.send(text),
+                    Ok(Message::Binary(data)) => socket.send(data),
+                    Ok(_) => Ok(()),
+                    Err(_) => break,
+                };
+                if result.is_err() {
+                    break;
+                }
             }
+        }
 
-            let get = b"GET / HTTP/1.1\r\n";
-            let sleep = b"GET /sleep HTTP/1.1\r\n";
+        /// Formats a duration like `1d 2h 3m 4s`, leaving out the leading units that are zero.
+        fn format_uptime(uptime: Duration) -> String {
+            let seconds = uptime.as_secs();
+            let units = [
+                (seconds / 86_400, "d"),
+                (seconds / 3600 % 24, "h"),
+                (seconds / 60 % 60, "m"),
+                (seconds % 60, "s"),
+            ];
+
+            let start = units.iter().position(|(value, _)| *value > 0).unwrap_or(3);
+            units[start..]
+                .iter()
+                .map(|(value, unit)| format!("{}{}", value, unit))
+                .collect::<Vec<_>>()
+                .join(" ")
+        }
 
-            let (status_line, filename) = if buffer.starts_with(get) {
-                ("HTTP/1.1 200 OK", "html/hello.html")
-            } else if buffer.starts_with(sleep) {
-                thread::sleep(Duration::from_secs(5));
-                ("HTTP/1.1 200 OK", "html/hello.html")
-            } else {
-                ("HTTP/1.1 404 NOT FOUND", "html/404.html")
+        /// Result of `/api/pi`.
+        #[cfg(feature = "json")]
+        #[derive(Debug, serde::Serialize)]
+        struct PiResult {
+            pi: f64,
+            threads: usize,
+            iterations: usize,
+            elapsed_ms: f64,
+        }
+
+        /// Reads the `threads` and `iterations` of the query, which default to 4 and 1 000 000.
+        /// The threads are the number of jobs the iterations are split into, which run on the
+        /// shared pool of the calculations.
+        #[cfg(feature = "json")]
+        fn pi_params(request: &Request) -> Result<(usize, usize), Response> {
+            let param = |name: &str, default: usize, max: usize| match request.query_param(name) {
+                None => Ok(default),
+                Some(value) => match value.parse::<usize>() {
+                    Ok(value) if (1..=max).contains(&value) => Ok(value),
+                    Ok(_) => Err(json::error(
+                        StatusCode::UNPROCESSABLE_ENTITY,
+                        format!("'{}' must be between 1 and {}", name, max),
+                    )),
+                    Err(_) => Err(json::error(
+                        StatusCode::BAD_REQUEST,
+                        format!("'{}' must be a positive integer", name),
+                    )),
+                },
             };
 
-            let contents = fs::read_to_string(filename)
-                .expect(format!("HTML file not found: {}", filename).as_str());
+            Ok((
+                param("threads", 4, 16)?,
+                param("iterations", 1_000_000, 10_000_000)?,
+            ))
+        }
 
-            let response = format!(
-                "{}\r\nContent-Length: {}\r\n\r\n{}",
-                status_line,
-                contents.len(),
-                contents
-            );
+        /// Calculates PI with the `threads` and `iterations` of the query, see [`pi_params`].
+        #[cfg(feature = "json")]
+        fn api_pi(request: &mut Request, pool: &ThreadPool) -> Response {
+            let (threads, iterations) = match pi_params(request) {
+                Ok(params) => params,
+                Err(response) => return response,
+            };
 
-            if let Err(err) = stream.write(response.as_bytes()) {
-                eprintln!("Error writing to stream: {}", err);
-                return;
+            let start = Instant::now();
+            let pi = super::pi::calculate_pi_on(pool, threads, iterations);
+
+            Json(PiResult {
+                pi,
+                threads,
+                iterations,
+                elapsed_ms: start.elapsed().as_secs_f64() * 1000.0,
+            })
+            .into()
+        }
+
+        /// Time a finished calculation of `/api/pi/progress` is kept, for the clients that
+        /// lost the connection before its result.
+        #[cfg(feature = "json")]
+        const PI_RESULT_LIFETIME: Duration = Duration::from_secs(60);
+
+        /// Time a calculation of `/api/pi/progress` goes on without any client following it,
+        /// waiting for the client to reconnect.
+        #[cfg(feature = "json")]
+        const PI_RESUME_WINDOW: Duration = Duration::from_secs(10);
+
+        /// Maximum number of calculations of `/api/pi/progress` running at once.
+        #[cfg(feature = "json")]
+        const MAX_PI_CALCULATIONS: usize = 16;
+
+        /// Calculations of `/api/pi/progress` by ID, kept while they run and for
+        /// [`PI_RESULT_LIFETIME`] after, so that the clients reconnecting follow the
+        /// calculation they started.
+        #[cfg(feature = "json")]
+        #[derive(Default)]
+        struct PiCalculations {
+            next_id: AtomicU64,
+            calculations: Mutex<HashMap<u64, Arc<Mutex<PiProgress>>>>,
+        }
+
+        /// Last events of a calculation and the clients following it.
+        #[cfg(feature = "json")]
+        #[derive(Default)]
+        struct PiProgress {
+            progress: Option<Event>,
+            /// `None` once finished if the calculation was cancelled.
+            result: Option<Event>,
+            finished: Option<Instant>,
+            clients: Vec<EventSender>,
+            /// Since when no client follows the calculation.
+            abandoned: Option<Instant>,
+        }
+
+        /// Same as `/api/pi`, but streams the progress of the calculation as Server-Sent Events:
+        /// `progress` events with the iterations done, whose ID is the ID of the calculation
+        /// and the percentage (`3-42`), then a `result` event with the result (`3-result`).
+        ///
+        /// A client reconnecting in the middle of the calculation gets its latest progress and
+        /// follows it, instead of starting a new one. Without any client following it for
+        /// [`PI_RESUME_WINDOW`], the calculation is cancelled. A client reconnecting after the
+        /// result gets `204 No Content`, which tells `EventSource` to stop reconnecting.
+        ///
+        /// The calculations run on the shared pool of `/api/pi`, and at most
+        /// [`MAX_PI_CALCULATIONS`] of them at once: the others get `503 Service Unavailable`.
+        #[cfg(feature = "json")]
+        fn api_pi_progress(
+            request: &mut Request,
+            pool: &Arc<ThreadPool>,
+            calculations: &PiCalculations,
+        ) -> Response {
+            let resumed = sse::last_event_id(request).and_then(|id| {
+                let (id, position) = id.split_once('-')?;
+                Some((id.parse::<u64>().ok()?, position))
+            });
+            if let Some((_, "result")) = resumed {
+                return Response::new(StatusCode::NO_CONTENT);
+            }
+
+            let mut running = calculations.calculations.lock().unwrap();
+            running.retain(|_, progress| {
+                let progress = progress.lock().unwrap();
+                match (progress.finished, &progress.result) {
+                    (None, _) => true,
+                    (Some(finished), Some(_)) => finished.elapsed() < PI_RESULT_LIFETIME,
+                    // Cancelled, so it cannot be resumed.
+                    (Some(_), None) => false,
+                }
+            });
+            if let Some(progress) = resumed.and_then(|(id, _)| running.get(&id)) {
+                let (events, response) = EventStream::new().start();
+                let mut progress = progress.lock().unwrap();
+                if let Some(event) = progress.result.as_ref().or(progress.progress.as_ref()) {
+                    let _ = events.send(event);
+                }
+                // The stream ends when its sender is dropped, right away if there is a result.
+                if progress.result.is_none() {
+                    progress.clients.push(events);
+                }
+                return response;
             }
-            if let Err(err) = stream.flush() {
-                eprintln!("Error flushing stream: {}", err);
-                return;
+
+            let (threads, iterations) = match pi_params(request) {
+                Ok(params) => params,
+                Err(response) => return response,
+            };
+            let unfinished = running
+                .values()
+                .filter(|progress| progress.lock().unwrap().finished.is_none())
+                .count();
+            if unfinished >= MAX_PI_CALCULATIONS {
+                return json::error(
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    "too many calculations are running",
+                )
+                .with_header("Retry-After", "1");
+            }
+            let id = calculations.next_id.fetch_add(1, Ordering::Relaxed);
+            let (events, response) = EventStream::new().start();
+            let progress = Arc::new(Mutex::new(PiProgress {
+                clients: vec![events],
+                ..PiProgress::default()
+            }));
+            running.insert(id, Arc::clone(&progress));
+            drop(running);
+
+            let pool = Arc::clone(pool);
+            thread::spawn(move || {
+                let start = Instant::now();
+                let interval = Duration::from_millis(100);
+                let report = |done: usize| {
+                    let data = serde_json::json!({ "done": done, "iterations": iterations });
+                    let event = Event::new(data.to_string())
+                        .with_event("progress")
+                        .with_id(format!("{}-{}", id, done * 100 / iterations));
+                    publish(&progress, event, |progress, event| progress.progress = Some(event));
+
+                    let mut progress = progress.lock().unwrap();
+                    if !progress.clients.is_empty() {
+                        progress.abandoned = None;
+                        return true;
+                    }
+                    let abandoned = progress.abandoned.get_or_insert_with(Instant::now);
+                    abandoned.elapsed() < PI_RESUME_WINDOW
+                };
+                let pi = super::pi::calculate_pi_with_progress_on(
+                    &pool, threads, iterations, interval, report,
+                );
+
+                let result = pi.map(|pi| PiResult {
+                    pi,
+                    threads,
+                    iterations,
+                    elapsed_ms: start.elapsed().as_secs_f64() * 1000.0,
+                });
+                if let Some(Ok(result)) = result.map(|result| serde_json::to_string(&result)) {
+                    let event = Event::new(result)
+                        .with_event("result")
+                        .with_id(format!("{}-result", id));
+                    publish(&progress, event, |progress, event| progress.result = Some(event));
+                }
+                let mut progress = progress.lock().unwrap();
+                progress.finished = Some(Instant::now());
+                progress.clients.clear();
+            });
+            response
+        }
+
+        /// Keeps the event of a calculation with `store`, for the clients reconnecting later,
+        /// and sends it to the clients following the calculation. The clients that left are
+        /// removed. The lock is not held while sending, as a slow client blocks the sender.
+        #[cfg(feature = "json")]
+        fn publish<F>(progress: &Mutex<PiProgress>, event: Event, store: F)
+        where
+            F: FnOnce(&mut PiProgress, Event),
+        {
+            let clients = {
+                let mut progress = progress.lock().unwrap();
+                store(&mut progress, event.clone());
+                mem::take(&mut progress.clients)
+            };
+            let clients: Vec<_> = clients
+                .into_iter()
+                .filter(|client| client.send(&event).is_ok())
+                .collect();
+            progress.lock().unwrap().clients.extend(clients);
+        }
+
+        #[cfg(test)]
+        mod tests {
+            #[cfg(feature = "json")]
+            use super::body::Body;
+            use super::request::Method;
+            use super::response::Response;
+            use super::rate_limit::Rate;
+            use super::testing::TestServer;
+            use super::vhost::UnknownHosts;
+            use super::*;
+            #[cfg(feature = "json")]
+            use std::io::Read;
+            use std::path::PathBuf;
+
+            fn server() -> TestServer {
+                TestServer::start(default_router(&Config::default()))
+            }
+
+            #[test]
+            fn index() {
+                let response = server().get("/").unwrap();
+                assert_eq!(response.status, StatusCode::OK);
+                assert_eq!(
+                    response.header("Content-Type"),
+                    Some("text/html; charset=utf-8")
+                );
+                assert!(response.text().contains("<p class=\"uptime\">Up for 0s.</p>"));
+            }
+
+            #[test]
+            fn not_found() {
+                let response = server().get("/missing/<b>").unwrap();
+                assert_eq!(response.status, StatusCode::NOT_FOUND);
+                assert!(response.text().contains("<code>/missing/&lt;b&gt;</code>"));
+            }
+
+            #[test]
+            fn static_files() {
+                let server = server();
+                let response = server.get("/style.css").unwrap();
+                assert_eq!(response.status, StatusCode::OK);
+                assert_eq!(response.header("Content-Type"), Some("text/css; charset=utf-8"));
+                let etag = response.header("ETag").unwrap();
+
+                let request =
+                    Request::new(Method::Get, "/style.css").with_header("If-None-Match", etag);
+                let response = server.send(&request).unwrap();
+                assert_eq!(response.status, StatusCode::NOT_MODIFIED);
+                assert!(response.body.is_empty());
+            }
+
+            #[test]
+            fn virtual_hosts() {
+                let mut config = Config {
+                    hosts: vec![(String::from("*.example.com"), PathBuf::from("templates"))],
+                    access_log: None,
+                    ..Config::default()
+                };
+                let get = |config: &Config, host: &str, path: &str| {
+                    TestServer::start(default_handler(config, &Arc::default()))
+                        .send(&Request::new(Method::Get, path).with_header("Host", host))
+                        .unwrap()
+                };
+
+                let response = get(&config, "www.example.com", "/hello.html");
+                assert_eq!(response.status, StatusCode::OK);
+                assert!(response.text().contains("{{ uptime }}"));
+                let response = get(&config, "www.example.com", "/style.css");
+                assert_eq!(response.status, StatusCode::NOT_FOUND);
+                assert_eq!(get(&config, "localhost", "/style.css").status, StatusCode::OK);
+
+                config.unknown_hosts = UnknownHosts::Misdirected;
+                let response = get(&config, "localhost", "/style.css");
+                assert_eq!(response.status, StatusCode::MISDIRECTED_REQUEST);
+            }
+
+            #[test]
+            fn reverse_proxy() {
+                let upstream = TestServer::start(|request: &mut Request| {
+                    Response::new(StatusCode::OK).with_body(request.path.clone())
+                });
+                let config = Config {
+                    proxies: vec![(
+                        String::from("/backend"),
+                        vec![format!("{}/base", upstream.address())],
+                    )],
+                    ..Config::default()
+                };
+                let server = TestServer::start(default_router(&config));
+
+                let response = server.get("/backend/users/1").unwrap();
+                assert_eq!(response.status, StatusCode::OK);
+                assert_eq!(response.text(), "/base/users/1");
+                assert_eq!(server.get("/").unwrap().status, StatusCode::OK);
+            }
+
+            #[test]
+            fn rate_limit() {
+                let mut config = Config {
+                    rate_limit_rate: Some(Rate::new(1, Duration::from_secs(60))),
+                    rate_limit_burst: Some(2),
+                    access_log: None,
+                    ..Config::default()
+                };
+                let server = TestServer::start(default_handler(&config, &Arc::default()));
+
+                assert_eq!(server.get("/").unwrap().status, StatusCode::OK);
+                assert_eq!(server.get("/").unwrap().status, StatusCode::OK);
+                assert_eq!(server.get("/").unwrap().status, StatusCode::OK);
+
+                config.rate_limit_allow_localhost = false;
+                let server = TestServer::start(default_handler(&config, &Arc::default()));
+                let mut client = server.client().unwrap();
+                assert_eq!(client.get("/").unwrap().status, StatusCode::OK);
+                assert_eq!(client.get("/").unwrap().status, StatusCode::OK);
+                let response = client.get("/").unwrap();
+                assert_eq!(response.status, StatusCode::TOO_MANY_REQUESTS);
+                assert_eq!(response.header("Retry-After"), Some("60"));
+            }
+
+            #[test]
+            fn cors() {
+                let config = Config {
+                    cors_paths: vec![String::from("/")],
+                    cors_origins: vec![String::from("http://localhost:3000")],
+                    cors_max_age: Some(Duration::from_secs(600)),
+                    ..Config::default()
+                };
+                let server = TestServer::start(default_router(&config));
+                let request = |method| {
+                    Request::new(method, "/").with_header("Origin", "http://localhost:3000")
+                };
+
+                let preflight =
+                    request(Method::Options).with_header("Access-Control-Request-Method", "GET");
+                let response = server.send(&preflight).unwrap();
+                assert_eq!(response.status, StatusCode::NO_CONTENT);
+                assert_eq!(
+                    response.header("Access-Control-Allow-Methods"),
+                    Some("GET, HEAD")
+                );
+                assert_eq!(response.header("Access-Control-Max-Age"), Some("600"));
+                // The route is under the mount of the files, but keeps its methods.
+                let preflight =
+                    request(Method::Options).with_header("Access-Control-Request-Method", "DELETE");
+                assert_eq!(server.send(&preflight).unwrap().status, StatusCode::FORBIDDEN);
+
+                let response = server.send(&request(Method::Get)).unwrap();
+                assert_eq!(response.status, StatusCode::OK);
+                assert_eq!(
+                    response.header("Access-Control-Allow-Origin"),
+                    Some("http://localhost:3000")
+                );
+                let response = server.get("/style.css").unwrap();
+                assert_eq!(response.header("Access-Control-Allow-Origin"), None);
+            }
+
+            #[test]
+            fn websocket_requires_upgrade() {
+                let response = server().get("/ws/echo").unwrap();
+                assert_eq!(response.status, StatusCode::UPGRADE_REQUIRED);
+                assert_eq!(response.header("Upgrade"), Some("websocket"));
+            }
+
+            #[cfg(feature = "json")]
+            #[test]
+            fn api_pi() {
+                let server = server();
+                let response = server.get("/api/pi?threads=2&iterations=1000").unwrap();
+                assert_eq!(response.status, StatusCode::OK);
+                let result: serde_json::Value = serde_json::from_slice(&response.body).unwrap();
+                assert!((result["pi"].as_f64().unwrap() - std::f64::consts::PI).abs() < 1e-3);
+                assert_eq!(result["threads"], 2);
+
+                let response = server.get("/api/pi?threads=0").unwrap();
+                assert_eq!(response.status, StatusCode::UNPROCESSABLE_ENTITY);
+            }
+
+            #[cfg(feature = "json")]
+            #[test]
+            fn api_pi_progress_resumes() {
+                let pool = Arc::new(ThreadPool::new(2));
+                let calculations = PiCalculations::default();
+                let target = "/api/pi/progress?threads=2&iterations=200000";
+                let mut request = Request::new(Method::Get, target);
+                let response = api_pi_progress(&mut request, &pool, &calculations);
+                let Body::Stream(mut reader) = response.body else {
+                    panic!("not a stream");
+                };
+
+                // Every read returns the next event. The client leaves after the first one.
+                let mut buffer = [0; 256];
+                let read = reader.read(&mut buffer).unwrap();
+                let event = String::from_utf8_lossy(&buffer[..read]).into_owned();
+                let id = event
+                    .lines()
+                    .find_map(|line| line.strip_prefix("id: "))
+                    .unwrap()
+                    .to_string();
+                assert!(id.starts_with("0-"), "{}", event);
+                drop(reader);
+
+                let mut request =
+                    Request::new(Method::Get, target).with_header("Last-Event-ID", id);
+                let response = api_pi_progress(&mut request, &pool, &calculations);
+                let events = String::from_utf8(response.body.into_bytes().unwrap()).unwrap();
+                assert!(events.contains("event: result\nid: 0-result\n"), "{}", events);
+                assert!(!events.contains("id: 1-"), "{}", events);
+                assert_eq!(calculations.next_id.load(Ordering::Relaxed), 1);
+
+                let mut request = Request::new(Method::Get, target)
+                    .with_header("Last-Event-ID", "0-result");
+                let response = api_pi_progress(&mut request, &pool, &calculations);
+                assert_eq!(response.status, StatusCode::NO_CONTENT);
+            }
+
+            #[cfg(feature = "json")]
+            #[test]
+            fn api_pi_progress_limit() {
+                let pool = Arc::new(ThreadPool::new(1));
+                let calculations = PiCalculations::default();
+                for id in 0..MAX_PI_CALCULATIONS as u64 {
+                    let progress = Arc::new(Mutex::new(PiProgress::default()));
+                    calculations.calculations.lock().unwrap().insert(id, progress);
+                }
+                let next_id = MAX_PI_CALCULATIONS as u64;
+                calculations.next_id.store(next_id, Ordering::Relaxed);
+
+                let mut request = Request::new(Method::Get, "/api/pi/progress");
+                let response = api_pi_progress(&mut request, &pool, &calculations);
+                assert_eq!(response.status, StatusCode::SERVICE_UNAVAILABLE);
+                assert_eq!(response.headers.get("Retry-After"), Some("1"));
+
+                // A cancelled calculation is forgotten, and leaves room for another one.
+                let cancelled = Arc::clone(&calculations.calculations.lock().unwrap()[&0]);
+                cancelled.lock().unwrap().finished = Some(Instant::now());
+                let mut request = Request::new(Method::Get, "/api/pi/progress?iterations=10")
+                    .with_header("Last-Event-ID", "0-50");
+                let response = api_pi_progress(&mut request, &pool, &calculations);
+                assert_eq!(response.status, StatusCode::OK);
+                let running = calculations.calculations.lock().unwrap();
+                assert!(!running.contains_key(&0) && running.contains_key(&next_id));
             }
         }
     }
@@ -420,7 +1147,13 @@ pub mod final_project {
     /// ```
     pub mod pi {
         use super::thread_pool::ThreadPool;
-        use std::sync::{Arc, Mutex};
+        use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+        use std::sync::{mpsc, Arc};
+        use std::thread;
+        use std::time::Duration;
+
+        /// Iterations a job calculates between two updates of the progress.
+        const PROGRESS_STEP: usize = 10_000;
 
         /// Calculates the number pi by using the following integral (0 to 1):
         /// ```text
@@ -439,20 +1172,110 @@ pub mod final_project {
         /// information can be found in the [`ThreadPool`] module.
         pub fn calculate_pi(num_threads: usize, iterations: usize) -> f64 {
             let pool = ThreadPool::new(num_threads);
-            let pi = Arc::new(Mutex::new(0.0));
+            calculate_pi_on(&pool, num_threads, iterations)
+        }
 
-            for id in 0..iterations {
-                let pi = Arc::clone(&pi);
-                pool.execute(move || {
-                    let value = integrate(id, iterations);
+        /// Same as [`calculate_pi`], but with the threads of an existing pool, which can be
+        /// shared by several calculations. The iterations are split into `jobs` ranges, each one
+        /// calculated by a single job of the pool.
+        ///
+        /// # Panics
+        /// This function will panic if the number of jobs is 0.
+        pub fn calculate_pi_on(pool: &ThreadPool, jobs: usize, iterations: usize) -> f64 {
+            let done = Arc::new(AtomicUsize::new(0));
+            let cancelled = Arc::new(AtomicBool::new(false));
+            queue_jobs(pool, jobs, iterations, &done, &cancelled)
+                .iter()
+                .sum()
+        }
+
+        /// Same as [`calculate_pi`], but calls `progress` with the number of iterations done
+        /// every `interval` while the calculation runs, and once more with all of them at the
+        /// end.
+        ///
+        /// # Panics
+        /// This function will panic if the number of threads is less than or equal to 0.
+        pub fn calculate_pi_with_progress<F: FnMut(usize)>(
+            num_threads: usize,
+            iterations: usize,
+            interval: Duration,
+            mut progress: F,
+        ) -> f64 {
+            let pool = ThreadPool::new(num_threads);
+            let report = |done| {
+                progress(done);
+                true
+            };
+            calculate_pi_with_progress_on(&pool, num_threads, iterations, interval, report)
+                .unwrap_or_default()
+        }
 
-                    let mut guard = pi.lock().unwrap();
-                    *guard += value;
+        /// Same as [`calculate_pi_with_progress`], but with the threads of an existing pool, like
+        /// [`calculate_pi_on`]. The calculation stops as soon as `progress` returns `false`, and
+        /// then `None` is returned.
+        ///
+        /// # Panics
+        /// This function will panic if the number of jobs is 0.
+        pub fn calculate_pi_with_progress_on<F: FnMut(usize) -> bool>(
+            pool: &ThreadPool,
+            jobs: usize,
+            iterations: usize,
+            interval: Duration,
+            mut progress: F,
+        ) -> Option<f64> {
+            let done = Arc::new(AtomicUsize::new(0));
+            let cancelled = Arc::new(AtomicBool::new(false));
+            let sums = queue_jobs(pool, jobs, iterations, &done, &cancelled);
+
+            loop {
+                let count = done.load(Ordering::SeqCst);
+                if !progress(count) {
+                    // The jobs still queued return right away.
+                    cancelled.store(true, Ordering::SeqCst);
+                    return None;
+                }
+                if count == iterations {
+                    break;
+                }
+                thread::sleep(interval);
+            }
+            Some(sums.iter().sum())
+        }
+
+        /// Queues one job per range of iterations. Every job adds the iterations it calculated
+        /// to `done` as it goes, and sends its part of the sum once it finishes. The receiver
+        /// ends when all the jobs have finished. The jobs stop early once `cancelled` is set.
+        fn queue_jobs(
+            pool: &ThreadPool,
+            jobs: usize,
+            iterations: usize,
+            done: &Arc<AtomicUsize>,
+            cancelled: &Arc<AtomicBool>,
+        ) -> mpsc::Receiver<f64> {
+            assert!(jobs > 0, "The calculation needs at least one job");
+            let (sender, receiver) = mpsc::channel();
+
+            for job in 0..jobs {
+                let (start, end) = (iterations * job / jobs, iterations * (job + 1) / jobs);
+                let sender = sender.clone();
+                let done = Arc::clone(done);
+                let cancelled = Arc::clone(cancelled);
+                pool.execute(move || {
+                    let mut sum = 0.0;
+                    for step in (start..end).step_by(PROGRESS_STEP) {
+                        if cancelled.load(Ordering::SeqCst) {
+                            return;
+                        }
+                        let step_end = (step + PROGRESS_STEP).min(end);
+                        sum += (step..step_end)
+                            .map(|id| integrate(id, iterations))
+                            .sum::<f64>();
+                        done.fetch_add(step_end - step, Ordering::SeqCst);
+                    }
+                    let _ = sender.send(sum);
                 })
             }
-            drop(pool);
-            let pi = *pi.lock().unwrap();
-            pi
+            receiver
         }
 
         fn integrate(iteration: usize, max_iterations: usize) -> f64 {
@@ -487,6 +1310,33 @@ pub mod final_project {
                 check_difference(pi, 1e-5);
             }
 
+            #[test]
+            fn progress() {
+                let mut reports = Vec::new();
+                let pi = calculate_pi_with_progress(4, 10_000, Duration::from_millis(1), |done| {
+                    reports.push(done)
+                });
+
+                check_difference(pi, 1e-5);
+                assert_eq!(reports.last(), Some(&10_000));
+                assert!(reports.windows(2).all(|pair| pair[0] <= pair[1]));
+            }
+
+            #[test]
+            fn shared_pool() {
+                let pool = ThreadPool::new(2);
+                check_difference(calculate_pi_on(&pool, 3, 1_000), 1e-5);
+                let pi = calculate_pi_with_progress_on(&pool, 8, 10, Duration::ZERO, |_| true);
+                check_difference(pi.unwrap(), 1e-2);
+
+                let mut reports = 0;
+                let pi = calculate_pi_with_progress_on(&pool, 2, 10_000_000, Duration::ZERO, |_| {
+                    reports += 1;
+                    reports < 3
+                });
+                assert_eq!((pi, reports), (None, 3));
+            }
+
             #[test]
             fn large_iterations_and_threads() {
                 let pi = calculate_pi(8, 1_000_000);
@@ -0,0 +1,239 @@
+use super::date::DateTime;
+use super::encoding::escape_json;
+use super::middleware::{Middleware, Next, REQUEST_ID_HEADER};
+use super::request::Request;
+use super::response::Response;
+use std::io::{self, Write};
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::Instant;
+
+/// Format of the lines written by the [`AccessLog`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    /// The [Common Log Format] of the Apache and Nginx servers, understood by most log analyzers:
+    ///
+    /// ```text
+    /// 127.0.0.1 - - [10/Oct/2000:13:55:36 +0000] "GET /index.html HTTP/1.1" 200 2326
+    /// ```
+    ///
+    /// [Common Log Format]: https://httpd.apache.org/docs/current/logs.html#common
+    Common,
+    /// One JSON object per line, with the latency and the request ID too:
+    ///
+    /// ```text
+    /// {"time":"2000-10-10T13:55:36Z","remote":"127.0.0.1","method":"GET","path":"/index.html",
+    ///  "query":null,"version":"HTTP/1.1","status":200,"bytes":2326,"duration_ms":0.412,
+    ///  "request_id":"2f1a-000001"}
+    /// ```
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "common" | "clf" => Ok(LogFormat::Common),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Details of a request and its response written in a line of the log.
+struct Entry {
+    received: DateTime,
+    remote: Option<String>,
+    method: String,
+    path: String,
+    query: Option<String>,
+    version: String,
+    status: u16,
+    /// `None` for streamed bodies, whose length is not known in advance.
+    bytes: Option<u64>,
+    duration_ms: f64,
+    request_id: Option<String>,
+}
+
+impl Entry {
+    fn common(&self) -> String {
+        let target = match &self.query {
+            Some(query) => format!("{}?{}", self.path, query),
+            None => self.path.clone(),
+        };
+
+        format!(
+            "{} - - [{}] \"{}\" {} {}",
+            self.remote.as_deref().unwrap_or("-"),
+            self.received.clf(),
+            format!("{} {} {}", self.method, target, self.version).escape_default(),
+            self.status,
+            self.bytes
+                .map_or_else(|| String::from("-"), |b| b.to_string())
+        )
+    }
+
+    fn json(&self) -> String {
+        let string = |value: Option<&str>| match value {
+            Some(value) => format!("\"{}\"", escape_json(value)),
+            None => String::from("null"),
+        };
+
+        format!(
+            "{{\"time\":\"{}\",\"remote\":{},\"method\":\"{}\",\"path\":{},\"query\":{},\
+             \"version\":\"{}\",\"status\":{},\"bytes\":{},\"duration_ms\":{:.3},\
+             \"request_id\":{}}}",
+            self.received,
+            string(self.remote.as_deref()),
+            self.method,
+            string(Some(&self.path)),
+            string(self.query.as_deref()),
+            self.version,
+            self.status,
+            self.bytes
+                .map_or_else(|| String::from("null"), |b| b.to_string()),
+            self.duration_ms,
+            string(self.request_id.as_deref())
+        )
+    }
+}
+
+/// Middleware writing a line for every request, in the [`LogFormat`] chosen.
+///
+/// The request line is recorded before calling the rest of the pipeline, so it is the one sent by
+/// the client even if a handler changes the path. The latency is the time spent by the rest of the
+/// pipeline to produce the response. The request IDs are only logged if [`RequestId`] is added to
+/// the pipeline after the log, so that it sets the header of the response before the log reads it.
+///
+/// [`RequestId`]: super::middleware::RequestId
+pub struct AccessLog {
+    format: LogFormat,
+    output: Mutex<Box<dyn Write + Send>>,
+}
+
+impl AccessLog {
+    pub fn new(format: LogFormat, output: impl Write + Send + 'static) -> AccessLog {
+        AccessLog {
+            format,
+            output: Mutex::new(Box::new(output)),
+        }
+    }
+
+    pub fn stdout(format: LogFormat) -> AccessLog {
+        AccessLog::new(format, io::stdout())
+    }
+
+    fn log(&self, line: &str) {
+        let mut output = self.output.lock().unwrap_or_else(|err| err.into_inner());
+
+        if let Err(err) = writeln!(output, "{}", line).and_then(|_| output.flush()) {
+            eprintln!("Error writing the access log: {}", err);
+        }
+    }
+}
+
+impl Middleware for AccessLog {
+    fn handle(&self, request: &mut Request, next: Next<'_>) -> Response {
+        let received = DateTime::now();
+        let start = Instant::now();
+        let (path, query) = (request.path.clone(), request.query.clone());
+
+        let response = next.run(request);
+
+        let entry = Entry {
+            received,
+            remote: request.peer_addr.map(|address| address.ip().to_string()),
+            method: request.method.to_string(),
+            path,
+            query,
+            version: request.version.to_string(),
+            status: response.status.as_u16(),
+            bytes: response.body.len(),
+            duration_ms: start.elapsed().as_secs_f64() * 1000.0,
+            request_id: response.headers.get(REQUEST_ID_HEADER).map(str::to_string),
+        };
+
+        self.log(&match self.format {
+            LogFormat::Common => entry.common(),
+            LogFormat::Json => entry.json(),
+        });
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::middleware::{Pipeline, RequestId};
+    use super::super::request::Method;
+    use super::super::response::StatusCode;
+    use super::super::router::Handler;
+    use super::*;
+    use std::sync::Arc;
+
+    /// Writer that can be read back after being moved into the log.
+    #[derive(Clone, Default)]
+    struct SharedOutput(Arc<Mutex<Vec<u8>>>);
+
+    impl SharedOutput {
+        fn text(&self) -> String {
+            String::from_utf8(self.0.lock().unwrap().clone()).unwrap()
+        }
+    }
+
+    impl Write for SharedOutput {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn log_request(format: LogFormat) -> String {
+        let output = SharedOutput::default();
+        let pipeline = Pipeline::new(|request: &mut Request| {
+            request.path = String::from("/changed");
+            Response::new(StatusCode::OK).with_body("ok")
+        })
+        .with(AccessLog::new(format, output.clone()))
+        .with(RequestId::new());
+
+        let mut request = Request::new(Method::Get, "/search?q=\"rust\"")
+            .with_header(REQUEST_ID_HEADER, "test-1");
+        request.peer_addr = Some("192.168.1.2:50000".parse().unwrap());
+        pipeline.handle(&mut request);
+
+        output.text()
+    }
+
+    #[test]
+    fn common_format() {
+        let log = log_request(LogFormat::Common);
+
+        let (host, rest) = log.split_once(" - - [").unwrap();
+        assert_eq!(host, "192.168.1.2");
+        assert!(rest.ends_with("] \"GET /search?q=\\\"rust\\\" HTTP/1.1\" 200 2\n"));
+    }
+
+    #[test]
+    fn json_format() {
+        let log = log_request(LogFormat::Json);
+
+        assert!(log.starts_with("{\"time\":\""));
+        assert!(log.contains(
+            "\"remote\":\"192.168.1.2\",\"method\":\"GET\",\"path\":\"/search\",\
+             \"query\":\"q=\\\"rust\\\"\",\"version\":\"HTTP/1.1\",\"status\":200,\"bytes\":2,"
+        ));
+        assert!(log.contains(",\"duration_ms\":"));
+        assert!(log.ends_with(",\"request_id\":\"test-1\"}\n"));
+    }
+
+    #[test]
+    fn parse_format() {
+        assert_eq!("JSON".parse(), Ok(LogFormat::Json));
+        assert_eq!("common".parse(), Ok(LogFormat::Common));
+        assert_eq!("xml".parse::<LogFormat>(), Err(()));
+    }
+}
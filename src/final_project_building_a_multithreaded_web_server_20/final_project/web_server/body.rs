@@ -0,0 +1,249 @@
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read, Write};
+
+/// Size of the chunks used to copy files and streams into the connection.
+pub const CHUNK_SIZE: usize = 8 * 1024;
+
+/// Body of a [`Response`](super::response::Response).
+///
+/// - [`Body::Bytes`]: the whole body is kept in memory.
+/// - [`Body::File`]: the file is copied into the connection in chunks, so big files are never
+///   loaded into memory. Its length is known beforehand, so it is sent with `Content-Length`.
+/// - [`Body::Stream`]: the reader is copied into the connection as it produces data. Its length
+///   is unknown, so it is sent with `Transfer-Encoding: chunked` and every chunk is flushed right
+///   away.
+pub enum Body {
+    Bytes(Vec<u8>),
+    File { file: File, len: u64 },
+    Stream(Box<dyn Read + Send>),
+}
+
+impl Body {
+    pub fn empty() -> Body {
+        Body::Bytes(Vec::new())
+    }
+
+    /// Creates a body with the contents of the file, from its current position to the end.
+    pub fn file(mut file: File) -> io::Result<Body> {
+        let len = file.metadata()?.len();
+        let position = io::Seek::stream_position(&mut file)?;
+
+        Ok(Body::File {
+            file,
+            len: len.saturating_sub(position),
+        })
+    }
+
+    /// Creates a body with everything read from the reader until it returns EOF.
+    pub fn stream(reader: impl Read + Send + 'static) -> Body {
+        Body::Stream(Box::new(reader))
+    }
+
+    /// Length of the body, or `None` if it is a stream.
+    pub fn len(&self) -> Option<u64> {
+        match self {
+            Body::Bytes(bytes) => Some(bytes.len() as u64),
+            Body::File { len, .. } => Some(*len),
+            Body::Stream(_) => None,
+        }
+    }
+
+    /// Checks if the body is known to be empty. Streams are never considered empty.
+    pub fn is_empty(&self) -> bool {
+        self.len() == Some(0)
+    }
+
+    /// The contents of the body, if it is kept in memory.
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match self {
+            Body::Bytes(bytes) => Some(bytes),
+            _ => None,
+        }
+    }
+
+    /// Reads the whole body into memory.
+    pub fn into_bytes(self) -> io::Result<Vec<u8>> {
+        let mut bytes = Vec::new();
+
+        match self {
+            Body::Bytes(body) => return Ok(body),
+            Body::File { file, len } => {
+                file.take(len).read_to_end(&mut bytes)?;
+            }
+            Body::Stream(mut reader) => {
+                reader.read_to_end(&mut bytes)?;
+            }
+        }
+
+        Ok(bytes)
+    }
+
+    /// Writes the body as it is, without any framing. Streams are flushed after every read.
+    pub fn write_to<W: Write>(self, writer: &mut W) -> io::Result<()> {
+        match self {
+            Body::Bytes(bytes) => writer.write_all(&bytes),
+            Body::File { file, len } => {
+                let copied = io::copy(&mut file.take(len), writer)?;
+                if copied < len {
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        "file truncated while it was sent",
+                    ));
+                }
+                Ok(())
+            }
+            Body::Stream(mut reader) => copy_flushing(&mut reader, writer),
+        }
+    }
+
+    /// Writes the body with the chunked transfer coding, terminated by the last (empty) chunk.
+    pub fn write_chunked_to<W: Write>(self, writer: &mut W) -> io::Result<()> {
+        let mut chunked = ChunkedWriter::new(writer);
+        self.write_to(&mut chunked)?;
+        chunked.finish().map(|_| ())
+    }
+}
+
+impl Default for Body {
+    fn default() -> Self {
+        Body::empty()
+    }
+}
+
+impl fmt::Debug for Body {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Body::Bytes(bytes) => f.debug_tuple("Bytes").field(&bytes.len()).finish(),
+            Body::File { len, .. } => f.debug_struct("File").field("len", len).finish(),
+            Body::Stream(_) => f.write_str("Stream"),
+        }
+    }
+}
+
+impl From<Vec<u8>> for Body {
+    fn from(bytes: Vec<u8>) -> Self {
+        Body::Bytes(bytes)
+    }
+}
+
+impl From<&[u8]> for Body {
+    fn from(bytes: &[u8]) -> Self {
+        Body::Bytes(bytes.to_vec())
+    }
+}
+
+impl From<String> for Body {
+    fn from(text: String) -> Self {
+        Body::Bytes(text.into_bytes())
+    }
+}
+
+impl From<&str> for Body {
+    fn from(text: &str) -> Self {
+        Body::Bytes(text.as_bytes().to_vec())
+    }
+}
+
+/// Copies the reader into the writer, flushing after every read so the client receives the data
+/// as soon as it is produced.
+fn copy_flushing<R: Read + ?Sized, W: Write>(reader: &mut R, writer: &mut W) -> io::Result<()> {
+    let mut buffer = [0; CHUNK_SIZE];
+
+    loop {
+        let read = match reader.read(&mut buffer) {
+            Ok(0) => return Ok(()),
+            Ok(read) => read,
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        };
+        writer.write_all(&buffer[..read])?;
+        writer.flush()?;
+    }
+}
+
+/// Writer encoding everything written into it with the chunked transfer coding. Every call to
+/// `write` produces one chunk, and [`finish`](ChunkedWriter::finish) must be called at the end to
+/// write the last chunk.
+///
+/// # Example
+///
+/// ```rust
+/// let mut output = Vec::new();
+/// let mut writer = ChunkedWriter::new(&mut output);
+/// writer.write_all(b"Hello")?;
+/// writer.finish()?;
+///
+/// assert_eq!(output, b"5\r\nHello\r\n0\r\n\r\n");
+/// ```
+pub struct ChunkedWriter<W: Write> {
+    inner: W,
+}
+
+impl<W: Write> ChunkedWriter<W> {
+    pub fn new(inner: W) -> ChunkedWriter<W> {
+        ChunkedWriter { inner }
+    }
+
+    /// Writes the last chunk, without trailer fields, and returns the inner writer.
+    pub fn finish(mut self) -> io::Result<W> {
+        self.inner.write_all(b"0\r\n\r\n")?;
+        Ok(self.inner)
+    }
+}
+
+impl<W: Write> Write for ChunkedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        // An empty chunk would be read as the end of the body.
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        write!(self.inner, "{:X}\r\n", buf.len())?;
+        self.inner.write_all(buf)?;
+        self.inner.write_all(b"\r\n")?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn chunked_stream() {
+        let body = Body::stream(Cursor::new(vec![b'a'; CHUNK_SIZE + 3]));
+        assert_eq!(body.len(), None);
+
+        let mut output = Vec::new();
+        body.write_chunked_to(&mut output).unwrap();
+
+        let mut expected = format!("{:X}\r\n", CHUNK_SIZE).into_bytes();
+        expected.extend_from_slice(&[b'a'; CHUNK_SIZE]);
+        expected.extend_from_slice(b"\r\n3\r\naaa\r\n0\r\n\r\n");
+        assert_eq!(output, expected);
+    }
+
+    #[test]
+    fn empty_chunked_body() {
+        let mut output = Vec::new();
+        Body::empty().write_chunked_to(&mut output).unwrap();
+        assert_eq!(output, b"0\r\n\r\n");
+    }
+
+    #[test]
+    fn into_bytes() {
+        assert_eq!(Body::from("text").into_bytes().unwrap(), b"text");
+        assert_eq!(
+            Body::stream(Cursor::new(b"stream".to_vec()))
+                .into_bytes()
+                .unwrap(),
+            b"stream"
+        );
+    }
+}
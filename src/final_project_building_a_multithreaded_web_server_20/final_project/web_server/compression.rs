@@ -0,0 +1,404 @@
+use super::body::Body;
+use super::headers::Headers;
+use super::middleware::Middleware;
+use super::request::Request;
+use super::response::Response;
+use std::io::{Cursor, Read};
+
+/// Bodies smaller than this are sent uncompressed by default: the headers of the coding and the
+/// CPU time cost more than the bytes saved.
+pub const MIN_SIZE: usize = 1024;
+
+/// Brotli quality from 0 to 11. The highest ones are too slow to compress on every request.
+#[cfg(feature = "brotli")]
+const BROTLI_QUALITY: u32 = 5;
+/// Base 2 logarithm of the Brotli window size.
+#[cfg(feature = "brotli")]
+const BROTLI_WINDOW: u32 = 22;
+
+/// Content codings the server can compress the responses with. Every coding is only available
+/// with the cargo feature of the same name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    #[cfg(feature = "brotli")]
+    Brotli,
+    #[cfg(feature = "gzip")]
+    Gzip,
+    /// The zlib format, which is what HTTP calls `deflate`.
+    #[cfg(feature = "deflate")]
+    Deflate,
+}
+
+impl Encoding {
+    /// The codings enabled in this build, from the most to the least preferred.
+    pub const SUPPORTED: &'static [Encoding] = &[
+        #[cfg(feature = "brotli")]
+        Encoding::Brotli,
+        #[cfg(feature = "gzip")]
+        Encoding::Gzip,
+        #[cfg(feature = "deflate")]
+        Encoding::Deflate,
+    ];
+
+    /// Name of the coding in the `Accept-Encoding` and `Content-Encoding` headers.
+    pub fn as_str(&self) -> &'static str {
+        match *self {
+            #[cfg(feature = "brotli")]
+            Encoding::Brotli => "br",
+            #[cfg(feature = "gzip")]
+            Encoding::Gzip => "gzip",
+            #[cfg(feature = "deflate")]
+            Encoding::Deflate => "deflate",
+        }
+    }
+
+    fn matches(&self, name: &str) -> bool {
+        name.eq_ignore_ascii_case(self.as_str())
+            || (self.as_str() == "gzip" && name.eq_ignore_ascii_case("x-gzip"))
+    }
+
+    /// Picks the coding for a request with the `Accept-Encoding` header: the supported one with
+    /// the highest quality value, preferring the order of [`Encoding::SUPPORTED`] on ties.
+    /// Returns `None` if the response must not be compressed.
+    pub fn negotiate(accept_encoding: &str) -> Option<Encoding> {
+        let mut best: Option<(Encoding, f32)> = None;
+
+        for &encoding in Encoding::SUPPORTED {
+            let quality = quality(accept_encoding, |name| encoding.matches(name));
+            if quality > 0.0 && best.is_none_or(|(_, best)| quality > best) {
+                best = Some((encoding, quality));
+            }
+        }
+
+        best.map(|(encoding, _)| encoding)
+    }
+
+    /// Wraps the reader with an encoder that compresses what it reads.
+    #[cfg_attr(
+        not(any(feature = "gzip", feature = "deflate", feature = "brotli")),
+        allow(unused_variables)
+    )]
+    fn encoder(self, reader: Box<dyn Read + Send>) -> Box<dyn Read + Send> {
+        match self {
+            #[cfg(feature = "brotli")]
+            Encoding::Brotli => Box::new(brotli::CompressorReader::new(
+                reader,
+                super::body::CHUNK_SIZE,
+                BROTLI_QUALITY,
+                BROTLI_WINDOW,
+            )),
+            #[cfg(feature = "gzip")]
+            Encoding::Gzip => Box::new(flate2::read::GzEncoder::new(
+                reader,
+                flate2::Compression::default(),
+            )),
+            #[cfg(feature = "deflate")]
+            Encoding::Deflate => Box::new(flate2::read::ZlibEncoder::new(
+                reader,
+                flate2::Compression::default(),
+            )),
+        }
+    }
+
+    /// Compresses the body. Bodies in memory are compressed right away, so their length is still
+    /// known, while files and streams are compressed as they are sent.
+    pub fn encode(self, body: Body) -> Body {
+        match body {
+            Body::Bytes(bytes) => {
+                let mut encoded = Vec::new();
+                self.encoder(Box::new(Cursor::new(bytes)))
+                    .read_to_end(&mut encoded)
+                    .expect("compressing in memory cannot fail");
+                Body::Bytes(encoded)
+            }
+            Body::File { file, len } => Body::Stream(self.encoder(Box::new(file.take(len)))),
+            Body::Stream(reader) => Body::Stream(self.encoder(reader)),
+        }
+    }
+}
+
+/// Quality value given to the coding by the `Accept-Encoding` header, from 0 (not acceptable) to
+/// 1. Codings not listed get the quality of `*`, or 0 if there is no `*`.
+fn quality(accept_encoding: &str, matches: impl Fn(&str) -> bool) -> f32 {
+    let mut wildcard = 0.0;
+
+    for item in accept_encoding.split(',') {
+        let mut parts = item.split(';');
+        let name = parts.next().unwrap_or_default().trim();
+        let quality = parts
+            .filter_map(|parameter| parameter.split_once('='))
+            .find(|(key, _)| key.trim().eq_ignore_ascii_case("q"))
+            .map_or(1.0, |(_, value)| value.trim().parse().unwrap_or(0.0));
+
+        if matches(name) {
+            return quality;
+        }
+        if name == "*" {
+            wildcard = quality;
+        }
+    }
+
+    wildcard
+}
+
+/// Checks if a media type is worth compressing: text, JavaScript, JSON, XML and SVG. Images,
+/// videos, archives and fonts are already compressed. Event streams are excluded because the
+/// encoders hold back the data until they have enough to compress.
+pub fn compressible(content_type: &str) -> bool {
+    let media_type = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+
+    (media_type.starts_with("text/") && media_type != "text/event-stream")
+        || media_type.ends_with("+json")
+        || media_type.ends_with("+xml")
+        || matches!(
+            media_type.as_str(),
+            "application/json" | "application/javascript" | "application/xml"
+        )
+}
+
+/// Adds a request header to the `Vary` header, e.g. `Accept-Encoding` so caches keep one
+/// response per coding.
+pub(super) fn add_vary(headers: &mut Headers, name: &str) {
+    if headers.contains_token("Vary", name) || headers.contains_token("Vary", "*") {
+        return;
+    }
+
+    match headers.get("Vary") {
+        Some(vary) => {
+            let vary = format!("{}, {}", vary, name);
+            headers.insert("Vary", vary);
+        }
+        None => headers.insert("Vary", name),
+    }
+}
+
+/// Middleware compressing the responses with the best coding accepted by the client, see
+/// [`Encoding::negotiate`].
+///
+/// Only the [`compressible`] media types are compressed, and only if the response has no
+/// `Content-Encoding` yet and its body is not smaller than the minimum size. Streams are always
+/// compressed, as their size is unknown. All the responses that could be compressed get
+/// `Vary: Accept-Encoding`, even the ones sent uncompressed.
+///
+/// Without any of the `gzip`, `deflate` or `brotli` features the middleware does nothing.
+pub struct Compression {
+    min_size: u64,
+}
+
+impl Compression {
+    pub fn new() -> Compression {
+        Compression {
+            min_size: MIN_SIZE as u64,
+        }
+    }
+
+    /// Sets the size under which the bodies are sent uncompressed.
+    pub fn with_min_size(mut self, size: usize) -> Compression {
+        self.min_size = size as u64;
+        self
+    }
+}
+
+impl Default for Compression {
+    fn default() -> Self {
+        Compression::new()
+    }
+}
+
+impl Middleware for Compression {
+    fn after(&self, request: &Request, response: &mut Response) {
+        let eligible = response
+            .headers
+            .get("Content-Type")
+            .is_some_and(compressible);
+        if Encoding::SUPPORTED.is_empty() || !eligible {
+            return;
+        }
+        add_vary(&mut response.headers, "Accept-Encoding");
+
+        // No content, partial content and not modified.
+        if matches!(response.status.as_u16(), 204 | 206 | 304)
+            || response.headers.contains("Content-Encoding")
+            || response.body.len().is_some_and(|len| len < self.min_size)
+        {
+            return;
+        }
+
+        if let Some(encoding) = request
+            .header("Accept-Encoding")
+            .and_then(Encoding::negotiate)
+        {
+            let body = std::mem::take(&mut response.body);
+            response.body = encoding.encode(body);
+            response
+                .headers
+                .insert("Content-Encoding", encoding.as_str());
+
+            // The encoded bytes differ from the ones the strong entity tag and the byte ranges
+            // of the handler refer to.
+            if let Some(etag) = response
+                .headers
+                .get("ETag")
+                .filter(|etag| etag.starts_with('"'))
+            {
+                let weak = format!("W/{}", etag);
+                response.headers.insert("ETag", weak);
+            }
+            response.headers.remove("Accept-Ranges");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::middleware::Pipeline;
+    use super::super::request::Method;
+    #[cfg(any(feature = "gzip", feature = "deflate", feature = "brotli"))]
+    use super::super::response::StatusCode;
+    use super::super::router::Handler;
+    use super::*;
+
+    #[test]
+    fn quality_values() {
+        let gzip = |name: &str| name == "gzip";
+
+        assert_eq!(quality("gzip, br", gzip), 1.0);
+        assert_eq!(quality("br;q=1, gzip;q=0.5", gzip), 0.5);
+        assert_eq!(quality("br, *;q=0.2", gzip), 0.2);
+        assert_eq!(quality("*, gzip;q=0", gzip), 0.0);
+        assert_eq!(quality("identity", gzip), 0.0);
+        assert_eq!(quality("gzip;q=oops", gzip), 0.0);
+    }
+
+    #[test]
+    fn compressible_types() {
+        assert!(compressible("text/html; charset=utf-8"));
+        assert!(compressible("application/json"));
+        assert!(compressible("application/ld+json"));
+        assert!(compressible("image/svg+xml"));
+        assert!(!compressible("image/png"));
+        assert!(!compressible("application/zip"));
+        assert!(!compressible("text/event-stream"));
+    }
+
+    fn respond<F>(accept_encoding: Option<&str>, response: F) -> Response
+    where
+        F: Fn() -> Response + Send + Sync + 'static,
+    {
+        let pipeline = Pipeline::new(move |_: &mut Request| response()).with(Compression::new());
+
+        let mut request = Request::new(Method::Get, "/");
+        if let Some(accept_encoding) = accept_encoding {
+            request = request.with_header("Accept-Encoding", accept_encoding);
+        }
+        pipeline.handle(&mut request)
+    }
+
+    fn page(size: usize) -> Response {
+        Response::html("<p>compress me</p>".repeat(size / 18 + 1))
+    }
+
+    #[test]
+    #[cfg(not(any(feature = "gzip", feature = "deflate", feature = "brotli")))]
+    fn disabled() {
+        let response = respond(Some("gzip, deflate, br"), || page(4096));
+        assert!(!response.headers.contains("Content-Encoding"));
+        assert!(!response.headers.contains("Vary"));
+    }
+
+    #[test]
+    #[cfg(feature = "gzip")]
+    fn gzip() {
+        use flate2::read::GzDecoder;
+
+        let response = respond(Some("gzip"), || {
+            page(4096)
+                .with_header("ETag", "\"1-2\"")
+                .with_header("Accept-Ranges", "bytes")
+        });
+        assert_eq!(response.headers.get("Content-Encoding"), Some("gzip"));
+        assert_eq!(response.headers.get("Vary"), Some("Accept-Encoding"));
+        assert_eq!(response.headers.get("ETag"), Some("W/\"1-2\""));
+        assert!(!response.headers.contains("Accept-Ranges"));
+
+        let compressed = response.body.into_bytes().unwrap();
+        assert!(compressed.len() < 4096);
+        let mut decoded = Vec::new();
+        GzDecoder::new(&compressed[..])
+            .read_to_end(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, page(4096).body.into_bytes().unwrap());
+    }
+
+    #[test]
+    #[cfg(feature = "deflate")]
+    fn deflate_stream() {
+        use flate2::read::ZlibDecoder;
+
+        let response = respond(Some("deflate"), || {
+            Response::new(StatusCode::OK)
+                .with_header("Content-Type", "text/plain")
+                .with_body(Body::stream(Cursor::new(b"streamed".repeat(10))))
+        });
+
+        // Streams are compressed whatever their size.
+        assert_eq!(response.headers.get("Content-Encoding"), Some("deflate"));
+        assert_eq!(response.body.len(), None);
+        let mut decoded = Vec::new();
+        ZlibDecoder::new(Cursor::new(response.body.into_bytes().unwrap()))
+            .read_to_end(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, b"streamed".repeat(10));
+    }
+
+    #[test]
+    #[cfg(all(feature = "gzip", feature = "brotli"))]
+    fn negotiation() {
+        assert_eq!(Encoding::negotiate("gzip, br"), Some(Encoding::Brotli));
+        assert_eq!(Encoding::negotiate("gzip, br;q=0.5"), Some(Encoding::Gzip));
+        assert_eq!(Encoding::negotiate("*"), Some(Encoding::Brotli));
+        assert_eq!(Encoding::negotiate("identity"), None);
+
+        let response = respond(Some("br"), || page(4096));
+        let mut decoded = Vec::new();
+        brotli::Decompressor::new(Cursor::new(response.body.into_bytes().unwrap()), 4096)
+            .read_to_end(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, page(4096).body.into_bytes().unwrap());
+    }
+
+    #[test]
+    #[cfg(any(feature = "gzip", feature = "deflate", feature = "brotli"))]
+    fn skipped_responses() {
+        let all = Some("gzip, deflate, br");
+
+        // Not asked for, but the response still depends on the header.
+        let response = respond(None, || page(4096));
+        assert!(!response.headers.contains("Content-Encoding"));
+        assert_eq!(response.headers.get("Vary"), Some("Accept-Encoding"));
+
+        let small = respond(all, || page(100));
+        assert!(!small.headers.contains("Content-Encoding"));
+
+        let image = respond(all, || {
+            Response::new(StatusCode::OK)
+                .with_header("Content-Type", "image/png")
+                .with_body(vec![0; 4096])
+        });
+        assert!(!image.headers.contains("Content-Encoding"));
+        assert!(!image.headers.contains("Vary"));
+
+        let encoded = respond(all, || {
+            page(4096)
+                .with_header("Content-Encoding", "gzip")
+                .with_header("Vary", "Cookie")
+        });
+        assert_eq!(encoded.body.len(), page(4096).body.len());
+        assert_eq!(encoded.headers.get("Vary"), Some("Cookie, Accept-Encoding"));
+    }
+}
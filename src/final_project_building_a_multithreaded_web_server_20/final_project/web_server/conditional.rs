@@ -0,0 +1,279 @@
+use super::date::DateTime;
+use super::request::{Method, Request};
+use super::response::{Response, StatusCode};
+use std::fs::Metadata;
+use std::ops::Range;
+
+/// More ranges than this in a `Range` header are ignored and the full representation is sent,
+/// as a request for many small or overlapping ranges costs more to serve than the whole file.
+pub const MAX_RANGES: usize = 16;
+
+/// Validators of a representation, compared with the conditional headers of the requests to
+/// tell whether the copy cached by the client is still fresh.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Validators {
+    /// Strong entity tag, quotes included.
+    pub etag: String,
+    pub last_modified: Option<DateTime>,
+}
+
+impl Validators {
+    /// Builds the validators of a file from its size and modification time, which change
+    /// whenever the file is written.
+    pub fn from_metadata(metadata: &Metadata) -> Validators {
+        let last_modified = metadata.modified().ok().map(DateTime::from_system_time);
+        let mtime = last_modified.map_or(0, |date| date.to_unix());
+
+        Validators {
+            etag: format!("\"{:x}-{:x}\"", mtime, metadata.len()),
+            last_modified,
+        }
+    }
+
+    /// Adds the `ETag` and `Last-Modified` headers to the response.
+    pub fn apply(&self, mut response: Response) -> Response {
+        response.headers.insert("ETag", self.etag.clone());
+        if let Some(date) = self.last_modified {
+            response
+                .headers
+                .insert("Last-Modified", date.http().to_string());
+        }
+        response
+    }
+
+    /// Evaluates `If-None-Match` or, when the request does not have it, `If-Modified-Since`.
+    /// Returns `true` if the client already has the representation and only needs a
+    /// `304 Not Modified` response.
+    pub fn not_modified(&self, request: &Request) -> bool {
+        if !matches!(request.method, Method::Get | Method::Head) {
+            return false;
+        }
+
+        if let Some(value) = request.headers.get("If-None-Match") {
+            return value.trim() == "*" || entity_tags(value).any(|etag| weak_eq(etag, &self.etag));
+        }
+
+        match (request.headers.get("If-Modified-Since"), self.last_modified) {
+            (Some(value), Some(last_modified)) => {
+                DateTime::parse_http(value).is_some_and(|since| last_modified <= since)
+            }
+            _ => false,
+        }
+    }
+
+    /// Evaluates `If-Range`: the `Range` header is only honored if the representation still
+    /// matches the entity tag or the date sent by the client.
+    pub fn range_applies(&self, request: &Request) -> bool {
+        let Some(value) = request.headers.get("If-Range") else {
+            return true;
+        };
+        let value = value.trim();
+
+        if value.starts_with('"') {
+            // Weak entity tags never match, since the comparison must be strong.
+            value == self.etag
+        } else if value.starts_with("W/") {
+            false
+        } else {
+            DateTime::parse_http(value).is_some_and(|date| Some(date) == self.last_modified)
+        }
+    }
+
+    /// Builds the `304 Not Modified` response, which only repeats the validators.
+    pub fn not_modified_response(&self) -> Response {
+        self.apply(Response::new(StatusCode::NOT_MODIFIED))
+    }
+}
+
+/// Iterates over the entity tags of a comma-separated list such as `If-None-Match`.
+fn entity_tags(list: &str) -> impl Iterator<Item = &str> {
+    list.split(',')
+        .map(str::trim)
+        .filter(|etag| !etag.is_empty())
+}
+
+/// Weak comparison of two entity tags: they match if their opaque tags are equal, whether they
+/// are weak or not.
+pub fn weak_eq(a: &str, b: &str) -> bool {
+    a.strip_prefix("W/").unwrap_or(a) == b.strip_prefix("W/").unwrap_or(b)
+}
+
+/// Ranges requested by a `Range` header, for a representation of a known length.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Ranges {
+    /// No `Range` header, or one that is not valid or not in bytes: the whole representation
+    /// is sent with `200 OK`.
+    Full,
+    /// The satisfiable ranges, in the order requested, each clamped to the representation.
+    Partial(Vec<Range<u64>>),
+    /// None of the ranges is inside the representation: `416 Range Not Satisfiable`.
+    Unsatisfiable,
+}
+
+impl Ranges {
+    /// Parses the value of a `Range` header such as `bytes=0-499, -500` for a representation of
+    /// `len` bytes. Each range is either `first-last`, `first-` up to the end, or `-suffix`
+    /// for the last bytes.
+    pub fn parse(header: Option<&str>, len: u64) -> Ranges {
+        let Some(specs) = header.and_then(|value| value.trim().strip_prefix("bytes=")) else {
+            return Ranges::Full;
+        };
+
+        let number = |digits: &str| -> Option<u64> {
+            match digits.bytes().all(|b| b.is_ascii_digit()) {
+                true => digits.parse().ok(),
+                false => None,
+            }
+        };
+
+        let mut ranges = Vec::new();
+        for spec in specs.split(',').map(str::trim) {
+            let Some((first, last)) = spec.split_once('-') else {
+                return Ranges::Full;
+            };
+            let (first, last) = (first.trim(), last.trim());
+
+            let range = match (first.is_empty(), last.is_empty()) {
+                (true, true) => return Ranges::Full,
+                (true, false) => match number(last) {
+                    Some(suffix) => len.saturating_sub(suffix)..len,
+                    None => return Ranges::Full,
+                },
+                (false, _) => {
+                    let Some(first) = number(first) else {
+                        return Ranges::Full;
+                    };
+                    let last = match last.is_empty() {
+                        true => u64::MAX,
+                        false => match number(last) {
+                            Some(last) if last >= first => last,
+                            _ => return Ranges::Full,
+                        },
+                    };
+                    first..last.saturating_add(1).min(len)
+                }
+            };
+
+            if !range.is_empty() {
+                ranges.push(range);
+            }
+        }
+
+        match ranges.len() {
+            0 => Ranges::Unsatisfiable,
+            n if n > MAX_RANGES => Ranges::Full,
+            _ => Ranges::Partial(ranges),
+        }
+    }
+}
+
+/// Value of the `Content-Range` header of a part.
+pub fn content_range(range: &Range<u64>, len: u64) -> String {
+    format!("bytes {}-{}/{}", range.start, range.end - 1, len)
+}
+
+/// Builds the `416 Range Not Satisfiable` response, telling the client the actual length.
+pub fn range_not_satisfiable(len: u64) -> Response {
+    Response::error(StatusCode::RANGE_NOT_SATISFIABLE)
+        .with_header("Content-Range", format!("bytes */{}", len))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn validators() -> Validators {
+        Validators {
+            etag: String::from("\"5-a\""),
+            last_modified: Some(DateTime::from_unix(971_186_136)),
+        }
+    }
+
+    fn request(headers: &[(&str, &str)]) -> Request {
+        let mut request = Request::new(Method::Get, "/file.txt");
+        for (name, value) in headers {
+            request.headers.insert(*name, *value);
+        }
+        request
+    }
+
+    #[test]
+    fn if_none_match() {
+        let validators = validators();
+
+        for value in ["\"5-a\"", "W/\"5-a\"", "\"x\", \"5-a\"", "*"] {
+            let request = request(&[("If-None-Match", value)]);
+            assert!(validators.not_modified(&request), "{}", value);
+        }
+
+        // If-None-Match takes precedence over If-Modified-Since.
+        let both = request(&[
+            ("If-None-Match", "\"x\""),
+            ("If-Modified-Since", "Tue, 10 Oct 2000 13:55:36 GMT"),
+        ]);
+        assert!(!validators.not_modified(&both));
+
+        let mut post = request(&[("If-None-Match", "\"5-a\"")]);
+        post.method = Method::Post;
+        assert!(!validators.not_modified(&post));
+    }
+
+    #[test]
+    fn if_modified_since() {
+        let validators = validators();
+        let not_modified =
+            |value: &str| validators.not_modified(&request(&[("If-Modified-Since", value)]));
+
+        assert!(not_modified("Tue, 10 Oct 2000 13:55:36 GMT"));
+        assert!(not_modified("Wed, 11 Oct 2000 00:00:00 GMT"));
+        assert!(!not_modified("Tue, 10 Oct 2000 13:55:35 GMT"));
+        assert!(!not_modified("yesterday"));
+    }
+
+    #[test]
+    fn if_range() {
+        let validators = validators();
+        let applies = |value: &str| validators.range_applies(&request(&[("If-Range", value)]));
+
+        assert!(validators.range_applies(&request(&[])));
+        assert!(applies("\"5-a\""));
+        assert!(applies("Tue, 10 Oct 2000 13:55:36 GMT"));
+        assert!(!applies("W/\"5-a\""));
+        assert!(!applies("\"6-a\""));
+        assert!(!applies("Wed, 11 Oct 2000 00:00:00 GMT"));
+    }
+
+    #[test]
+    #[allow(clippy::single_range_in_vec_init)]
+    fn ranges() {
+        let parse = |value: &str| Ranges::parse(Some(value), 1000);
+
+        assert_eq!(Ranges::parse(None, 1000), Ranges::Full);
+        assert_eq!(parse("bytes=0-499"), Ranges::Partial(vec![0..500]));
+        assert_eq!(parse("bytes=500-"), Ranges::Partial(vec![500..1000]));
+        assert_eq!(parse("bytes=-200"), Ranges::Partial(vec![800..1000]));
+        assert_eq!(parse("bytes=900-1999"), Ranges::Partial(vec![900..1000]));
+        assert_eq!(parse("bytes=-2000"), Ranges::Partial(vec![0..1000]));
+        assert_eq!(
+            parse("bytes=0-0, -1, 2000-"),
+            Ranges::Partial(vec![0..1, 999..1000])
+        );
+
+        assert_eq!(parse("bytes=1000-"), Ranges::Unsatisfiable);
+        assert_eq!(parse("bytes=-0"), Ranges::Unsatisfiable);
+        assert_eq!(Ranges::parse(Some("bytes=0-"), 0), Ranges::Unsatisfiable);
+
+        for ignored in [
+            "items=0-1",
+            "bytes=5-1",
+            "bytes=a-b",
+            "bytes=-",
+            "bytes=1",
+            "bytes=+1-2",
+        ] {
+            assert_eq!(parse(ignored), Ranges::Full, "{}", ignored);
+        }
+        let many = vec!["0-1"; MAX_RANGES + 1].join(",");
+        assert_eq!(parse(&format!("bytes={}", many)), Ranges::Full);
+    }
+}
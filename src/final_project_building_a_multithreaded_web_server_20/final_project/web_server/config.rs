@@ -0,0 +1,862 @@
+use super::access_log::LogFormat;
+use super::compression;
+use super::cors;
+use super::proxy::{self, Upstream};
+use super::rate_limit::Rate;
+use super::request::{Limits, Method, MAX_UPLOAD_SIZE};
+use super::router;
+use super::vhost::{self, UnknownHosts};
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Prefix of the environment variables read by [`Config::apply_env`].
+pub const ENV_PREFIX: &str = "WEB_SERVER_";
+
+/// Errors found while loading the configuration.
+#[derive(Debug)]
+pub enum ConfigError {
+    /// The configuration file could not be read.
+    Io(PathBuf, io::Error),
+    /// A line of the configuration file is not a section, a `key = value` pair or a comment.
+    Syntax {
+        line: usize,
+        message: &'static str,
+    },
+    UnknownKey(String),
+    InvalidValue {
+        key: String,
+        value: String,
+    },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io(path, err) => write!(f, "could not read {}: {}", path.display(), err),
+            ConfigError::Syntax { line, message } => write!(f, "line {}: {}", line, message),
+            ConfigError::UnknownKey(key) => write!(f, "unknown configuration key '{}'", key),
+            ConfigError::InvalidValue { key, value } => {
+                write!(f, "invalid value '{}' for '{}'", value, key)
+            }
+        }
+    }
+}
+
+impl Error for ConfigError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ConfigError::Io(_, err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+/// How the server waits for the requests of its connections.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Backend {
+    /// Every connection is served by a thread of the pool, which blocks while it waits for the
+    /// requests. Simple, but every idle or slow client keeps a thread busy.
+    #[default]
+    ThreadPool,
+    /// A single thread waits for the requests of all the connections with epoll (or the
+    /// equivalent of the platform), and only the complete requests are handed to the pool.
+    EventLoop,
+}
+
+impl FromStr for Backend {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "thread_pool" | "threads" => Ok(Backend::ThreadPool),
+            "event_loop" | "epoll" => Ok(Backend::EventLoop),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Options of the web server. They can be set in code, usually through
+/// [`ServerBuilder`](super::server::ServerBuilder), loaded from a configuration file or from
+/// environment variables.
+///
+/// The configuration file uses a small subset of TOML (or INI), with one `key = value` pair per
+/// line. Strings can be quoted, `#` and `;` start a comment, and the keys of a `[section]` are
+/// prefixed with the name of the section, so `cert` inside `[tls]` is the key `tls_cert`. The
+/// section `[server]` has no prefix.
+///
+/// ```toml
+/// [server]
+/// address = "0.0.0.0"
+/// port = 8080
+/// workers = 8
+/// backend = "event_loop"       # "thread_pool" or "event_loop"
+/// document_root = "html"
+/// keep_alive_timeout = "5s"
+/// read_timeout = "30s"
+/// write_timeout = "30s"
+/// shutdown_timeout = 10        # seconds
+/// max_queued_connections = 64
+/// max_connections = 1024      # open at once with the event loop
+/// max_headers_size = "64K"
+/// max_body_size = "1M"
+/// max_upload_size = "1G"       # multipart uploads streamed to the handlers
+/// access_log = "json"          # "common", "json" or "off"
+/// compression = true           # needs the `gzip`, `deflate` or `brotli` features
+/// compression_min_size = "1K"
+/// unknown_hosts = "fallback"   # "fallback", "misdirected" (421) or "not_found" (404)
+///
+/// [hosts]                      # host name = document root
+/// example.com = "sites/example"
+/// "*.example.com" = "sites/subdomains"
+///
+/// [proxy]                      # path prefix = upstream URLs
+/// "/api" = "http://127.0.0.1:9000, http://127.0.0.1:9001"
+/// timeout = "30s"
+/// health_check = "/health"
+/// health_interval = "10s"
+///
+/// [rate_limit]                 # per client IP address
+/// rate = "10/s"                # "off" to disable it
+/// burst = 20
+/// allow = "10.0.0.1, 10.0.0.2"
+/// allow_localhost = true
+///
+/// [cors]                       # paths opted in to CORS
+/// paths = "/api/*"
+/// origins = "https://app.example.com"
+/// methods = "GET, POST"        # the methods of the routes by default
+/// headers = "Content-Type, Authorization"
+/// expose_headers = "X-Request-Id"
+/// credentials = true
+/// max_age = "10m"
+///
+/// [templates]
+/// dir = "templates"
+/// reload = true                # development mode
+///
+/// [tls]                        # needs the `tls` feature
+/// cert = "tls/localhost.pem"
+/// key = "tls/localhost-key.pem"
+/// ```
+///
+/// Every key can also be set with an environment variable named [`ENV_PREFIX`] followed by the
+/// key in uppercase, e.g. `WEB_SERVER_PORT=8080`. The keys of `[hosts]` are host names and the
+/// prefixes of `[proxy]` are paths, which are better set in the file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Config {
+    /// Host name or IP address the server binds to.
+    pub address: String,
+    /// Port the server binds to. `0` lets the system choose a free port.
+    pub port: u16,
+    /// Number of threads serving the connections.
+    pub workers: usize,
+    /// How the connections are waited on, see [`Backend`].
+    pub backend: Backend,
+    /// Directory with the files served by the server.
+    pub document_root: PathBuf,
+    /// Time a persistent connection can stay idle before it is closed. Cannot be zero, like the
+    /// other timeouts of the connections.
+    pub keep_alive_timeout: Duration,
+    /// Maximum time receiving a request, from its first byte to the end of its body, except for
+    /// the multipart uploads streamed to the handlers, which only wait this long for every read.
+    /// Requests that take longer are answered with `408 Request Timeout`.
+    pub read_timeout: Duration,
+    /// Maximum time waiting for the client to accept more bytes of a response.
+    pub write_timeout: Duration,
+    /// Time given to the in-flight requests to finish once the shutdown starts.
+    pub shutdown_timeout: Duration,
+    /// Maximum number of accepted connections waiting for a free thread. Connections accepted
+    /// when the queue is full are answered with `503 Service Unavailable`. Cannot be zero, as
+    /// every connection waits in the queue until a thread takes it.
+    pub max_queued_connections: usize,
+    /// Maximum number of connections the event loop keeps open at once, as each of them can
+    /// buffer a whole request. Further connections are answered with
+    /// `503 Service Unavailable`. Cannot be zero.
+    pub max_connections: usize,
+    /// Maximum length of the request line.
+    pub max_request_line: usize,
+    /// Maximum combined length of the header lines of a request.
+    pub max_headers_size: usize,
+    /// Maximum number of header fields of a request.
+    pub max_headers: usize,
+    /// Maximum size of the body of a request, except for the multipart uploads streamed to the
+    /// handlers, see [`Request::body_reader`](super::request::Request::body_reader).
+    pub max_body_size: usize,
+    /// Maximum size of the body of a multipart upload streamed to the handlers instead of the
+    /// [`max_body_size`](Config::max_body_size). Bigger uploads are answered with
+    /// `413 Payload Too Large` before the handler is called.
+    pub max_upload_size: usize,
+    /// Format of the access log written to stdout, or `None` to disable it.
+    pub access_log: Option<LogFormat>,
+    /// Compress the text responses for the clients that accept it, see
+    /// [`Compression`](super::compression::Compression).
+    pub compression: bool,
+    /// Size under which the responses are sent uncompressed.
+    pub compression_min_size: usize,
+    /// Sites served according to the `Host` header of the requests, as host names or `*.`
+    /// patterns (see [`VirtualHosts`](super::vhost::VirtualHosts)) with their document root.
+    /// The [`document_root`](Config::document_root) serves the other hosts, unless
+    /// [`unknown_hosts`](Config::unknown_hosts) rejects them: it must then be listed here to be
+    /// served under a name.
+    pub hosts: Vec<(String, PathBuf)>,
+    /// What happens to the requests for the hosts missing from [`hosts`](Config::hosts).
+    pub unknown_hosts: UnknownHosts,
+    /// Path prefixes forwarded by a [`Proxy`](super::proxy::Proxy), with the URLs of their
+    /// upstreams.
+    pub proxies: Vec<(String, Vec<String>)>,
+    /// Maximum time waiting for an upstream to send the next part of its response.
+    pub proxy_timeout: Duration,
+    /// Path requested to check the health of the upstreams, or `None` to disable the checks.
+    pub proxy_health_check: Option<String>,
+    /// Time between the health checks of the upstreams.
+    pub proxy_health_interval: Duration,
+    /// Requests allowed to every client IP address by a
+    /// [`RateLimiter`](super::rate_limit::RateLimiter), or `None` to disable it.
+    pub rate_limit_rate: Option<Rate>,
+    /// Requests a client can send at once, the requests of the rate by default.
+    pub rate_limit_burst: Option<u32>,
+    /// Addresses that are never limited.
+    pub rate_limit_allow: Vec<IpAddr>,
+    /// Never limit the loopback addresses.
+    pub rate_limit_allow_localhost: bool,
+    /// Route patterns of the paths that opt in to [`Cors`](super::cors::Cors), e.g. `/api/*`.
+    pub cors_paths: Vec<String>,
+    /// Origins allowed to call the [`cors_paths`](Config::cors_paths), or `*` for any origin.
+    pub cors_origins: Vec<String>,
+    /// Methods allowed to the preflight requests. If empty, they are the ones of the routes, or
+    /// any method for the paths handled by a mount.
+    pub cors_methods: Vec<Method>,
+    /// Request headers allowed to the preflight requests.
+    pub cors_headers: Vec<String>,
+    /// Response headers the pages of the origins can read.
+    pub cors_expose_headers: Vec<String>,
+    /// Let the pages send cookies and HTTP authentication.
+    pub cors_credentials: bool,
+    /// Time the browsers can cache the answer of a preflight request.
+    pub cors_max_age: Option<Duration>,
+    /// Directory with the templates of the pages rendered by the server.
+    pub templates_dir: PathBuf,
+    /// Parse the templates again when their files change, see
+    /// [`Templates::with_reload`](super::template::Templates::with_reload).
+    pub templates_reload: bool,
+    /// PEM file with the certificate chain used for HTTPS. The server only speaks HTTPS when
+    /// both the certificate and the key are set.
+    pub tls_cert: Option<PathBuf>,
+    /// PEM file with the private key of the certificate.
+    pub tls_key: Option<PathBuf>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        let limits = Limits::default();
+
+        Config {
+            address: String::from("127.0.0.1"),
+            port: 7878,
+            workers: 4,
+            backend: Backend::ThreadPool,
+            document_root: PathBuf::from("html"),
+            keep_alive_timeout: Duration::from_secs(5),
+            read_timeout: Duration::from_secs(30),
+            write_timeout: Duration::from_secs(30),
+            shutdown_timeout: Duration::from_secs(10),
+            max_queued_connections: 64,
+            max_connections: 1024,
+            max_request_line: limits.max_request_line,
+            max_headers_size: limits.max_headers_size,
+            max_headers: limits.max_headers,
+            max_body_size: limits.max_body_size,
+            max_upload_size: MAX_UPLOAD_SIZE,
+            access_log: Some(LogFormat::Common),
+            compression: true,
+            compression_min_size: compression::MIN_SIZE,
+            hosts: Vec::new(),
+            unknown_hosts: UnknownHosts::Fallback,
+            proxies: Vec::new(),
+            proxy_timeout: proxy::DEFAULT_TIMEOUT,
+            proxy_health_check: None,
+            proxy_health_interval: Duration::from_secs(10),
+            rate_limit_rate: None,
+            rate_limit_burst: None,
+            rate_limit_allow: Vec::new(),
+            rate_limit_allow_localhost: true,
+            cors_paths: Vec::new(),
+            cors_origins: Vec::new(),
+            cors_methods: Vec::new(),
+            cors_headers: Vec::new(),
+            cors_expose_headers: Vec::new(),
+            cors_credentials: false,
+            cors_max_age: None,
+            templates_dir: PathBuf::from("templates"),
+            templates_reload: false,
+            tls_cert: None,
+            tls_key: None,
+        }
+    }
+}
+
+impl Config {
+    /// Size limits used to read the requests.
+    pub fn limits(&self) -> Limits {
+        Limits {
+            max_request_line: self.max_request_line,
+            max_headers_size: self.max_headers_size,
+            max_headers: self.max_headers,
+            max_body_size: self.max_body_size,
+        }
+    }
+
+    /// Sets the option with the given key from its textual value.
+    ///
+    /// - Durations are numbers of seconds, optionally followed by the unit `ms`, `s` or `m`.
+    /// - Sizes are numbers of bytes, optionally followed by the unit `K`, `M` or `G` (powers of
+    ///   1024).
+    /// - The keys starting with `hosts_` add a site to [`hosts`](Config::hosts), replacing the
+    ///   one with the same host name.
+    /// - The keys starting with `proxy_/` add a prefix to [`proxies`](Config::proxies), with a
+    ///   list of upstreams separated by commas, replacing the one with the same prefix.
+    /// - Lists, such as the allowed addresses or origins, are separated by commas.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), ConfigError> {
+        let invalid = || ConfigError::InvalidValue {
+            key: key.to_string(),
+            value: value.to_string(),
+        };
+
+        if let Some(name) = key.strip_prefix("hosts_") {
+            let name = unquote(name).ok_or_else(invalid)?.to_ascii_lowercase();
+            if !vhost::is_valid_pattern(&name) || value.is_empty() {
+                return Err(invalid());
+            }
+            self.hosts.retain(|(host, _)| *host != name);
+            self.hosts.push((name, PathBuf::from(value)));
+            return Ok(());
+        }
+        if let Some(prefix) = key
+            .strip_prefix("proxy_")
+            .and_then(unquote)
+            .filter(|prefix| prefix.starts_with('/'))
+        {
+            let upstreams: Vec<String> = split_list(value).map(String::from).collect();
+            if upstreams.is_empty() || upstreams.iter().any(|url| url.parse::<Upstream>().is_err())
+            {
+                return Err(invalid());
+            }
+            self.proxies.retain(|(other, _)| other != prefix);
+            self.proxies.push((prefix.to_string(), upstreams));
+            return Ok(());
+        }
+
+        match key {
+            "address" if !value.is_empty() => self.address = value.to_string(),
+            "port" => self.port = value.parse().map_err(|_| invalid())?,
+            "workers" => match value.parse() {
+                Ok(workers) if workers > 0 => self.workers = workers,
+                _ => return Err(invalid()),
+            },
+            "backend" => self.backend = value.parse().map_err(|_| invalid())?,
+            "document_root" if !value.is_empty() => self.document_root = PathBuf::from(value),
+            "keep_alive_timeout" => {
+                self.keep_alive_timeout = parse_timeout(value).ok_or_else(invalid)?
+            }
+            "shutdown_timeout" => {
+                self.shutdown_timeout = parse_duration(value).ok_or_else(invalid)?
+            }
+            "read_timeout" => self.read_timeout = parse_timeout(value).ok_or_else(invalid)?,
+            "write_timeout" => self.write_timeout = parse_timeout(value).ok_or_else(invalid)?,
+            "max_queued_connections" => match value.parse() {
+                Ok(max) if max > 0 => self.max_queued_connections = max,
+                _ => return Err(invalid()),
+            },
+            "max_connections" => match value.parse() {
+                Ok(max) if max > 0 => self.max_connections = max,
+                _ => return Err(invalid()),
+            },
+            "max_request_line" => self.max_request_line = parse_size(value).ok_or_else(invalid)?,
+            "max_headers_size" => self.max_headers_size = parse_size(value).ok_or_else(invalid)?,
+            "max_headers" => self.max_headers = value.parse().map_err(|_| invalid())?,
+            "max_body_size" => self.max_body_size = parse_size(value).ok_or_else(invalid)?,
+            "max_upload_size" => self.max_upload_size = parse_size(value).ok_or_else(invalid)?,
+            "access_log" if value.eq_ignore_ascii_case("off") => self.access_log = None,
+            "access_log" => self.access_log = Some(value.parse().map_err(|_| invalid())?),
+            "compression" => self.compression = parse_bool(value).ok_or_else(invalid)?,
+            "compression_min_size" => {
+                self.compression_min_size = parse_size(value).ok_or_else(invalid)?
+            }
+            "unknown_hosts" => self.unknown_hosts = value.parse().map_err(|_| invalid())?,
+            "proxy_timeout" => self.proxy_timeout = parse_duration(value).ok_or_else(invalid)?,
+            "proxy_health_check" if value.is_empty() => self.proxy_health_check = None,
+            "proxy_health_check" if value.starts_with('/') => {
+                self.proxy_health_check = Some(value.to_string())
+            }
+            "proxy_health_interval" => match parse_duration(value) {
+                Some(interval) if !interval.is_zero() => self.proxy_health_interval = interval,
+                _ => return Err(invalid()),
+            },
+            "rate_limit_rate" if value.eq_ignore_ascii_case("off") => self.rate_limit_rate = None,
+            "rate_limit_rate" => self.rate_limit_rate = Some(value.parse().map_err(|_| invalid())?),
+            "rate_limit_burst" => match value.parse() {
+                Ok(burst) if burst > 0 => self.rate_limit_burst = Some(burst),
+                _ => return Err(invalid()),
+            },
+            "rate_limit_allow" => {
+                self.rate_limit_allow = split_list(value)
+                    .map(|address| address.parse().map_err(|_| invalid()))
+                    .collect::<Result<_, _>>()?
+            }
+            "rate_limit_allow_localhost" => {
+                self.rate_limit_allow_localhost = parse_bool(value).ok_or_else(invalid)?
+            }
+            "cors_paths" if split_list(value).all(router::is_valid_pattern) => {
+                self.cors_paths = split_list(value).map(String::from).collect()
+            }
+            "cors_origins" if split_list(value).all(cors::is_valid_origin) => {
+                // Any origin with credentials would let every site read the API as the user.
+                if self.cors_credentials && split_list(value).any(|origin| origin == "*") {
+                    return Err(invalid());
+                }
+                self.cors_origins = split_list(value).map(String::from).collect()
+            }
+            "cors_methods" => {
+                self.cors_methods = split_list(value)
+                    .map(|method| method.to_ascii_uppercase().parse().map_err(|_| invalid()))
+                    .collect::<Result<_, _>>()?
+            }
+            "cors_headers" | "cors_expose_headers" if split_list(value).all(is_header_name) => {
+                let headers = split_list(value).map(String::from).collect();
+                match key {
+                    "cors_headers" => self.cors_headers = headers,
+                    _ => self.cors_expose_headers = headers,
+                }
+            }
+            "cors_credentials" => match parse_bool(value) {
+                Some(true) if self.cors_origins.iter().any(|origin| origin == "*") => {
+                    return Err(invalid())
+                }
+                Some(credentials) => self.cors_credentials = credentials,
+                None => return Err(invalid()),
+            },
+            "cors_max_age" => self.cors_max_age = Some(parse_duration(value).ok_or_else(invalid)?),
+            "templates_dir" if !value.is_empty() => self.templates_dir = PathBuf::from(value),
+            "templates_reload" => self.templates_reload = parse_bool(value).ok_or_else(invalid)?,
+            "tls_cert" if !value.is_empty() => self.tls_cert = Some(PathBuf::from(value)),
+            "tls_key" if !value.is_empty() => self.tls_key = Some(PathBuf::from(value)),
+            "address"
+            | "document_root"
+            | "templates_dir"
+            | "tls_cert"
+            | "tls_key"
+            | "proxy_health_check"
+            | "cors_paths"
+            | "cors_origins"
+            | "cors_headers"
+            | "cors_expose_headers" => return Err(invalid()),
+            _ => return Err(ConfigError::UnknownKey(key.to_string())),
+        }
+
+        Ok(())
+    }
+
+    /// Loads the options set in the configuration file, keeping the current value of the rest.
+    pub fn apply_file(&mut self, path: impl AsRef<Path>) -> Result<(), ConfigError> {
+        let path = path.as_ref();
+        let contents =
+            fs::read_to_string(path).map_err(|err| ConfigError::Io(path.to_path_buf(), err))?;
+
+        self.apply_str(&contents)
+    }
+
+    /// Loads the options from the contents of a configuration file.
+    pub fn apply_str(&mut self, contents: &str) -> Result<(), ConfigError> {
+        let mut section = String::new();
+
+        for (number, line) in contents.lines().enumerate() {
+            let line = strip_comment(line).trim();
+            let syntax = |message| ConfigError::Syntax {
+                line: number + 1,
+                message,
+            };
+
+            if line.is_empty() {
+                continue;
+            }
+
+            if let Some(name) = line.strip_prefix('[') {
+                let name = name
+                    .strip_suffix(']')
+                    .ok_or_else(|| syntax("unclosed section"))?;
+                section = match name.trim() {
+                    "server" => String::new(),
+                    name => format!("{}_", name),
+                };
+                continue;
+            }
+
+            let (key, value) = line
+                .split_once('=')
+                .ok_or_else(|| syntax("expected 'key = value'"))?;
+            let value = unquote(value.trim()).ok_or_else(|| syntax("unclosed string"))?;
+
+            self.set(&format!("{}{}", section, key.trim()), value)?;
+        }
+
+        Ok(())
+    }
+
+    /// Loads the options set in the environment variables starting with [`ENV_PREFIX`].
+    pub fn apply_env(&mut self) -> Result<(), ConfigError> {
+        self.apply_vars(std::env::vars())
+    }
+
+    /// Loads the options from a list of `(name, value)` variables, ignoring the ones without the
+    /// [`ENV_PREFIX`]. Used by [`Config::apply_env`].
+    pub fn apply_vars<I>(&mut self, vars: I) -> Result<(), ConfigError>
+    where
+        I: IntoIterator<Item = (String, String)>,
+    {
+        for (name, value) in vars {
+            if let Some(key) = name.strip_prefix(ENV_PREFIX) {
+                // The path of the configuration file is read by the server, not the config.
+                if key != "CONFIG" {
+                    self.set(&key.to_ascii_lowercase(), &value)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Removes the comment of the line, if any. Comment characters inside quotes are kept.
+fn strip_comment(line: &str) -> &str {
+    let mut quoted = false;
+
+    for (i, c) in line.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            '#' | ';' if !quoted => return &line[..i],
+            _ => {}
+        }
+    }
+
+    line
+}
+
+fn unquote(value: &str) -> Option<&str> {
+    match value.strip_prefix('"') {
+        Some(rest) => rest.strip_suffix('"'),
+        None => Some(value),
+    }
+}
+
+/// Splits a list separated by commas, without the empty items.
+fn split_list(value: &str) -> impl Iterator<Item = &str> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+}
+
+/// Header names of the CORS lists, where `*` means any header.
+fn is_header_name(name: &str) -> bool {
+    name == "*"
+        || name
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"-_".contains(&b))
+}
+
+/// Parses values like `30`, `30s`, `500ms` or `2m`.
+pub fn parse_duration(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    let (number, unit) = split_unit(value);
+    let number: u64 = number.parse().ok()?;
+
+    match unit.to_ascii_lowercase().as_str() {
+        "" | "s" => Some(Duration::from_secs(number)),
+        "ms" => Some(Duration::from_millis(number)),
+        "m" => Some(Duration::from_secs(number.checked_mul(60)?)),
+        _ => None,
+    }
+}
+
+/// Parses the duration of a timeout of the sockets, which cannot be zero.
+fn parse_timeout(value: &str) -> Option<Duration> {
+    parse_duration(value).filter(|timeout| !timeout.is_zero())
+}
+
+/// Parses `true`/`false`, `on`/`off` and `yes`/`no`.
+pub fn parse_bool(value: &str) -> Option<bool> {
+    match value.trim().to_ascii_lowercase().as_str() {
+        "true" | "on" | "yes" => Some(true),
+        "false" | "off" | "no" => Some(false),
+        _ => None,
+    }
+}
+
+/// Parses values like `1024`, `64K`, `10M` or `1GB`.
+pub fn parse_size(value: &str) -> Option<usize> {
+    let value = value.trim();
+    let (number, unit) = split_unit(value);
+    let number: usize = number.parse().ok()?;
+
+    let multiplier = match unit.to_ascii_uppercase().trim_end_matches('B') {
+        "" => 1,
+        "K" => 1024,
+        "M" => 1024 * 1024,
+        "G" => 1024 * 1024 * 1024,
+        _ => return None,
+    };
+    number.checked_mul(multiplier)
+}
+
+fn split_unit(value: &str) -> (&str, &str) {
+    let end = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    (&value[..end], value[end..].trim())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_file() {
+        let mut config = Config::default();
+        config
+            .apply_str(
+                "# Server options\n\
+                 [server]\n\
+                 address = \"0.0.0.0\"   # everywhere\n\
+                 port = 8080\n\
+                 \n\
+                 workers=8\n\
+                 backend = event_loop\n\
+                 document_root = \"public # files\"\n\
+                 keep_alive_timeout = \"500ms\"\n\
+                 shutdown_timeout = 30 ; INI comment\n\
+                 read_timeout = 10\n\
+                 max_queued_connections = 8\n\
+                 max_connections = 256\n\
+                 max_headers_size = 16K\n\
+                 max_body_size = 1M\n\
+                 max_upload_size = 100M\n\
+                 access_log = off\n\
+                 compression = no\n\
+                 compression_min_size = 2K\n\
+                 unknown_hosts = 421\n\
+                 [hosts]\n\
+                 Example.com = sites/example\n\
+                 \"*.example.com\" = sites/subdomains\n\
+                 example.com = \"sites/example.com\"\n\
+                 [proxy]\n\
+                 \"/api\" = \"http://127.0.0.1:9000, 127.0.0.1:9001/v1\"\n\
+                 timeout = 5s\n\
+                 health_check = /health\n\
+                 [rate_limit]\n\
+                 rate = 5/s\n\
+                 burst = 10\n\
+                 allow = \"10.0.0.1, ::1\"\n\
+                 allow_localhost = no\n\
+                 [cors]\n\
+                 paths = \"/api/*, /public\"\n\
+                 origins = \"https://app.example.com, http://localhost:3000\"\n\
+                 methods = \"get, DELETE\"\n\
+                 headers = Content-Type\n\
+                 credentials = yes\n\
+                 max_age = 10m\n\
+                 [templates]\n\
+                 dir = pages\n\
+                 reload = on\n\
+                 [tls]\n\
+                 cert = certs/server.pem\n\
+                 key = certs/server-key.pem\n",
+            )
+            .unwrap();
+
+        assert_eq!(
+            config,
+            Config {
+                address: String::from("0.0.0.0"),
+                port: 8080,
+                workers: 8,
+                backend: Backend::EventLoop,
+                document_root: PathBuf::from("public # files"),
+                keep_alive_timeout: Duration::from_millis(500),
+                read_timeout: Duration::from_secs(10),
+                shutdown_timeout: Duration::from_secs(30),
+                max_queued_connections: 8,
+                max_connections: 256,
+                max_headers_size: 16 * 1024,
+                max_body_size: 1024 * 1024,
+                max_upload_size: 100 * 1024 * 1024,
+                access_log: None,
+                compression: false,
+                compression_min_size: 2048,
+                hosts: vec![
+                    (
+                        String::from("*.example.com"),
+                        PathBuf::from("sites/subdomains")
+                    ),
+                    (
+                        String::from("example.com"),
+                        PathBuf::from("sites/example.com")
+                    ),
+                ],
+                unknown_hosts: UnknownHosts::Misdirected,
+                proxies: vec![(
+                    String::from("/api"),
+                    vec![
+                        String::from("http://127.0.0.1:9000"),
+                        String::from("127.0.0.1:9001/v1")
+                    ]
+                )],
+                proxy_timeout: Duration::from_secs(5),
+                proxy_health_check: Some(String::from("/health")),
+                rate_limit_rate: Some(Rate::new(5, Duration::from_secs(1))),
+                rate_limit_burst: Some(10),
+                rate_limit_allow: vec!["10.0.0.1".parse().unwrap(), "::1".parse().unwrap()],
+                rate_limit_allow_localhost: false,
+                cors_paths: vec![String::from("/api/*"), String::from("/public")],
+                cors_origins: vec![
+                    String::from("https://app.example.com"),
+                    String::from("http://localhost:3000")
+                ],
+                cors_methods: vec![Method::Get, Method::Delete],
+                cors_headers: vec![String::from("Content-Type")],
+                cors_credentials: true,
+                cors_max_age: Some(Duration::from_secs(600)),
+                templates_dir: PathBuf::from("pages"),
+                templates_reload: true,
+                tls_cert: Some(PathBuf::from("certs/server.pem")),
+                tls_key: Some(PathBuf::from("certs/server-key.pem")),
+                ..Config::default()
+            }
+        );
+    }
+
+    #[test]
+    fn file_errors() {
+        let mut config = Config::default();
+
+        assert!(matches!(
+            config.apply_str("port = 1\nport 2"),
+            Err(ConfigError::Syntax { line: 2, .. })
+        ));
+        assert!(matches!(
+            config.apply_str("[server\n"),
+            Err(ConfigError::Syntax { line: 1, .. })
+        ));
+        assert!(matches!(
+            config.apply_str("port = 99999"),
+            Err(ConfigError::InvalidValue { .. })
+        ));
+        assert!(matches!(
+            config.apply_str("workers = 0"),
+            Err(ConfigError::InvalidValue { .. })
+        ));
+        assert!(matches!(
+            config.apply_str("max_queued_connections = 0"),
+            Err(ConfigError::InvalidValue { .. })
+        ));
+        assert!(matches!(
+            config.apply_str("max_connections = 0"),
+            Err(ConfigError::InvalidValue { .. })
+        ));
+        for timeout in ["keep_alive_timeout", "read_timeout", "write_timeout"] {
+            assert!(matches!(
+                config.apply_str(&format!("{} = 0", timeout)),
+                Err(ConfigError::InvalidValue { .. })
+            ));
+        }
+        assert!(matches!(
+            config.apply_str("backend = fibers"),
+            Err(ConfigError::InvalidValue { .. })
+        ));
+        assert!(matches!(
+            config.apply_str("[hosts]\nexample.com:80 = sites"),
+            Err(ConfigError::InvalidValue { .. })
+        ));
+        assert!(matches!(
+            config.apply_str("[proxy]\n/api = https://example.com"),
+            Err(ConfigError::InvalidValue { .. })
+        ));
+        assert!(matches!(
+            config.apply_str("[proxy]\nhealth_check = health"),
+            Err(ConfigError::InvalidValue { .. })
+        ));
+        assert!(matches!(
+            config.apply_str("[rate_limit]\nrate = 10"),
+            Err(ConfigError::InvalidValue { .. })
+        ));
+        assert!(matches!(
+            config.apply_str("[rate_limit]\nallow = localhost"),
+            Err(ConfigError::InvalidValue { .. })
+        ));
+        assert!(matches!(
+            config.apply_str("[cors]\norigins = app.example.com"),
+            Err(ConfigError::InvalidValue { .. })
+        ));
+        assert!(matches!(
+            config.apply_str("[cors]\nmethods = GET, FETCH"),
+            Err(ConfigError::InvalidValue { .. })
+        ));
+        assert!(matches!(
+            Config::default().apply_str("[cors]\norigins = *\ncredentials = true"),
+            Err(ConfigError::InvalidValue { key, .. }) if key == "cors_credentials"
+        ));
+        assert!(matches!(
+            Config::default().apply_str("[cors]\ncredentials = on\norigins = \"https://a.com, *\""),
+            Err(ConfigError::InvalidValue { key, .. }) if key == "cors_origins"
+        ));
+        assert!(matches!(
+            config.apply_str("unknown_hosts = 500"),
+            Err(ConfigError::InvalidValue { .. })
+        ));
+        assert!(matches!(
+            config.apply_str("[other]\nport = 1"),
+            Err(ConfigError::UnknownKey(key)) if key == "other_port"
+        ));
+        assert!(matches!(
+            config.apply_file("does/not/exist.toml"),
+            Err(ConfigError::Io(..))
+        ));
+    }
+
+    #[test]
+    fn environment_variables() {
+        let mut config = Config::default();
+        let vars = [
+            ("WEB_SERVER_PORT", "0"),
+            ("WEB_SERVER_WORKERS", "2"),
+            ("WEB_SERVER_ACCESS_LOG", "json"),
+            ("WEB_SERVER_CONFIG", "server.toml"),
+            ("PORT", "1234"),
+        ];
+
+        config
+            .apply_vars(vars.iter().map(|(k, v)| (k.to_string(), v.to_string())))
+            .unwrap();
+        assert_eq!(config.port, 0);
+        assert_eq!(config.workers, 2);
+        assert_eq!(config.access_log, Some(LogFormat::Json));
+    }
+
+    #[test]
+    fn units() {
+        assert_eq!(parse_duration("10"), Some(Duration::from_secs(10)));
+        assert_eq!(parse_duration("2m"), Some(Duration::from_secs(120)));
+        assert_eq!(parse_duration("250 ms"), Some(Duration::from_millis(250)));
+        assert_eq!(parse_duration("1h"), None);
+        assert_eq!(parse_size("64k"), Some(64 * 1024));
+        assert_eq!(parse_size("2MB"), Some(2 * 1024 * 1024));
+        assert_eq!(parse_size("-1"), None);
+        assert_eq!(parse_bool("On"), Some(true));
+        assert_eq!(parse_bool("1"), None);
+    }
+}
@@ -0,0 +1,648 @@
+use super::config::Config;
+use super::form::MULTIPART;
+use super::request::{self, Limits, Method, ParseError, Request, Version};
+use super::response::{Response, StatusCode};
+use super::router::Handler;
+#[cfg(feature = "tls")]
+use super::tls::TlsStream;
+use super::upgrade::Upgrade;
+use std::collections::HashMap;
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::net::{Shutdown, TcpStream};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+struct Tracked {
+    stream: TcpStream,
+    idle: bool,
+    closed: bool,
+}
+
+/// Registry of the open connections of a server, used to close them when the server shuts down.
+///
+/// A connection is idle while it waits for the next request of a persistent connection. When
+/// the shutdown starts the idle connections are closed right away, while the busy ones are closed
+/// after sending the response to their current request.
+#[derive(Default)]
+pub struct Connections {
+    shutting_down: AtomicBool,
+    next_id: AtomicUsize,
+    open: Mutex<HashMap<usize, Tracked>>,
+}
+
+impl Connections {
+    pub fn new() -> Connections {
+        Connections::default()
+    }
+
+    /// Number of open connections.
+    pub fn len(&self) -> usize {
+        self.open.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn is_shutting_down(&self) -> bool {
+        self.shutting_down.load(Ordering::SeqCst)
+    }
+
+    /// Starts the shutdown: closes the idle connections and makes the busy ones close after
+    /// their current response.
+    pub fn shutdown(&self) {
+        let mut open = self.open.lock().unwrap();
+        self.shutting_down.store(true, Ordering::SeqCst);
+
+        for tracked in open.values_mut().filter(|tracked| tracked.idle) {
+            // Wakes up the thread blocked reading the next request.
+            let _ = tracked.stream.shutdown(Shutdown::Read);
+            tracked.closed = true;
+        }
+    }
+
+    /// Closes all the connections, even the ones in the middle of a request.
+    pub fn close_all(&self) {
+        let mut open = self.open.lock().unwrap();
+        self.shutting_down.store(true, Ordering::SeqCst);
+
+        for tracked in open.values_mut() {
+            let _ = tracked.stream.shutdown(Shutdown::Both);
+            tracked.closed = true;
+        }
+    }
+
+    pub(super) fn register(&self, stream: &TcpStream) -> io::Result<usize> {
+        let id = self.next_id.fetch_add(1, Ordering::SeqCst);
+        let tracked = Tracked {
+            stream: stream.try_clone()?,
+            idle: false,
+            closed: false,
+        };
+
+        self.open.lock().unwrap().insert(id, tracked);
+        Ok(id)
+    }
+
+    /// Marks the connection as idle or busy. Returns `false` if the connection has been closed by
+    /// the shutdown or the shutdown has already started, meaning that the connection must not
+    /// wait for more requests.
+    pub(super) fn set_idle(&self, id: usize, idle: bool) -> bool {
+        let mut open = self.open.lock().unwrap();
+        let tracked = open.get_mut(&id).expect("Connection is not registered");
+
+        tracked.idle = idle;
+        !tracked.closed && !self.is_shutting_down()
+    }
+
+    pub(super) fn unregister(&self, id: usize) {
+        self.open.lock().unwrap().remove(&id);
+    }
+}
+
+/// Serves all the requests sent through the connection until the client closes it, asks to close
+/// it with `Connection: close`, stays idle for more than the
+/// [`keep_alive_timeout`](Config::keep_alive_timeout) or the server starts shutting down.
+///
+/// Pipelined requests (sent before the previous response arrives) are read one after another
+/// from the same buffer, so the responses are always sent in the order of the requests. The
+/// responses are only flushed when there are no more buffered requests, so a pipeline is
+/// answered with as few writes as possible.
+///
+/// After a response with an [`Upgrade`], the connection is handed over to the upgrade and the
+/// thread is free to serve another connection.
+///
+/// The bodies of the multipart uploads are streamed to the handlers, see
+/// [`Request::body_reader`].
+pub fn handle_connection(
+    stream: TcpStream,
+    handler: &dyn Handler,
+    connections: &Arc<Connections>,
+    config: &Config,
+) {
+    let Some(id) = open(&stream, connections, config) else {
+        return;
+    };
+
+    match serve(&stream, &stream, true, handler, connections, id, config) {
+        Some((upgrade, buffered)) => match stream.try_clone() {
+            Ok(io) => upgrade.spawn(io, &stream, buffered, Arc::clone(connections), id),
+            Err(err) => {
+                eprintln!("Error upgrading the connection: {}", err);
+                connections.unregister(id);
+            }
+        },
+        None => connections.unregister(id),
+    }
+}
+
+/// Same as [`handle_connection`], but speaking HTTPS. The bodies of the multipart uploads are
+/// read before calling the handlers, like the other bodies.
+#[cfg(feature = "tls")]
+pub fn handle_tls_connection(
+    stream: TcpStream,
+    tls: std::sync::Arc<rustls::ServerConfig>,
+    handler: &dyn Handler,
+    connections: &Arc<Connections>,
+    config: &Config,
+) {
+    let Some(id) = open(&stream, connections, config) else {
+        return;
+    };
+
+    match TlsStream::new(&stream, tls) {
+        Ok(tls_stream) => match serve(
+            &stream,
+            &tls_stream,
+            false,
+            handler,
+            connections,
+            id,
+            config,
+        ) {
+            Some((upgrade, buffered)) => match stream.try_clone() {
+                Ok(socket) => {
+                    let io = tls_stream.into_owned(socket);
+                    upgrade.spawn(io, &stream, buffered, Arc::clone(connections), id);
+                    return;
+                }
+                Err(err) => eprintln!("Error upgrading the connection: {}", err),
+            },
+            None => {
+                let _ = tls_stream.close();
+            }
+        },
+        Err(err) => eprintln!("Error starting the TLS session: {}", err),
+    }
+    connections.unregister(id);
+}
+
+/// Sets the timeouts of the connection and registers it. Returns the ID of the connection.
+fn open(stream: &TcpStream, connections: &Connections, config: &Config) -> Option<usize> {
+    let timeouts = stream
+        .set_read_timeout(Some(config.read_timeout))
+        .and_then(|_| stream.set_write_timeout(Some(config.write_timeout)));
+    if let Err(err) = timeouts {
+        eprintln!("Error setting the stream timeouts: {}", err);
+        return None;
+    }
+
+    match connections.register(stream) {
+        Ok(id) => Some(id),
+        Err(err) => {
+            eprintln!("Error registering the connection: {}", err);
+            None
+        }
+    }
+}
+
+/// Serves the requests of the connection, read and written through `io`: the socket itself or a
+/// TLS session over it. Returns the upgrade of the last response, if any, with the bytes
+/// already received after its request.
+///
+/// With `stream_uploads`, which requires `io` to be the socket, the bodies of the multipart
+/// uploads are left on the connection for the handler.
+fn serve<S>(
+    stream: &TcpStream,
+    io: S,
+    stream_uploads: bool,
+    handler: &dyn Handler,
+    connections: &Connections,
+    id: usize,
+    config: &Config,
+) -> Option<(Upgrade, Vec<u8>)>
+where
+    S: Read + Write + Copy,
+{
+    let limits = config.limits();
+    let peer_addr = stream.peer_addr().ok();
+    let mut reader = BufReader::new(Deadline {
+        stream,
+        io,
+        timeout: config.read_timeout,
+        deadline: None,
+    });
+    let mut writer = BufWriter::new(io);
+    let mut first = true;
+
+    loop {
+        // Waits for the next request of a persistent connection as idle, so that the shutdown
+        // can close it. The first request is not waited as idle, as the client has just connected.
+        if !first && reader.buffer().is_empty() {
+            if !connections.set_idle(id, true) {
+                break;
+            }
+            let received = wait_idle(stream, &mut reader, config);
+            if !connections.set_idle(id, false) || !matches!(received, Ok(true)) {
+                break;
+            }
+        }
+        first = false;
+
+        reader.get_mut().start();
+        let request = match stream_uploads {
+            true => read_request(&mut reader, stream, &limits, config.max_upload_size),
+            false => Request::read_with_limits(&mut reader, &limits),
+        };
+        if let Err(err) = reader.get_mut().end() {
+            eprintln!("Error setting the stream timeouts: {}", err);
+            break;
+        }
+        let (mut response, request, keep_alive) = match request {
+            Ok(mut request) => {
+                request.peer_addr = peer_addr;
+                let response = handler.handle(&mut request);
+                // The rest of a body the handler did not read is still on the connection.
+                let keep_alive = keep_alive(&request)
+                    && request.unread_body().unwrap_or(0) == 0
+                    && !closes(&response, request.version)
+                    && !connections.is_shutting_down();
+                (response, (request.method, request.version), keep_alive)
+            }
+            Err(ParseError::ConnectionClosed) => break,
+            // The client started a request but stopped sending it.
+            Err(err) if is_timeout(&err) => (
+                Response::error(StatusCode::REQUEST_TIMEOUT),
+                (Method::Get, Version::Http11),
+                false,
+            ),
+            Err(err) => match err.status() {
+                Some(status) => (
+                    Response::error(status),
+                    (Method::Get, Version::Http11),
+                    false,
+                ),
+                None => {
+                    eprintln!("Error reading from stream: {}", err);
+                    break;
+                }
+            },
+        };
+
+        let upgrade = take_upgrade(&mut response);
+        let keep_alive = keep_alive && upgrade.is_none();
+
+        let result = write_response(&mut writer, response, request, keep_alive).and_then(|_| {
+            if !keep_alive || reader.buffer().is_empty() {
+                writer.flush()
+            } else {
+                Ok(())
+            }
+        });
+        if let Err(err) = result {
+            eprintln!("Error writing to stream: {}", err);
+            return None;
+        }
+
+        if let Some(upgrade) = upgrade {
+            return Some((upgrade, reader.buffer().to_vec()));
+        }
+        if !keep_alive {
+            break;
+        }
+    }
+
+    if let Err(err) = writer.flush() {
+        eprintln!("Error flushing stream: {}", err);
+    }
+    None
+}
+
+/// Reads a request, but leaves the body of a `multipart/form-data` request sent with a
+/// `Content-Length` on the connection, to be read by the handler from a clone of the socket.
+/// Such a body is limited by `max_upload_size` instead of the `max_body_size` of the limits.
+fn read_request<R: Read>(
+    reader: &mut BufReader<R>,
+    stream: &TcpStream,
+    limits: &Limits,
+    max_upload_size: usize,
+) -> Result<Request, ParseError> {
+    let mut request = Request::read_head(reader, limits)?;
+    let multipart = request
+        .header("Content-Type")
+        .and_then(|value| value.split(';').next())
+        .is_some_and(|media_type| media_type.trim().eq_ignore_ascii_case(MULTIPART));
+    let length = match request::content_length(&request.headers)? {
+        Some(length) if multipart && !request.headers.contains("Transfer-Encoding") => length,
+        _ => {
+            request.body = request::read_body(reader, &request.headers, limits)?;
+            return Ok(request);
+        }
+    };
+    if length > max_upload_size {
+        return Err(ParseError::BodyTooLarge);
+    }
+
+    // The start of the body may already be buffered, but not more of it is read from the
+    // socket, so the requests pipelined after it stay there.
+    let buffered = reader.buffer().len().min(length);
+    let start = reader.buffer()[..buffered].to_vec();
+    reader.consume(buffered);
+    let body = io::Cursor::new(start).chain(stream.try_clone()?);
+    request.defer_body(Box::new(body), length as u64);
+    Ok(request)
+}
+
+/// Reader of the requests of a connection, which gives every request the read timeout in total.
+/// The timeout of the socket only limits each read, so a client sending a byte now and then
+/// would keep the thread busy forever.
+struct Deadline<'a, S> {
+    stream: &'a TcpStream,
+    io: S,
+    timeout: Duration,
+    deadline: Option<Instant>,
+}
+
+impl<S> Deadline<'_, S> {
+    fn start(&mut self) {
+        self.deadline = Some(Instant::now() + self.timeout);
+    }
+
+    /// Gives the socket its timeout back, for the bodies the handlers read from it.
+    fn end(&mut self) -> io::Result<()> {
+        self.deadline = None;
+        self.stream.set_read_timeout(Some(self.timeout))
+    }
+}
+
+impl<S: Read> Read for Deadline<'_, S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if let Some(deadline) = self.deadline {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return Err(io::ErrorKind::TimedOut.into());
+            }
+            self.stream.set_read_timeout(Some(remaining))?;
+        }
+        self.io.read(buf)
+    }
+}
+
+/// Waits up to the keep-alive timeout for the next request of a persistent connection. Returns
+/// `false` if the client closed the connection or did not send anything.
+fn wait_idle<R: Read>(
+    stream: &TcpStream,
+    reader: &mut BufReader<R>,
+    config: &Config,
+) -> io::Result<bool> {
+    stream.set_read_timeout(Some(config.keep_alive_timeout))?;
+    let received = match reader.fill_buf() {
+        Ok(buffer) => !buffer.is_empty(),
+        Err(err)
+            if matches!(
+                err.kind(),
+                io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+            ) =>
+        {
+            false
+        }
+        Err(err) => return Err(err),
+    };
+    stream.set_read_timeout(Some(config.read_timeout))?;
+    Ok(received)
+}
+
+/// Checks if the client wants to keep the connection open after the response. HTTP/1.1
+/// connections are persistent unless the client sends `Connection: close`, while HTTP/1.0 ones
+/// are only persistent with `Connection: keep-alive`.
+pub fn keep_alive(request: &Request) -> bool {
+    match request.version {
+        Version::Http11 => !request.headers.contains_token("Connection", "close"),
+        Version::Http10 => request.headers.contains_token("Connection", "keep-alive"),
+    }
+}
+
+/// Takes the upgrade of the response, which only happens with `101 Switching Protocols`. The
+/// connection is not persistent then, as it no longer speaks HTTP after the response.
+pub(super) fn take_upgrade(response: &mut Response) -> Option<Upgrade> {
+    response
+        .upgrade
+        .take()
+        .filter(|_| response.status == StatusCode::SWITCHING_PROTOCOLS)
+}
+
+/// Handlers can force the connection to be closed by setting `Connection: close`. Streams sent to
+/// HTTP/1.0 clients are not chunked, so their end is signalled by closing the connection.
+pub(super) fn closes(response: &Response, version: Version) -> bool {
+    response.headers.contains_token("Connection", "close")
+        || (version == Version::Http10 && response.body.len().is_none())
+}
+
+pub(super) fn write_response<W: Write>(
+    writer: &mut W,
+    mut response: Response,
+    (method, version): (Method, Version),
+    keep_alive: bool,
+) -> io::Result<()> {
+    if response.status == StatusCode::SWITCHING_PROTOCOLS {
+        // The handler sets `Connection: Upgrade`.
+    } else if keep_alive {
+        response.headers.insert("Connection", "keep-alive");
+    } else {
+        response.headers.insert("Connection", "close");
+    }
+
+    if method == Method::Head {
+        response.write_head_to(writer)
+    } else if version == Version::Http10 {
+        response.write_unchunked_to(writer)
+    } else {
+        response.write_to(writer)
+    }
+}
+
+fn is_timeout(err: &ParseError) -> bool {
+    match err {
+        ParseError::Io(err) => matches!(
+            err.kind(),
+            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+        ),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::body::Body;
+    use super::super::router::Router;
+    use super::*;
+    use std::io::Read;
+    use std::net::TcpListener;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    /// Serves a single connection with a router that echoes the path of the request.
+    fn serve_one() -> (TcpStream, thread::JoinHandle<()>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+
+        let server = thread::spawn(move || {
+            let router = Router::new()
+                .get("/stream", |_: &mut Request| {
+                    // Every part of the chain is returned by a different read, so in its own chunk.
+                    let reader = (&b"first "[..]).chain(&b"second"[..]);
+                    Response::new(StatusCode::OK).with_body(Body::stream(reader))
+                })
+                .get("/*", |request: &mut Request| {
+                    Response::new(StatusCode::OK).with_body(request.path.clone())
+                });
+            let (stream, _) = listener.accept().unwrap();
+            let config = Config {
+                keep_alive_timeout: Duration::from_millis(500),
+                read_timeout: Duration::from_millis(300),
+                ..Config::default()
+            };
+            handle_connection(stream, &router, &Arc::default(), &config);
+        });
+
+        (TcpStream::connect(address).unwrap(), server)
+    }
+
+    fn read_all(mut stream: TcpStream) -> String {
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn pipelined_requests() {
+        let (mut stream, server) = serve_one();
+
+        stream
+            .write_all(
+                b"GET /first HTTP/1.1\r\nHost: a\r\n\r\n\
+                  GET /second HTTP/1.1\r\nHost: a\r\n\r\n\
+                  HEAD /third HTTP/1.1\r\nHost: a\r\n\r\n\
+                  GET /fourth HTTP/1.1\r\nHost: a\r\nConnection: close\r\n\r\n",
+            )
+            .unwrap();
+
+        let response = read_all(stream);
+        server.join().unwrap();
+
+        let bodies: Vec<&str> = response
+            .split("HTTP/1.1 200 OK\r\n")
+            .skip(1)
+            .map(|response| response.split("\r\n\r\n").nth(1).unwrap())
+            .collect();
+        assert_eq!(bodies, ["/first", "/second", "", "/fourth"]);
+        assert_eq!(response.matches("Connection: keep-alive").count(), 3);
+        assert!(response.ends_with("Connection: close\r\nContent-Length: 7\r\n\r\n/fourth"));
+    }
+
+    #[test]
+    fn sequential_requests() {
+        let (mut stream, server) = serve_one();
+        let mut buffer = [0; 1024];
+
+        for path in ["/a", "/b"] {
+            let request = format!("GET {} HTTP/1.1\r\nHost: a\r\n\r\n", path);
+            stream.write_all(request.as_bytes()).unwrap();
+            let read = stream.read(&mut buffer).unwrap();
+            assert!(String::from_utf8_lossy(&buffer[..read]).ends_with(path));
+        }
+
+        drop(stream);
+        server.join().unwrap();
+    }
+
+    #[test]
+    fn http_10_closes_by_default() {
+        let (mut stream, server) = serve_one();
+
+        stream.write_all(b"GET /old HTTP/1.0\r\n\r\n").unwrap();
+
+        let response = read_all(stream);
+        server.join().unwrap();
+        assert!(response.contains("Connection: close\r\n"));
+        assert!(response.ends_with("/old"));
+    }
+
+    #[test]
+    fn malformed_request_closes() {
+        let (mut stream, server) = serve_one();
+
+        stream
+            .write_all(b"GET / HTTP/1.1\r\n\r\nGET /next HTTP/1.1\r\nHost: a\r\n\r\n")
+            .unwrap();
+
+        let response = read_all(stream);
+        server.join().unwrap();
+        assert!(response.starts_with("HTTP/1.1 400 Bad Request\r\n"));
+        assert!(!response.contains("/next"));
+    }
+
+    #[test]
+    fn streamed_responses() {
+        let (mut stream, server) = serve_one();
+
+        stream
+            .write_all(
+                b"GET /stream HTTP/1.1\r\nHost: a\r\n\r\n\
+                  GET /stream HTTP/1.0\r\nConnection: keep-alive\r\n\r\n",
+            )
+            .unwrap();
+
+        let response = read_all(stream);
+        server.join().unwrap();
+
+        let (chunked, unchunked) = response[15..].split_once("HTTP/1.1 200 OK").unwrap();
+        assert!(chunked.contains("Transfer-Encoding: chunked\r\n"));
+        assert!(chunked.ends_with("\r\n\r\n6\r\nfirst \r\n6\r\nsecond\r\n0\r\n\r\n"));
+        assert!(unchunked.contains("Connection: close\r\n"));
+        assert!(unchunked.ends_with("\r\n\r\nfirst second"));
+    }
+
+    #[test]
+    fn read_timeout() {
+        let (mut stream, server) = serve_one();
+
+        stream.write_all(b"GET /slow HTTP/1.1\r\nHo").unwrap();
+
+        let response = read_all(stream);
+        server.join().unwrap();
+        assert!(response.starts_with("HTTP/1.1 408 Request Timeout\r\n"));
+        assert!(response.contains("Connection: close\r\n"));
+    }
+
+    #[test]
+    fn read_timeout_of_trickled_requests() {
+        let (mut stream, server) = serve_one();
+        stream
+            .set_read_timeout(Some(Duration::from_millis(50)))
+            .unwrap();
+
+        // Every byte arrives well within the read timeout, but not the whole request.
+        let start = Instant::now();
+        stream.write_all(b"GET / HTTP/1.1\r\nX-Slow: ").unwrap();
+        let mut first = [0];
+        for _ in 0..40 {
+            stream.write_all(b"a").unwrap();
+            if stream.read(&mut first).is_ok() {
+                break;
+            }
+        }
+        assert!(start.elapsed() < Duration::from_secs(1));
+
+        stream.set_read_timeout(None).unwrap();
+        let response = String::from_utf8(first.to_vec()).unwrap() + &read_all(stream);
+        server.join().unwrap();
+        assert!(response.starts_with("HTTP/1.1 408 Request Timeout\r\n"));
+    }
+
+    #[test]
+    fn idle_timeout() {
+        let (mut stream, server) = serve_one();
+        let start = Instant::now();
+
+        stream
+            .write_all(b"GET /a HTTP/1.1\r\nHost: a\r\n\r\n")
+            .unwrap();
+
+        let response = read_all(stream);
+        server.join().unwrap();
+        assert!(response.ends_with("/a"));
+        assert!(start.elapsed() >= Duration::from_millis(500));
+    }
+}
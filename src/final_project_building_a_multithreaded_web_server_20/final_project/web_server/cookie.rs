@@ -0,0 +1,253 @@
+use super::request::is_token;
+use std::fmt;
+use std::time::Duration;
+
+/// Whether the browser sends the cookie with the requests coming from other sites.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SameSite {
+    /// Only with the requests from the same site.
+    Strict,
+    /// Also with the top-level navigations from other sites, like following a link. What
+    /// browsers use when the attribute is missing.
+    Lax,
+    /// With all the requests. Browsers only accept it on [`Secure`](Cookie::secure) cookies.
+    None,
+}
+
+impl SameSite {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SameSite::Strict => "Strict",
+            SameSite::Lax => "Lax",
+            SameSite::None => "None",
+        }
+    }
+}
+
+/// Cookie set on the client with the `Set-Cookie` header of a response, see
+/// [`Response::with_cookie`](super::response::Response::with_cookie).
+///
+/// Without [`max_age`](Cookie::max_age), the cookie is deleted when the browser is closed.
+///
+/// ```rust
+/// let cookie = Cookie::new("theme", "dark")
+///     .path("/")
+///     .max_age(Duration::from_secs(3600))
+///     .http_only(true)
+///     .same_site(SameSite::Lax);
+/// assert_eq!(
+///     cookie.to_string(),
+///     "theme=dark; Path=/; Max-Age=3600; HttpOnly; SameSite=Lax"
+/// );
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cookie {
+    name: String,
+    value: String,
+    path: Option<String>,
+    domain: Option<String>,
+    max_age: Option<Duration>,
+    secure: bool,
+    http_only: bool,
+    same_site: Option<SameSite>,
+}
+
+impl Cookie {
+    /// Creates a cookie without attributes.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the name is not a token, or if the value has characters not allowed in cookies:
+    /// controls, spaces, `"`, `,`, `;` and `\`. Values that can contain them must be encoded,
+    /// e.g. with percent-encoding.
+    pub fn new(name: impl Into<String>, value: impl Into<String>) -> Cookie {
+        let (name, value) = (name.into(), value.into());
+        assert!(
+            !name.is_empty() && name.bytes().all(is_token),
+            "Invalid cookie name: {}",
+            name
+        );
+        assert!(
+            value.bytes().all(is_cookie_octet),
+            "Invalid cookie value: {}",
+            value
+        );
+
+        Cookie {
+            name,
+            value,
+            path: None,
+            domain: None,
+            max_age: None,
+            secure: false,
+            http_only: false,
+            same_site: None,
+        }
+    }
+
+    /// Cookie that deletes the cookie with the name from the client. It must have the same path
+    /// and domain as the cookie it deletes.
+    pub fn removal(name: impl Into<String>) -> Cookie {
+        Cookie::new(name, "").max_age(Duration::ZERO)
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn value(&self) -> &str {
+        &self.value
+    }
+
+    /// Path the cookie is sent to, with all its subpaths. Defaults to the directory of the
+    /// request that set it.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the path contains `;` or control characters.
+    pub fn path(mut self, path: impl Into<String>) -> Cookie {
+        self.path = Some(attribute_value(path.into()));
+        self
+    }
+
+    /// Domain the cookie is sent to, with all its subdomains. Without it, the cookie is only
+    /// sent to the host that set it.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the domain contains `;` or control characters.
+    pub fn domain(mut self, domain: impl Into<String>) -> Cookie {
+        self.domain = Some(attribute_value(domain.into()));
+        self
+    }
+
+    /// Time after which the client deletes the cookie. Zero deletes it right away.
+    pub fn max_age(mut self, max_age: Duration) -> Cookie {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    /// Only sends the cookie over HTTPS.
+    pub fn secure(mut self, secure: bool) -> Cookie {
+        self.secure = secure;
+        self
+    }
+
+    /// Hides the cookie from the scripts of the page, which protects it from XSS.
+    pub fn http_only(mut self, http_only: bool) -> Cookie {
+        self.http_only = http_only;
+        self
+    }
+
+    pub fn same_site(mut self, same_site: SameSite) -> Cookie {
+        self.same_site = Some(same_site);
+        self
+    }
+}
+
+/// Writes the value of the `Set-Cookie` header.
+impl fmt::Display for Cookie {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}={}", self.name, self.value)?;
+        if let Some(path) = &self.path {
+            write!(f, "; Path={}", path)?;
+        }
+        if let Some(domain) = &self.domain {
+            write!(f, "; Domain={}", domain)?;
+        }
+        if let Some(max_age) = self.max_age {
+            write!(f, "; Max-Age={}", max_age.as_secs())?;
+        }
+        if self.secure {
+            f.write_str("; Secure")?;
+        }
+        if self.http_only {
+            f.write_str("; HttpOnly")?;
+        }
+        if let Some(same_site) = self.same_site {
+            write!(f, "; SameSite={}", same_site.as_str())?;
+        }
+        Ok(())
+    }
+}
+
+/// Parses the value of a `Cookie` header into its `(name, value)` pairs, in order. Quoted values
+/// are unquoted, and malformed pairs are skipped.
+pub fn parse(header: &str) -> Vec<(String, String)> {
+    header
+        .split(';')
+        .filter_map(|pair| {
+            let (name, value) = pair.split_once('=')?;
+            let name = name.trim();
+            let value = value.trim();
+            let value = match value.strip_prefix('"') {
+                Some(quoted) => quoted.strip_suffix('"')?,
+                None => value,
+            };
+            match !name.is_empty() && name.bytes().all(is_token) {
+                true => Some((name.to_string(), value.to_string())),
+                false => None,
+            }
+        })
+        .collect()
+}
+
+fn attribute_value(value: String) -> String {
+    assert!(
+        !value.bytes().any(|b| b == b';' || b.is_ascii_control()),
+        "Invalid cookie attribute: {}",
+        value
+    );
+    value
+}
+
+/// Characters allowed in cookie values (RFC 6265, 4.1.1).
+fn is_cookie_octet(b: u8) -> bool {
+    matches!(b, 0x21 | 0x23..=0x2b | 0x2d..=0x3a | 0x3c..=0x5b | 0x5d..=0x7e)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn set_cookie() {
+        assert_eq!(Cookie::new("id", "42").to_string(), "id=42");
+        assert_eq!(
+            Cookie::new("session", "abc.def")
+                .path("/app")
+                .domain("example.com")
+                .max_age(Duration::from_secs(86_400))
+                .secure(true)
+                .http_only(true)
+                .same_site(SameSite::Strict)
+                .to_string(),
+            "session=abc.def; Path=/app; Domain=example.com; Max-Age=86400; Secure; HttpOnly; \
+             SameSite=Strict"
+        );
+        assert_eq!(
+            Cookie::removal("session").path("/").to_string(),
+            "session=; Path=/; Max-Age=0"
+        );
+    }
+
+    #[test]
+    #[should_panic]
+    fn invalid_value() {
+        Cookie::new("name", "a; b");
+    }
+
+    #[test]
+    fn parse_header() {
+        assert_eq!(
+            parse("a=1; b=\"two\";c=; invalid; =x; d=x=y"),
+            [
+                (String::from("a"), String::from("1")),
+                (String::from("b"), String::from("two")),
+                (String::from("c"), String::new()),
+                (String::from("d"), String::from("x=y")),
+            ]
+        );
+        assert!(parse("").is_empty());
+    }
+}
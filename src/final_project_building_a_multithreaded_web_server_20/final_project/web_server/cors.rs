@@ -0,0 +1,367 @@
+use super::compression::add_vary;
+use super::request::{Method, Request};
+use super::response::{Response, StatusCode};
+use std::time::Duration;
+
+/// Methods allowed to a preflight request when neither [`Cors::allow_methods`] nor the handlers
+/// of the path tell them, which are the methods a page can use without a preflight.
+const SAFELISTED_METHODS: [Method; 3] = [Method::Get, Method::Head, Method::Post];
+
+/// Cross-Origin Resource Sharing: which pages of other origins can call the server from the
+/// browser, and how. The paths opt in with [`Router::cors`](super::router::Router::cors), which
+/// answers their preflight requests (`OPTIONS` with `Access-Control-Request-Method`) and adds
+/// the `Access-Control-*` headers to their responses.
+///
+/// Requests from origins that are not allowed are still served, but without the headers, so
+/// the browser does not let the page read the response. Their preflight requests get
+/// `403 Forbidden`.
+///
+/// # Example
+///
+/// ```rust
+/// let cors = Cors::new()
+///     .allow_origin("https://app.example.com")
+///     .allow_headers(["Content-Type", "Authorization"])
+///     .allow_credentials(true)
+///     .max_age(Duration::from_secs(600));
+/// let router = Router::new()
+///     .get("/api/users", list_users)
+///     .post("/api/users", create_user)
+///     .cors("/api/*", cors);
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Cors {
+    origins: Vec<String>,
+    methods: Vec<Method>,
+    headers: Vec<String>,
+    expose_headers: Vec<String>,
+    credentials: bool,
+    max_age: Option<Duration>,
+}
+
+impl Cors {
+    /// CORS policy without any allowed origin.
+    pub fn new() -> Cors {
+        Cors::default()
+    }
+
+    /// Allows the origin, such as `https://app.example.com`, or any origin with `*`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the origin is not valid, see [`is_valid_origin`], or if it is `*` and the
+    /// credentials are allowed.
+    pub fn allow_origin(mut self, origin: &str) -> Cors {
+        assert!(is_valid_origin(origin), "Invalid CORS origin: {}", origin);
+        assert!(
+            !(self.credentials && origin == "*"),
+            "CORS credentials cannot be allowed to any origin"
+        );
+        self.origins.push(origin.to_ascii_lowercase());
+        self
+    }
+
+    /// Methods allowed to the preflight requests. By default they are the methods of the routes
+    /// matching the path, any method for the paths handled by a mount, which accepts them all,
+    /// and `GET`, `HEAD` and `POST` for the rest.
+    pub fn allow_methods(mut self, methods: impl IntoIterator<Item = Method>) -> Cors {
+        self.methods.extend(methods);
+        self
+    }
+
+    /// Request headers allowed to the preflight requests, or any header with `*`. Headers such
+    /// as `Content-Type: application/json` or `Authorization` need a preflight.
+    pub fn allow_headers<S: AsRef<str>>(mut self, headers: impl IntoIterator<Item = S>) -> Cors {
+        self.headers.extend(
+            headers
+                .into_iter()
+                .map(|header| header.as_ref().to_ascii_lowercase()),
+        );
+        self
+    }
+
+    /// Response headers the page can read besides the safelisted ones, like `Content-Type`.
+    pub fn expose_headers<S: AsRef<str>>(mut self, headers: impl IntoIterator<Item = S>) -> Cors {
+        self.expose_headers.extend(
+            headers
+                .into_iter()
+                .map(|header| header.as_ref().to_string()),
+        );
+        self
+    }
+
+    /// Lets the pages send cookies and HTTP authentication with their requests. The origins must
+    /// then be listed: allowing the credentials to any origin would let every site make requests
+    /// as the user and read their responses.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the credentials are allowed and `*` is one of the allowed origins.
+    pub fn allow_credentials(mut self, credentials: bool) -> Cors {
+        assert!(
+            !(credentials && self.origins.iter().any(|origin| origin == "*")),
+            "CORS credentials cannot be allowed to any origin"
+        );
+        self.credentials = credentials;
+        self
+    }
+
+    /// Time the browser can cache the answer of a preflight request.
+    pub fn max_age(mut self, max_age: Duration) -> Cors {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    pub fn is_allowed_origin(&self, origin: &str) -> bool {
+        self.origins
+            .iter()
+            .any(|allowed| allowed == "*" || allowed.eq_ignore_ascii_case(origin))
+    }
+
+    /// Answers a preflight request. `route_methods` are the methods of the routes matching the
+    /// path, or `None` if the path is handled by a mount, which accepts any method.
+    pub fn preflight(&self, request: &Request, route_methods: Option<&[Method]>) -> Response {
+        let forbidden = Response::error(StatusCode::FORBIDDEN);
+        let (Some(origin), Some(method)) = (
+            request.header("Origin"),
+            request.header("Access-Control-Request-Method"),
+        ) else {
+            return forbidden;
+        };
+
+        let mut methods = match route_methods {
+            _ if !self.methods.is_empty() => self.methods.clone(),
+            Some(methods) if !methods.is_empty() => methods.to_vec(),
+            Some(_) => SAFELISTED_METHODS.to_vec(),
+            // Only the requested method is listed, as there is no `*` with credentials.
+            None => method.parse::<Method>().into_iter().collect(),
+        };
+        if methods.contains(&Method::Get) && !methods.contains(&Method::Head) {
+            methods.push(Method::Head);
+        }
+        let method_allowed = method
+            .parse::<Method>()
+            .is_ok_and(|method| methods.contains(&method));
+
+        let headers: Vec<&str> = request
+            .headers
+            .get_all("Access-Control-Request-Headers")
+            .flat_map(|headers| headers.split(','))
+            .map(str::trim)
+            .filter(|header| !header.is_empty())
+            .collect();
+        let headers_allowed = self.headers.iter().any(|allowed| allowed == "*")
+            || headers.iter().all(|header| {
+                self.headers
+                    .iter()
+                    .any(|allowed| allowed.eq_ignore_ascii_case(header))
+            });
+
+        if !self.is_allowed_origin(origin) || !method_allowed || !headers_allowed {
+            return forbidden;
+        }
+
+        let methods: Vec<&str> = methods.iter().map(Method::as_str).collect();
+        let mut response = Response::new(StatusCode::NO_CONTENT)
+            .with_header("Access-Control-Allow-Methods", methods.join(", "));
+        if !headers.is_empty() {
+            response
+                .headers
+                .insert("Access-Control-Allow-Headers", headers.join(", "));
+        }
+        if let Some(max_age) = self.max_age {
+            response
+                .headers
+                .insert("Access-Control-Max-Age", max_age.as_secs().to_string());
+        }
+        self.apply(request, &mut response);
+        response
+    }
+
+    /// Adds the `Access-Control-*` headers to the response if the origin of the request is
+    /// allowed.
+    pub fn apply(&self, request: &Request, response: &mut Response) {
+        let any_origin = self.origins.iter().any(|origin| origin == "*");
+        if !any_origin {
+            add_vary(&mut response.headers, "Origin");
+        }
+        let Some(origin) = request
+            .header("Origin")
+            .filter(|origin| self.is_allowed_origin(origin))
+        else {
+            return;
+        };
+
+        let headers = &mut response.headers;
+        match any_origin {
+            true => headers.insert("Access-Control-Allow-Origin", "*"),
+            false => headers.insert("Access-Control-Allow-Origin", origin),
+        }
+        if self.credentials {
+            headers.insert("Access-Control-Allow-Credentials", "true");
+        }
+        if !self.expose_headers.is_empty() && request.method != Method::Options {
+            headers.insert(
+                "Access-Control-Expose-Headers",
+                self.expose_headers.join(", "),
+            );
+        }
+    }
+}
+
+/// Returns `true` if the request is a CORS preflight request.
+pub fn is_preflight(request: &Request) -> bool {
+    request.method == Method::Options
+        && request.headers.contains("Origin")
+        && request.headers.contains("Access-Control-Request-Method")
+}
+
+/// Returns `true` if the origin is `*` or a scheme and a host, with an optional port, like
+/// `https://example.com:8443`.
+pub fn is_valid_origin(origin: &str) -> bool {
+    if origin == "*" {
+        return true;
+    }
+    let Some((scheme, host)) = origin.split_once("://") else {
+        return false;
+    };
+    // The port follows the brackets of an IPv6 address.
+    let port_start = host.find(']').map_or(0, |end| end + 1);
+    let host = match host[port_start..].find(':') {
+        Some(colon) if host[port_start + colon + 1..].parse::<u16>().is_ok() => {
+            &host[..port_start + colon]
+        }
+        Some(_) => return false,
+        None => host,
+    };
+
+    !scheme.is_empty()
+        && scheme
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"+-.".contains(&b))
+        && !host.is_empty()
+        && host
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"-.[]:".contains(&b))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn preflight(cors: &Cors, origin: &str, method: &str, headers: &str) -> Response {
+        let mut request = Request::new(Method::Options, "/api/users")
+            .with_header("Origin", origin)
+            .with_header("Access-Control-Request-Method", method);
+        if !headers.is_empty() {
+            request = request.with_header("Access-Control-Request-Headers", headers);
+        }
+        cors.preflight(&request, Some(&[Method::Get, Method::Post]))
+    }
+
+    #[test]
+    fn preflight_requests() {
+        let cors = Cors::new()
+            .allow_origin("https://App.example.com")
+            .allow_headers(["Content-Type"])
+            .allow_credentials(true)
+            .max_age(Duration::from_secs(600));
+
+        let response = preflight(&cors, "https://app.example.com", "POST", "content-type");
+        assert_eq!(response.status, StatusCode::NO_CONTENT);
+        let header = |name| response.headers.get(name);
+        assert_eq!(
+            header("Access-Control-Allow-Origin"),
+            Some("https://app.example.com")
+        );
+        assert_eq!(
+            header("Access-Control-Allow-Methods"),
+            Some("GET, POST, HEAD")
+        );
+        assert_eq!(header("Access-Control-Allow-Headers"), Some("content-type"));
+        assert_eq!(header("Access-Control-Allow-Credentials"), Some("true"));
+        assert_eq!(header("Access-Control-Max-Age"), Some("600"));
+        assert_eq!(header("Vary"), Some("Origin"));
+
+        let forbidden = [
+            ("https://other.example.com", "GET", ""),
+            ("https://app.example.com", "DELETE", ""),
+            ("https://app.example.com", "GET", "Content-Type, X-Token"),
+        ];
+        for (origin, method, headers) in forbidden {
+            let response = preflight(&cors, origin, method, headers);
+            assert_eq!(response.status, StatusCode::FORBIDDEN);
+            assert!(!response.headers.contains("Access-Control-Allow-Origin"));
+        }
+
+        let cors = Cors::new()
+            .allow_origin("*")
+            .allow_methods([Method::Delete])
+            .allow_headers(["*"]);
+        let response = preflight(&cors, "http://localhost:3000", "DELETE", "X-Token");
+        assert_eq!(response.status, StatusCode::NO_CONTENT);
+        assert_eq!(
+            response.headers.get("Access-Control-Allow-Origin"),
+            Some("*")
+        );
+        assert_eq!(
+            response.headers.get("Access-Control-Allow-Headers"),
+            Some("X-Token")
+        );
+        assert!(!response.headers.contains("Vary"));
+    }
+
+    #[test]
+    fn actual_requests() {
+        let cors = Cors::new()
+            .allow_origin("https://app.example.com")
+            .expose_headers(["X-Request-Id"]);
+        let apply = |origin: &str| {
+            let request = Request::new(Method::Get, "/").with_header("Origin", origin);
+            let mut response = Response::new(StatusCode::OK).with_header("Vary", "Accept-Encoding");
+            cors.apply(&request, &mut response);
+            response.headers
+        };
+
+        let headers = apply("https://app.example.com");
+        assert_eq!(
+            headers.get("Access-Control-Allow-Origin"),
+            Some("https://app.example.com")
+        );
+        assert_eq!(
+            headers.get("Access-Control-Expose-Headers"),
+            Some("X-Request-Id")
+        );
+        assert!(!headers.contains("Access-Control-Allow-Credentials"));
+        assert_eq!(headers.get("Vary"), Some("Accept-Encoding, Origin"));
+
+        let headers = apply("https://evil.example.com");
+        assert!(!headers.contains("Access-Control-Allow-Origin"));
+        assert_eq!(headers.get("Vary"), Some("Accept-Encoding, Origin"));
+    }
+
+    #[test]
+    #[should_panic]
+    fn credentials_with_any_origin() {
+        Cors::new().allow_origin("*").allow_credentials(true);
+    }
+
+    #[test]
+    #[should_panic]
+    fn any_origin_with_credentials() {
+        Cors::new().allow_credentials(true).allow_origin("*");
+    }
+
+    #[test]
+    fn origins() {
+        assert!(is_valid_origin("*"));
+        assert!(is_valid_origin("https://example.com"));
+        assert!(is_valid_origin("http://localhost:3000"));
+        assert!(is_valid_origin("http://[::1]:8080"));
+        assert!(!is_valid_origin("example.com"));
+        assert!(!is_valid_origin("https://example.com/"));
+        assert!(!is_valid_origin("https://example.com:port"));
+        assert!(!is_valid_origin("https://"));
+        assert!(!is_valid_origin("http://[::1]:"));
+    }
+}
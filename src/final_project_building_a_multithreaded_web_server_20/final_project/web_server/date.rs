@@ -0,0 +1,251 @@
+use std::fmt;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// The Unix epoch was a Thursday.
+const WEEKDAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+
+/// Date and time in UTC, split into its calendar fields. Used to format the dates written in
+/// headers and logs without depending on a date crate.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct DateTime {
+    pub year: i64,
+    /// Month of the year, from 1 to 12.
+    pub month: u8,
+    /// Day of the month, from 1 to 31.
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+impl DateTime {
+    /// Converts a number of seconds since the Unix epoch. Uses the algorithm `civil_from_days`
+    /// by Howard Hinnant.
+    pub fn from_unix(seconds: i64) -> DateTime {
+        let days = seconds.div_euclid(86_400);
+        let time = seconds.rem_euclid(86_400);
+
+        let days = days + 719_468;
+        let era = days.div_euclid(146_097);
+        let day_of_era = days.rem_euclid(146_097);
+        let year_of_era =
+            (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+        let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+        let month = (5 * day_of_year + 2) / 153;
+        let day = day_of_year - (153 * month + 2) / 5 + 1;
+        let month = if month < 10 { month + 3 } else { month - 9 };
+        let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+        DateTime {
+            year,
+            month: month as u8,
+            day: day as u8,
+            hour: (time / 3600) as u8,
+            minute: (time % 3600 / 60) as u8,
+            second: (time % 60) as u8,
+        }
+    }
+
+    /// Converts a system time, dropping the fractions of a second.
+    pub fn from_system_time(time: SystemTime) -> DateTime {
+        let seconds = match time.duration_since(UNIX_EPOCH) {
+            Ok(duration) => duration.as_secs() as i64,
+            Err(err) => -(err.duration().as_secs_f64().ceil() as i64),
+        };
+        DateTime::from_unix(seconds)
+    }
+
+    /// Parses a HTTP date in the preferred format of RFC 9110, `Sun, 06 Nov 1994 08:49:37 GMT`.
+    /// The obsolete RFC 850 and asctime formats are not accepted; the conditional headers using
+    /// them are ignored, which only makes the server send full responses.
+    pub fn parse_http(value: &str) -> Option<DateTime> {
+        let rest = value.trim().split_once(", ")?.1;
+        let mut fields = rest.split(' ');
+        let day = fields.next().filter(|day| day.len() == 2)?.parse().ok()?;
+        let month = fields.next()?;
+        let month = MONTHS.iter().position(|name| *name == month)? as u8 + 1;
+        let year = fields.next().filter(|year| year.len() == 4)?.parse().ok()?;
+        let mut time = fields.next()?.split(':');
+        let mut field = |max: u8| {
+            time.next()
+                .filter(|field| field.len() == 2)
+                .and_then(|field| field.parse().ok())
+                .filter(|field| *field <= max)
+        };
+        let (hour, minute, second) = (field(23)?, field(59)?, field(60)?);
+        if time.next().is_some()
+            || fields.next() != Some("GMT")
+            || fields.next().is_some()
+            || !(1..=31).contains(&day)
+        {
+            return None;
+        }
+
+        // Normalizes impossible days such as February 30.
+        let date = DateTime {
+            year,
+            month,
+            day,
+            hour,
+            minute,
+            second,
+        };
+        Some(DateTime::from_unix(date.to_unix()))
+    }
+
+    /// Number of seconds since the Unix epoch. Uses the algorithm `days_from_civil` by Howard
+    /// Hinnant, the inverse of [`from_unix`](DateTime::from_unix).
+    pub fn to_unix(self) -> i64 {
+        let month = i64::from(self.month);
+        let year = if month <= 2 { self.year - 1 } else { self.year };
+        let era = year.div_euclid(400);
+        let year_of_era = year.rem_euclid(400);
+        let day_of_year = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5
+            + i64::from(self.day)
+            - 1;
+        let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+        let days = era * 146_097 + day_of_era - 719_468;
+
+        days * 86_400
+            + i64::from(self.hour) * 3600
+            + i64::from(self.minute) * 60
+            + i64::from(self.second)
+    }
+
+    pub fn now() -> DateTime {
+        DateTime::from_system_time(SystemTime::now())
+    }
+
+    /// Abbreviated English name of the month, as used by the log and HTTP date formats.
+    pub fn month_name(&self) -> &'static str {
+        MONTHS[usize::from(self.month - 1)]
+    }
+
+    /// Abbreviated English name of the day of the week.
+    pub fn weekday_name(&self) -> &'static str {
+        WEEKDAYS[self.to_unix().div_euclid(86_400).rem_euclid(7) as usize]
+    }
+
+    /// Formats the date as in the HTTP headers: `Tue, 10 Oct 2000 13:55:36 GMT`.
+    pub fn http(&self) -> impl fmt::Display + '_ {
+        Http(self)
+    }
+
+    /// Formats the date as in the Common Log Format: `10/Oct/2000:13:55:36 +0000`.
+    pub fn clf(&self) -> impl fmt::Display + '_ {
+        Clf(self)
+    }
+}
+
+/// Formats the date as in RFC 3339: `2000-10-10T13:55:36Z`.
+impl fmt::Display for DateTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+            self.year, self.month, self.day, self.hour, self.minute, self.second
+        )
+    }
+}
+
+struct Clf<'a>(&'a DateTime);
+
+impl fmt::Display for Clf<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let date = self.0;
+        write!(
+            f,
+            "{:02}/{}/{:04}:{:02}:{:02}:{:02} +0000",
+            date.day,
+            date.month_name(),
+            date.year,
+            date.hour,
+            date.minute,
+            date.second
+        )
+    }
+}
+
+struct Http<'a>(&'a DateTime);
+
+impl fmt::Display for Http<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let date = self.0;
+        write!(
+            f,
+            "{}, {:02} {} {:04} {:02}:{:02}:{:02} GMT",
+            date.weekday_name(),
+            date.day,
+            date.month_name(),
+            date.year,
+            date.hour,
+            date.minute,
+            date.second
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_unix() {
+        assert_eq!(DateTime::from_unix(0).to_string(), "1970-01-01T00:00:00Z");
+        assert_eq!(
+            DateTime::from_unix(971_186_136).to_string(),
+            "2000-10-10T13:55:36Z"
+        );
+        // Leap day of a leap century.
+        assert_eq!(
+            DateTime::from_unix(951_782_400).to_string(),
+            "2000-02-29T00:00:00Z"
+        );
+        assert_eq!(DateTime::from_unix(-1).to_string(), "1969-12-31T23:59:59Z");
+    }
+
+    #[test]
+    fn clf() {
+        assert_eq!(
+            DateTime::from_unix(971_186_136).clf().to_string(),
+            "10/Oct/2000:13:55:36 +0000"
+        );
+    }
+
+    #[test]
+    fn to_unix() {
+        for seconds in [0, -1, 951_782_400, 971_186_136, 4_102_444_800] {
+            assert_eq!(DateTime::from_unix(seconds).to_unix(), seconds);
+        }
+    }
+
+    #[test]
+    fn http() {
+        let date = DateTime::from_unix(971_186_136);
+        assert_eq!(date.http().to_string(), "Tue, 10 Oct 2000 13:55:36 GMT");
+        assert_eq!(
+            DateTime::parse_http("Tue, 10 Oct 2000 13:55:36 GMT"),
+            Some(date)
+        );
+        assert_eq!(
+            DateTime::from_unix(0).http().to_string(),
+            "Thu, 01 Jan 1970 00:00:00 GMT"
+        );
+
+        for invalid in [
+            "",
+            "Tuesday, 10-Oct-00 13:55:36 GMT",
+            "Tue Oct 10 13:55:36 2000",
+            "Tue, 10 Oct 2000 13:55:36 UTC",
+            "Tue, 10 Oct 2000 24:00:00 GMT",
+            "Tue, 32 Oct 2000 13:55:36 GMT",
+            "Tue, 10 Okt 2000 13:55:36 GMT",
+        ] {
+            assert_eq!(DateTime::parse_http(invalid), None, "{}", invalid);
+        }
+    }
+}
@@ -0,0 +1,120 @@
+/// Decodes the `%XX` escape sequences of an URL component. Invalid escape sequences are kept
+/// as they are. Returns `None` if the decoded bytes are not valid UTF-8.
+pub fn percent_decode(input: &str) -> Option<String> {
+    let bytes = input.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            if let (Some(high), Some(low)) = (hex_value(bytes[i + 1]), hex_value(bytes[i + 2])) {
+                decoded.push(high << 4 | low);
+                i += 3;
+                continue;
+            }
+        }
+        decoded.push(bytes[i]);
+        i += 1;
+    }
+
+    String::from_utf8(decoded).ok()
+}
+
+/// Encodes all the bytes of the input except the unreserved characters of RFC 3986
+/// (`A-Z a-z 0-9 - . _ ~`) and `/`, so the result can be used as the path of an URL.
+pub fn percent_encode_path(input: &str) -> String {
+    let mut encoded = String::with_capacity(input.len());
+
+    for &b in input.as_bytes() {
+        if b.is_ascii_alphanumeric() || b"-._~/".contains(&b) {
+            encoded.push(b as char);
+        } else {
+            encoded.push_str(&format!("%{:02X}", b));
+        }
+    }
+
+    encoded
+}
+
+/// Escapes the characters with a special meaning in HTML, so the text can be safely inserted
+/// in an element or an attribute value.
+pub fn escape_html(input: &str) -> String {
+    let mut escaped = String::with_capacity(input.len());
+
+    for c in input.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+/// Escapes the text so it can be written inside a JSON string.
+pub fn escape_json(input: &str) -> String {
+    let mut escaped = String::with_capacity(input.len());
+
+    for c in input.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\t' => escaped.push_str("\\t"),
+            c if c < ' ' => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            _ => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+fn hex_value(b: u8) -> Option<u8> {
+    match b {
+        b'0'..=b'9' => Some(b - b'0'),
+        b'a'..=b'f' => Some(b - b'a' + 10),
+        b'A'..=b'F' => Some(b - b'A' + 10),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode() {
+        assert_eq!(percent_decode("/a%20b/%C3%A9").as_deref(), Some("/a b/é"));
+        assert_eq!(percent_decode("%2e%2E").as_deref(), Some(".."));
+        assert_eq!(percent_decode("100%").as_deref(), Some("100%"));
+        assert_eq!(percent_decode("%zz%4").as_deref(), Some("%zz%4"));
+        assert_eq!(percent_decode("%FF"), None);
+    }
+
+    #[test]
+    fn encode() {
+        assert_eq!(percent_encode_path("/a b/é.txt"), "/a%20b/%C3%A9.txt");
+        assert_eq!(percent_encode_path("safe-name_1.~"), "safe-name_1.~");
+    }
+
+    #[test]
+    fn escape() {
+        assert_eq!(
+            escape_html("<a href=\"x\">Tom & 'Jerry'</a>"),
+            "&lt;a href=&quot;x&quot;&gt;Tom &amp; &#39;Jerry&#39;&lt;/a&gt;"
+        );
+    }
+
+    #[test]
+    fn escape_json_string() {
+        assert_eq!(
+            escape_json("say \"hi\"\\\n\u{1}é"),
+            "say \\\"hi\\\"\\\\\\n\\u0001é"
+        );
+    }
+}
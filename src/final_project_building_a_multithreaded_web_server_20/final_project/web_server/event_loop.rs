@@ -0,0 +1,794 @@
+use super::body::CHUNK_SIZE;
+use super::config::Config;
+use super::connection::{closes, keep_alive, take_upgrade, write_response, Connections};
+use super::metrics::{ConnectionGuard, Metrics};
+use super::request::{self, Limits, Method, ParseError, Request, Version};
+use super::response::{Response, StatusCode};
+use super::router::Handler;
+use super::server::{close_with, service_unavailable, ServerHandle};
+use super::upgrade::Upgrade;
+use mio::net::{TcpListener, TcpStream};
+use mio::{Events, Interest, Poll, Token, Waker};
+use std::collections::HashMap;
+use std::io::{self, BufWriter, Cursor, Read, Write};
+use std::net::{self, Shutdown, SocketAddr};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use threadpool::ThreadPool;
+
+const LISTENER: Token = Token(0);
+const WAKER: Token = Token(1);
+
+struct Connection {
+    stream: TcpStream,
+    /// Clone of the socket given to the workers to write the responses.
+    writer: Arc<net::TcpStream>,
+    peer_addr: SocketAddr,
+    /// Bytes received but not parsed yet: the start of a request or pipelined requests.
+    buffer: Vec<u8>,
+    /// Head of the request being received, parsed once and removed from the buffer while the
+    /// body is still arriving.
+    head: Option<Request>,
+    /// End of the chunks of a chunked body already found complete in the buffer.
+    scanned: usize,
+    /// A worker is handling a request of the connection. Nothing else is read until it is done,
+    /// so the responses are sent in the order of the requests.
+    busy: bool,
+    /// The connection waits for the next request of a persistent connection.
+    idle: bool,
+    /// When the connection is closed if nothing else is received: the read timeout while a
+    /// request is being received and the keep-alive timeout while the connection is idle.
+    deadline: Instant,
+    _open: ConnectionGuard,
+}
+
+impl Connection {
+    /// Reads everything available in the socket, stopping early if the buffer reaches `limit`.
+    /// Returns `false` if the client closed its side of the connection.
+    fn read_available(&mut self, limit: usize) -> io::Result<bool> {
+        let mut chunk = [0; CHUNK_SIZE];
+
+        while self.buffer.len() < limit {
+            match self.stream.read(&mut chunk) {
+                Ok(0) => return Ok(false),
+                Ok(read) => self.buffer.extend_from_slice(&chunk[..read]),
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => break,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => return Err(err),
+            }
+        }
+
+        Ok(true)
+    }
+
+    /// Parses the first request of the buffer, removing it from the buffer if it is complete.
+    ///
+    /// The head is parsed once, then the body waits until all of its bytes are in the buffer, so
+    /// a body received in many reads is not parsed again from its start on every one of them.
+    fn parse(&mut self, config: &Config) -> Result<Request, ParseError> {
+        let limits = config.limits();
+        let head = match &mut self.head {
+            Some(head) => head,
+            None => {
+                let mut reader = Cursor::new(&self.buffer[..]);
+                let head = Request::read_head(&mut reader, &limits)?;
+                let parsed = reader.position() as usize;
+                self.buffer.drain(..parsed);
+                self.scanned = 0;
+                self.head.insert(head)
+            }
+        };
+
+        let length = if head.headers.contains("Transfer-Encoding") {
+            match chunked_body_length(&self.buffer, &mut self.scanned, &limits) {
+                Some(length) => length,
+                None => return Err(ParseError::UnexpectedEof),
+            }
+        } else {
+            // The invalid lengths are left to `read_body`, which rejects them.
+            match request::content_length(&head.headers) {
+                Ok(Some(length)) if length <= limits.max_body_size => length,
+                _ => 0,
+            }
+        };
+        if self.buffer.len() < length {
+            return Err(ParseError::UnexpectedEof);
+        }
+
+        let mut reader = Cursor::new(&self.buffer[..length]);
+        let body = request::read_body(&mut reader, &head.headers, &limits)?;
+        let parsed = reader.position() as usize;
+        self.buffer.drain(..parsed);
+
+        let mut request = self.head.take().expect("the head is parsed");
+        request.body = body;
+        request.peer_addr = Some(self.peer_addr);
+        Ok(request)
+    }
+}
+
+/// Finds the length of the chunked body at the start of `buffer` without decoding it, going on
+/// from `scanned`, the end of the chunks already found complete. Returns `None` while the body
+/// is incomplete, and the whole buffer if the chunks are invalid so that the parser reports why.
+fn chunked_body_length(buffer: &[u8], scanned: &mut usize, limits: &Limits) -> Option<usize> {
+    let mut reader = Cursor::new(buffer);
+    reader.set_position(*scanned as u64);
+
+    loop {
+        let size = match request::read_chunk_size(&mut reader) {
+            Ok(size) => size,
+            Err(ParseError::UnexpectedEof) => return None,
+            Err(_) => return Some(buffer.len()),
+        };
+        if size == 0 {
+            return match request::read_headers(&mut reader, limits) {
+                Ok(_) => Some(reader.position() as usize),
+                Err(ParseError::UnexpectedEof) => None,
+                Err(_) => Some(buffer.len()),
+            };
+        }
+        if size > limits.max_body_size {
+            return Some(buffer.len());
+        }
+
+        let end = reader.position() as usize + size;
+        // The line ending after the data may be split between two reads too.
+        if buffer.len() < end + 2 && b"\r\n".starts_with(&buffer[end.min(buffer.len())..]) {
+            return None;
+        }
+        reader.set_position(end as u64);
+        match request::read_chunk_end(&mut reader) {
+            Ok(()) => *scanned = reader.position() as usize,
+            Err(_) => return Some(buffer.len()),
+        }
+    }
+}
+
+/// Sent by a worker once the response has been written.
+struct Done {
+    token: Token,
+    keep_alive: bool,
+    /// The connection is handed over to another protocol.
+    upgrade: Option<Upgrade>,
+}
+
+/// Runs the [`Backend::EventLoop`](super::config::Backend::EventLoop) of a server until it is
+/// stopped.
+///
+/// The thread calling this function waits for the events of the listener and all the
+/// connections with [`mio`], which uses epoll on Linux. The bytes received are buffered per
+/// connection until they form a complete request, which is then sent to the pool to be handled.
+/// The worker writes the response with blocking writes (a file or a stream can be much bigger
+/// than the buffer of the socket) and hands the connection back to the loop.
+///
+/// So the threads of the pool are only busy while the handlers run and the responses are sent:
+/// idle persistent connections and clients sending their requests slowly do not take any.
+/// Handlers that block, like `/sleep`, still keep their thread until they return. Upgraded
+/// connections leave the loop and run in a thread of their own, see [`Upgrade`].
+pub(super) fn run(
+    listener: net::TcpListener,
+    handler: Arc<dyn Handler>,
+    config: Arc<Config>,
+    metrics: Arc<Metrics>,
+    handle: ServerHandle,
+) -> io::Result<()> {
+    listener.set_nonblocking(true)?;
+    let mut listener = TcpListener::from_std(listener);
+    let poll = Poll::new()?;
+    poll.registry()
+        .register(&mut listener, LISTENER, Interest::READABLE)?;
+    let waker = Arc::new(Waker::new(poll.registry(), WAKER)?);
+    let (sender, receiver) = mpsc::channel();
+
+    let mut event_loop = EventLoop {
+        poll,
+        waker,
+        connections: HashMap::new(),
+        next_token: WAKER.0 + 1,
+        pool: ThreadPool::new(config.workers),
+        handler,
+        config,
+        metrics,
+        handle,
+        sender,
+        receiver,
+        upgraded: Arc::new(Connections::new()),
+    };
+    let mut events = Events::with_capacity(1024);
+
+    // `ServerHandle::stop` connects to the listener, which wakes up the loop.
+    while !event_loop.handle.is_shutting_down() {
+        event_loop.wait(&mut events, event_loop.next_timeout())?;
+
+        for event in events.iter() {
+            match event.token() {
+                LISTENER => event_loop.accept(&listener),
+                WAKER => {}
+                token => {
+                    if event_loop.connections.get(&token).is_some_and(|c| !c.busy) {
+                        event_loop.receive(token);
+                    }
+                }
+            }
+        }
+
+        event_loop.finish_responses();
+        event_loop.expire();
+    }
+
+    println!("Shutting down.");
+    drop(listener);
+    event_loop.shutdown(&mut events)
+}
+
+struct EventLoop {
+    poll: Poll,
+    waker: Arc<Waker>,
+    connections: HashMap<Token, Connection>,
+    next_token: usize,
+    pool: ThreadPool,
+    handler: Arc<dyn Handler>,
+    config: Arc<Config>,
+    metrics: Arc<Metrics>,
+    handle: ServerHandle,
+    sender: Sender<Done>,
+    receiver: Receiver<Done>,
+    /// Connections that left the loop after an upgrade, closed by the shutdown.
+    upgraded: Arc<Connections>,
+}
+
+impl EventLoop {
+    fn wait(&mut self, events: &mut Events, timeout: Option<Duration>) -> io::Result<()> {
+        match self.poll.poll(events, timeout) {
+            Err(err) if err.kind() == io::ErrorKind::Interrupted => {
+                events.clear();
+                Ok(())
+            }
+            result => result,
+        }
+    }
+
+    /// Time until the first deadline of the connections that are not busy.
+    fn next_timeout(&self) -> Option<Duration> {
+        self.connections
+            .values()
+            .filter(|connection| !connection.busy)
+            .map(|connection| connection.deadline)
+            .min()
+            .map(|deadline| deadline.saturating_duration_since(Instant::now()))
+    }
+
+    /// Accepts all the pending connections.
+    fn accept(&mut self, listener: &TcpListener) {
+        loop {
+            match listener.accept() {
+                Ok((stream, _)) if self.connections.len() >= self.config.max_connections => {
+                    self.refuse(stream)
+                }
+                Ok((stream, peer_addr)) => {
+                    if let Err(err) = self.register(stream, peer_addr) {
+                        eprintln!("Error registering the connection: {}", err);
+                    }
+                }
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => return,
+                Err(err) if err.kind() == io::ErrorKind::ConnectionAborted => {}
+                Err(err) => {
+                    eprintln!("Stream error: {}", err);
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Answers a connection above [`Config::max_connections`] without keeping it open.
+    fn refuse(&self, stream: TcpStream) {
+        self.metrics.connection_rejected();
+        let stream = net::TcpStream::from(stream);
+        if stream.set_nonblocking(false).is_ok() {
+            close_with(&stream, service_unavailable());
+        }
+    }
+
+    fn register(&mut self, stream: TcpStream, peer_addr: SocketAddr) -> io::Result<()> {
+        let stream = net::TcpStream::from(stream);
+        stream.set_write_timeout(Some(self.config.write_timeout))?;
+        let writer = Arc::new(stream.try_clone()?);
+        let mut stream = TcpStream::from_std(stream);
+
+        let token = Token(self.next_token);
+        self.next_token += 1;
+        self.poll
+            .registry()
+            .register(&mut stream, token, Interest::READABLE)?;
+
+        self.connections.insert(
+            token,
+            Connection {
+                stream,
+                writer,
+                peer_addr,
+                buffer: Vec::new(),
+                head: None,
+                scanned: 0,
+                busy: false,
+                idle: false,
+                deadline: Instant::now() + self.config.read_timeout,
+                _open: self.metrics.connection_opened(),
+            },
+        );
+        Ok(())
+    }
+
+    /// Reads what the client sent and hands the next request to the pool once it is complete.
+    fn receive(&mut self, token: Token) {
+        let config = Arc::clone(&self.config);
+        let Some(connection) = self.connections.get_mut(&token) else {
+            return;
+        };
+
+        // Enough for any request within the limits, leaving room for the chunk size lines.
+        let limit = config.max_request_line + config.max_headers_size + 2 * config.max_body_size;
+        let received = connection.buffer.len();
+        let open = match connection.read_available(limit) {
+            Ok(open) => open,
+            Err(err) => {
+                eprintln!("Error reading from stream: {}", err);
+                self.close(token);
+                return;
+            }
+        };
+        // The read timeout starts with the first bytes of the request, not with every read.
+        if connection.idle && connection.buffer.len() > received {
+            connection.idle = false;
+            connection.deadline = Instant::now() + config.read_timeout;
+        }
+
+        match connection.parse(&config) {
+            Ok(request) => self.dispatch(token, request),
+            Err(ParseError::ConnectionClosed | ParseError::UnexpectedEof) if !open => {
+                self.close(token)
+            }
+            Err(ParseError::ConnectionClosed | ParseError::UnexpectedEof) => {
+                if connection.buffer.len() >= limit {
+                    self.answer(token, Response::error(StatusCode::PAYLOAD_TOO_LARGE));
+                }
+            }
+            Err(err) => match err.status() {
+                Some(status) => self.answer(token, Response::error(status)),
+                None => {
+                    eprintln!("Error reading from stream: {}", err);
+                    self.close(token);
+                }
+            },
+        }
+    }
+
+    fn dispatch(&mut self, token: Token, mut request: Request) {
+        if self.pool.queued_count() >= self.config.max_queued_connections {
+            self.metrics.connection_rejected();
+            self.answer(token, service_unavailable());
+            return;
+        }
+
+        let Some(connection) = self.connections.get_mut(&token) else {
+            return;
+        };
+        connection.busy = true;
+
+        let writer = Arc::clone(&connection.writer);
+        let handler = Arc::clone(&self.handler);
+        let metrics = Arc::clone(&self.metrics);
+        let handle = self.handle.clone();
+        let sender = self.sender.clone();
+        let waker = Arc::clone(&self.waker);
+
+        metrics.connection_queued();
+        self.pool.execute(move || {
+            metrics.connection_dequeued();
+            let mut response = handler.handle(&mut request);
+            let upgrade = take_upgrade(&mut response);
+            let keep_alive = keep_alive(&request)
+                && !closes(&response, request.version)
+                && !handle.is_shutting_down()
+                && upgrade.is_none();
+
+            let result = respond(
+                &writer,
+                response,
+                (request.method, request.version),
+                keep_alive,
+            );
+            if let Err(err) = &result {
+                eprintln!("Error writing to stream: {}", err);
+            }
+
+            let _ = sender.send(Done {
+                token,
+                keep_alive: keep_alive && result.is_ok(),
+                upgrade: upgrade.filter(|_| result.is_ok()),
+            });
+            let _ = waker.wake();
+        });
+    }
+
+    /// Takes back the connections whose response has been sent, closing them, going on with
+    /// their next request or handing them over to their upgrade.
+    fn finish_responses(&mut self) {
+        while let Ok(done) = self.receiver.try_recv() {
+            let Done {
+                token,
+                keep_alive,
+                upgrade,
+            } = done;
+            let shutting_down = self.handle.is_shutting_down();
+
+            if let Some(upgrade) = upgrade {
+                self.upgrade(token, upgrade);
+                continue;
+            }
+            match self.connections.get_mut(&token) {
+                Some(connection) if keep_alive && !shutting_down => {
+                    connection.busy = false;
+                    connection.idle = connection.buffer.is_empty();
+                    connection.deadline = Instant::now()
+                        + if connection.idle {
+                            self.config.keep_alive_timeout
+                        } else {
+                            self.config.read_timeout
+                        };
+                    // The next request may be buffered already, and the bytes received while the
+                    // connection was busy did not produce a new event.
+                    self.receive(token);
+                }
+                _ => self.close(token),
+            }
+        }
+    }
+
+    /// Closes the idle connections past the keep-alive timeout and answers the ones past the read
+    /// timeout with `408 Request Timeout`.
+    fn expire(&mut self) {
+        let now = Instant::now();
+        let expired: Vec<(Token, bool)> = self
+            .connections
+            .iter()
+            .filter(|(_, connection)| !connection.busy && connection.deadline <= now)
+            .map(|(token, connection)| (*token, connection.idle))
+            .collect();
+
+        for (token, idle) in expired {
+            if idle {
+                self.close(token);
+            } else {
+                self.answer(token, Response::error(StatusCode::REQUEST_TIMEOUT));
+            }
+        }
+    }
+
+    /// Sends a response from the loop and closes the connection. Only used for errors, whose
+    /// responses are small enough to fit in the buffer of the socket.
+    fn answer(&mut self, token: Token, response: Response) {
+        if let Some(mut connection) = self.connections.remove(&token) {
+            let _ = self.poll.registry().deregister(&mut connection.stream);
+            close_with(&connection.writer, response);
+        }
+    }
+
+    /// Removes the connection from the loop and runs the upgrade with a blocking clone of the
+    /// socket, starting with the bytes received after the request.
+    fn upgrade(&mut self, token: Token, upgrade: Upgrade) {
+        let Some(mut connection) = self.connections.remove(&token) else {
+            return;
+        };
+        let _ = self.poll.registry().deregister(&mut connection.stream);
+
+        let socket = &connection.writer;
+        let io = socket
+            .set_nonblocking(false)
+            .and_then(|_| socket.try_clone());
+        let registered = io.and_then(|io| Ok((io, self.upgraded.register(socket)?)));
+        match registered {
+            Ok((io, id)) => upgrade.spawn(
+                io,
+                socket,
+                connection.buffer,
+                Arc::clone(&self.upgraded),
+                id,
+            ),
+            Err(err) => eprintln!("Error upgrading the connection: {}", err),
+        }
+    }
+
+    fn close(&mut self, token: Token) {
+        if let Some(mut connection) = self.connections.remove(&token) {
+            let _ = self.poll.registry().deregister(&mut connection.stream);
+        }
+    }
+
+    /// Closes the idle connections right away and gives the busy ones up to the shutdown timeout
+    /// to send their responses.
+    fn shutdown(mut self, events: &mut Events) -> io::Result<()> {
+        self.upgraded.shutdown();
+        let waiting: Vec<Token> = self
+            .connections
+            .iter()
+            .filter(|(_, connection)| !connection.busy)
+            .map(|(token, _)| *token)
+            .collect();
+        for token in waiting {
+            self.close(token);
+        }
+
+        let deadline = Instant::now() + self.config.shutdown_timeout;
+        while !self.connections.is_empty() {
+            let now = Instant::now();
+            if now >= deadline {
+                eprintln!(
+                    "Closing {} connections after the shutdown timeout.",
+                    self.connections.len()
+                );
+                for connection in self.connections.values() {
+                    let _ = connection.stream.shutdown(Shutdown::Both);
+                }
+                self.connections.clear();
+                break;
+            }
+
+            self.wait(events, Some(deadline - now))?;
+            self.finish_responses();
+        }
+
+        self.pool.join();
+        Ok(())
+    }
+}
+
+/// Writes the response from a worker. The socket is made blocking while the response is
+/// written, so the body is sent at the pace of the client, bounded by the write timeout.
+fn respond(
+    stream: &net::TcpStream,
+    response: Response,
+    request: (Method, Version),
+    keep_alive: bool,
+) -> io::Result<()> {
+    stream.set_nonblocking(false)?;
+
+    let result = {
+        let mut writer = BufWriter::new(stream);
+        write_response(&mut writer, response, request, keep_alive).and_then(|_| writer.flush())
+    };
+
+    let restored = stream.set_nonblocking(true);
+    result.and(restored)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::config::Backend;
+    use super::super::router::Router;
+    use super::super::server::ServerBuilder;
+    use super::*;
+    use std::thread;
+
+    fn start(workers: usize) -> ServerHandle {
+        let router = Router::new()
+            .get("/slow", |_: &mut Request| {
+                thread::sleep(Duration::from_millis(300));
+                Response::new(StatusCode::OK).with_body("slow")
+            })
+            .post("/echo", |request: &mut Request| {
+                Response::new(StatusCode::OK).with_body(request.body.clone())
+            })
+            .get("/*", |request: &mut Request| {
+                Response::new(StatusCode::OK).with_body(request.path.clone())
+            });
+
+        ServerBuilder::new()
+            .port(0)
+            .workers(workers)
+            .backend(Backend::EventLoop)
+            .read_timeout(Duration::from_millis(300))
+            .build(router)
+            .unwrap()
+            .spawn()
+    }
+
+    fn connect(handle: &ServerHandle) -> net::TcpStream {
+        let stream = net::TcpStream::connect(handle.local_addr()).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+        stream
+    }
+
+    /// Reads a response with a body of known length.
+    fn read_response(stream: &mut net::TcpStream) -> String {
+        let mut response = Vec::new();
+        let mut byte = [0];
+
+        while !response.ends_with(b"\r\n\r\n") {
+            stream.read_exact(&mut byte).unwrap();
+            response.push(byte[0]);
+        }
+        let head = String::from_utf8(response).unwrap();
+        let length: usize = head
+            .lines()
+            .find_map(|line| line.strip_prefix("Content-Length: "))
+            .unwrap()
+            .parse()
+            .unwrap();
+
+        let mut body = vec![0; length];
+        stream.read_exact(&mut body).unwrap();
+        head + &String::from_utf8(body).unwrap()
+    }
+
+    #[test]
+    fn idle_connections_do_not_take_workers() {
+        let handle = start(1);
+
+        // With the thread pool, the only worker would wait for the next request of the first one.
+        let mut idle: Vec<_> = (0..5).map(|_| connect(&handle)).collect();
+        for (i, stream) in idle.iter_mut().enumerate() {
+            let request = format!("GET /{} HTTP/1.1\r\nHost: a\r\n\r\n", i);
+            stream.write_all(request.as_bytes()).unwrap();
+            assert!(read_response(stream).ends_with(&format!("\r\n\r\n/{}", i)));
+        }
+
+        // A request sent slowly does not block the others either.
+        let mut slow = connect(&handle);
+        slow.write_all(b"GET /partial HTTP/1.1\r\n").unwrap();
+
+        let start = Instant::now();
+        let mut stream = connect(&handle);
+        stream
+            .write_all(b"GET /last HTTP/1.1\r\nHost: a\r\n\r\n")
+            .unwrap();
+        assert!(read_response(&mut stream).ends_with("/last"));
+        assert!(start.elapsed() < Duration::from_millis(200));
+
+        slow.write_all(b"Host: a\r\n\r\n").unwrap();
+        assert!(read_response(&mut slow).ends_with("/partial"));
+
+        handle.shutdown();
+    }
+
+    #[test]
+    fn pipelined_requests() {
+        let handle = start(2);
+        let mut stream = connect(&handle);
+
+        stream
+            .write_all(
+                b"GET /slow HTTP/1.1\r\nHost: a\r\n\r\n\
+                  GET /second HTTP/1.1\r\nHost: a\r\n\r\n\
+                  GET /third HTTP/1.1\r\nHo",
+            )
+            .unwrap();
+        assert!(read_response(&mut stream).ends_with("slow"));
+        assert!(read_response(&mut stream).ends_with("/second"));
+
+        stream
+            .write_all(b"st: a\r\nConnection: close\r\n\r\n")
+            .unwrap();
+        let last = read_response(&mut stream);
+        assert!(last.contains("Connection: close\r\n"));
+        assert!(last.ends_with("/third"));
+
+        handle.shutdown();
+    }
+
+    #[test]
+    fn bodies_received_in_pieces() {
+        let handle = start(1);
+        let mut stream = connect(&handle);
+
+        let pieces: [&[u8]; 4] = [
+            b"POST /echo HTTP/1.1\r\nHost: a\r\nContent-Length: 11\r\n\r\nhel",
+            b"lo wo",
+            b"rld",
+            b"POST /echo HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: chunked\r\n\r\n5\r\nch",
+        ];
+        for piece in pieces {
+            stream.write_all(piece).unwrap();
+            thread::sleep(Duration::from_millis(20));
+        }
+        assert!(read_response(&mut stream).ends_with("\r\n\r\nhello world"));
+
+        for piece in [&b"unk\r"[..], b"\n6\r\ned bod\r\n0\r", b"\n\r\n"] {
+            stream.write_all(piece).unwrap();
+            thread::sleep(Duration::from_millis(20));
+        }
+        assert!(read_response(&mut stream).ends_with("\r\n\r\nchunked bod"));
+
+        handle.shutdown();
+    }
+
+    #[test]
+    fn refuses_connections_above_the_maximum() {
+        let handle = ServerBuilder::new()
+            .port(0)
+            .backend(Backend::EventLoop)
+            .max_connections(2)
+            .build(Router::new())
+            .unwrap()
+            .spawn();
+
+        let first = connect(&handle);
+        let _second = connect(&handle);
+        thread::sleep(Duration::from_millis(50));
+
+        let mut refused = connect(&handle);
+        let mut response = String::new();
+        refused.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 503 Service Unavailable\r\n"));
+
+        // Closing a connection makes room for another one.
+        drop(first);
+        thread::sleep(Duration::from_millis(50));
+        let mut stream = connect(&handle);
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nHost: a\r\n\r\n")
+            .unwrap();
+        assert!(read_response(&mut stream).starts_with("HTTP/1.1 404 Not Found\r\n"));
+
+        handle.shutdown();
+    }
+
+    #[test]
+    fn read_timeout_and_errors() {
+        let handle = start(1);
+
+        let mut partial = connect(&handle);
+        partial.write_all(b"GET / HTTP/1.1\r\nHo").unwrap();
+        let mut response = String::new();
+        partial.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 408 Request Timeout\r\n"));
+
+        // The timeout is for the whole request, not for every byte of it.
+        let mut trickled = connect(&handle);
+        trickled
+            .set_read_timeout(Some(Duration::from_millis(50)))
+            .unwrap();
+        trickled.write_all(b"GET / HTTP/1.1\r\nX-Slow: ").unwrap();
+        let mut first = [0];
+        for _ in 0..40 {
+            trickled.write_all(b"a").unwrap();
+            if trickled.read(&mut first).is_ok() {
+                break;
+            }
+        }
+        trickled.set_read_timeout(None).unwrap();
+        let mut response = String::from_utf8(first.to_vec()).unwrap();
+        trickled.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 408 Request Timeout\r\n"));
+
+        let mut malformed = connect(&handle);
+        malformed.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+        let mut response = String::new();
+        malformed.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 400 Bad Request\r\n"));
+
+        handle.shutdown();
+    }
+
+    #[test]
+    fn shutdown_finishes_in_flight_requests() {
+        let handle = start(1);
+
+        let mut busy = connect(&handle);
+        busy.write_all(b"GET /slow HTTP/1.1\r\nHost: a\r\n\r\n")
+            .unwrap();
+        let mut idle = connect(&handle);
+        thread::sleep(Duration::from_millis(100));
+
+        handle.shutdown();
+
+        let response = read_response(&mut busy);
+        assert!(response.contains("Connection: close\r\n"));
+        assert!(response.ends_with("slow"));
+        assert_eq!(idle.read(&mut [0; 16]).unwrap(), 0);
+        assert!(net::TcpStream::connect(handle.local_addr()).is_err());
+    }
+}
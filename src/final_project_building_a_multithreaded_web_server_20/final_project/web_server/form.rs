@@ -0,0 +1,845 @@
+use super::encoding::percent_decode;
+use super::request::Request;
+use super::response::{Response, StatusCode};
+use std::env;
+use std::error::Error;
+use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+pub const URLENCODED: &str = "application/x-www-form-urlencoded";
+pub const MULTIPART: &str = "multipart/form-data";
+
+/// Maximum size of the headers of a part of a multipart body.
+const MAX_PART_HEADERS: usize = 8 * 1024;
+
+/// Maximum size of the preamble before the first part of a multipart body, and of the epilogue
+/// after the last one, which are both ignored.
+const MAX_PREAMBLE: usize = 8 * 1024;
+
+/// Bytes of a multipart body read at once.
+const READ_SIZE: usize = 8 * 1024;
+
+/// Decodes a query string or an `application/x-www-form-urlencoded` body into its pairs, in
+/// order. `+` is decoded as a space, a pair without `=` has an empty value, and the pairs that
+/// are not valid UTF-8 once decoded are skipped.
+pub fn parse_urlencoded(input: &str) -> Vec<(String, String)> {
+    let decode = |component: &str| percent_decode(&component.replace('+', " "));
+
+    input
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| pair.split_once('=').unwrap_or((pair, "")))
+        .filter_map(|(name, value)| Some((decode(name)?, decode(value)?)))
+        .collect()
+}
+
+/// Errors reading the form sent in the body of a request.
+#[derive(Debug)]
+pub enum FormError {
+    /// The `Content-Type` of the request, if any, is not a form.
+    UnsupportedMediaType(Option<String>),
+    /// The body does not match its `Content-Type`.
+    Malformed(&'static str),
+    /// A text field is bigger than [`FormParser::max_field_size`].
+    FieldTooLarge(String),
+    /// A file is bigger than [`FormParser::max_file_size`].
+    FileTooLarge(String),
+    /// The form has more parts or files than allowed.
+    TooManyParts,
+    /// An uploaded file could not be written.
+    Io(io::Error),
+}
+
+impl FormError {
+    /// `415 Unsupported Media Type`, `400 Bad Request`, `413 Payload Too Large` and `500 Internal
+    /// Server Error`.
+    pub fn status(&self) -> StatusCode {
+        match self {
+            FormError::UnsupportedMediaType(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            FormError::Malformed(_) => StatusCode::BAD_REQUEST,
+            FormError::FieldTooLarge(_) | FormError::FileTooLarge(_) | FormError::TooManyParts => {
+                StatusCode::PAYLOAD_TOO_LARGE
+            }
+            FormError::Io(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+impl fmt::Display for FormError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FormError::UnsupportedMediaType(Some(content_type)) => {
+                write!(
+                    f,
+                    "expected {} or {}, got {}",
+                    URLENCODED, MULTIPART, content_type
+                )
+            }
+            FormError::UnsupportedMediaType(None) => {
+                write!(f, "expected {} or {}", URLENCODED, MULTIPART)
+            }
+            FormError::Malformed(message) => write!(f, "malformed form: {}", message),
+            FormError::FieldTooLarge(name) => write!(f, "the field '{}' is too large", name),
+            FormError::FileTooLarge(name) => write!(f, "the file of '{}' is too large", name),
+            FormError::TooManyParts => f.write_str("too many fields or files"),
+            FormError::Io(err) => write!(f, "could not store the upload: {}", err),
+        }
+    }
+}
+
+impl Error for FormError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            FormError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for FormError {
+    fn from(err: io::Error) -> Self {
+        FormError::Io(err)
+    }
+}
+
+impl From<FormError> for Response {
+    fn from(err: FormError) -> Response {
+        let response = Response::error(err.status());
+        match err {
+            FormError::UnsupportedMediaType(_) => {
+                response.with_header("Accept", format!("{}, {}", URLENCODED, MULTIPART))
+            }
+            _ => response,
+        }
+    }
+}
+
+/// File of a multipart form, stored in a temporary file which is deleted when the value is
+/// dropped, unless it is moved somewhere else with [`UploadedFile::persist`].
+#[derive(Debug)]
+pub struct UploadedFile {
+    /// Name of the field of the form.
+    pub name: String,
+    /// Name of the file on the computer of the client. It must not be trusted as a path.
+    pub filename: Option<String>,
+    pub content_type: Option<String>,
+    pub size: u64,
+    /// `None` once the file has been persisted.
+    path: Option<PathBuf>,
+}
+
+impl UploadedFile {
+    /// Path of the temporary file.
+    pub fn path(&self) -> &Path {
+        self.path.as_deref().unwrap_or_else(|| Path::new(""))
+    }
+
+    pub fn open(&self) -> io::Result<File> {
+        File::open(self.path())
+    }
+
+    /// Moves the temporary file to the destination, replacing the file that may be there.
+    pub fn persist(mut self, destination: impl AsRef<Path>) -> io::Result<()> {
+        let destination = destination.as_ref();
+        let path = self.path.take().unwrap_or_default();
+
+        // Renaming fails across file systems, where the file is copied instead.
+        if fs::rename(&path, destination).is_err() {
+            let copied = fs::copy(&path, destination);
+            let _ = fs::remove_file(&path);
+            copied?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for UploadedFile {
+    fn drop(&mut self) {
+        if let Some(path) = &self.path {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+/// Fields and files of a form.
+#[derive(Debug, Default)]
+pub struct Form {
+    fields: Vec<(String, String)>,
+    files: Vec<UploadedFile>,
+}
+
+impl Form {
+    /// Value of the first field with the name.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.fields
+            .iter()
+            .find(|(field, _)| field == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Values of all the fields with the name, like the options of a multiple select.
+    pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.fields
+            .iter()
+            .filter(move |(field, _)| field == name)
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// All the text fields, in order.
+    pub fn fields(&self) -> &[(String, String)] {
+        &self.fields
+    }
+
+    /// First file uploaded with the field name.
+    pub fn file(&self, name: &str) -> Option<&UploadedFile> {
+        self.files.iter().find(|file| file.name == name)
+    }
+
+    pub fn files(&self) -> &[UploadedFile] {
+        &self.files
+    }
+
+    /// Takes the files out of the form, for example to persist them.
+    pub fn into_files(self) -> Vec<UploadedFile> {
+        self.files
+    }
+}
+
+/// Parser of the forms sent in the body of the requests, as `application/x-www-form-urlencoded`
+/// or `multipart/form-data`.
+///
+/// The files of a multipart form are written to temporary files in chunks as they are parsed, so
+/// the form does not keep them in memory. The body is read with [`Request::body_reader`], which
+/// streams it from the connection when the server did not read it before calling the handler.
+/// Each text field and each file has its own size limit. [`FormParser::parse_multipart`] parses
+/// a body read from any stream.
+///
+/// # Example
+///
+/// ```rust
+/// Router::new().post("/upload", |request: &mut Request| {
+///     let form = match FormParser::new().max_file_size(1024 * 1024).parse(request) {
+///         Ok(form) => form,
+///         Err(err) => return err.into(),
+///     };
+///     match form.file("avatar") {
+///         Some(file) => Response::html(format!("Received {} bytes", file.size)),
+///         None => Response::error(StatusCode::BAD_REQUEST),
+///     }
+/// })
+/// ```
+#[derive(Debug, Clone)]
+pub struct FormParser {
+    max_field_size: usize,
+    max_file_size: u64,
+    max_parts: usize,
+    max_files: usize,
+    temp_dir: PathBuf,
+}
+
+impl Default for FormParser {
+    fn default() -> Self {
+        FormParser {
+            max_field_size: 64 * 1024,
+            max_file_size: 10 * 1024 * 1024,
+            max_parts: 100,
+            max_files: 10,
+            temp_dir: env::temp_dir(),
+        }
+    }
+}
+
+impl FormParser {
+    pub fn new() -> FormParser {
+        FormParser::default()
+    }
+
+    /// Maximum size of a text field of a multipart form. 64 KiB by default.
+    pub fn max_field_size(mut self, size: usize) -> FormParser {
+        self.max_field_size = size;
+        self
+    }
+
+    /// Maximum size of each file. 10 MiB by default.
+    pub fn max_file_size(mut self, size: u64) -> FormParser {
+        self.max_file_size = size;
+        self
+    }
+
+    /// Maximum number of fields and files. 100 by default.
+    pub fn max_parts(mut self, max: usize) -> FormParser {
+        self.max_parts = max;
+        self
+    }
+
+    /// Maximum number of files. 10 by default.
+    pub fn max_files(mut self, max: usize) -> FormParser {
+        self.max_files = max;
+        self
+    }
+
+    /// Directory of the temporary files, the one of the system by default.
+    pub fn temp_dir(mut self, dir: impl Into<PathBuf>) -> FormParser {
+        self.temp_dir = dir.into();
+        self
+    }
+
+    /// Parses the form in the body of the request, according to its `Content-Type`.
+    pub fn parse(&self, request: &Request) -> Result<Form, FormError> {
+        let content_type = request.header("Content-Type");
+        let media_type = content_type
+            .and_then(|value| value.split(';').next())
+            .map(|media_type| media_type.trim().to_ascii_lowercase());
+
+        match media_type.as_deref() {
+            Some(URLENCODED) => {
+                let body = std::str::from_utf8(&request.body)
+                    .map_err(|_| FormError::Malformed("invalid UTF-8"))?;
+                let fields = parse_urlencoded(body);
+                if fields.len() > self.max_parts {
+                    return Err(FormError::TooManyParts);
+                }
+                Ok(Form {
+                    fields,
+                    files: Vec::new(),
+                })
+            }
+            Some(MULTIPART) => {
+                let boundary = content_type
+                    .and_then(|value| parameter(value, "boundary"))
+                    .filter(|boundary| (1..=70).contains(&boundary.len()))
+                    .ok_or(FormError::Malformed("missing boundary"))?;
+                self.parse_multipart(request.body_reader(), &boundary)
+            }
+            _ => Err(FormError::UnsupportedMediaType(
+                content_type.map(String::from),
+            )),
+        }
+    }
+
+    /// Parses a `multipart/form-data` body read from the reader.
+    pub fn parse_multipart<R: Read>(&self, reader: R, boundary: &str) -> Result<Form, FormError> {
+        // The first delimiter does not need the line break before it, so one is added.
+        let mut multipart = Multipart {
+            reader: io::Cursor::new(&b"\r\n"[..]).chain(reader),
+            buffer: Vec::new(),
+            eof: false,
+        };
+        let delimiter = format!("\r\n--{}", boundary).into_bytes();
+        let mut form = Form::default();
+
+        // Everything before the first delimiter is a preamble that is ignored.
+        let mut preamble = 0;
+        let found = multipart.read_until(&delimiter, &mut |bytes| {
+            preamble += bytes.len();
+            if preamble > MAX_PREAMBLE {
+                return Err(FormError::Malformed("preamble too large"));
+            }
+            Ok(())
+        })?;
+        if !found {
+            return Err(FormError::Malformed("missing delimiter"));
+        }
+
+        loop {
+            // A delimiter followed by `--` ends the body, otherwise by a line break.
+            multipart.fill(2)?;
+            if multipart.buffer.starts_with(b"--") {
+                multipart.skip_epilogue()?;
+                return Ok(form);
+            }
+            let mut headers = Vec::new();
+            let found = multipart.read_until(b"\r\n\r\n", &mut |bytes| {
+                headers.extend_from_slice(bytes);
+                if headers.len() > MAX_PART_HEADERS {
+                    return Err(FormError::Malformed("part headers too large"));
+                }
+                Ok(())
+            })?;
+            if !found || !headers.starts_with(b"\r\n") && !headers.is_empty() {
+                return Err(FormError::Malformed("invalid part"));
+            }
+
+            if form.fields.len() + form.files.len() == self.max_parts {
+                return Err(FormError::TooManyParts);
+            }
+            let part = Part::parse(&headers)?;
+            match part.filename {
+                Some(filename) => {
+                    if form.files.len() == self.max_files {
+                        return Err(FormError::TooManyParts);
+                    }
+                    let mut file =
+                        self.write_file(&mut multipart, &delimiter, part.name, filename)?;
+                    file.content_type = part.content_type;
+                    form.files.push(file);
+                }
+                None => {
+                    let mut value = Vec::new();
+                    let found = multipart.read_until(&delimiter, &mut |bytes| {
+                        value.extend_from_slice(bytes);
+                        if value.len() > self.max_field_size {
+                            return Err(FormError::FieldTooLarge(part.name.clone()));
+                        }
+                        Ok(())
+                    })?;
+                    if !found {
+                        return Err(FormError::Malformed("missing delimiter"));
+                    }
+                    let value = String::from_utf8(value)
+                        .map_err(|_| FormError::Malformed("invalid UTF-8"))?;
+                    form.fields.push((part.name, value));
+                }
+            }
+        }
+    }
+
+    /// Writes the contents of the part to a new temporary file, until the delimiter.
+    fn write_file<R: Read>(
+        &self,
+        multipart: &mut Multipart<R>,
+        delimiter: &[u8],
+        name: String,
+        filename: String,
+    ) -> Result<UploadedFile, FormError> {
+        let (path, file) = temp_file(&self.temp_dir)?;
+        // Removes the file if anything fails.
+        let mut upload = UploadedFile {
+            name,
+            filename: Some(filename).filter(|filename| !filename.is_empty()),
+            content_type: None,
+            size: 0,
+            path: Some(path),
+        };
+
+        let mut writer = io::BufWriter::new(file);
+        let found = multipart.read_until(delimiter, &mut |bytes| {
+            upload.size += bytes.len() as u64;
+            if upload.size > self.max_file_size {
+                return Err(FormError::FileTooLarge(upload.name.clone()));
+            }
+            writer.write_all(bytes).map_err(FormError::Io)
+        })?;
+        if !found {
+            return Err(FormError::Malformed("missing delimiter"));
+        }
+        writer.flush()?;
+
+        Ok(upload)
+    }
+}
+
+/// Creates a new file with an unique name in the directory.
+fn temp_file(dir: &Path) -> io::Result<(PathBuf, File)> {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+    loop {
+        let count = COUNTER.fetch_add(1, Ordering::Relaxed);
+        let path = dir.join(format!("upload-{}-{}.tmp", process::id(), count));
+        match OpenOptions::new().write(true).create_new(true).open(&path) {
+            Ok(file) => return Ok((path, file)),
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(err) => return Err(err),
+        }
+    }
+}
+
+/// Multipart body read in chunks, split at the delimiters.
+struct Multipart<R> {
+    reader: R,
+    /// Bytes read but not consumed yet.
+    buffer: Vec<u8>,
+    eof: bool,
+}
+
+impl<R: Read> Multipart<R> {
+    /// Reads until the buffer has at least `len` bytes, or the reader is at EOF.
+    fn fill(&mut self, len: usize) -> io::Result<()> {
+        let mut chunk = [0; READ_SIZE];
+        while self.buffer.len() < len && !self.eof {
+            match self.reader.read(&mut chunk) {
+                Ok(0) => self.eof = true,
+                Ok(read) => self.buffer.extend_from_slice(&chunk[..read]),
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads the epilogue after the last delimiter up to the end of the body, so a connection
+    /// the body was streamed from can serve the next request.
+    fn skip_epilogue(&mut self) -> Result<(), FormError> {
+        self.buffer.drain(..2);
+        let mut epilogue = 0;
+
+        loop {
+            self.fill(READ_SIZE)?;
+            epilogue += self.buffer.len();
+            if epilogue > MAX_PREAMBLE {
+                return Err(FormError::Malformed("epilogue too large"));
+            }
+            if self.eof {
+                return Ok(());
+            }
+            self.buffer.clear();
+        }
+    }
+
+    /// Passes the bytes before the delimiter to `output`, in chunks, and consumes the delimiter.
+    /// Returns `false` if the body ends without it.
+    fn read_until(
+        &mut self,
+        delimiter: &[u8],
+        output: &mut dyn FnMut(&[u8]) -> Result<(), FormError>,
+    ) -> Result<bool, FormError> {
+        loop {
+            self.fill(delimiter.len() + READ_SIZE)?;
+
+            if let Some(start) = find(&self.buffer, delimiter) {
+                output(&self.buffer[..start])?;
+                self.buffer.drain(..start + delimiter.len());
+                return Ok(true);
+            }
+            if self.eof {
+                return Ok(false);
+            }
+            // The end of the buffer may be the start of the delimiter, so it is kept.
+            let safe = self.buffer.len() + 1 - delimiter.len();
+            output(&self.buffer[..safe])?;
+            self.buffer.drain(..safe);
+        }
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+/// Headers of a part of a multipart body.
+#[derive(Debug, PartialEq, Eq)]
+struct Part {
+    name: String,
+    filename: Option<String>,
+    content_type: Option<String>,
+}
+
+impl Part {
+    fn parse(headers: &[u8]) -> Result<Part, FormError> {
+        let headers =
+            std::str::from_utf8(headers).map_err(|_| FormError::Malformed("invalid header"))?;
+        let mut disposition = None;
+        let mut content_type = None;
+
+        for line in headers.split("\r\n").filter(|line| !line.is_empty()) {
+            let (name, value) = line
+                .split_once(':')
+                .ok_or(FormError::Malformed("invalid header"))?;
+            if name.trim().eq_ignore_ascii_case("Content-Disposition") {
+                disposition = Some(value.trim());
+            } else if name.trim().eq_ignore_ascii_case("Content-Type") {
+                content_type = Some(value.trim().to_string());
+            }
+        }
+
+        let disposition = disposition
+            .filter(|value| {
+                let kind = value.split(';').next().unwrap_or_default();
+                kind.trim().eq_ignore_ascii_case("form-data")
+            })
+            .ok_or(FormError::Malformed("missing Content-Disposition"))?;
+        Ok(Part {
+            name: parameter(disposition, "name").ok_or(FormError::Malformed("missing name"))?,
+            filename: parameter(disposition, "filename"),
+            content_type,
+        })
+    }
+}
+
+/// Value of a parameter of a header like `form-data; name="field"`, unquoted.
+fn parameter(header: &str, name: &str) -> Option<String> {
+    let mut rest = header.split_once(';')?.1;
+
+    while !rest.is_empty() {
+        let (key, after) = rest.split_once('=')?;
+        let after = after.trim_start();
+        let (value, next) = match after.strip_prefix('"') {
+            // Quoted strings can contain `;` and escaped quotes.
+            Some(quoted) => {
+                let mut value = String::new();
+                let mut chars = quoted.char_indices();
+                let end = loop {
+                    match chars.next()? {
+                        (i, '"') => break i,
+                        (_, '\\') => value.push(chars.next()?.1),
+                        (_, c) => value.push(c),
+                    }
+                };
+                let next = quoted[end + 1..]
+                    .split_once(';')
+                    .map_or("", |(_, next)| next);
+                (value, next)
+            }
+            None => match after.split_once(';') {
+                Some((value, next)) => (value.trim().to_string(), next),
+                None => (after.trim().to_string(), ""),
+            },
+        };
+
+        if key.trim().eq_ignore_ascii_case(name) {
+            return Some(value);
+        }
+        rest = next;
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::request::Method;
+    use super::super::router::Router;
+    use super::super::server::ServerBuilder;
+    use super::super::testing::TestServer;
+    use super::*;
+
+    fn multipart(body: &str) -> Request {
+        Request::new(Method::Post, "/")
+            .with_header("Content-Type", "multipart/form-data; boundary=XyZ")
+            .with_body(body.replace('\n', "\r\n"))
+    }
+
+    const BODY: &str = "preamble\n\
+        --XyZ\n\
+        Content-Disposition: form-data; name=\"title\"\n\
+        \n\
+        Hello, world!\n\
+        --XyZ\n\
+        Content-Disposition: form-data; name=\"tags\"\n\
+        \n\
+        a\n\
+        --XyZ\n\
+        Content-Disposition: form-data; name=\"file\"; filename=\"notes; v2.txt\"\n\
+        Content-Type: text/plain\n\
+        \n\
+        line 1\n\
+        line 2 --XyZ\n\
+        --XyZ\n\
+        Content-Disposition: form-data; name=\"tags\"\n\
+        \n\
+        b\n\
+        --XyZ--\n\
+        epilogue";
+
+    #[test]
+    fn urlencoded() {
+        assert_eq!(
+            parse_urlencoded("a=1&b=x+y%21&&flag&c=%E2%82%AC&bad=%FF"),
+            [
+                (String::from("a"), String::from("1")),
+                (String::from("b"), String::from("x y!")),
+                (String::from("flag"), String::new()),
+                (String::from("c"), String::from("€")),
+            ]
+        );
+
+        let request = Request::new(Method::Post, "/")
+            .with_header(
+                "Content-Type",
+                "application/x-www-form-urlencoded; charset=utf-8",
+            )
+            .with_body("name=J%C3%BCrgen&lang=rust&lang=c");
+        let form = FormParser::new().parse(&request).unwrap();
+        assert_eq!(form.get("name"), Some("Jürgen"));
+        assert_eq!(form.get_all("lang").collect::<Vec<_>>(), ["rust", "c"]);
+        assert_eq!(form.get("missing"), None);
+    }
+
+    #[test]
+    fn multipart_form() {
+        let form = FormParser::new().parse(&multipart(BODY)).unwrap();
+        assert_eq!(form.get("title"), Some("Hello, world!"));
+        assert_eq!(form.get_all("tags").collect::<Vec<_>>(), ["a", "b"]);
+
+        let file = form.file("file").unwrap();
+        assert_eq!(file.filename.as_deref(), Some("notes; v2.txt"));
+        assert_eq!(file.content_type.as_deref(), Some("text/plain"));
+        assert_eq!(file.size, 20);
+        let path = file.path().to_path_buf();
+        assert_eq!(fs::read(&path).unwrap(), b"line 1\r\nline 2 --XyZ");
+
+        drop(form);
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn small_reads() {
+        /// Reader returning one byte at a time, so the delimiters are split between reads.
+        struct Trickle<'a>(&'a [u8]);
+
+        impl Read for Trickle<'_> {
+            fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+                let len = self.0.len().min(buf.len()).min(1);
+                buf[..len].copy_from_slice(&self.0[..len]);
+                self.0 = &self.0[len..];
+                Ok(len)
+            }
+        }
+
+        let body = BODY.replace('\n', "\r\n");
+        let form = FormParser::new()
+            .parse_multipart(Trickle(body.as_bytes()), "XyZ")
+            .unwrap();
+        assert_eq!(form.get("title"), Some("Hello, world!"));
+        assert_eq!(form.file("file").unwrap().size, 20);
+    }
+
+    #[test]
+    fn persist() {
+        let form = FormParser::new().parse(&multipart(BODY)).unwrap();
+        let destination = env::temp_dir().join(format!("persisted-{}.txt", process::id()));
+
+        let file = form.into_files().pop().unwrap();
+        let path = file.path().to_path_buf();
+        file.persist(&destination).unwrap();
+        assert!(!path.exists());
+        assert_eq!(fs::read(&destination).unwrap(), b"line 1\r\nline 2 --XyZ");
+        fs::remove_file(destination).unwrap();
+    }
+
+    #[test]
+    fn limits() {
+        let status = |parser: FormParser| parser.parse(&multipart(BODY)).unwrap_err().status();
+
+        assert_eq!(
+            status(FormParser::new().max_file_size(19)),
+            StatusCode::PAYLOAD_TOO_LARGE
+        );
+        assert_eq!(
+            status(FormParser::new().max_field_size(12)),
+            StatusCode::PAYLOAD_TOO_LARGE
+        );
+        assert_eq!(
+            status(FormParser::new().max_parts(3)),
+            StatusCode::PAYLOAD_TOO_LARGE
+        );
+        assert_eq!(
+            status(FormParser::new().max_files(0)),
+            StatusCode::PAYLOAD_TOO_LARGE
+        );
+        assert!(FormParser::new()
+            .max_file_size(20)
+            .parse(&multipart(BODY))
+            .is_ok());
+    }
+
+    #[test]
+    fn errors() {
+        let parse = |request: &Request| FormParser::new().parse(request).unwrap_err();
+
+        let request = Request::new(Method::Post, "/").with_header("Content-Type", "text/plain");
+        assert_eq!(parse(&request).status(), StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        let response: Response = parse(&request).into();
+        assert!(response.headers.get("Accept").unwrap().contains(MULTIPART));
+
+        let request = multipart(BODY).with_header("Content-Type", MULTIPART);
+        assert!(matches!(
+            parse(&request),
+            FormError::Malformed("missing boundary")
+        ));
+        let truncated = &BODY[..BODY.find("line 2").unwrap()];
+        assert!(matches!(
+            parse(&multipart(truncated)),
+            FormError::Malformed("missing delimiter")
+        ));
+        let no_name = "--XyZ\nContent-Disposition: form-data\n\nx\n--XyZ--\n";
+        assert_eq!(parse(&multipart(no_name)).status(), StatusCode::BAD_REQUEST);
+
+        let filler = "x".repeat(MAX_PREAMBLE + 1);
+        assert!(matches!(
+            parse(&multipart(&(filler.clone() + BODY))),
+            FormError::Malformed("preamble too large")
+        ));
+        assert!(matches!(
+            parse(&multipart(&(BODY.to_string() + &filler))),
+            FormError::Malformed("epilogue too large")
+        ));
+    }
+
+    #[test]
+    fn streamed_upload() {
+        let destination = env::temp_dir().join(format!("streamed-{}.bin", process::id()));
+        let target = destination.clone();
+        let router = Router::new()
+            .post("/upload", move |request: &mut Request| {
+                let form = match request.form() {
+                    Ok(form) => form,
+                    Err(err) => return err.into(),
+                };
+                let file = form.into_files().pop().unwrap();
+                let size = file.size;
+                match file.persist(&target) {
+                    Ok(()) => Response::new(StatusCode::OK).with_body(size.to_string()),
+                    Err(_) => Response::error(StatusCode::INTERNAL_SERVER_ERROR),
+                }
+            })
+            .post("/echo", |request: &mut Request| {
+                Response::new(StatusCode::OK).with_body(request.body.clone())
+            });
+        let builder = ServerBuilder::new()
+            .max_body_size(1024)
+            .max_upload_size(512 * 1024);
+        let server = TestServer::with_builder(builder, router);
+
+        let content = vec![b'x'; 256 * 1024];
+        let mut body = b"--XyZ\r\n\
+            Content-Disposition: form-data; name=\"file\"; filename=\"big.bin\"\r\n\r\n"
+            .to_vec();
+        body.extend_from_slice(&content);
+        body.extend_from_slice(b"\r\n--XyZ--\r\n");
+        let upload = |body: Vec<u8>| {
+            Request::new(Method::Post, "/upload")
+                .with_header("Content-Type", "multipart/form-data; boundary=XyZ")
+                .with_body(body)
+        };
+
+        let mut client = server.client().unwrap();
+        let response = client.send(&upload(body)).unwrap();
+        assert_eq!(response.text(), content.len().to_string());
+        assert_eq!(fs::read(&destination).unwrap(), content);
+        fs::remove_file(&destination).unwrap();
+
+        // The body was read up to its end, so the connection can be used again, while the
+        // other bodies are still limited.
+        let response = client
+            .send(&Request::new(Method::Post, "/echo").with_body(vec![b'x'; 2048]))
+            .unwrap();
+        assert_eq!(response.status, StatusCode::PAYLOAD_TOO_LARGE);
+
+        // The uploads have their own limit, checked before the handler is called.
+        let response = server
+            .client()
+            .unwrap()
+            .send(&upload(vec![b'x'; 1024 * 1024]))
+            .unwrap();
+        assert_eq!(response.status, StatusCode::PAYLOAD_TOO_LARGE);
+        assert!(!destination.exists());
+    }
+
+    #[test]
+    fn parameters() {
+        let header = "form-data; name=\"a \\\"b\\\"\"; filename=c.txt";
+        assert_eq!(parameter(header, "name").as_deref(), Some("a \"b\""));
+        assert_eq!(parameter(header, "filename").as_deref(), Some("c.txt"));
+        assert_eq!(parameter(header, "other"), None);
+        assert_eq!(
+            parameter("multipart/form-data; BOUNDARY=abc", "boundary").as_deref(),
+            Some("abc")
+        );
+    }
+}
@@ -0,0 +1,139 @@
+use std::fmt;
+
+/// Ordered list of header fields. Header names are compared case-insensitively, as required by
+/// the HTTP specification, but the original casing is preserved when the headers are written back
+/// to a stream. The same name can appear multiple times (e.g. `Set-Cookie`).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Headers {
+    entries: Vec<(String, String)>,
+}
+
+impl Headers {
+    /// Creates an empty collection of headers.
+    pub fn new() -> Headers {
+        Headers {
+            entries: Vec::new(),
+        }
+    }
+
+    /// Returns the value of the first header with the given name.
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.entries
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Returns the values of all the headers with the given name, in the order they were added.
+    pub fn get_all<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a str> + 'a {
+        self.entries
+            .iter()
+            .filter(move |(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// Checks if a header with the given name exists.
+    pub fn contains(&self, name: &str) -> bool {
+        self.get(name).is_some()
+    }
+
+    /// Checks if any of the comma separated values of the header contains the given token,
+    /// ignoring case. Useful for headers like `Connection: keep-alive, Upgrade`.
+    pub fn contains_token(&self, name: &str, token: &str) -> bool {
+        self.get_all(name)
+            .flat_map(|value| value.split(','))
+            .any(|value| value.trim().eq_ignore_ascii_case(token))
+    }
+
+    /// Sets the header to the given value, replacing all the previous values with the same name.
+    pub fn insert(&mut self, name: impl Into<String>, value: impl Into<String>) {
+        let name = name.into();
+        self.remove(&name);
+        self.entries.push((name, value.into()));
+    }
+
+    /// Adds a new value for the header without removing the previous ones.
+    pub fn append(&mut self, name: impl Into<String>, value: impl Into<String>) {
+        self.entries.push((name.into(), value.into()));
+    }
+
+    /// Removes all the headers with the given name.
+    pub fn remove(&mut self, name: &str) {
+        self.entries
+            .retain(|(key, _)| !key.eq_ignore_ascii_case(name));
+    }
+
+    /// Iterates over all the `(name, value)` pairs.
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.entries
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+impl fmt::Display for Headers {
+    /// Formats the headers as they are sent over the wire, each one followed by `\r\n`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (name, value) in &self.entries {
+            write!(f, "{}: {}\r\n", name, value)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn case_insensitive_lookup() {
+        let mut headers = Headers::new();
+        headers.insert("Content-Type", "text/html");
+
+        assert_eq!(headers.get("content-type"), Some("text/html"));
+        assert_eq!(headers.get("CONTENT-TYPE"), Some("text/html"));
+        assert_eq!(headers.get("Content-Length"), None);
+    }
+
+    #[test]
+    fn insert_replaces_and_append_keeps() {
+        let mut headers = Headers::new();
+        headers.append("Set-Cookie", "a=1");
+        headers.append("set-cookie", "b=2");
+        assert_eq!(
+            headers.get_all("Set-Cookie").collect::<Vec<_>>(),
+            ["a=1", "b=2"]
+        );
+
+        headers.insert("SET-COOKIE", "c=3");
+        assert_eq!(headers.get_all("Set-Cookie").collect::<Vec<_>>(), ["c=3"]);
+        assert_eq!(headers.len(), 1);
+    }
+
+    #[test]
+    fn tokens() {
+        let mut headers = Headers::new();
+        headers.insert("Connection", "keep-alive, Upgrade");
+
+        assert!(headers.contains_token("connection", "upgrade"));
+        assert!(headers.contains_token("connection", "Keep-Alive"));
+        assert!(!headers.contains_token("connection", "close"));
+    }
+
+    #[test]
+    fn display() {
+        let mut headers = Headers::new();
+        headers.insert("Host", "localhost");
+        headers.insert("Accept", "*/*");
+
+        assert_eq!(headers.to_string(), "Host: localhost\r\nAccept: */*\r\n");
+    }
+}
@@ -0,0 +1,265 @@
+use super::request::Request;
+use super::response::{Response, StatusCode};
+use super::router::Handler;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::error::Category;
+use std::error::Error;
+use std::fmt;
+use std::marker::PhantomData;
+
+pub const CONTENT_TYPE: &str = "application/json";
+
+/// Value sent as the JSON body of a `200 OK` response:
+///
+/// ```rust
+/// Router::new().get("/api/version", |_: &mut Request| Json("1.0").into())
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Json<T>(pub T);
+
+impl<T: Serialize> From<Json<T>> for Response {
+    fn from(json: Json<T>) -> Response {
+        response(StatusCode::OK, &json.0)
+    }
+}
+
+/// Creates a response with the value serialized as its JSON body. Values that cannot be
+/// serialized, such as maps with non-string keys, give `500 Internal Server Error`.
+pub fn response<T: Serialize + ?Sized>(status: StatusCode, value: &T) -> Response {
+    match serde_json::to_vec(value) {
+        Ok(body) => Response::new(status)
+            .with_header("Content-Type", CONTENT_TYPE)
+            .with_body(body),
+        Err(err) => error(StatusCode::INTERNAL_SERVER_ERROR, err),
+    }
+}
+
+/// Creates an error response with the body `{"error": "<message>"}`.
+pub fn error(status: StatusCode, message: impl fmt::Display) -> Response {
+    let body = serde_json::json!({ "error": message.to_string() });
+    Response::new(status)
+        .with_header("Content-Type", CONTENT_TYPE)
+        .with_body(body.to_string())
+}
+
+/// Errors reading the JSON body of a request.
+#[derive(Debug)]
+pub enum JsonError {
+    /// The `Content-Type` of the request, if any, is not JSON.
+    UnsupportedMediaType(Option<String>),
+    /// The body is not valid JSON, or it is truncated.
+    Syntax(serde_json::Error),
+    /// The body is valid JSON, but it does not match the expected type.
+    Data(serde_json::Error),
+}
+
+impl JsonError {
+    /// `415 Unsupported Media Type`, `400 Bad Request` and `422 Unprocessable Entity`
+    /// respectively.
+    pub fn status(&self) -> StatusCode {
+        match self {
+            JsonError::UnsupportedMediaType(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            JsonError::Syntax(_) => StatusCode::BAD_REQUEST,
+            JsonError::Data(_) => StatusCode::UNPROCESSABLE_ENTITY,
+        }
+    }
+}
+
+impl fmt::Display for JsonError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            JsonError::UnsupportedMediaType(Some(content_type)) => {
+                write!(f, "expected {}, got {}", CONTENT_TYPE, content_type)
+            }
+            JsonError::UnsupportedMediaType(None) => write!(f, "expected {}", CONTENT_TYPE),
+            JsonError::Syntax(err) => write!(f, "invalid JSON: {}", err),
+            JsonError::Data(err) => write!(f, "invalid value: {}", err),
+        }
+    }
+}
+
+impl Error for JsonError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            JsonError::UnsupportedMediaType(_) => None,
+            JsonError::Syntax(err) | JsonError::Data(err) => Some(err),
+        }
+    }
+}
+
+impl From<JsonError> for Response {
+    fn from(err: JsonError) -> Response {
+        let response = error(err.status(), &err);
+        match err {
+            JsonError::UnsupportedMediaType(_) => response.with_header("Accept", CONTENT_TYPE),
+            _ => response,
+        }
+    }
+}
+
+/// Returns `true` for `application/json` and the `+json` media types, whatever their
+/// parameters.
+pub fn is_json(content_type: &str) -> bool {
+    let media_type = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+
+    media_type == CONTENT_TYPE
+        || media_type.starts_with("application/") && media_type.ends_with("+json")
+}
+
+/// Deserializes the JSON body of the request. The request must be sent with a JSON
+/// `Content-Type`, see [`is_json`].
+pub fn parse<T: DeserializeOwned>(request: &Request) -> Result<T, JsonError> {
+    match request.header("Content-Type") {
+        Some(content_type) if is_json(content_type) => {}
+        content_type => {
+            return Err(JsonError::UnsupportedMediaType(
+                content_type.map(String::from),
+            ))
+        }
+    }
+
+    serde_json::from_slice(&request.body).map_err(|err| match err.classify() {
+        Category::Data => JsonError::Data(err),
+        Category::Io | Category::Syntax | Category::Eof => JsonError::Syntax(err),
+    })
+}
+
+/// Handler created by [`handler`].
+pub struct JsonHandler<F, I, O> {
+    function: F,
+    types: PhantomData<fn(I) -> O>,
+}
+
+/// Creates a handler that deserializes the JSON body of the requests into `I`, and serializes
+/// the `O` returned by the function as the JSON body of a `200 OK` response. The function can
+/// return any other response as an error, for example with [`error`]:
+///
+/// ```rust
+/// #[derive(Deserialize)]
+/// struct Sum { a: i64, b: i64 }
+///
+/// Router::new().post("/api/sum", json::handler(|sum: Sum, _: &mut Request| {
+///     sum.a.checked_add(sum.b)
+///         .ok_or_else(|| json::error(StatusCode::UNPROCESSABLE_ENTITY, "overflow"))
+/// }))
+/// ```
+pub fn handler<F, I, O>(function: F) -> JsonHandler<F, I, O>
+where
+    F: Fn(I, &mut Request) -> Result<O, Response>,
+{
+    JsonHandler {
+        function,
+        types: PhantomData,
+    }
+}
+
+impl<F, I, O> Handler for JsonHandler<F, I, O>
+where
+    F: Fn(I, &mut Request) -> Result<O, Response> + Send + Sync + 'static,
+    I: DeserializeOwned + 'static,
+    O: Serialize + 'static,
+{
+    fn handle(&self, request: &mut Request) -> Response {
+        let input = match parse(request) {
+            Ok(input) => input,
+            Err(err) => return err.into(),
+        };
+
+        match (self.function)(input, request) {
+            Ok(output) => Json(output).into(),
+            Err(response) => response,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::request::Method;
+    use super::*;
+    use serde::Deserialize;
+
+    #[derive(Debug, Deserialize, Serialize, PartialEq)]
+    struct Sum {
+        a: i64,
+        b: i64,
+    }
+
+    fn sum_handler() -> impl Handler {
+        handler(|sum: Sum, _: &mut Request| {
+            sum.a
+                .checked_add(sum.b)
+                .ok_or_else(|| error(StatusCode::UNPROCESSABLE_ENTITY, "overflow"))
+        })
+    }
+
+    fn post(content_type: Option<&str>, body: &str) -> Response {
+        let mut request = Request::new(Method::Post, "/api/sum").with_body(body);
+        if let Some(content_type) = content_type {
+            request = request.with_header("Content-Type", content_type);
+        }
+        sum_handler().handle(&mut request)
+    }
+
+    fn body(response: Response) -> serde_json::Value {
+        serde_json::from_slice(&response.body.into_bytes().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn json_handler() {
+        let response = post(Some("application/json"), r#"{"a": 1, "b": 2}"#);
+        assert_eq!(response.status, StatusCode::OK);
+        assert_eq!(response.headers.get("Content-Type"), Some(CONTENT_TYPE));
+        assert_eq!(body(response), serde_json::json!(3));
+
+        let response = post(
+            Some("application/json"),
+            r#"{"a": 9223372036854775807, "b": 1}"#,
+        );
+        assert_eq!(response.status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(body(response), serde_json::json!({"error": "overflow"}));
+    }
+
+    #[test]
+    fn errors() {
+        let response = post(None, r#"{"a": 1, "b": 2}"#);
+        assert_eq!(response.status, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+        assert_eq!(response.headers.get("Accept"), Some(CONTENT_TYPE));
+        assert_eq!(
+            post(Some("text/plain"), "{}").status,
+            StatusCode::UNSUPPORTED_MEDIA_TYPE
+        );
+
+        let response = post(Some("application/json"), r#"{"a": 1,"#);
+        assert_eq!(response.status, StatusCode::BAD_REQUEST);
+        assert!(body(response)["error"]
+            .as_str()
+            .unwrap()
+            .starts_with("invalid JSON"));
+
+        let response = post(Some("application/json"), r#"{"a": "1", "b": 2}"#);
+        assert_eq!(response.status, StatusCode::UNPROCESSABLE_ENTITY);
+        let response = post(Some("application/json"), r#"{"a": 1}"#);
+        assert_eq!(response.status, StatusCode::UNPROCESSABLE_ENTITY);
+    }
+
+    #[test]
+    fn json_content_types() {
+        assert!(is_json("application/json"));
+        assert!(is_json("Application/JSON; charset=utf-8"));
+        assert!(is_json("application/problem+json"));
+        assert!(!is_json("text/json+html"));
+        assert!(!is_json("text/plain"));
+    }
+
+    #[test]
+    fn serialize() {
+        let response: Response = Json(Sum { a: 1, b: 2 }).into();
+        assert_eq!(body(response), serde_json::json!({"a": 1, "b": 2}));
+    }
+}
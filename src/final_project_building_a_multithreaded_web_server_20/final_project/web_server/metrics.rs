@@ -0,0 +1,318 @@
+use super::middleware::{Middleware, Next};
+use super::request::{Method, Request};
+use super::response::{Response, StatusCode};
+use super::router::Handler;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// Upper bounds, in seconds, of the buckets of the request duration histogram.
+pub const DURATION_BUCKETS: [f64; 11] = [
+    0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0,
+];
+
+/// Histogram with the buckets of [`DURATION_BUCKETS`]. Every observation is only counted in its
+/// own bucket; the counts are accumulated when the histogram is rendered.
+#[derive(Debug, Default)]
+struct Histogram {
+    buckets: [AtomicU64; DURATION_BUCKETS.len()],
+    /// Observations bigger than the last bucket.
+    overflow: AtomicU64,
+    sum_micros: AtomicU64,
+}
+
+impl Histogram {
+    fn observe(&self, duration: Duration) {
+        let seconds = duration.as_secs_f64();
+
+        match DURATION_BUCKETS.iter().position(|&bound| seconds <= bound) {
+            Some(bucket) => self.buckets[bucket].fetch_add(1, Ordering::Relaxed),
+            None => self.overflow.fetch_add(1, Ordering::Relaxed),
+        };
+        self.sum_micros
+            .fetch_add(duration.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    fn render(&self, output: &mut String, name: &str) {
+        let mut count = 0;
+
+        for (bound, bucket) in DURATION_BUCKETS.iter().zip(&self.buckets) {
+            count += bucket.load(Ordering::Relaxed);
+            let _ = writeln!(output, "{}_bucket{{le=\"{}\"}} {}", name, bound, count);
+        }
+        count += self.overflow.load(Ordering::Relaxed);
+
+        let sum = self.sum_micros.load(Ordering::Relaxed) as f64 / 1_000_000.0;
+        let _ = writeln!(output, "{}_bucket{{le=\"+Inf\"}} {}", name, count);
+        let _ = writeln!(output, "{}_sum {}", name, sum);
+        let _ = writeln!(output, "{}_count {}", name, count);
+    }
+}
+
+/// Metrics of a server, exposed in the [Prometheus text format].
+///
+/// The same `Arc<Metrics>` is used in three places:
+///
+/// - Given to the server with [`ServerBuilder::metrics`](super::server::ServerBuilder::metrics),
+///   which keeps the number of open and queued connections up to date.
+/// - Added to a [`Pipeline`](super::middleware::Pipeline) as a middleware, which counts the
+///   requests by method and status and records how long they take.
+/// - Routed as a handler, usually on `/metrics`, which answers with all the metrics.
+///
+/// ```rust
+/// let metrics = Arc::new(Metrics::new());
+/// let router = Router::new().get("/metrics", Arc::clone(&metrics));
+/// let handler = Pipeline::new(router).with(Arc::clone(&metrics));
+/// let server = ServerBuilder::new().metrics(metrics).build(handler)?;
+/// ```
+///
+/// [Prometheus text format]: https://prometheus.io/docs/instrumenting/exposition_formats/
+#[derive(Debug, Default)]
+pub struct Metrics {
+    requests: Mutex<BTreeMap<(Method, u16), u64>>,
+    response_bytes: AtomicU64,
+    duration: Histogram,
+    requests_in_flight: AtomicUsize,
+    open_connections: AtomicUsize,
+    queued_connections: AtomicUsize,
+    rejected_connections: AtomicU64,
+}
+
+impl Metrics {
+    pub fn new() -> Metrics {
+        Metrics::default()
+    }
+
+    /// Records a request that has been answered.
+    pub fn record(
+        &self,
+        method: Method,
+        status: StatusCode,
+        bytes: Option<u64>,
+        duration: Duration,
+    ) {
+        *self
+            .requests
+            .lock()
+            .unwrap()
+            .entry((method, status.as_u16()))
+            .or_default() += 1;
+
+        self.response_bytes
+            .fetch_add(bytes.unwrap_or_default(), Ordering::Relaxed);
+        self.duration.observe(duration);
+    }
+
+    /// Number of requests answered with the method and status.
+    pub fn requests(&self, method: Method, status: StatusCode) -> u64 {
+        let requests = self.requests.lock().unwrap();
+        requests
+            .get(&(method, status.as_u16()))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    /// Number of open connections, either served by a thread of the pool or waiting in the event
+    /// loop for their next request.
+    pub fn open_connections(&self) -> usize {
+        self.open_connections.load(Ordering::Relaxed)
+    }
+
+    /// Number of connections (or, with the event loop, requests) waiting for a free thread of the
+    /// pool.
+    pub fn queued_connections(&self) -> usize {
+        self.queued_connections.load(Ordering::Relaxed)
+    }
+
+    /// Number of connections answered with `503 Service Unavailable` because the queue was full.
+    pub fn rejected_connections(&self) -> u64 {
+        self.rejected_connections.load(Ordering::Relaxed)
+    }
+
+    pub(super) fn connection_rejected(&self) {
+        self.rejected_connections.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(super) fn connection_queued(&self) {
+        self.queued_connections.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// A queued connection (or request) got a thread of the pool.
+    pub(super) fn connection_dequeued(&self) {
+        self.queued_connections.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    /// A connection starts being served. Returns a guard that marks the connection as closed when
+    /// dropped.
+    pub(super) fn connection_opened(self: &Arc<Self>) -> ConnectionGuard {
+        self.open_connections.fetch_add(1, Ordering::Relaxed);
+        ConnectionGuard(Arc::clone(self))
+    }
+
+    /// All the metrics in the Prometheus text format.
+    pub fn render(&self) -> String {
+        let mut output = String::new();
+
+        describe(
+            &mut output,
+            "http_requests_total",
+            "counter",
+            "Number of requests answered.",
+        );
+        for ((method, status), count) in self.requests.lock().unwrap().iter() {
+            let _ = writeln!(
+                output,
+                "http_requests_total{{method=\"{}\",status=\"{}\"}} {}",
+                method, status, count
+            );
+        }
+
+        let counters = [
+            (
+                "http_response_bytes_total",
+                "Bytes sent in response bodies of known length.",
+                self.response_bytes.load(Ordering::Relaxed),
+            ),
+            (
+                "http_connections_rejected_total",
+                "Connections rejected because the queue of the pool was full.",
+                self.rejected_connections.load(Ordering::Relaxed),
+            ),
+        ];
+        for (name, help, value) in counters {
+            describe(&mut output, name, "counter", help);
+            let _ = writeln!(output, "{} {}", name, value);
+        }
+
+        describe(
+            &mut output,
+            "http_request_duration_seconds",
+            "histogram",
+            "Time spent producing the responses.",
+        );
+        self.duration
+            .render(&mut output, "http_request_duration_seconds");
+
+        let gauges = [
+            (
+                "http_requests_in_flight",
+                "Requests being handled.",
+                &self.requests_in_flight,
+            ),
+            (
+                "http_connections_open",
+                "Open connections.",
+                &self.open_connections,
+            ),
+            (
+                "http_connections_queued",
+                "Connections or requests waiting for a free thread of the pool.",
+                &self.queued_connections,
+            ),
+        ];
+        for (name, help, value) in gauges {
+            describe(&mut output, name, "gauge", help);
+            let _ = writeln!(output, "{} {}", name, value.load(Ordering::Relaxed));
+        }
+
+        output
+    }
+}
+
+/// Writes the `HELP` and `TYPE` comments of a metric.
+fn describe(output: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(output, "# HELP {} {}", name, help);
+    let _ = writeln!(output, "# TYPE {} {}", name, kind);
+}
+
+pub(super) struct ConnectionGuard(Arc<Metrics>);
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.0.open_connections.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Counts the requests and records their duration.
+impl Middleware for Arc<Metrics> {
+    fn handle(&self, request: &mut Request, next: Next<'_>) -> Response {
+        let method = request.method;
+        let start = Instant::now();
+        self.requests_in_flight.fetch_add(1, Ordering::Relaxed);
+
+        let response = next.run(request);
+
+        self.requests_in_flight.fetch_sub(1, Ordering::Relaxed);
+        self.record(
+            method,
+            response.status,
+            response.body.len(),
+            start.elapsed(),
+        );
+        response
+    }
+}
+
+/// Answers with all the metrics in the Prometheus text format.
+impl Handler for Arc<Metrics> {
+    fn handle(&self, _request: &mut Request) -> Response {
+        Response::new(StatusCode::OK)
+            .with_header("Content-Type", "text/plain; version=0.0.4; charset=utf-8")
+            .with_body(self.render())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::middleware::Pipeline;
+    use super::super::router::Router;
+    use super::*;
+
+    #[test]
+    fn records_requests() {
+        let metrics = Arc::new(Metrics::new());
+        let router =
+            Router::new()
+                .get("/metrics", Arc::clone(&metrics))
+                .get("/slow", |_: &mut Request| {
+                    std::thread::sleep(Duration::from_millis(30));
+                    Response::new(StatusCode::OK).with_body("done")
+                });
+        let pipeline = Pipeline::new(router).with(Arc::clone(&metrics));
+
+        for path in ["/slow", "/missing", "/missing"] {
+            pipeline.handle(&mut Request::new(Method::Get, path));
+        }
+        assert_eq!(metrics.requests(Method::Get, StatusCode::NOT_FOUND), 2);
+
+        let response = pipeline.handle(&mut Request::new(Method::Get, "/metrics"));
+        let text = String::from_utf8(response.body.into_bytes().unwrap()).unwrap();
+
+        assert!(text.contains("http_requests_total{method=\"GET\",status=\"200\"} 1\n"));
+        assert!(text.contains("http_requests_total{method=\"GET\",status=\"404\"} 2\n"));
+        assert!(text.contains("http_request_duration_seconds_bucket{le=\"0.025\"} 2\n"));
+        assert!(text.contains("http_request_duration_seconds_bucket{le=\"0.05\"} 3\n"));
+        assert!(text.contains("http_request_duration_seconds_bucket{le=\"+Inf\"} 3\n"));
+        assert!(text.contains("http_request_duration_seconds_count 3\n"));
+        assert!(text.contains("# TYPE http_connections_queued gauge\n"));
+        // The request to /metrics is being handled while the metrics are rendered.
+        assert!(text.contains("http_requests_in_flight 1\n"));
+    }
+
+    #[test]
+    fn connection_gauges() {
+        let metrics = Arc::new(Metrics::new());
+
+        metrics.connection_queued();
+        metrics.connection_queued();
+        metrics.connection_dequeued();
+        let guard = metrics.connection_opened();
+        assert_eq!(metrics.queued_connections(), 1);
+        assert_eq!(metrics.open_connections(), 1);
+
+        drop(guard);
+        assert_eq!(metrics.open_connections(), 0);
+    }
+}
@@ -0,0 +1,303 @@
+use super::request::Request;
+use super::response::{Response, StatusCode};
+use super::router::Handler;
+use std::any::Any;
+use std::panic::{self, AssertUnwindSafe};
+use std::process;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+/// Behavior added around a [`Handler`], shared by all the requests, such as logging or adding
+/// headers. Middlewares are chained with a [`Pipeline`].
+///
+/// Most middlewares only need the [`before`](Middleware::before) and
+/// [`after`](Middleware::after) hooks. Middlewares that need to keep some state during the
+/// request, or to control how the rest of the pipeline is called, can override
+/// [`handle`](Middleware::handle) instead.
+pub trait Middleware: Send + Sync + 'static {
+    /// Called before the handler. Returning a response stops the request from reaching the rest
+    /// of the pipeline, but the response still goes through the `after` hooks of the previous
+    /// middlewares.
+    fn before(&self, _request: &mut Request) -> Option<Response> {
+        None
+    }
+
+    /// Called with the response returned by the rest of the pipeline.
+    fn after(&self, _request: &Request, _response: &mut Response) {}
+
+    /// Runs the middleware around the rest of the pipeline, `next`.
+    fn handle(&self, request: &mut Request, next: Next<'_>) -> Response {
+        let mut response = match self.before(request) {
+            Some(response) => response,
+            None => next.run(request),
+        };
+        self.after(request, &mut response);
+        response
+    }
+}
+
+/// Handler wrapped by a list of middlewares. The middlewares run in the order they are added:
+/// the `before` hook of the first middleware is the first one called, and its `after` hook is the
+/// last one.
+///
+/// # Example
+///
+/// ```rust
+/// let handler = Pipeline::new(router)
+///     .with(CatchPanic)
+///     .with(AccessLog::stdout(LogFormat::Common))
+///     .with(RequestId::new());
+/// ```
+pub struct Pipeline {
+    middlewares: Vec<Box<dyn Middleware>>,
+    handler: Box<dyn Handler>,
+}
+
+impl Pipeline {
+    pub fn new<H: Handler>(handler: H) -> Pipeline {
+        Pipeline {
+            middlewares: Vec::new(),
+            handler: Box::new(handler),
+        }
+    }
+
+    /// Adds a middleware, which runs inside all the previous ones.
+    pub fn with<M: Middleware>(mut self, middleware: M) -> Pipeline {
+        self.middlewares.push(Box::new(middleware));
+        self
+    }
+}
+
+impl Handler for Pipeline {
+    fn handle(&self, request: &mut Request) -> Response {
+        Next {
+            middlewares: &self.middlewares,
+            handler: self.handler.as_ref(),
+        }
+        .run(request)
+    }
+}
+
+/// Rest of a [`Pipeline`] after a middleware: the middlewares that have not run yet and the
+/// final handler.
+#[derive(Clone, Copy)]
+pub struct Next<'a> {
+    middlewares: &'a [Box<dyn Middleware>],
+    handler: &'a dyn Handler,
+}
+
+impl Next<'_> {
+    /// Passes the request to the rest of the pipeline and returns its response.
+    pub fn run(self, request: &mut Request) -> Response {
+        match self.middlewares.split_first() {
+            Some((middleware, rest)) => {
+                let next = Next {
+                    middlewares: rest,
+                    handler: self.handler,
+                };
+                middleware.handle(request, next)
+            }
+            None => self.handler.handle(request),
+        }
+    }
+}
+
+/// Turns a panic of the handler into a `500 Internal Server Error`, so the client gets an answer
+/// and the worker thread keeps serving connections. Besides the output of the panic hook, the
+/// request that caused the panic is printed to stderr.
+pub struct CatchPanic;
+
+impl Middleware for CatchPanic {
+    fn handle(&self, request: &mut Request, next: Next<'_>) -> Response {
+        match panic::catch_unwind(AssertUnwindSafe(|| next.run(request))) {
+            Ok(response) => response,
+            Err(payload) => {
+                eprintln!(
+                    "Handler panicked serving {} {}: {}",
+                    request.method,
+                    request.path,
+                    panic_message(payload.as_ref())
+                );
+                Response::error(StatusCode::INTERNAL_SERVER_ERROR)
+            }
+        }
+    }
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message
+    } else {
+        "unknown panic"
+    }
+}
+
+/// Name of the header with the identifier of the request, see [`RequestId`].
+pub const REQUEST_ID_HEADER: &str = "X-Request-Id";
+
+/// Gives every request an unique identifier, sent back in the `X-Request-Id` header of the
+/// response so that a client can refer to its request, e.g. when reporting an error.
+///
+/// The identifier is added to the headers of the request, so the handlers and the next
+/// middlewares can read it. If the client (or a proxy in front of the server) already sent a
+/// valid identifier it is kept.
+pub struct RequestId {
+    prefix: String,
+    counter: AtomicU64,
+}
+
+impl RequestId {
+    /// The identifiers are made of a prefix unique to this instance and a counter.
+    pub fn new() -> RequestId {
+        let started = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_millis() as u64)
+            .unwrap_or_default();
+
+        RequestId {
+            prefix: format!("{:x}{:x}", process::id(), started & 0xffff_ffff),
+            counter: AtomicU64::new(1),
+        }
+    }
+
+    /// Generates a new identifier.
+    pub fn next_id(&self) -> String {
+        let id = self.counter.fetch_add(1, Ordering::Relaxed);
+        format!("{}-{:06}", self.prefix, id)
+    }
+}
+
+impl Default for RequestId {
+    fn default() -> Self {
+        RequestId::new()
+    }
+}
+
+impl Middleware for RequestId {
+    fn before(&self, request: &mut Request) -> Option<Response> {
+        let valid = request.header(REQUEST_ID_HEADER).is_some_and(|id| {
+            !id.is_empty()
+                && id.len() <= 64
+                && id
+                    .bytes()
+                    .all(|b| b.is_ascii_alphanumeric() || b"-_.".contains(&b))
+        });
+
+        if !valid {
+            request.headers.insert(REQUEST_ID_HEADER, self.next_id());
+        }
+        None
+    }
+
+    fn after(&self, request: &Request, response: &mut Response) {
+        if let Some(id) = request.header(REQUEST_ID_HEADER) {
+            response.headers.insert(REQUEST_ID_HEADER, id);
+        }
+    }
+}
+
+/// Adds the `X-Response-Time` header with the time spent by the rest of the pipeline to produce
+/// the response, in milliseconds. The time to send a streamed body is not included.
+pub struct ResponseTime;
+
+impl Middleware for ResponseTime {
+    fn handle(&self, request: &mut Request, next: Next<'_>) -> Response {
+        let start = Instant::now();
+        let mut response = next.run(request);
+        let elapsed = start.elapsed().as_secs_f64() * 1000.0;
+
+        response
+            .headers
+            .insert("X-Response-Time", format!("{:.3}ms", elapsed));
+        response
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::request::Method;
+    use super::*;
+    use std::sync::{Arc, Mutex};
+
+    /// Middleware recording the order of its hooks.
+    struct Trace(&'static str, Arc<Mutex<Vec<String>>>);
+
+    impl Middleware for Trace {
+        fn before(&self, _request: &mut Request) -> Option<Response> {
+            self.1.lock().unwrap().push(format!("before {}", self.0));
+            None
+        }
+
+        fn after(&self, _request: &Request, _response: &mut Response) {
+            self.1.lock().unwrap().push(format!("after {}", self.0));
+        }
+    }
+
+    fn ok(_: &mut Request) -> Response {
+        Response::new(StatusCode::OK).with_body("ok")
+    }
+
+    #[test]
+    fn order() {
+        let trace = Arc::new(Mutex::new(Vec::new()));
+        let pipeline = Pipeline::new(ok)
+            .with(Trace("a", Arc::clone(&trace)))
+            .with(Trace("b", Arc::clone(&trace)));
+
+        pipeline.handle(&mut Request::new(Method::Get, "/"));
+        assert_eq!(
+            *trace.lock().unwrap(),
+            ["before a", "before b", "after b", "after a"]
+        );
+    }
+
+    #[test]
+    fn catch_panic() {
+        let pipeline =
+            Pipeline::new(|_: &mut Request| -> Response { panic!("boom") }).with(CatchPanic);
+
+        let response = pipeline.handle(&mut Request::new(Method::Get, "/"));
+        assert_eq!(response.status, StatusCode::INTERNAL_SERVER_ERROR);
+    }
+
+    #[test]
+    fn request_id() {
+        let pipeline = Pipeline::new(|request: &mut Request| {
+            Response::new(StatusCode::OK).with_body(
+                request
+                    .header(REQUEST_ID_HEADER)
+                    .unwrap_or_default()
+                    .to_string(),
+            )
+        })
+        .with(RequestId::new());
+
+        let response = pipeline.handle(&mut Request::new(Method::Get, "/"));
+        let id = response.headers.get(REQUEST_ID_HEADER).unwrap().to_string();
+        assert_eq!(response.body.into_bytes().unwrap(), id.as_bytes());
+
+        let second = pipeline.handle(&mut Request::new(Method::Get, "/"));
+        assert_ne!(second.headers.get(REQUEST_ID_HEADER), Some(id.as_str()));
+
+        let mut request = Request::new(Method::Get, "/").with_header(REQUEST_ID_HEADER, "abc-1");
+        let response = pipeline.handle(&mut request);
+        assert_eq!(response.headers.get(REQUEST_ID_HEADER), Some("abc-1"));
+
+        let mut request = Request::new(Method::Get, "/").with_header(REQUEST_ID_HEADER, "a b");
+        let response = pipeline.handle(&mut request);
+        assert_ne!(response.headers.get(REQUEST_ID_HEADER), Some("a b"));
+    }
+
+    #[test]
+    fn response_time() {
+        let response = Pipeline::new(ok)
+            .with(ResponseTime)
+            .handle(&mut Request::new(Method::Get, "/"));
+
+        let time = response.headers.get("X-Response-Time").unwrap();
+        assert!(time.ends_with("ms"));
+        assert!(time.trim_end_matches("ms").parse::<f64>().is_ok());
+    }
+}
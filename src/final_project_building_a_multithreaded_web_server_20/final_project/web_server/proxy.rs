@@ -0,0 +1,784 @@
+use super::body::Body;
+use super::headers::Headers;
+use super::request::{self, Limits, Method, Request};
+use super::response::{self, Response, StatusCode};
+use super::router::Handler;
+use std::error::Error;
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, Once, Weak};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Maximum time waiting for the connection to an upstream, and for the answer to a health check.
+pub const DEFAULT_CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Maximum time waiting for an upstream to send the next part of its response.
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Time an upstream is skipped after a failed connection, unless a health check finds it up
+/// before.
+pub const FAIL_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Bodies of responses with a `Content-Length` up to this size are read at once, so they keep
+/// their length. Bigger ones, and the ones without a length, are streamed.
+const BUFFERED_BODY_SIZE: usize = 64 * 1024;
+
+/// Headers that only concern a single connection, which are never forwarded (RFC 9110, 7.6.1).
+/// `Transfer-Encoding` is set again for the next connection.
+const HOP_BY_HOP: [&str; 9] = [
+    "Connection",
+    "Keep-Alive",
+    "Proxy-Connection",
+    "Proxy-Authenticate",
+    "Proxy-Authorization",
+    "TE",
+    "Trailer",
+    "Transfer-Encoding",
+    "Upgrade",
+];
+
+/// Invalid upstream URL.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvalidUpstream(pub String);
+
+impl fmt::Display for InvalidUpstream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid upstream URL '{}'", self.0)
+    }
+}
+
+impl Error for InvalidUpstream {}
+
+/// Server the requests are forwarded to, given as a URL like `http://127.0.0.1:9000/base`. Only
+/// plain HTTP is supported. The path of the URL, if any, is prepended to the forwarded paths.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Upstream {
+    /// Host and port, as sent in the `Host` header.
+    authority: String,
+    host: String,
+    port: u16,
+    /// Path without the trailing `/`, empty for the root.
+    base_path: String,
+}
+
+impl Upstream {
+    pub fn authority(&self) -> &str {
+        &self.authority
+    }
+
+    /// Target of the request for the path, relative to the base path of the upstream.
+    fn target(&self, path: &str, query: Option<&str>) -> String {
+        match query {
+            Some(query) => format!("{}{}?{}", self.base_path, path, query),
+            None => format!("{}{}", self.base_path, path),
+        }
+    }
+
+    /// Connects to the first address of the host that accepts the connection.
+    fn connect(&self, timeout: Duration) -> io::Result<TcpStream> {
+        let mut last_error = io::Error::new(io::ErrorKind::NotFound, "host not found");
+        for address in (self.host.as_str(), self.port).to_socket_addrs()? {
+            match TcpStream::connect_timeout(&address, timeout) {
+                Ok(stream) => return Ok(stream),
+                Err(err) => last_error = err,
+            }
+        }
+        Err(last_error)
+    }
+}
+
+impl FromStr for Upstream {
+    type Err = InvalidUpstream;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || InvalidUpstream(s.to_string());
+
+        let rest = s
+            .get(..7)
+            .filter(|scheme| scheme.eq_ignore_ascii_case("http://"))
+            .map(|_| &s[7..])
+            .unwrap_or(s);
+        let (authority, path) = match rest.find('/') {
+            Some(start) => rest.split_at(start),
+            None => (rest, ""),
+        };
+        if authority.contains(['@', '?', '#']) || path.contains(['?', '#']) || rest.contains("://")
+        {
+            return Err(invalid());
+        }
+
+        let (host, port) = match authority.rsplit_once(':') {
+            Some((host, port)) if !host.ends_with(':') => {
+                (host, port.parse().map_err(|_| invalid())?)
+            }
+            _ => (authority, 80),
+        };
+        let host = host.trim_start_matches('[').trim_end_matches(']');
+        if host.is_empty() || host.contains(char::is_whitespace) {
+            return Err(invalid());
+        }
+
+        Ok(Upstream {
+            authority: authority.to_string(),
+            host: host.to_string(),
+            port,
+            base_path: path.trim_end_matches('/').to_string(),
+        })
+    }
+}
+
+impl fmt::Display for Upstream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "http://{}{}", self.authority, self.base_path)
+    }
+}
+
+/// An upstream with its health.
+#[derive(Debug)]
+struct Backend {
+    upstream: Upstream,
+    /// Result of the last health check.
+    healthy: AtomicBool,
+    /// End of the time the upstream is skipped after a failed connection.
+    failed_until: Mutex<Option<Instant>>,
+}
+
+impl Backend {
+    fn is_up(&self) -> bool {
+        let failed = match *self.failed_until.lock().unwrap() {
+            Some(until) => Instant::now() < until,
+            None => false,
+        };
+        self.healthy.load(Ordering::Relaxed) && !failed
+    }
+
+    fn set_failed(&self, failed: bool) {
+        *self.failed_until.lock().unwrap() = failed.then(|| Instant::now() + FAIL_TIMEOUT);
+    }
+
+    /// Sends `GET path` and checks that the upstream answers with a `2xx` or `3xx` status.
+    fn check(&self, path: &str, timeout: Duration) -> bool {
+        let check = || -> io::Result<StatusCode> {
+            let mut stream = self.upstream.connect(timeout)?;
+            stream.set_read_timeout(Some(timeout))?;
+            stream.set_write_timeout(Some(timeout))?;
+            write!(
+                stream,
+                "GET {} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n\r\n",
+                self.upstream.target(path, None),
+                self.upstream.authority
+            )?;
+            let (status, _) = response::read_head(&mut BufReader::new(stream), &limits())?;
+            Ok(status)
+        };
+
+        matches!(check(), Ok(status) if (200..400).contains(&status.as_u16()))
+    }
+}
+
+/// Size limits of the responses of the upstreams. Their bodies are streamed, so they have no
+/// limit.
+fn limits() -> Limits {
+    Limits {
+        max_body_size: usize::MAX,
+        ..Limits::default()
+    }
+}
+
+/// Reverse proxy forwarding the requests to other HTTP servers, the upstreams, usually mounted
+/// on a prefix of the [`Router`](super::router::Router), which is removed from the forwarded
+/// path.
+///
+/// The upstreams are used in turn (round-robin). An upstream that refuses the connection is
+/// skipped for [`FAIL_TIMEOUT`] and the request goes to the next one, and with
+/// [`Proxy::health_check`] the upstreams are also checked periodically. When all of them are
+/// down, they are tried anyway.
+///
+/// The forwarded request gets:
+///
+/// - The `Host` of the upstream, unless [`Proxy::preserve_host`] is set. The original one is sent
+///   in `X-Forwarded-Host`.
+/// - The address of the client appended to `X-Forwarded-For`.
+/// - No hop-by-hop headers, and a new connection which is closed after the response.
+///
+/// The response is streamed back to the client as the upstream sends it. Upstreams that do not
+/// answer give `502 Bad Gateway`, or `504 Gateway Timeout` when they take too long. Upgrades to
+/// other protocols, like WebSocket, are not forwarded.
+///
+/// # Example
+///
+/// ```rust
+/// let router = Router::new().mount(
+///     "/api",
+///     Proxy::new(["http://127.0.0.1:9000", "http://127.0.0.1:9001"])
+///         .health_check("/health", Duration::from_secs(10)),
+/// );
+/// ```
+pub struct Proxy {
+    backends: Arc<Vec<Backend>>,
+    next: AtomicUsize,
+    connect_timeout: Duration,
+    timeout: Duration,
+    preserve_host: bool,
+    health_check: Option<(String, Duration)>,
+    health_check_started: Once,
+}
+
+impl Proxy {
+    /// Creates a proxy to the upstreams, see [`Upstream`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if there are no upstreams or if one of them is invalid.
+    pub fn new<I>(upstreams: I) -> Proxy
+    where
+        I: IntoIterator,
+        I::Item: AsRef<str>,
+    {
+        let backends: Vec<Backend> = upstreams
+            .into_iter()
+            .map(|upstream| Backend {
+                upstream: upstream
+                    .as_ref()
+                    .parse()
+                    .unwrap_or_else(|err| panic!("{}", err)),
+                healthy: AtomicBool::new(true),
+                failed_until: Mutex::new(None),
+            })
+            .collect();
+        assert!(!backends.is_empty(), "Proxy without upstreams");
+
+        Proxy {
+            backends: Arc::new(backends),
+            next: AtomicUsize::new(0),
+            connect_timeout: DEFAULT_CONNECT_TIMEOUT,
+            timeout: DEFAULT_TIMEOUT,
+            preserve_host: false,
+            health_check: None,
+            health_check_started: Once::new(),
+        }
+    }
+
+    /// Maximum time waiting for the connection to an upstream.
+    pub fn connect_timeout(mut self, timeout: Duration) -> Proxy {
+        self.connect_timeout = timeout;
+        self
+    }
+
+    /// Maximum time waiting for an upstream to send the next part of its response.
+    pub fn timeout(mut self, timeout: Duration) -> Proxy {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Forwards the `Host` header of the client instead of the one of the upstream.
+    pub fn preserve_host(mut self, preserve_host: bool) -> Proxy {
+        self.preserve_host = preserve_host;
+        self
+    }
+
+    /// Sends `GET path` to every upstream at each interval, and skips the ones that do not
+    /// answer with a `2xx` or `3xx` status until they do. The checks run in a thread of their
+    /// own, started by the first request, which ends once the proxy is dropped.
+    pub fn health_check(mut self, path: &str, interval: Duration) -> Proxy {
+        self.health_check = Some((path.to_string(), interval));
+        self
+    }
+
+    fn start_health_check(&self) {
+        let Some((path, interval)) = self.health_check.clone() else {
+            return;
+        };
+        let backends = Arc::downgrade(&self.backends);
+        let timeout = self.connect_timeout;
+
+        let result = thread::Builder::new()
+            .name(String::from("health-check"))
+            .spawn(move || check_health(backends, &path, interval, timeout));
+        if let Err(err) = result {
+            eprintln!("Error starting the health checks: {}", err);
+        }
+    }
+
+    /// Upstreams in the order they are tried for the next request: the next ones in turn that
+    /// are up, then the rest.
+    fn candidates(&self) -> Vec<&Backend> {
+        let start = self.next.fetch_add(1, Ordering::Relaxed);
+        let count = self.backends.len();
+        let (mut up, down): (Vec<&Backend>, Vec<&Backend>) = (0..count)
+            .map(|i| &self.backends[(start + i) % count])
+            .partition(|backend| backend.is_up());
+
+        up.extend(down);
+        up
+    }
+
+    fn forward(&self, request: &Request) -> Result<Response, ProxyError> {
+        let (backend, stream) = self
+            .candidates()
+            .into_iter()
+            .find_map(
+                |backend| match backend.upstream.connect(self.connect_timeout) {
+                    Ok(stream) => {
+                        backend.set_failed(false);
+                        Some((backend, stream))
+                    }
+                    Err(err) => {
+                        eprintln!("Error connecting to {}: {}", backend.upstream, err);
+                        backend.set_failed(true);
+                        None
+                    }
+                },
+            )
+            .ok_or(ProxyError::Unavailable)?;
+        let upstream = &backend.upstream;
+
+        stream.set_read_timeout(Some(self.timeout))?;
+        stream.set_write_timeout(Some(self.timeout))?;
+        let mut writer = io::BufWriter::new(stream.try_clone()?);
+        writer.write_all(&self.request_head(request, upstream).into_bytes())?;
+        io::copy(&mut request.body_reader(), &mut writer)?;
+        writer.flush()?;
+
+        let mut reader = BufReader::new(stream);
+        let (status, headers) = loop {
+            let (status, headers) = response::read_head(&mut reader, &limits())?;
+            // Interim responses, like `100 Continue`, were already dealt with for the client.
+            match status.as_u16() {
+                101 => return Err(ProxyError::Upgrade),
+                100..=199 => continue,
+                _ => break (status, headers),
+            }
+        };
+
+        let mut response = Response::new(status);
+        for (name, value) in headers.iter() {
+            if !is_hop_by_hop(name, &headers) {
+                response.headers.append(name, value);
+            }
+        }
+        // Without a body, the `Content-Length` of the upstream is the one of the representation.
+        if request.method == Method::Head || !status.allows_body() {
+            return Ok(response);
+        }
+        // Otherwise the body is framed again for the client.
+        response.headers.remove("Content-Length");
+
+        response.body = if request::is_chunked(&headers) {
+            Body::stream(ChunkedReader::new(reader))
+        } else {
+            match request::content_length(&headers)? {
+                Some(length) if length <= BUFFERED_BODY_SIZE => {
+                    let mut body = vec![0; length];
+                    reader.read_exact(&mut body)?;
+                    Body::Bytes(body)
+                }
+                Some(length) => Body::stream(LengthReader {
+                    reader,
+                    remaining: length as u64,
+                }),
+                // The end of the body is the end of the connection.
+                None => Body::stream(reader),
+            }
+        };
+        Ok(response)
+    }
+
+    /// Request line and headers of the request forwarded to the upstream.
+    fn request_head(&self, request: &Request, upstream: &Upstream) -> String {
+        let mut headers = Headers::new();
+        for (name, value) in request.headers.iter() {
+            if !is_hop_by_hop(name, &request.headers) && !name.eq_ignore_ascii_case("Expect") {
+                headers.append(name, value);
+            }
+        }
+
+        if let Some(host) = request.header("Host") {
+            if !self.preserve_host {
+                headers.insert("X-Forwarded-Host", host);
+                headers.insert("Host", upstream.authority.as_str());
+            }
+        } else {
+            headers.insert("Host", upstream.authority.as_str());
+        }
+        if let Some(address) = request.peer_addr {
+            let forwarded_for = match request.header("X-Forwarded-For") {
+                Some(previous) => format!("{}, {}", previous, address.ip()),
+                None => address.ip().to_string(),
+            };
+            headers.insert("X-Forwarded-For", forwarded_for);
+        }
+        headers.insert("Connection", "close");
+        let length = request.unread_body().unwrap_or(request.body.len() as u64);
+        if length > 0 || matches!(request.method, Method::Post | Method::Put) {
+            headers.insert("Content-Length", length.to_string());
+        }
+
+        format!(
+            "{} {} HTTP/1.1\r\n{}\r\n",
+            request.method,
+            upstream.target(&request.path, request.query.as_deref()),
+            headers
+        )
+    }
+}
+
+impl Handler for Proxy {
+    fn handle(&self, request: &mut Request) -> Response {
+        self.health_check_started
+            .call_once(|| self.start_health_check());
+
+        match self.forward(request) {
+            Ok(response) => response,
+            Err(err) => {
+                eprintln!(
+                    "Error forwarding {} {}: {}",
+                    request.method, request.path, err
+                );
+                Response::error(err.status())
+            }
+        }
+    }
+}
+
+/// Checks the health of the upstreams at each interval, until the proxy is dropped.
+fn check_health(backends: Weak<Vec<Backend>>, path: &str, interval: Duration, timeout: Duration) {
+    while let Some(backends) = backends.upgrade() {
+        for backend in backends.iter() {
+            let healthy = backend.check(path, timeout);
+            if healthy != backend.healthy.swap(healthy, Ordering::Relaxed) {
+                let state = if healthy { "up" } else { "down" };
+                eprintln!("Upstream {} is {}", backend.upstream, state);
+            }
+            if healthy {
+                backend.set_failed(false);
+            }
+        }
+
+        drop(backends);
+        thread::sleep(interval);
+    }
+}
+
+/// Returns `true` if the header must not be forwarded: the standard hop-by-hop headers and the
+/// ones listed in `Connection`.
+fn is_hop_by_hop(name: &str, headers: &Headers) -> bool {
+    HOP_BY_HOP
+        .iter()
+        .any(|header| header.eq_ignore_ascii_case(name))
+        || headers
+            .get_all("Connection")
+            .flat_map(|value| value.split(','))
+            .any(|token| token.trim().eq_ignore_ascii_case(name))
+}
+
+#[derive(Debug)]
+enum ProxyError {
+    /// No upstream accepted the connection.
+    Unavailable,
+    Io(io::Error),
+    /// The upstream switched to another protocol.
+    Upgrade,
+}
+
+impl ProxyError {
+    fn status(&self) -> StatusCode {
+        match self {
+            ProxyError::Io(err)
+                if matches!(
+                    err.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) =>
+            {
+                StatusCode::GATEWAY_TIMEOUT
+            }
+            _ => StatusCode::BAD_GATEWAY,
+        }
+    }
+}
+
+impl fmt::Display for ProxyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProxyError::Unavailable => f.write_str("no upstream available"),
+            ProxyError::Io(err) => err.fmt(f),
+            ProxyError::Upgrade => f.write_str("the upstream upgraded the connection"),
+        }
+    }
+}
+
+impl From<io::Error> for ProxyError {
+    fn from(err: io::Error) -> Self {
+        ProxyError::Io(err)
+    }
+}
+
+impl From<request::ParseError> for ProxyError {
+    fn from(err: request::ParseError) -> Self {
+        ProxyError::Io(err.into())
+    }
+}
+
+/// Body of a response with a `Content-Length`, which fails if the upstream closes the connection
+/// before sending all of it, instead of ending as if it were complete.
+struct LengthReader<R> {
+    reader: R,
+    remaining: u64,
+}
+
+impl<R: Read> Read for LengthReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.remaining == 0 {
+            return Ok(0);
+        }
+        let max = buf
+            .len()
+            .min(self.remaining.try_into().unwrap_or(usize::MAX));
+        let read = self.reader.read(&mut buf[..max])?;
+        if read == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        self.remaining -= read as u64;
+        Ok(read)
+    }
+}
+
+/// Body of a response with the chunked coding, decoded as it is read.
+struct ChunkedReader<R> {
+    reader: R,
+    /// Bytes left in the current chunk.
+    remaining: usize,
+    done: bool,
+}
+
+impl<R: BufRead> ChunkedReader<R> {
+    fn new(reader: R) -> ChunkedReader<R> {
+        ChunkedReader {
+            reader,
+            remaining: 0,
+            done: false,
+        }
+    }
+}
+
+impl<R: BufRead> Read for ChunkedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.done || buf.is_empty() {
+            return Ok(0);
+        }
+        if self.remaining == 0 {
+            self.remaining = request::read_chunk_size(&mut self.reader)?;
+            if self.remaining == 0 {
+                self.done = true;
+                request::read_headers(&mut self.reader, &limits())?;
+                return Ok(0);
+            }
+        }
+
+        let max = buf.len().min(self.remaining);
+        let read = self.reader.read(&mut buf[..max])?;
+        if read == 0 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        self.remaining -= read;
+        if self.remaining == 0 {
+            request::read_chunk_end(&mut self.reader)?;
+        }
+        Ok(read)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::testing::TestServer;
+    use super::*;
+    use std::io::Cursor;
+    use std::net::SocketAddr;
+
+    /// Upstream answering with its name, the path and the forwarding headers it received.
+    fn upstream(name: &'static str) -> TestServer {
+        TestServer::start(move |request: &mut Request| {
+            if request.path == "/health" && name == "sick" {
+                return Response::error(StatusCode::SERVICE_UNAVAILABLE);
+            }
+            let target = match &request.query {
+                Some(query) => format!("{}?{}", request.path, query),
+                None => request.path.clone(),
+            };
+            let body = format!(
+                "{} {} {} host={} forwarded-host={} forwarded-for={} private={} body={}",
+                name,
+                request.method,
+                target,
+                request.header("Host").unwrap_or("-"),
+                request.header("X-Forwarded-Host").unwrap_or("-"),
+                request.header("X-Forwarded-For").unwrap_or("-"),
+                request.header("X-Private").unwrap_or("-"),
+                String::from_utf8_lossy(&request.body),
+            );
+            Response::new(StatusCode::OK)
+                .with_header("Content-Type", "text/plain")
+                .with_body(body)
+        })
+    }
+
+    /// Address of an upstream that is down.
+    fn unused_address() -> SocketAddr {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        listener.local_addr().unwrap()
+    }
+
+    fn url(server: &TestServer) -> String {
+        format!("http://{}", server.address())
+    }
+
+    #[test]
+    fn upstream_urls() {
+        let upstream: Upstream = "http://127.0.0.1:9000/base/".parse().unwrap();
+        assert_eq!(upstream.authority(), "127.0.0.1:9000");
+        assert_eq!(upstream.target("/a", Some("b")), "/base/a?b");
+        assert_eq!(upstream.to_string(), "http://127.0.0.1:9000/base");
+
+        let upstream: Upstream = "localhost".parse().unwrap();
+        assert_eq!((upstream.host.as_str(), upstream.port), ("localhost", 80));
+        let upstream: Upstream = "[::1]:8080".parse().unwrap();
+        assert_eq!((upstream.host.as_str(), upstream.port), ("::1", 8080));
+
+        assert!("https://example.com".parse::<Upstream>().is_err());
+        assert!("http://user@example.com".parse::<Upstream>().is_err());
+        assert!("http://example.com:port".parse::<Upstream>().is_err());
+        assert!("http://".parse::<Upstream>().is_err());
+    }
+
+    #[test]
+    fn forwards_requests() {
+        let upstream = upstream("one");
+        let router = super::super::router::Router::new()
+            .mount("/api", Proxy::new([format!("{}/v1", url(&upstream))]));
+        let server = TestServer::start(router);
+
+        let request = Request::new(Method::Post, "/api/users?page=2")
+            .with_header("Host", "example.com")
+            .with_header("X-Forwarded-For", "10.0.0.1")
+            .with_header("Connection", "X-Private")
+            .with_header("X-Private", "secret")
+            .with_body("data");
+        let response = server.send(&request).unwrap();
+        assert_eq!(response.status, StatusCode::OK);
+        assert_eq!(
+            response.text(),
+            format!(
+                "one POST /v1/users?page=2 host={} forwarded-host=example.com \
+                 forwarded-for=10.0.0.1, 127.0.0.1 private=- body=data",
+                upstream.address()
+            )
+        );
+        assert_eq!(response.header("Content-Type"), Some("text/plain"));
+
+        let response = server.get("/api").unwrap();
+        assert!(response.text().starts_with("one GET /v1/ "));
+    }
+
+    #[test]
+    fn streams_responses() {
+        let upstream = TestServer::start(|_: &mut Request| {
+            let reader = Cursor::new(vec![b'x'; 3 * BUFFERED_BODY_SIZE]);
+            Response::new(StatusCode::OK).with_body(Body::stream(reader))
+        });
+        let server = TestServer::start(Proxy::new([url(&upstream)]));
+
+        let response = server.get("/big").unwrap();
+        assert_eq!(response.status, StatusCode::OK);
+        assert_eq!(response.header("Transfer-Encoding"), Some("chunked"));
+        assert_eq!(response.body, vec![b'x'; 3 * BUFFERED_BODY_SIZE]);
+
+        let response = server.send(&Request::new(Method::Head, "/big")).unwrap();
+        assert_eq!(response.status, StatusCode::OK);
+        assert!(response.body.is_empty());
+    }
+
+    #[test]
+    fn head_keeps_length() {
+        let upstream =
+            TestServer::start(
+                |request: &mut Request| match request.header("If-None-Match") {
+                    Some(_) => {
+                        Response::new(StatusCode::NOT_MODIFIED).with_header("Content-Length", "11")
+                    }
+                    None => Response::new(StatusCode::OK).with_body("hello world"),
+                },
+            );
+        let server = TestServer::start(Proxy::new([url(&upstream)]));
+
+        let response = server.send(&Request::new(Method::Head, "/file")).unwrap();
+        assert_eq!(response.status, StatusCode::OK);
+        assert_eq!(response.header("Content-Length"), Some("11"));
+        assert!(response.body.is_empty());
+
+        let response = server.get("/file").unwrap();
+        assert_eq!(response.header("Content-Length"), Some("11"));
+        assert_eq!(response.text(), "hello world");
+
+        let request = Request::new(Method::Get, "/file").with_header("If-None-Match", "\"v1\"");
+        let response = server.send(&request).unwrap();
+        assert_eq!(response.status, StatusCode::NOT_MODIFIED);
+        assert_eq!(response.header("Content-Length"), Some("11"));
+    }
+
+    #[test]
+    fn round_robin() {
+        let (one, two) = (upstream("one"), upstream("two"));
+        let proxy = Proxy::new([url(&one), format!("http://{}", unused_address()), url(&two)]);
+        let server = TestServer::start(proxy);
+
+        let names: Vec<String> = (0..4)
+            .map(|_| server.get("/").unwrap().text()[..3].to_string())
+            .collect();
+        assert_eq!(names, ["one", "two", "two", "one"]);
+    }
+
+    #[test]
+    fn health_checks() {
+        let (sick, healthy) = (upstream("sick"), upstream("fine"));
+        let proxy = Proxy::new([url(&sick), url(&healthy)])
+            .health_check("/health", Duration::from_millis(50));
+        let server = TestServer::start(proxy);
+
+        server.get("/").unwrap();
+        thread::sleep(Duration::from_millis(200));
+        for _ in 0..3 {
+            assert!(server.get("/").unwrap().text().starts_with("fine"));
+        }
+    }
+
+    #[test]
+    fn upstream_errors() {
+        let server = TestServer::start(Proxy::new([format!("http://{}", unused_address())]));
+        assert_eq!(server.get("/").unwrap().status, StatusCode::BAD_GATEWAY);
+
+        let slow = TestServer::start(|_: &mut Request| {
+            thread::sleep(Duration::from_millis(500));
+            Response::new(StatusCode::OK)
+        });
+        let proxy = Proxy::new([url(&slow)]).timeout(Duration::from_millis(50));
+        let server = TestServer::start(proxy);
+        assert_eq!(server.get("/").unwrap().status, StatusCode::GATEWAY_TIMEOUT);
+    }
+
+    #[test]
+    fn chunked_reader() {
+        let body = "4\r\nWiki\r\n5;ext=1\r\npedia\r\n0\r\nTrailer: x\r\n\r\n";
+        let mut decoded = String::new();
+        ChunkedReader::new(Cursor::new(body))
+            .read_to_string(&mut decoded)
+            .unwrap();
+        assert_eq!(decoded, "Wikipedia");
+
+        let mut reader = ChunkedReader::new(Cursor::new("4\r\nWi"));
+        assert!(reader.read_to_end(&mut Vec::new()).is_err());
+    }
+}
@@ -0,0 +1,267 @@
+use super::config::parse_duration;
+use super::middleware::Middleware;
+use super::request::Request;
+use super::response::{Response, StatusCode};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Time between two removals of the buckets that are full again.
+const PURGE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Number of requests allowed in a period, written like `10/s`, `100/m`, `1/10s` or `5/500ms`.
+/// The period uses the units of [`parse_duration`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rate {
+    pub requests: u32,
+    pub per: Duration,
+}
+
+impl Rate {
+    /// # Panics
+    ///
+    /// Panics if the number of requests or the period is zero.
+    pub fn new(requests: u32, per: Duration) -> Rate {
+        assert!(requests > 0, "The rate must allow at least one request");
+        assert!(!per.is_zero(), "The period of the rate cannot be zero");
+        Rate { requests, per }
+    }
+
+    /// Time to earn one request back.
+    fn interval(&self) -> Duration {
+        self.per / self.requests
+    }
+}
+
+impl FromStr for Rate {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (requests, per) = s.split_once('/').ok_or(())?;
+        let requests: u32 = requests.trim().parse().map_err(|_| ())?;
+        let per = per.trim();
+        let per = match per.starts_with(|c: char| c.is_ascii_digit()) {
+            true => parse_duration(per),
+            false => parse_duration(&format!("1{}", per)),
+        };
+
+        match per {
+            Some(per) if requests > 0 && !per.is_zero() => Ok(Rate { requests, per }),
+            _ => Err(()),
+        }
+    }
+}
+
+impl fmt::Display for Rate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.per.subsec_millis() {
+            0 => write!(f, "{}/{}s", self.requests, self.per.as_secs()),
+            _ => write!(f, "{}/{}ms", self.requests, self.per.as_millis()),
+        }
+    }
+}
+
+/// Requests a client can still send, refilled over time.
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// Middleware limiting the requests of every client IP address with a token bucket: a client
+/// can send a burst of requests at once, and then as many requests as the [`Rate`] allows. The
+/// requests above the limit are answered with `429 Too Many Requests`, with a `Retry-After`
+/// header telling when the next one is allowed.
+///
+/// The loopback addresses are not limited unless
+/// [`allow_localhost(false)`](RateLimiter::allow_localhost) is set, and neither are the requests
+/// without a peer address, which were not received from a connection.
+///
+/// # Example
+///
+/// ```rust
+/// // Bursts of 20 requests, then 5 requests per second.
+/// let limiter = RateLimiter::new(Rate::new(5, Duration::from_secs(1))).burst(20);
+/// let handler = Pipeline::new(router).with(limiter);
+/// ```
+pub struct RateLimiter {
+    rate: Rate,
+    burst: u32,
+    allowed: HashSet<IpAddr>,
+    allow_localhost: bool,
+    buckets: Mutex<HashMap<IpAddr, Bucket>>,
+    last_purge: Mutex<Instant>,
+}
+
+impl RateLimiter {
+    /// Limits the clients to the rate, with bursts of as many requests as the rate allows in
+    /// its period.
+    pub fn new(rate: Rate) -> RateLimiter {
+        RateLimiter {
+            rate,
+            burst: rate.requests,
+            allowed: HashSet::new(),
+            allow_localhost: true,
+            buckets: Mutex::new(HashMap::new()),
+            last_purge: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Number of requests a client can send at once, after being idle.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the burst is zero.
+    pub fn burst(mut self, burst: u32) -> RateLimiter {
+        assert!(burst > 0, "The burst must allow at least one request");
+        self.burst = burst;
+        self
+    }
+
+    /// Never limits the address, e.g. the one of a monitoring service.
+    pub fn allow(mut self, address: IpAddr) -> RateLimiter {
+        self.allowed.insert(address.to_canonical());
+        self
+    }
+
+    /// Whether the loopback addresses are never limited, which is the default.
+    pub fn allow_localhost(mut self, allow: bool) -> RateLimiter {
+        self.allow_localhost = allow;
+        self
+    }
+
+    /// Takes a request from the bucket of the address. Returns the time until the next request
+    /// is allowed if the bucket is empty.
+    pub fn check(&self, address: IpAddr) -> Result<(), Duration> {
+        let address = address.to_canonical();
+        if self.allowed.contains(&address) || (self.allow_localhost && address.is_loopback()) {
+            return Ok(());
+        }
+
+        let now = Instant::now();
+        if now.duration_since(*self.last_purge.lock().unwrap()) >= PURGE_INTERVAL {
+            self.purge(now);
+        }
+
+        let interval = self.rate.interval().as_secs_f64();
+        let burst = f64::from(self.burst);
+        let mut buckets = self.buckets.lock().unwrap();
+        let bucket = buckets.entry(address).or_insert(Bucket {
+            tokens: burst,
+            updated: now,
+        });
+
+        let refilled = now.duration_since(bucket.updated).as_secs_f64() / interval;
+        bucket.tokens = (bucket.tokens + refilled).min(burst);
+        bucket.updated = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(Duration::from_secs_f64((1.0 - bucket.tokens) * interval))
+        }
+    }
+
+    /// Removes the buckets that are full again, which are the same as a missing bucket.
+    fn purge(&self, now: Instant) {
+        let interval = self.rate.interval().as_secs_f64();
+        let burst = f64::from(self.burst);
+        self.buckets.lock().unwrap().retain(|_, bucket| {
+            bucket.tokens + now.duration_since(bucket.updated).as_secs_f64() / interval < burst
+        });
+        *self.last_purge.lock().unwrap() = now;
+    }
+}
+
+impl Middleware for RateLimiter {
+    fn before(&self, request: &mut Request) -> Option<Response> {
+        let address = request.peer_addr?.ip();
+        let retry_after = self.check(address).err()?;
+
+        // Retry-After only has a precision of seconds, rounded up so the retry is allowed.
+        let seconds = retry_after.as_secs() + u64::from(retry_after.subsec_nanos() > 0);
+        Some(
+            Response::error(StatusCode::TOO_MANY_REQUESTS)
+                .with_header("Retry-After", seconds.max(1).to_string()),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::request::Method;
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn parse_rate() {
+        let rate = |s: &str| s.parse::<Rate>();
+        assert_eq!(rate("10/s"), Ok(Rate::new(10, Duration::from_secs(1))));
+        assert_eq!(rate("100 / m"), Ok(Rate::new(100, Duration::from_secs(60))));
+        assert_eq!(rate("1/10s"), Ok(Rate::new(1, Duration::from_secs(10))));
+        assert_eq!(
+            rate("5/500ms"),
+            Ok(Rate::new(5, Duration::from_millis(500)))
+        );
+        assert_eq!(rate("0/s"), Err(()));
+        assert_eq!(rate("1/0s"), Err(()));
+        assert_eq!(rate("10"), Err(()));
+        assert_eq!(rate("10/h"), Err(()));
+        assert_eq!(
+            Rate::new(5, Duration::from_millis(500)).to_string(),
+            "5/500ms"
+        );
+    }
+
+    #[test]
+    fn token_bucket() {
+        let limiter = RateLimiter::new(Rate::new(10, Duration::from_secs(1))).burst(3);
+        let client: IpAddr = "192.168.1.2".parse().unwrap();
+        let other: IpAddr = "192.168.1.3".parse().unwrap();
+
+        for _ in 0..3 {
+            assert_eq!(limiter.check(client), Ok(()));
+        }
+        let retry_after = limiter.check(client).unwrap_err();
+        assert!(retry_after > Duration::ZERO && retry_after <= Duration::from_millis(100));
+        assert_eq!(limiter.check(other), Ok(()));
+
+        thread::sleep(Duration::from_millis(110));
+        assert_eq!(limiter.check(client), Ok(()));
+        assert!(limiter.check(client).is_err());
+    }
+
+    #[test]
+    fn allow_list() {
+        let rate = Rate::new(1, Duration::from_secs(60));
+        let allowed: IpAddr = "10.0.0.1".parse().unwrap();
+        let limiter = RateLimiter::new(rate).allow(allowed);
+
+        for address in ["127.0.0.1", "::1", "::ffff:127.0.0.1", "10.0.0.1"] {
+            let address: IpAddr = address.parse().unwrap();
+            assert_eq!(limiter.check(address), Ok(()));
+            assert_eq!(limiter.check(address), Ok(()));
+        }
+
+        let limiter = RateLimiter::new(rate).allow_localhost(false);
+        let localhost: IpAddr = "127.0.0.1".parse().unwrap();
+        assert_eq!(limiter.check(localhost), Ok(()));
+        assert!(limiter.check(localhost).is_err());
+    }
+
+    #[test]
+    fn too_many_requests() {
+        let limiter = RateLimiter::new(Rate::new(1, Duration::from_secs(30)));
+        let mut request = Request::new(Method::Get, "/");
+        assert!(limiter.before(&mut request).is_none());
+
+        request.peer_addr = Some("192.168.1.2:50000".parse().unwrap());
+        assert!(limiter.before(&mut request).is_none());
+        let response = limiter.before(&mut request).unwrap();
+        assert_eq!(response.status, StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers.get("Retry-After"), Some("30"));
+    }
+}
@@ -0,0 +1,977 @@
+use super::cookie;
+use super::form::{parse_urlencoded, Form, FormError, FormParser};
+use super::headers::Headers;
+use super::response::StatusCode;
+use super::session::Session;
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::io::{self, BufRead, Read, Take};
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+
+/// Maximum length in bytes of the request line (`GET /path HTTP/1.1`).
+pub const MAX_REQUEST_LINE: usize = 8 * 1024;
+/// Maximum combined length in bytes of all the header lines of a request.
+pub const MAX_HEADERS_SIZE: usize = 64 * 1024;
+/// Maximum number of header fields of a request.
+pub const MAX_HEADERS: usize = 100;
+/// Maximum length in bytes of the line with the size of a chunk, including its extensions.
+pub const MAX_CHUNK_LINE: usize = 1024;
+/// Maximum length in bytes of the body of a request.
+pub const MAX_BODY_SIZE: usize = 10 * 1024 * 1024;
+/// Maximum length in bytes of a multipart body streamed to the handler instead, see
+/// [`Request::body_reader`].
+pub const MAX_UPLOAD_SIZE: usize = 1024 * 1024 * 1024;
+
+/// Size limits applied while reading a request, to avoid exhausting the memory of the server.
+/// The default values are the `MAX_*` constants of this module.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    pub max_request_line: usize,
+    pub max_headers_size: usize,
+    pub max_headers: usize,
+    pub max_body_size: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            max_request_line: MAX_REQUEST_LINE,
+            max_headers_size: MAX_HEADERS_SIZE,
+            max_headers: MAX_HEADERS,
+            max_body_size: MAX_BODY_SIZE,
+        }
+    }
+}
+
+/// Request methods defined by the HTTP/1.1 specification.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Method {
+    Get,
+    Head,
+    Post,
+    Put,
+    Delete,
+    Connect,
+    Options,
+    Trace,
+    Patch,
+}
+
+impl Method {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Method::Get => "GET",
+            Method::Head => "HEAD",
+            Method::Post => "POST",
+            Method::Put => "PUT",
+            Method::Delete => "DELETE",
+            Method::Connect => "CONNECT",
+            Method::Options => "OPTIONS",
+            Method::Trace => "TRACE",
+            Method::Patch => "PATCH",
+        }
+    }
+}
+
+impl FromStr for Method {
+    type Err = ParseError;
+
+    /// Methods are case-sensitive, so `get` is not a valid method.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "GET" => Ok(Method::Get),
+            "HEAD" => Ok(Method::Head),
+            "POST" => Ok(Method::Post),
+            "PUT" => Ok(Method::Put),
+            "DELETE" => Ok(Method::Delete),
+            "CONNECT" => Ok(Method::Connect),
+            "OPTIONS" => Ok(Method::Options),
+            "TRACE" => Ok(Method::Trace),
+            "PATCH" => Ok(Method::Patch),
+            _ if !s.is_empty() && s.bytes().all(is_token) => Err(ParseError::UnknownMethod),
+            _ => Err(ParseError::InvalidRequestLine),
+        }
+    }
+}
+
+impl fmt::Display for Method {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Supported versions of the protocol.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Version {
+    Http10,
+    Http11,
+}
+
+impl FromStr for Version {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "HTTP/1.0" => Ok(Version::Http10),
+            "HTTP/1.1" => Ok(Version::Http11),
+            _ if s.starts_with("HTTP/") => Err(ParseError::UnsupportedVersion),
+            _ => Err(ParseError::InvalidRequestLine),
+        }
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Version::Http10 => f.write_str("HTTP/1.0"),
+            Version::Http11 => f.write_str("HTTP/1.1"),
+        }
+    }
+}
+
+/// Errors that can happen while reading a request from a stream. Apart from
+/// [`ParseError::ConnectionClosed`] and [`ParseError::Io`], all the errors are caused by the
+/// client and have a matching [`status`](ParseError::status) that should be sent back.
+#[derive(Debug)]
+pub enum ParseError {
+    /// The stream was closed before any byte of a new request was received.
+    ConnectionClosed,
+    /// The stream failed while reading the request.
+    Io(io::Error),
+    /// The stream was closed in the middle of a request.
+    UnexpectedEof,
+    InvalidRequestLine,
+    UnknownMethod,
+    InvalidTarget,
+    UnsupportedVersion,
+    InvalidHeader,
+    MissingHost,
+    RequestLineTooLong,
+    HeadersTooLarge,
+    InvalidContentLength,
+    UnsupportedTransferEncoding,
+    /// The body sent with the chunked coding is malformed.
+    InvalidChunk,
+    BodyTooLarge,
+}
+
+impl ParseError {
+    /// Status of the response that should be sent to the client, or `None` if nothing can be
+    /// sent because the connection is no longer usable.
+    pub fn status(&self) -> Option<StatusCode> {
+        match self {
+            ParseError::ConnectionClosed | ParseError::Io(_) | ParseError::UnexpectedEof => None,
+            ParseError::InvalidRequestLine
+            | ParseError::InvalidTarget
+            | ParseError::InvalidHeader
+            | ParseError::MissingHost
+            | ParseError::InvalidContentLength
+            | ParseError::InvalidChunk => Some(StatusCode::BAD_REQUEST),
+            ParseError::UnknownMethod | ParseError::UnsupportedTransferEncoding => {
+                Some(StatusCode::NOT_IMPLEMENTED)
+            }
+            ParseError::UnsupportedVersion => Some(StatusCode::HTTP_VERSION_NOT_SUPPORTED),
+            ParseError::RequestLineTooLong => Some(StatusCode::URI_TOO_LONG),
+            ParseError::HeadersTooLarge => Some(StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE),
+            ParseError::BodyTooLarge => Some(StatusCode::PAYLOAD_TOO_LARGE),
+        }
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::ConnectionClosed => f.write_str("connection closed"),
+            ParseError::Io(err) => write!(f, "I/O error: {}", err),
+            ParseError::UnexpectedEof => {
+                f.write_str("connection closed in the middle of a request")
+            }
+            ParseError::InvalidRequestLine => f.write_str("invalid request line"),
+            ParseError::UnknownMethod => f.write_str("unknown method"),
+            ParseError::InvalidTarget => f.write_str("invalid request target"),
+            ParseError::UnsupportedVersion => f.write_str("unsupported HTTP version"),
+            ParseError::InvalidHeader => f.write_str("invalid header field"),
+            ParseError::MissingHost => f.write_str("missing Host header"),
+            ParseError::RequestLineTooLong => f.write_str("request line too long"),
+            ParseError::HeadersTooLarge => f.write_str("header fields too large"),
+            ParseError::InvalidContentLength => f.write_str("invalid Content-Length"),
+            ParseError::UnsupportedTransferEncoding => f.write_str("unsupported Transfer-Encoding"),
+            ParseError::InvalidChunk => f.write_str("invalid chunked body"),
+            ParseError::BodyTooLarge => f.write_str("body too large"),
+        }
+    }
+}
+
+impl Error for ParseError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ParseError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ParseError {
+    fn from(err: io::Error) -> Self {
+        ParseError::Io(err)
+    }
+}
+
+/// Used when a message is read as a stream, like the responses of other servers.
+impl From<ParseError> for io::Error {
+    fn from(err: ParseError) -> Self {
+        match err {
+            ParseError::Io(err) => err,
+            ParseError::ConnectionClosed | ParseError::UnexpectedEof => {
+                io::ErrorKind::UnexpectedEof.into()
+            }
+            err => io::Error::new(io::ErrorKind::InvalidData, err.to_string()),
+        }
+    }
+}
+
+/// HTTP request received from a client.
+///
+/// The request target is split into the [`path`](Request::path) and the optional
+/// [`query`](Request::query) (the text after `?`). Neither of them is percent-decoded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Request {
+    pub method: Method,
+    pub path: String,
+    pub query: Option<String>,
+    pub version: Version,
+    pub headers: Headers,
+    /// Body of the request, already decoded if it was sent with `Transfer-Encoding: chunked`.
+    /// Empty if the body was left on the connection, see [`Request::body_reader`].
+    pub body: Vec<u8>,
+    /// Parameters extracted from the path by the [`Router`](super::router::Router), e.g. `id`
+    /// for the pattern `/users/:id`.
+    pub params: HashMap<String, String>,
+    /// Address of the client that sent the request, if it was received from a connection.
+    pub peer_addr: Option<SocketAddr>,
+    /// Session of the client, set by the [`Sessions`](super::session::Sessions) middleware.
+    pub session: Option<Session>,
+    deferred_body: Option<DeferredBody>,
+}
+
+impl Request {
+    /// Creates a HTTP/1.1 request without headers or body. The target can contain a query.
+    pub fn new(method: Method, target: &str) -> Request {
+        let (path, query) = split_target(target);
+        Request {
+            method,
+            path,
+            query,
+            version: Version::Http11,
+            headers: Headers::new(),
+            body: Vec::new(),
+            params: HashMap::new(),
+            peer_addr: None,
+            session: None,
+            deferred_body: None,
+        }
+    }
+
+    /// Reads a full request (request line, headers and body) from the reader. Only the bytes of
+    /// the request are consumed, so the reader can be used again for the next request of the
+    /// connection.
+    ///
+    /// Both `\r\n` and a bare `\n` are accepted as line terminators, and empty lines before the
+    /// request line are ignored, as recommended by RFC 9112.
+    pub fn read_from<R: BufRead>(reader: &mut R) -> Result<Request, ParseError> {
+        Request::read_with_limits(reader, &Limits::default())
+    }
+
+    /// Same as [`Request::read_from`], but with custom size limits.
+    pub fn read_with_limits<R: BufRead>(
+        reader: &mut R,
+        limits: &Limits,
+    ) -> Result<Request, ParseError> {
+        let mut request = Request::read_head(reader, limits)?;
+        request.body = read_body(reader, &request.headers, limits)?;
+        Ok(request)
+    }
+
+    /// Reads the request line and the headers, leaving the body in the reader.
+    pub(super) fn read_head<R: BufRead>(
+        reader: &mut R,
+        limits: &Limits,
+    ) -> Result<Request, ParseError> {
+        let mut line = Vec::new();
+
+        // Skip empty lines left by a previous request, but without allowing an endless stream.
+        for _ in 0..=4 {
+            line.clear();
+            if read_line(reader, limits.max_request_line, &mut line)?.is_none() {
+                return Err(ParseError::ConnectionClosed);
+            }
+            if !line.is_empty() {
+                break;
+            }
+        }
+        if line.is_empty() {
+            return Err(ParseError::InvalidRequestLine);
+        }
+
+        let request_line =
+            std::str::from_utf8(&line).map_err(|_| ParseError::InvalidRequestLine)?;
+        let mut parts = request_line.split(' ');
+        let (method, target, version) =
+            match (parts.next(), parts.next(), parts.next(), parts.next()) {
+                (Some(method), Some(target), Some(version), None) => (method, target, version),
+                _ => return Err(ParseError::InvalidRequestLine),
+            };
+        let method: Method = method.parse()?;
+        let version: Version = version.parse()?;
+        let (target, authority) = parse_target(method, target)?;
+        let (path, query) = split_target(target);
+
+        let mut headers = read_headers(reader, limits)?;
+        if version == Version::Http11 && !headers.contains("Host") {
+            return Err(ParseError::MissingHost);
+        }
+        // The host of the absolute form takes precedence over the header (RFC 9112, 3.2.2).
+        if let Some(authority) = authority {
+            headers.insert("Host", authority);
+        }
+
+        Ok(Request {
+            method,
+            path,
+            query,
+            version,
+            headers,
+            body: Vec::new(),
+            params: HashMap::new(),
+            peer_addr: None,
+            session: None,
+            deferred_body: None,
+        })
+    }
+
+    /// Returns the value of the first header with the given name.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name)
+    }
+
+    /// Returns the value of a path parameter captured by the router.
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params.get(name).map(String::as_str)
+    }
+
+    /// Returns the percent-decoded value of the first query parameter with the given name, with
+    /// `+` decoded as a space as in HTML forms. A parameter without `=` has an empty value.
+    pub fn query_param(&self, name: &str) -> Option<String> {
+        self.query_params()
+            .into_iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value)
+    }
+
+    /// Returns all the percent-decoded parameters of the query, in order, see
+    /// [`parse_urlencoded`].
+    pub fn query_params(&self) -> Vec<(String, String)> {
+        self.query
+            .as_deref()
+            .map(parse_urlencoded)
+            .unwrap_or_default()
+    }
+
+    /// Reader of the body.
+    ///
+    /// The thread pool backend of the server does not read the body of a `multipart/form-data`
+    /// request sent with a `Content-Length` over plain HTTP before calling the handler: it is
+    /// streamed from the connection through this reader, so uploads are not kept in memory, and
+    /// [`body`](Request::body) stays empty. Such a body is limited by the `max_upload_size` of
+    /// the server instead of its `max_body_size`, and it can only be read once, also by the
+    /// clones of the request.
+    pub fn body_reader(&self) -> Box<dyn Read + '_> {
+        match &self.deferred_body {
+            Some(deferred) => Box::new(deferred),
+            None => Box::new(self.body.as_slice()),
+        }
+    }
+
+    /// Leaves the body on the connection, to be read with [`Request::body_reader`].
+    pub(super) fn defer_body(&mut self, reader: Box<dyn Read + Send>, length: u64) {
+        self.deferred_body = Some(DeferredBody(Arc::new(Mutex::new(reader.take(length)))));
+    }
+
+    /// Bytes of a body left on the connection that were not read yet, or `None` if the body was
+    /// read with the request.
+    pub(super) fn unread_body(&self) -> Option<u64> {
+        self.deferred_body.as_ref().map(DeferredBody::remaining)
+    }
+
+    /// Parses the form sent in the body, with the default limits of [`FormParser`]. The files of
+    /// a multipart form are stored in temporary files.
+    pub fn form(&self) -> Result<Form, FormError> {
+        FormParser::new().parse(self)
+    }
+
+    /// Returns the value of the first cookie with the given name sent in the `Cookie` headers.
+    pub fn cookie(&self, name: &str) -> Option<String> {
+        self.cookies()
+            .into_iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value)
+    }
+
+    /// Returns all the cookies sent in the `Cookie` headers, in order.
+    pub fn cookies(&self) -> Vec<(String, String)> {
+        self.headers
+            .get_all("Cookie")
+            .flat_map(cookie::parse)
+            .collect()
+    }
+
+    /// Adds a header to the request. Mostly useful to build requests in tests.
+    pub fn with_header(mut self, name: impl Into<String>, value: impl Into<String>) -> Request {
+        self.headers.insert(name, value);
+        self
+    }
+
+    pub fn with_body(mut self, body: impl Into<Vec<u8>>) -> Request {
+        self.body = body.into();
+        self
+    }
+}
+
+/// Body left on the connection, shared by the clones of the request.
+#[derive(Clone)]
+struct DeferredBody(Arc<Mutex<Take<Box<dyn Read + Send>>>>);
+
+impl DeferredBody {
+    fn remaining(&self) -> u64 {
+        self.0.lock().unwrap().limit()
+    }
+}
+
+impl Read for &DeferredBody {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().read(buf)
+    }
+}
+
+impl fmt::Debug for DeferredBody {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DeferredBody")
+            .field("remaining", &self.remaining())
+            .finish()
+    }
+}
+
+impl PartialEq for DeferredBody {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for DeferredBody {}
+
+/// Reads a line without its terminator into `line`. Returns `Ok(None)` if the stream is at EOF
+/// before reading any byte.
+pub(super) fn read_line<R: BufRead>(
+    reader: &mut R,
+    limit: usize,
+    line: &mut Vec<u8>,
+) -> Result<Option<()>, ParseError> {
+    let read = reader.take(limit as u64 + 2).read_until(b'\n', line)?;
+
+    if read == 0 {
+        return Ok(None);
+    }
+    if line.last() != Some(&b'\n') {
+        return Err(if read > limit {
+            ParseError::RequestLineTooLong
+        } else {
+            ParseError::UnexpectedEof
+        });
+    }
+
+    line.pop();
+    if line.last() == Some(&b'\r') {
+        line.pop();
+    }
+    Ok(Some(()))
+}
+
+pub(super) fn read_headers<R: BufRead>(
+    reader: &mut R,
+    limits: &Limits,
+) -> Result<Headers, ParseError> {
+    let mut headers = Headers::new();
+    let mut remaining = limits.max_headers_size;
+    let mut line = Vec::new();
+
+    loop {
+        line.clear();
+        match read_line(reader, remaining, &mut line) {
+            Ok(Some(())) => {}
+            Ok(None) => return Err(ParseError::UnexpectedEof),
+            Err(ParseError::RequestLineTooLong) => return Err(ParseError::HeadersTooLarge),
+            Err(err) => return Err(err),
+        }
+        if line.is_empty() {
+            return Ok(headers);
+        }
+
+        remaining = remaining.saturating_sub(line.len() + 2);
+        if headers.len() == limits.max_headers {
+            return Err(ParseError::HeadersTooLarge);
+        }
+
+        let (name, value) = parse_header(&line)?;
+        headers.append(name, value);
+    }
+}
+
+fn parse_header(line: &[u8]) -> Result<(String, String), ParseError> {
+    let colon = line
+        .iter()
+        .position(|&b| b == b':')
+        .ok_or(ParseError::InvalidHeader)?;
+    let (name, value) = (&line[..colon], &line[colon + 1..]);
+
+    // Rejects obsolete line folding and whitespace between the name and the colon.
+    if name.is_empty() || !name.iter().copied().all(is_token) {
+        return Err(ParseError::InvalidHeader);
+    }
+    if value.iter().any(|&b| (b < 0x20 && b != b'\t') || b == 0x7f) {
+        return Err(ParseError::InvalidHeader);
+    }
+
+    let name = String::from_utf8(name.to_vec()).map_err(|_| ParseError::InvalidHeader)?;
+    let value = String::from_utf8_lossy(value)
+        .trim_matches(|c| c == ' ' || c == '\t')
+        .to_string();
+    Ok((name, value))
+}
+
+pub(super) fn read_body<R: BufRead>(
+    reader: &mut R,
+    headers: &Headers,
+    limits: &Limits,
+) -> Result<Vec<u8>, ParseError> {
+    if headers.contains("Transfer-Encoding") {
+        // A message with both headers could be used to smuggle a second request through a proxy
+        // that uses the other one to find the end of the body.
+        if headers.contains("Content-Length") {
+            return Err(ParseError::InvalidContentLength);
+        }
+        if !is_chunked(headers) {
+            return Err(ParseError::UnsupportedTransferEncoding);
+        }
+        return read_chunked_body(reader, limits);
+    }
+
+    let length = match content_length(headers)? {
+        Some(length) => length,
+        None => return Ok(Vec::new()),
+    };
+    if length > limits.max_body_size {
+        return Err(ParseError::BodyTooLarge);
+    }
+
+    // The length is not trusted before the bytes arrive, so the buffer grows as they are read.
+    let mut body = Vec::new();
+    reader.take(length as u64).read_to_end(&mut body)?;
+    if body.len() < length {
+        return Err(ParseError::UnexpectedEof);
+    }
+    Ok(body)
+}
+
+/// Only the chunked coding on its own is supported, other codings (like `gzip, chunked`) would
+/// need to be decoded too.
+pub(super) fn is_chunked(headers: &Headers) -> bool {
+    let mut codings = headers
+        .get_all("Transfer-Encoding")
+        .flat_map(|value| value.split(','))
+        .map(str::trim)
+        .filter(|coding| !coding.is_empty());
+
+    matches!(codings.next(), Some(coding) if coding.eq_ignore_ascii_case("chunked"))
+        && codings.next().is_none()
+}
+
+/// Decodes a body sent with the chunked coding. Every chunk starts with its size in hexadecimal,
+/// optionally followed by extensions that are ignored, and the body ends with an empty chunk
+/// followed by the trailer fields, which are discarded.
+pub(super) fn read_chunked_body<R: BufRead>(
+    reader: &mut R,
+    limits: &Limits,
+) -> Result<Vec<u8>, ParseError> {
+    let mut body = Vec::new();
+
+    loop {
+        let size = read_chunk_size(reader)?;
+        if size == 0 {
+            break;
+        }
+        if size > limits.max_body_size - body.len() {
+            return Err(ParseError::BodyTooLarge);
+        }
+
+        let start = body.len();
+        reader.take(size as u64).read_to_end(&mut body)?;
+        if body.len() - start < size {
+            return Err(ParseError::UnexpectedEof);
+        }
+
+        read_chunk_end(reader)?;
+    }
+
+    // The trailer fields have the same syntax and limits as the header fields.
+    read_headers(reader, limits)?;
+    Ok(body)
+}
+
+/// Reads the line starting a chunk and returns the size of the chunk.
+pub(super) fn read_chunk_size<R: BufRead>(reader: &mut R) -> Result<usize, ParseError> {
+    let mut line = Vec::new();
+    match read_line(reader, MAX_CHUNK_LINE, &mut line) {
+        Ok(Some(())) => {}
+        Ok(None) => return Err(ParseError::UnexpectedEof),
+        Err(ParseError::RequestLineTooLong) => return Err(ParseError::InvalidChunk),
+        Err(err) => return Err(err),
+    }
+
+    let size = line.split(|&b| b == b';').next().unwrap_or_default();
+    let size = std::str::from_utf8(size)
+        .ok()
+        .map(|size| size.trim_matches(|c| c == ' ' || c == '\t'))
+        .filter(|size| !size.is_empty() && size.bytes().all(|b| b.is_ascii_hexdigit()))
+        .ok_or(ParseError::InvalidChunk)?;
+    // Sizes too big for an usize are definitely too big for the body.
+    Ok(usize::from_str_radix(size, 16).unwrap_or(usize::MAX))
+}
+
+/// Reads the empty line that must follow the data of a chunk.
+pub(super) fn read_chunk_end<R: BufRead>(reader: &mut R) -> Result<(), ParseError> {
+    let mut line = Vec::new();
+    match read_line(reader, 0, &mut line) {
+        Ok(Some(())) if line.is_empty() => Ok(()),
+        Ok(None) | Err(ParseError::UnexpectedEof) => Err(ParseError::UnexpectedEof),
+        Ok(Some(())) | Err(_) => Err(ParseError::InvalidChunk),
+    }
+}
+
+/// Parses the `Content-Length` header. Repeated headers are only valid if all of them have the
+/// same value.
+pub(super) fn content_length(headers: &Headers) -> Result<Option<usize>, ParseError> {
+    let mut length = None;
+
+    for value in headers.get_all("Content-Length").flat_map(|v| v.split(',')) {
+        let value = value.trim();
+        if value.is_empty() || !value.bytes().all(|b| b.is_ascii_digit()) {
+            return Err(ParseError::InvalidContentLength);
+        }
+        // Values too big for an usize are definitely too big for the body.
+        let value = value.parse().unwrap_or(usize::MAX);
+
+        match length {
+            Some(previous) if previous != value => return Err(ParseError::InvalidContentLength),
+            _ => length = Some(value),
+        }
+    }
+
+    Ok(length)
+}
+
+/// Validates the request target and converts the absolute form (`http://host/path`) into the
+/// origin form (`/path`). The host of the absolute form is returned with it.
+fn parse_target(method: Method, target: &str) -> Result<(&str, Option<&str>), ParseError> {
+    if target.bytes().any(|b| b <= b' ' || b == 0x7f || b == b'#') {
+        return Err(ParseError::InvalidTarget);
+    }
+
+    if target.starts_with('/') || (target == "*" && method == Method::Options) {
+        return Ok((target, None));
+    }
+
+    let lowercase = target.to_ascii_lowercase();
+    if lowercase.starts_with("http://") || lowercase.starts_with("https://") {
+        let after_scheme = &target[target.find("://").unwrap() + 3..];
+        let (authority, target) = match after_scheme.find(['/', '?']) {
+            Some(start) if after_scheme[start..].starts_with('/') => {
+                (&after_scheme[..start], &after_scheme[start..])
+            }
+            Some(_) => return Err(ParseError::InvalidTarget),
+            None => (after_scheme, "/"),
+        };
+        if authority.is_empty() || authority.contains('@') {
+            return Err(ParseError::InvalidTarget);
+        }
+        return Ok((target, Some(authority)));
+    }
+
+    Err(ParseError::InvalidTarget)
+}
+
+fn split_target(target: &str) -> (String, Option<String>) {
+    match target.split_once('?') {
+        Some((path, query)) => (path.to_string(), Some(query.to_string())),
+        None => (target.to_string(), None),
+    }
+}
+
+/// Characters allowed in a token (methods, header names and cookie names).
+pub(super) fn is_token(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn parse(raw: &str) -> Result<Request, ParseError> {
+        Request::read_from(&mut Cursor::new(raw.as_bytes()))
+    }
+
+    fn status(raw: &str) -> Option<StatusCode> {
+        parse(raw).unwrap_err().status()
+    }
+
+    #[test]
+    fn simple_get() {
+        let request =
+            parse("GET /index.html?lang=en&x=1 HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+
+        assert_eq!(request.method, Method::Get);
+        assert_eq!(request.path, "/index.html");
+        assert_eq!(request.query.as_deref(), Some("lang=en&x=1"));
+        assert_eq!(request.version, Version::Http11);
+        assert_eq!(request.header("host"), Some("localhost"));
+        assert!(request.body.is_empty());
+    }
+
+    #[test]
+    fn query_params() {
+        let request = Request::new(Method::Get, "/search?q=a+b%26c&empty&x=1&x=2");
+
+        assert_eq!(request.query_param("q").as_deref(), Some("a b&c"));
+        assert_eq!(request.query_param("empty").as_deref(), Some(""));
+        assert_eq!(request.query_param("x").as_deref(), Some("1"));
+        assert_eq!(request.query_param("missing"), None);
+        assert_eq!(Request::new(Method::Get, "/").query_param("q"), None);
+
+        let names: Vec<String> = request
+            .query_params()
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect();
+        assert_eq!(names, ["q", "empty", "x", "x"]);
+    }
+
+    #[test]
+    fn headers_in_any_order() {
+        let request =
+            parse("GET / HTTP/1.1\r\nAccept: */*\r\nUser-Agent:  test \r\nHost: localhost\r\n\r\n")
+                .unwrap();
+
+        assert_eq!(request.header("User-Agent"), Some("test"));
+        assert_eq!(request.header("accept"), Some("*/*"));
+        assert_eq!(request.headers.len(), 3);
+    }
+
+    #[test]
+    fn body_with_content_length() {
+        let request =
+            parse("POST /form HTTP/1.1\r\nHost: a\r\nContent-Length: 5\r\n\r\nhello world")
+                .unwrap();
+
+        assert_eq!(request.method, Method::Post);
+        assert_eq!(request.body, b"hello");
+    }
+
+    #[test]
+    fn chunked_body() {
+        let mut reader = Cursor::new(
+            &b"POST /upload HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: chunked\r\n\r\n\
+               5\r\nhello\r\n1;ext=value\r\n \r\nA \r\n0123456789\r\n0\r\nExpires: never\r\n\r\n\
+               GET /next HTTP/1.1\r\nHost: a\r\n\r\n"[..],
+        );
+
+        let request = Request::read_from(&mut reader).unwrap();
+        assert_eq!(request.body, b"hello 0123456789");
+        assert_eq!(Request::read_from(&mut reader).unwrap().path, "/next");
+    }
+
+    #[test]
+    fn invalid_chunked_bodies() {
+        let chunked = |body: &str| {
+            status(&format!(
+                "POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: chunked\r\n\r\n{}",
+                body
+            ))
+        };
+
+        assert_eq!(chunked("z\r\n"), Some(StatusCode::BAD_REQUEST));
+        assert_eq!(chunked("\r\n"), Some(StatusCode::BAD_REQUEST));
+        assert_eq!(
+            chunked("2\r\nabc\r\n0\r\n\r\n"),
+            Some(StatusCode::BAD_REQUEST)
+        );
+        assert_eq!(
+            chunked("FFFFFFFFFFFFFFFFFFFF\r\n"),
+            Some(StatusCode::PAYLOAD_TOO_LARGE)
+        );
+        assert!(matches!(
+            parse("POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nab"),
+            Err(ParseError::UnexpectedEof)
+        ));
+        assert_eq!(
+            status("POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: gzip, chunked\r\n\r\n"),
+            Some(StatusCode::NOT_IMPLEMENTED)
+        );
+        assert_eq!(
+            status(
+                "POST / HTTP/1.1\r\nHost: a\r\nTransfer-Encoding: chunked\r\n\
+                 Content-Length: 3\r\n\r\n0\r\n\r\n"
+            ),
+            Some(StatusCode::BAD_REQUEST)
+        );
+    }
+
+    #[test]
+    fn consecutive_requests() {
+        let mut reader = Cursor::new(
+            b"GET /a HTTP/1.1\r\nHost: a\r\n\r\n\r\nPOST /b HTTP/1.0\nContent-Length: 2\n\nhi"
+                .to_vec(),
+        );
+
+        let first = Request::read_from(&mut reader).unwrap();
+        let second = Request::read_from(&mut reader).unwrap();
+        assert_eq!(first.path, "/a");
+        assert_eq!(second.path, "/b");
+        assert_eq!(second.version, Version::Http10);
+        assert_eq!(second.body, b"hi");
+        assert!(matches!(
+            Request::read_from(&mut reader),
+            Err(ParseError::ConnectionClosed)
+        ));
+    }
+
+    #[test]
+    fn long_request() {
+        let long_value = "x".repeat(4000);
+        let raw = format!(
+            "GET / HTTP/1.1\r\nHost: a\r\nX-Long: {}\r\nCookie: {}\r\n\r\n",
+            long_value, long_value
+        );
+
+        let request = parse(&raw).unwrap();
+        assert_eq!(request.header("x-long"), Some(long_value.as_str()));
+    }
+
+    #[test]
+    fn absolute_target() {
+        let request =
+            parse("GET http://localhost:7878/sleep?x HTTP/1.1\r\nHost: a\r\n\r\n").unwrap();
+        assert_eq!(request.path, "/sleep");
+        assert_eq!(request.query.as_deref(), Some("x"));
+        assert_eq!(request.header("Host"), Some("localhost:7878"));
+
+        let request = parse("GET http://localhost HTTP/1.1\r\nHost: a\r\n\r\n").unwrap();
+        assert_eq!(request.path, "/");
+        assert_eq!(request.header("Host"), Some("localhost"));
+
+        assert!(parse("GET http:///a HTTP/1.1\r\nHost: a\r\n\r\n").is_err());
+        assert!(parse("GET http://user@evil/ HTTP/1.1\r\nHost: a\r\n\r\n").is_err());
+    }
+
+    #[test]
+    fn malformed_requests() {
+        let bad_request = Some(StatusCode::BAD_REQUEST);
+
+        assert_eq!(status("GET /\r\n\r\n"), bad_request);
+        assert_eq!(status("GET  / HTTP/1.1\r\nHost: a\r\n\r\n"), bad_request);
+        assert_eq!(status("GET index HTTP/1.1\r\nHost: a\r\n\r\n"), bad_request);
+        assert_eq!(status("GET / HTTP/1.1\r\n\r\n"), bad_request);
+        assert_eq!(status("GET / HTTP/1.1\r\nHost a\r\n\r\n"), bad_request);
+        assert_eq!(status("GET / HTTP/1.1\r\nHost : a\r\n\r\n"), bad_request);
+        assert_eq!(
+            status("GET / HTTP/1.1\r\nHost: a\r\n folded\r\n\r\n"),
+            bad_request
+        );
+        assert_eq!(
+            status("POST / HTTP/1.1\r\nHost: a\r\nContent-Length: abc\r\n\r\n"),
+            bad_request
+        );
+        assert_eq!(
+            status("POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 1\r\nContent-Length: 2\r\n\r\n"),
+            bad_request
+        );
+    }
+
+    #[test]
+    fn unsupported_requests() {
+        assert_eq!(
+            status("BREW / HTTP/1.1\r\nHost: a\r\n\r\n"),
+            Some(StatusCode::NOT_IMPLEMENTED)
+        );
+        assert_eq!(
+            status("GET / HTTP/2.0\r\nHost: a\r\n\r\n"),
+            Some(StatusCode::HTTP_VERSION_NOT_SUPPORTED)
+        );
+        assert_eq!(
+            status("POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 99999999999\r\n\r\n"),
+            Some(StatusCode::PAYLOAD_TOO_LARGE)
+        );
+    }
+
+    #[test]
+    fn limits() {
+        let raw = format!("GET /{} HTTP/1.1\r\n\r\n", "a".repeat(MAX_REQUEST_LINE));
+        assert_eq!(status(&raw), Some(StatusCode::URI_TOO_LONG));
+
+        let raw = format!(
+            "GET / HTTP/1.1\r\n{}\r\n",
+            "X-Header: value\r\n".repeat(MAX_HEADERS + 1)
+        );
+        assert_eq!(
+            status(&raw),
+            Some(StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE)
+        );
+
+        let raw = format!(
+            "GET / HTTP/1.1\r\nX-Header: {}\r\n\r\n",
+            "a".repeat(MAX_HEADERS_SIZE)
+        );
+        assert_eq!(
+            status(&raw),
+            Some(StatusCode::REQUEST_HEADER_FIELDS_TOO_LARGE)
+        );
+    }
+
+    #[test]
+    fn custom_limits() {
+        let limits = Limits {
+            max_body_size: 4,
+            ..Limits::default()
+        };
+        let mut reader =
+            Cursor::new(&b"POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 5\r\n\r\nhello"[..]);
+
+        let err = Request::read_with_limits(&mut reader, &limits).unwrap_err();
+        assert_eq!(err.status(), Some(StatusCode::PAYLOAD_TOO_LARGE));
+    }
+
+    #[test]
+    fn incomplete_requests() {
+        assert!(matches!(parse(""), Err(ParseError::ConnectionClosed)));
+        assert!(matches!(
+            parse("GET / HTTP/1.1\r\nHost: a\r\n"),
+            Err(ParseError::UnexpectedEof)
+        ));
+        assert!(matches!(
+            parse("POST / HTTP/1.1\r\nHost: a\r\nContent-Length: 10\r\n\r\nabc"),
+            Err(ParseError::UnexpectedEof)
+        ));
+    }
+}
@@ -0,0 +1,377 @@
+use super::body::Body;
+use super::cookie::Cookie;
+use super::headers::Headers;
+use super::request::{self, Limits};
+use super::upgrade::Upgrade;
+use std::fmt;
+use std::io::{self, BufRead, Write};
+
+/// Status code of a HTTP response. Only the most common codes have a named constant, but any
+/// three digit code can be built with [`StatusCode::new`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct StatusCode(u16);
+
+impl StatusCode {
+    pub const SWITCHING_PROTOCOLS: StatusCode = StatusCode(101);
+    pub const OK: StatusCode = StatusCode(200);
+    pub const NO_CONTENT: StatusCode = StatusCode(204);
+    pub const PARTIAL_CONTENT: StatusCode = StatusCode(206);
+    pub const MOVED_PERMANENTLY: StatusCode = StatusCode(301);
+    pub const NOT_MODIFIED: StatusCode = StatusCode(304);
+    pub const BAD_REQUEST: StatusCode = StatusCode(400);
+    pub const FORBIDDEN: StatusCode = StatusCode(403);
+    pub const NOT_FOUND: StatusCode = StatusCode(404);
+    pub const METHOD_NOT_ALLOWED: StatusCode = StatusCode(405);
+    pub const REQUEST_TIMEOUT: StatusCode = StatusCode(408);
+    pub const LENGTH_REQUIRED: StatusCode = StatusCode(411);
+    pub const PAYLOAD_TOO_LARGE: StatusCode = StatusCode(413);
+    pub const URI_TOO_LONG: StatusCode = StatusCode(414);
+    pub const UNSUPPORTED_MEDIA_TYPE: StatusCode = StatusCode(415);
+    pub const RANGE_NOT_SATISFIABLE: StatusCode = StatusCode(416);
+    pub const MISDIRECTED_REQUEST: StatusCode = StatusCode(421);
+    pub const UNPROCESSABLE_ENTITY: StatusCode = StatusCode(422);
+    pub const UPGRADE_REQUIRED: StatusCode = StatusCode(426);
+    pub const TOO_MANY_REQUESTS: StatusCode = StatusCode(429);
+    pub const REQUEST_HEADER_FIELDS_TOO_LARGE: StatusCode = StatusCode(431);
+    pub const INTERNAL_SERVER_ERROR: StatusCode = StatusCode(500);
+    pub const NOT_IMPLEMENTED: StatusCode = StatusCode(501);
+    pub const BAD_GATEWAY: StatusCode = StatusCode(502);
+    pub const SERVICE_UNAVAILABLE: StatusCode = StatusCode(503);
+    pub const GATEWAY_TIMEOUT: StatusCode = StatusCode(504);
+    pub const HTTP_VERSION_NOT_SUPPORTED: StatusCode = StatusCode(505);
+
+    /// Creates a status code from its numeric value.
+    ///
+    /// # Panics
+    ///
+    /// The code must be in the range `100..=999`.
+    pub fn new(code: u16) -> StatusCode {
+        assert!((100..=999).contains(&code), "Invalid status code: {}", code);
+        StatusCode(code)
+    }
+
+    pub fn as_u16(&self) -> u16 {
+        self.0
+    }
+
+    /// Returns `false` for the informational codes, `204 No Content` and `304 Not Modified`,
+    /// which never have a body nor the headers framing it.
+    pub fn allows_body(&self) -> bool {
+        !matches!(self.0, 100..=199 | 204 | 304)
+    }
+
+    /// Standard reason phrase of the status code, or an empty string if the code is unknown.
+    pub fn reason(&self) -> &'static str {
+        match self.0 {
+            100 => "Continue",
+            101 => "Switching Protocols",
+            200 => "OK",
+            201 => "Created",
+            202 => "Accepted",
+            204 => "No Content",
+            206 => "Partial Content",
+            301 => "Moved Permanently",
+            302 => "Found",
+            303 => "See Other",
+            304 => "Not Modified",
+            307 => "Temporary Redirect",
+            308 => "Permanent Redirect",
+            400 => "Bad Request",
+            401 => "Unauthorized",
+            403 => "Forbidden",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            408 => "Request Timeout",
+            411 => "Length Required",
+            412 => "Precondition Failed",
+            413 => "Payload Too Large",
+            414 => "URI Too Long",
+            415 => "Unsupported Media Type",
+            416 => "Range Not Satisfiable",
+            421 => "Misdirected Request",
+            422 => "Unprocessable Entity",
+            426 => "Upgrade Required",
+            429 => "Too Many Requests",
+            431 => "Request Header Fields Too Large",
+            500 => "Internal Server Error",
+            501 => "Not Implemented",
+            502 => "Bad Gateway",
+            503 => "Service Unavailable",
+            504 => "Gateway Timeout",
+            505 => "HTTP Version Not Supported",
+            _ => "",
+        }
+    }
+
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.0)
+    }
+
+    pub fn is_client_error(&self) -> bool {
+        (400..500).contains(&self.0)
+    }
+
+    pub fn is_server_error(&self) -> bool {
+        (500..600).contains(&self.0)
+    }
+}
+
+impl fmt::Display for StatusCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.0, self.reason())
+    }
+}
+
+/// HTTP response sent back to the client. The framing headers are always computed from the
+/// [`Body`] when the response is written, so they must not be set manually: bodies of known
+/// length are sent with `Content-Length`, and streams with `Transfer-Encoding: chunked`.
+///
+/// # Example
+///
+/// ```rust
+/// let response = Response::new(StatusCode::OK)
+///     .with_header("Content-Type", "text/plain")
+///     .with_body("Hello!");
+///
+/// let file = Response::new(StatusCode::OK).with_body(Body::file(File::open("video.mp4")?)?);
+/// ```
+#[derive(Debug)]
+pub struct Response {
+    pub status: StatusCode,
+    pub headers: Headers,
+    pub body: Body,
+    /// Protocol taking over the connection once a `101 Switching Protocols` response is sent.
+    pub upgrade: Option<Upgrade>,
+}
+
+impl Response {
+    /// Creates a response with the given status, no headers and an empty body.
+    pub fn new(status: StatusCode) -> Response {
+        Response {
+            status,
+            headers: Headers::new(),
+            body: Body::empty(),
+            upgrade: None,
+        }
+    }
+
+    /// Creates a `200 OK` response with a HTML body.
+    pub fn html(body: impl Into<Body>) -> Response {
+        Response::new(StatusCode::OK)
+            .with_header("Content-Type", "text/html; charset=utf-8")
+            .with_body(body)
+    }
+
+    /// Creates a plain text response whose body is the reason phrase of the status. Used for
+    /// errors generated by the server itself.
+    pub fn error(status: StatusCode) -> Response {
+        Response::new(status)
+            .with_header("Content-Type", "text/plain; charset=utf-8")
+            .with_body(status.to_string())
+    }
+
+    pub fn with_status(mut self, status: StatusCode) -> Response {
+        self.status = status;
+        self
+    }
+
+    /// Sets a header, replacing any previous value with the same name.
+    pub fn with_header(mut self, name: impl Into<String>, value: impl Into<String>) -> Response {
+        self.headers.insert(name, value);
+        self
+    }
+
+    /// Adds a `Set-Cookie` header, keeping the cookies already set.
+    pub fn with_cookie(mut self, cookie: Cookie) -> Response {
+        self.headers.append("Set-Cookie", cookie.to_string());
+        self
+    }
+
+    pub fn with_body(mut self, body: impl Into<Body>) -> Response {
+        self.body = body.into();
+        self
+    }
+
+    /// Hands the connection over to another protocol after the response, which should be a
+    /// `101 Switching Protocols` with the `Upgrade` and `Connection: Upgrade` headers.
+    pub fn with_upgrade(mut self, upgrade: Upgrade) -> Response {
+        self.upgrade = Some(upgrade);
+        self
+    }
+
+    /// Writes the status line, the headers and the body into the writer. The writer is not
+    /// flushed unless the body is a stream, so multiple responses can be buffered together.
+    pub fn write_to<W: Write>(self, writer: &mut W) -> io::Result<()> {
+        self.write_head(writer, false)?;
+
+        if !self.status.allows_body() {
+            Ok(())
+        } else if self.body.len().is_some() {
+            self.body.write_to(writer)
+        } else {
+            self.body.write_chunked_to(writer)
+        }
+    }
+
+    /// Writes the response for a HTTP/1.0 client, which does not understand the chunked coding.
+    /// Streams are sent as they are, without `Content-Length`, so the connection must be closed
+    /// afterwards to signal the end of the body.
+    pub fn write_unchunked_to<W: Write>(self, writer: &mut W) -> io::Result<()> {
+        write!(writer, "HTTP/1.1 {}\r\n", self.status)?;
+        write!(writer, "{}", self.headers)?;
+        match self.body.len() {
+            _ if !self.status.allows_body() => writer.write_all(b"\r\n")?,
+            Some(len) => write!(writer, "Content-Length: {}\r\n\r\n", len)?,
+            None => writer.write_all(b"\r\n")?,
+        }
+
+        match self.status.allows_body() {
+            true => self.body.write_to(writer),
+            false => Ok(()),
+        }
+    }
+
+    /// Writes only the status line and the headers, as required for the responses to `HEAD`
+    /// requests. The framing headers are still the ones of the body, unless the body is empty
+    /// and the response already has a `Content-Length`, like the answer of another server to
+    /// the same `HEAD` request forwarded by a [`Proxy`](super::proxy::Proxy).
+    pub fn write_head_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        self.write_head(writer, true)
+    }
+
+    fn write_head<W: Write>(&self, writer: &mut W, head_only: bool) -> io::Result<()> {
+        write!(writer, "HTTP/1.1 {}\r\n", self.status)?;
+        write!(writer, "{}", self.headers)?;
+        match self.body.len() {
+            _ if !self.status.allows_body() => writer.write_all(b"\r\n"),
+            Some(0) if head_only && self.headers.contains("Content-Length") => {
+                writer.write_all(b"\r\n")
+            }
+            Some(len) => write!(writer, "Content-Length: {}\r\n\r\n", len),
+            None => writer.write_all(b"Transfer-Encoding: chunked\r\n\r\n"),
+        }
+    }
+}
+
+/// Reads the status line and the headers of a response sent by another server, leaving the
+/// reader at the start of the body.
+pub(super) fn read_head<R: BufRead>(
+    reader: &mut R,
+    limits: &Limits,
+) -> io::Result<(StatusCode, Headers)> {
+    let mut line = Vec::new();
+    if request::read_line(reader, limits.max_request_line, &mut line)?.is_none() {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+
+    let line = String::from_utf8_lossy(&line);
+    let status = line
+        .strip_prefix("HTTP/1.1 ")
+        .or_else(|| line.strip_prefix("HTTP/1.0 "))
+        .and_then(|status| status.get(..3))
+        .filter(|code| code.bytes().all(|b| b.is_ascii_digit()))
+        .and_then(|code| code.parse().ok())
+        .filter(|code| (100..=999).contains(code))
+        .map(StatusCode::new)
+        .ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("invalid status line: {}", line),
+            )
+        })?;
+    let headers = request::read_headers(reader, limits)?;
+
+    Ok((status, headers))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn status_display() {
+        assert_eq!(StatusCode::OK.to_string(), "200 OK");
+        assert_eq!(StatusCode::NOT_FOUND.to_string(), "404 Not Found");
+        assert_eq!(StatusCode::new(299).to_string(), "299 ");
+    }
+
+    #[test]
+    #[should_panic]
+    fn invalid_status() {
+        StatusCode::new(42);
+    }
+
+    #[test]
+    fn write_response() {
+        let response = Response::new(StatusCode::OK)
+            .with_header("Content-Type", "text/plain")
+            .with_body("Hello!");
+
+        let mut output = Vec::new();
+        response.write_head_to(&mut output).unwrap();
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nContent-Length: 6\r\n\r\n"
+        );
+
+        let mut output = Vec::new();
+        response.write_to(&mut output).unwrap();
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nContent-Length: 6\r\n\r\nHello!"
+        );
+    }
+
+    #[test]
+    fn write_without_body() {
+        let response = Response::new(StatusCode::NOT_MODIFIED).with_header("ETag", "\"1\"");
+
+        let mut output = Vec::new();
+        response.write_to(&mut output).unwrap();
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "HTTP/1.1 304 Not Modified\r\nETag: \"1\"\r\n\r\n"
+        );
+
+        // A body set on such a response is never sent, also to HTTP/1.0 clients.
+        let mut output = Vec::new();
+        Response::new(StatusCode::NO_CONTENT)
+            .with_body("ignored")
+            .write_unchunked_to(&mut output)
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "HTTP/1.1 204 No Content\r\n\r\n"
+        );
+    }
+
+    #[test]
+    fn write_stream() {
+        let stream = || Body::stream(io::Cursor::new(b"Hello!".to_vec()));
+
+        let mut output = Vec::new();
+        Response::new(StatusCode::OK)
+            .with_body(stream())
+            .write_to(&mut output)
+            .unwrap();
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n6\r\nHello!\r\n0\r\n\r\n"
+        );
+
+        let mut output = Vec::new();
+        Response::new(StatusCode::OK)
+            .with_body(stream())
+            .write_unchunked_to(&mut output)
+            .unwrap();
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "HTTP/1.1 200 OK\r\n\r\nHello!"
+        );
+    }
+}
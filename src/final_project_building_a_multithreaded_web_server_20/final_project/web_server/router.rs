@@ -0,0 +1,569 @@
+use super::cors::{self, Cors};
+use super::request::{Method, Request};
+use super::response::{Response, StatusCode};
+
+/// Anything that can turn a request into a response. Implemented for all the closures with the
+/// signature `Fn(&mut Request) -> Response`, so most of the time a closure is enough.
+///
+/// The request is mutable so that the [`Router`] can fill in the path parameters and so that
+/// handlers wrapping other handlers can modify the request before passing it down.
+pub trait Handler: Send + Sync + 'static {
+    fn handle(&self, request: &mut Request) -> Response;
+}
+
+impl<F> Handler for F
+where
+    F: Fn(&mut Request) -> Response + Send + Sync + 'static,
+{
+    fn handle(&self, request: &mut Request) -> Response {
+        self(request)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Segment {
+    /// Segment that must match exactly.
+    Static(String),
+    /// Segment starting with `:`, matches any single segment.
+    Param(String),
+    /// Segment starting with `*`, matches the rest of the path (including nothing). Only allowed
+    /// as the last segment of the pattern.
+    Wildcard(String),
+}
+
+/// Parsed route pattern such as `/users/:id` or `/files/*path`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Pattern {
+    segments: Vec<Segment>,
+}
+
+impl Pattern {
+    /// # Panics
+    ///
+    /// Panics if the pattern does not start with `/`, if a parameter has no name or if a wildcard
+    /// is not the last segment.
+    fn parse(pattern: &str) -> Pattern {
+        assert!(
+            pattern.starts_with('/'),
+            "Route pattern must start with '/': {}",
+            pattern
+        );
+
+        let parts: Vec<&str> = split_path(pattern).collect();
+        let mut segments = Vec::with_capacity(parts.len());
+
+        for (i, part) in parts.iter().enumerate() {
+            let segment = if let Some(name) = part.strip_prefix(':') {
+                assert!(!name.is_empty(), "Unnamed parameter in route: {}", pattern);
+                Segment::Param(name.to_string())
+            } else if let Some(name) = part.strip_prefix('*') {
+                assert!(
+                    i == parts.len() - 1,
+                    "Wildcard must be the last segment of the route: {}",
+                    pattern
+                );
+                Segment::Wildcard(name.to_string())
+            } else {
+                Segment::Static(part.to_string())
+            };
+            segments.push(segment);
+        }
+
+        Pattern { segments }
+    }
+
+    /// Returns the captured parameters if the path matches the pattern. Anonymous wildcards
+    /// (`*`) are captured with the name `*`.
+    fn matches(&self, path: &str) -> Option<Vec<(String, String)>> {
+        let mut parts = split_path(path);
+        let mut params = Vec::new();
+
+        for segment in &self.segments {
+            match segment {
+                Segment::Static(expected) => {
+                    if parts.next()? != expected {
+                        return None;
+                    }
+                }
+                Segment::Param(name) => {
+                    params.push((name.clone(), parts.next()?.to_string()));
+                }
+                Segment::Wildcard(name) => {
+                    let name = if name.is_empty() { "*" } else { name };
+                    let rest: Vec<&str> = parts.by_ref().collect();
+                    params.push((name.to_string(), rest.join("/")));
+                }
+            }
+        }
+
+        match parts.next() {
+            Some(_) => None,
+            None => Some(params),
+        }
+    }
+}
+
+/// Returns `true` if the pattern is valid for [`Router::route`]: it starts with `/`, its
+/// parameters have a name and its wildcard, if any, is the last segment.
+pub fn is_valid_pattern(pattern: &str) -> bool {
+    let parts: Vec<&str> = split_path(pattern).collect();
+    pattern.starts_with('/')
+        && parts
+            .iter()
+            .enumerate()
+            .all(|(i, part)| *part != ":" && (!part.starts_with('*') || i == parts.len() - 1))
+}
+
+/// Splits a path into its segments, ignoring empty ones (so `/a//b/` is the same as `/a/b`).
+fn split_path(path: &str) -> impl Iterator<Item = &str> {
+    path.split('/').filter(|segment| !segment.is_empty())
+}
+
+struct Route {
+    method: Method,
+    pattern: Pattern,
+    handler: Box<dyn Handler>,
+}
+
+struct Mount {
+    prefix: String,
+    handler: Box<dyn Handler>,
+}
+
+/// Dispatches each request to the handler registered for its method and path.
+///
+/// Patterns are made of `/` separated segments, which can be:
+///
+/// - `name`: a segment that must be equal to `name`.
+/// - `:name`: any single segment, available as [`Request::param`]`("name")`.
+/// - `*name`: the rest of the path, which can contain `/`. Must be the last segment. If the name
+///   is omitted the value is stored as `*`.
+///
+/// The routes are tried in the order they were registered and the first match is used. When the
+/// path matches a route but not its method, the router answers with `405 Method Not Allowed` and
+/// an `Allow` header, even if the path is under a mount. If no route matches the path, the
+/// handlers mounted with [`Router::mount`] are tried, from the longest prefix to the shortest,
+/// and then the router answers with `404 Not Found` (or the handler set with
+/// [`Router::not_found`]). `HEAD` requests are handled by the `GET` route when
+/// there is no specific `HEAD` route.
+///
+/// # Example
+///
+/// ```rust
+/// let router = Router::new()
+///     .get("/", |_: &mut Request| Response::html("<h1>Hello!</h1>"))
+///     .get("/users/:id", |request: &mut Request| {
+///         Response::html(format!("User {}", request.param("id").unwrap()))
+///     })
+///     .mount("/api", api_router);
+/// ```
+#[derive(Default)]
+pub struct Router {
+    routes: Vec<Route>,
+    mounts: Vec<Mount>,
+    cors: Vec<(Pattern, Cors)>,
+    not_found: Option<Box<dyn Handler>>,
+}
+
+impl Router {
+    pub fn new() -> Router {
+        Router::default()
+    }
+
+    /// Registers a handler for the given method and pattern.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the pattern is invalid, see the [`Router`] documentation.
+    pub fn route<H: Handler>(mut self, method: Method, pattern: &str, handler: H) -> Router {
+        self.routes.push(Route {
+            method,
+            pattern: Pattern::parse(pattern),
+            handler: Box::new(handler),
+        });
+        self
+    }
+
+    pub fn get<H: Handler>(self, pattern: &str, handler: H) -> Router {
+        self.route(Method::Get, pattern, handler)
+    }
+
+    pub fn post<H: Handler>(self, pattern: &str, handler: H) -> Router {
+        self.route(Method::Post, pattern, handler)
+    }
+
+    pub fn put<H: Handler>(self, pattern: &str, handler: H) -> Router {
+        self.route(Method::Put, pattern, handler)
+    }
+
+    pub fn delete<H: Handler>(self, pattern: &str, handler: H) -> Router {
+        self.route(Method::Delete, pattern, handler)
+    }
+
+    pub fn patch<H: Handler>(self, pattern: &str, handler: H) -> Router {
+        self.route(Method::Patch, pattern, handler)
+    }
+
+    /// Delegates all the requests whose path starts with `prefix` to the handler, whatever their
+    /// method. The prefix is removed from the path while the handler runs, so a router mounted at
+    /// `/api` sees `/api/users` as `/users`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the prefix does not start with `/`.
+    pub fn mount<H: Handler>(mut self, prefix: &str, handler: H) -> Router {
+        assert!(
+            prefix.starts_with('/'),
+            "Mount prefix must start with '/': {}",
+            prefix
+        );
+
+        self.mounts.push(Mount {
+            prefix: prefix.trim_end_matches('/').to_string(),
+            handler: Box::new(handler),
+        });
+        self.mounts
+            .sort_by_key(|mount| std::cmp::Reverse(mount.prefix.len()));
+        self
+    }
+
+    /// Opts the paths matching the pattern in to [`Cors`]: their preflight requests are answered
+    /// by the router, and the `Access-Control-*` headers are added to the responses of the rest
+    /// of their requests, whether they are handled by a route or a mount. The first matching
+    /// pattern is used.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the pattern is invalid, see the [`Router`] documentation.
+    pub fn cors(mut self, pattern: &str, cors: Cors) -> Router {
+        self.cors.push((Pattern::parse(pattern), cors));
+        self
+    }
+
+    /// Handler used when no route matches the request.
+    pub fn not_found<H: Handler>(mut self, handler: H) -> Router {
+        self.not_found = Some(Box::new(handler));
+        self
+    }
+
+    fn dispatch(&self, request: &mut Request) -> Response {
+        let Some((_, cors)) = self
+            .cors
+            .iter()
+            .find(|(pattern, _)| pattern.matches(&request.path).is_some())
+        else {
+            return self.dispatch_route(request);
+        };
+
+        if cors::is_preflight(request) {
+            // Same precedence as `dispatch_route`: the routes, then the mounts.
+            let methods: Vec<Method> = self
+                .routes
+                .iter()
+                .filter(|route| route.pattern.matches(&request.path).is_some())
+                .map(|route| route.method)
+                .collect();
+            let mounted = self
+                .mounts
+                .iter()
+                .any(|mount| strip_mount_prefix(&request.path, &mount.prefix).is_some());
+            if methods.is_empty() && mounted {
+                return cors.preflight(request, None);
+            }
+            return cors.preflight(request, Some(&methods));
+        }
+        let mut response = self.dispatch_route(request);
+        cors.apply(request, &mut response);
+        response
+    }
+
+    fn dispatch_route(&self, request: &mut Request) -> Response {
+        let mut allowed = Vec::new();
+        let mut get_route = None;
+
+        for route in &self.routes {
+            let params = match route.pattern.matches(&request.path) {
+                Some(params) => params,
+                None => continue,
+            };
+
+            if route.method == request.method {
+                return call_route(route, params, request);
+            }
+            if route.method == Method::Get && request.method == Method::Head && get_route.is_none()
+            {
+                get_route = Some((route, params));
+            }
+            if !allowed.contains(&route.method) {
+                allowed.push(route.method);
+            }
+        }
+
+        if let Some((route, params)) = get_route {
+            return call_route(route, params, request);
+        }
+
+        if !allowed.is_empty() {
+            if allowed.contains(&Method::Get) && !allowed.contains(&Method::Head) {
+                allowed.push(Method::Head);
+            }
+            let allowed: Vec<&str> = allowed.iter().map(Method::as_str).collect();
+            return Response::error(StatusCode::METHOD_NOT_ALLOWED)
+                .with_header("Allow", allowed.join(", "));
+        }
+
+        for mount in &self.mounts {
+            if let Some(rest) = strip_mount_prefix(&request.path, &mount.prefix) {
+                let rest = rest.to_string();
+                let path = std::mem::replace(&mut request.path, rest);
+                let response = mount.handler.handle(request);
+                request.path = path;
+                return response;
+            }
+        }
+
+        match &self.not_found {
+            Some(handler) => handler.handle(request),
+            None => Response::error(StatusCode::NOT_FOUND),
+        }
+    }
+}
+
+impl Handler for Router {
+    fn handle(&self, request: &mut Request) -> Response {
+        self.dispatch(request)
+    }
+}
+
+fn call_route(route: &Route, params: Vec<(String, String)>, request: &mut Request) -> Response {
+    request.params.extend(params);
+    route.handler.handle(request)
+}
+
+/// Returns the path relative to the prefix (always starting with `/`) if the prefix matches whole
+/// segments of the path.
+fn strip_mount_prefix<'a>(path: &'a str, prefix: &str) -> Option<&'a str> {
+    let rest = path.strip_prefix(prefix)?;
+    if rest.is_empty() {
+        Some("/")
+    } else if rest.starts_with('/') {
+        Some(rest)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(value: &'static str) -> impl Handler {
+        move |_: &mut Request| Response::new(StatusCode::OK).with_body(value)
+    }
+
+    fn body(router: &Router, method: Method, target: &str) -> (StatusCode, String) {
+        let response = router.handle(&mut Request::new(method, target));
+        (
+            response.status,
+            String::from_utf8(response.body.into_bytes().unwrap()).unwrap(),
+        )
+    }
+
+    #[test]
+    fn static_routes() {
+        let router = Router::new()
+            .get("/", text("index"))
+            .get("/about", text("about"))
+            .post("/about", text("post about"));
+
+        assert_eq!(body(&router, Method::Get, "/").1, "index");
+        assert_eq!(body(&router, Method::Get, "/about/").1, "about");
+        assert_eq!(body(&router, Method::Post, "/about").1, "post about");
+        assert_eq!(
+            body(&router, Method::Get, "/other").0,
+            StatusCode::NOT_FOUND
+        );
+    }
+
+    #[test]
+    fn path_parameters() {
+        let router = Router::new().get("/users/:id/posts/:post", |request: &mut Request| {
+            let body = format!(
+                "{}-{}",
+                request.param("id").unwrap(),
+                request.param("post").unwrap()
+            );
+            Response::new(StatusCode::OK).with_body(body)
+        });
+
+        assert_eq!(body(&router, Method::Get, "/users/42/posts/7").1, "42-7");
+        assert_eq!(
+            body(&router, Method::Get, "/users/42/posts").0,
+            StatusCode::NOT_FOUND
+        );
+    }
+
+    #[test]
+    fn wildcards() {
+        let router = Router::new()
+            .get("/files/*path", |request: &mut Request| {
+                Response::new(StatusCode::OK).with_body(request.param("path").unwrap().to_string())
+            })
+            .get("/*", |request: &mut Request| {
+                Response::new(StatusCode::OK)
+                    .with_body(format!("any {}", request.param("*").unwrap()))
+            });
+
+        assert_eq!(
+            body(&router, Method::Get, "/files/a/b/c.txt").1,
+            "a/b/c.txt"
+        );
+        assert_eq!(body(&router, Method::Get, "/files").1, "");
+        assert_eq!(body(&router, Method::Get, "/x/y").1, "any x/y");
+    }
+
+    #[test]
+    fn first_match_wins() {
+        let router = Router::new()
+            .get("/users/me", text("me"))
+            .get("/users/:id", text("other"));
+
+        assert_eq!(body(&router, Method::Get, "/users/me").1, "me");
+        assert_eq!(body(&router, Method::Get, "/users/1").1, "other");
+    }
+
+    #[test]
+    fn method_not_allowed() {
+        let router = Router::new()
+            .get("/items/:id", text("get"))
+            .delete("/items/:id", text("delete"));
+
+        let response = router.handle(&mut Request::new(Method::Post, "/items/1"));
+        assert_eq!(response.status, StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(response.headers.get("Allow"), Some("GET, DELETE, HEAD"));
+
+        // A mount does not take the requests of the routes under it.
+        let router = router.mount("/", text("mounted"));
+        let response = router.handle(&mut Request::new(Method::Post, "/items/1"));
+        assert_eq!(response.status, StatusCode::METHOD_NOT_ALLOWED);
+        assert_eq!(body(&router, Method::Post, "/other").1, "mounted");
+    }
+
+    #[test]
+    fn head_uses_get() {
+        let router = Router::new().get("/", text("index"));
+
+        assert_eq!(
+            body(&router, Method::Head, "/"),
+            (StatusCode::OK, "index".to_string())
+        );
+    }
+
+    #[test]
+    fn mounts() {
+        let api = Router::new().get("/users/:id", |request: &mut Request| {
+            Response::new(StatusCode::OK).with_body(format!(
+                "{} {}",
+                request.path,
+                request.param("id").unwrap()
+            ))
+        });
+        let router = Router::new()
+            .get("/", text("index"))
+            .mount("/api", api)
+            .mount("/api/v2/", text("v2"));
+
+        assert_eq!(body(&router, Method::Get, "/api/users/5").1, "/users/5 5");
+        assert_eq!(body(&router, Method::Get, "/api/v2/users/5").1, "v2");
+        assert_eq!(body(&router, Method::Get, "/api").0, StatusCode::NOT_FOUND);
+        assert_eq!(body(&router, Method::Get, "/apis").0, StatusCode::NOT_FOUND);
+
+        // The path is restored after the mounted handler returns.
+        let mut request = Request::new(Method::Get, "/api/users/5");
+        router.handle(&mut request);
+        assert_eq!(request.path, "/api/users/5");
+    }
+
+    #[test]
+    fn custom_not_found() {
+        let router = Router::new().not_found(|request: &mut Request| {
+            Response::new(StatusCode::NOT_FOUND).with_body(format!("{} not found", request.path))
+        });
+
+        assert_eq!(
+            body(&router, Method::Get, "/missing"),
+            (StatusCode::NOT_FOUND, "/missing not found".to_string())
+        );
+    }
+
+    #[test]
+    fn cors() {
+        let router = Router::new()
+            .get("/api/users", text("users"))
+            .post("/api/users", text("created"))
+            .get("/private", text("private"))
+            .mount("/api/files", text("files"))
+            .mount("/", text("fallback"))
+            .cors(
+                "/api/*",
+                Cors::new().allow_origin("https://app.example.com"),
+            );
+        let send = |method: Method, target: &str, preflight: Option<&str>| {
+            let mut request =
+                Request::new(method, target).with_header("Origin", "https://app.example.com");
+            if let Some(method) = preflight {
+                request = request.with_header("Access-Control-Request-Method", method);
+            }
+            router.handle(&mut request)
+        };
+
+        let response = send(Method::Options, "/api/users", Some("POST"));
+        assert_eq!(response.status, StatusCode::NO_CONTENT);
+        assert_eq!(
+            response.headers.get("Access-Control-Allow-Methods"),
+            Some("GET, POST, HEAD")
+        );
+        // The routes under a mount keep their methods.
+        let response = send(Method::Options, "/api/users", Some("DELETE"));
+        assert_eq!(response.status, StatusCode::FORBIDDEN);
+        // The mounted handlers accept any method.
+        let response = send(Method::Options, "/api/files/a.txt", Some("PUT"));
+        assert_eq!(response.status, StatusCode::NO_CONTENT);
+        assert_eq!(
+            response.headers.get("Access-Control-Allow-Methods"),
+            Some("PUT")
+        );
+        let response = send(Method::Options, "/api/files/a.txt", Some("FETCH"));
+        assert_eq!(response.status, StatusCode::FORBIDDEN);
+
+        let response = send(Method::Get, "/api/files/a.txt", None);
+        assert_eq!(
+            response.headers.get("Access-Control-Allow-Origin"),
+            Some("https://app.example.com")
+        );
+        let response = send(Method::Delete, "/api/users", None);
+        assert_eq!(response.status, StatusCode::METHOD_NOT_ALLOWED);
+        assert!(response.headers.contains("Access-Control-Allow-Origin"));
+
+        // The paths that did not opt in are left alone.
+        let response = send(Method::Get, "/private", None);
+        assert!(!response.headers.contains("Access-Control-Allow-Origin"));
+        let response = send(Method::Options, "/private", Some("GET"));
+        assert_eq!(response.status, StatusCode::METHOD_NOT_ALLOWED);
+    }
+
+    #[test]
+    fn valid_patterns() {
+        assert!(is_valid_pattern("/"));
+        assert!(is_valid_pattern("/users/:id/*rest"));
+        assert!(!is_valid_pattern("users"));
+        assert!(!is_valid_pattern("/users/:"));
+        assert!(!is_valid_pattern("/*rest/more"));
+    }
+
+    #[test]
+    #[should_panic]
+    fn wildcard_not_last() {
+        Router::new().get("/*rest/more", text(""));
+    }
+}
@@ -0,0 +1,761 @@
+use super::config::{Backend, Config, ConfigError};
+#[cfg(feature = "tls")]
+use super::connection::handle_tls_connection;
+use super::connection::{handle_connection, Connections};
+use super::event_loop;
+use super::metrics::Metrics;
+use super::response::{Response, StatusCode};
+use super::router::Handler;
+use super::tls::{self, TlsError};
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Read};
+use std::net::{
+    IpAddr, Ipv4Addr, Ipv6Addr, Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs,
+};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::Duration;
+use threadpool::ThreadPool;
+
+/// Value of the `Retry-After` header sent when the server is too busy to accept a connection.
+pub const RETRY_AFTER: Duration = Duration::from_secs(1);
+
+/// Errors that prevent the server from starting.
+#[derive(Debug)]
+pub enum ServerError {
+    Config(ConfigError),
+    /// HTTPS is configured but cannot be served.
+    Tls(TlsError),
+    /// The listener could not be bound, usually because the port is already in use.
+    Bind {
+        address: String,
+        source: io::Error,
+    },
+}
+
+impl fmt::Display for ServerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ServerError::Config(err) => write!(f, "invalid configuration: {}", err),
+            ServerError::Tls(err) => write!(f, "could not set up HTTPS: {}", err),
+            ServerError::Bind { address, source } => {
+                write!(f, "could not bind to {}: {}", address, source)
+            }
+        }
+    }
+}
+
+impl Error for ServerError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ServerError::Config(err) => Some(err),
+            ServerError::Tls(err) => Some(err),
+            ServerError::Bind { source, .. } => Some(source),
+        }
+    }
+}
+
+impl From<ConfigError> for ServerError {
+    fn from(err: ConfigError) -> Self {
+        ServerError::Config(err)
+    }
+}
+
+struct HandleState {
+    address: SocketAddr,
+    shutting_down: AtomicBool,
+    stopped: Mutex<bool>,
+    stopped_changed: Condvar,
+}
+
+/// Handle used to stop a running [`Server`] from another thread. It can be cloned freely.
+#[derive(Clone)]
+pub struct ServerHandle {
+    state: Arc<HandleState>,
+}
+
+impl ServerHandle {
+    fn new(address: SocketAddr) -> ServerHandle {
+        ServerHandle {
+            state: Arc::new(HandleState {
+                address,
+                shutting_down: AtomicBool::new(false),
+                stopped: Mutex::new(false),
+                stopped_changed: Condvar::new(),
+            }),
+        }
+    }
+
+    /// Address the server is listening on.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.state.address
+    }
+
+    pub fn is_shutting_down(&self) -> bool {
+        self.state.shutting_down.load(Ordering::SeqCst)
+    }
+
+    /// Stops the server and blocks until all the connections are closed and the threads joined.
+    ///
+    /// Must not be called from a handler of the same server, as the server waits for the handler
+    /// to finish. Use [`ServerHandle::stop`] instead.
+    pub fn shutdown(&self) {
+        self.stop();
+        self.wait();
+    }
+
+    /// Tells the server to stop accepting connections, without waiting for it to finish.
+    pub fn stop(&self) {
+        if self.state.shutting_down.swap(true, Ordering::SeqCst) {
+            return;
+        }
+
+        // The server is blocked accepting connections, so a connection is needed to wake it up.
+        let mut address = self.state.address;
+        if address.ip().is_unspecified() {
+            address.set_ip(match address.ip() {
+                IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::LOCALHOST),
+                IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::LOCALHOST),
+            });
+        }
+        let _ = TcpStream::connect_timeout(&address, Duration::from_secs(1));
+    }
+
+    /// Blocks until the server has stopped.
+    pub fn wait(&self) {
+        let mut stopped = self.state.stopped.lock().unwrap();
+        while !*stopped {
+            stopped = self.state.stopped_changed.wait(stopped).unwrap();
+        }
+    }
+
+    /// Stops the server when the process receives `SIGINT` (`Ctrl+C`) or `SIGTERM`.
+    ///
+    /// Only one signal handler can be set per process, so this method fails if it was already
+    /// called before.
+    pub fn stop_on_signals(&self) -> io::Result<()> {
+        let handle = self.clone();
+
+        ctrlc::set_handler(move || {
+            println!("Signal received, shutting down.");
+            handle.stop();
+        })
+        .map_err(|err| io::Error::other(err.to_string()))
+    }
+
+    fn mark_stopped(&self) {
+        *self.state.stopped.lock().unwrap() = true;
+        self.state.stopped_changed.notify_all();
+    }
+}
+
+/// Builder of a [`Server`]. The options start with the values of [`Config::default`] and can be
+/// changed one by one, or loaded from a configuration file and the environment variables, as
+/// described in [`Config`].
+///
+/// # Example
+///
+/// ```rust
+/// let builder = ServerBuilder::new()
+///     .config_file("web_server.toml")?
+///     .env()?
+///     .port(0)
+///     .workers(8);
+/// let router = default_router(builder.config());
+/// let server = builder.build(router)?;
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct ServerBuilder {
+    config: Config,
+    metrics: Option<Arc<Metrics>>,
+}
+
+impl ServerBuilder {
+    pub fn new() -> ServerBuilder {
+        ServerBuilder::default()
+    }
+
+    pub fn from_config(config: Config) -> ServerBuilder {
+        ServerBuilder {
+            config,
+            metrics: None,
+        }
+    }
+
+    /// Host name or IP address the server binds to.
+    pub fn address(mut self, address: impl Into<String>) -> ServerBuilder {
+        self.config.address = address.into();
+        self
+    }
+
+    /// Port the server binds to. Use `0` to let the system choose a free port, available through
+    /// [`Server::local_addr`].
+    pub fn port(mut self, port: u16) -> ServerBuilder {
+        self.config.port = port;
+        self
+    }
+
+    /// Number of threads serving the connections. Must be greater than zero.
+    pub fn workers(mut self, workers: usize) -> ServerBuilder {
+        self.config.workers = workers;
+        self
+    }
+
+    /// How the connections are waited on: by the threads of the pool or by an event loop.
+    pub fn backend(mut self, backend: Backend) -> ServerBuilder {
+        self.config.backend = backend;
+        self
+    }
+
+    pub fn document_root(mut self, document_root: impl Into<PathBuf>) -> ServerBuilder {
+        self.config.document_root = document_root.into();
+        self
+    }
+
+    /// Time a persistent connection can stay idle before it is closed. Must be greater than zero,
+    /// like the other timeouts of the connections.
+    pub fn keep_alive_timeout(mut self, timeout: Duration) -> ServerBuilder {
+        self.config.keep_alive_timeout = timeout;
+        self
+    }
+
+    /// Time given to the in-flight requests to finish once the shutdown starts.
+    pub fn shutdown_timeout(mut self, timeout: Duration) -> ServerBuilder {
+        self.config.shutdown_timeout = timeout;
+        self
+    }
+
+    /// Maximum time receiving a request, see [`Config::read_timeout`].
+    pub fn read_timeout(mut self, timeout: Duration) -> ServerBuilder {
+        self.config.read_timeout = timeout;
+        self
+    }
+
+    /// Maximum time waiting for the client to accept more bytes of a response.
+    pub fn write_timeout(mut self, timeout: Duration) -> ServerBuilder {
+        self.config.write_timeout = timeout;
+        self
+    }
+
+    /// Maximum number of accepted connections waiting for a free thread. Further connections are
+    /// answered with `503 Service Unavailable` and a `Retry-After` header. Must be greater than
+    /// zero.
+    pub fn max_queued_connections(mut self, max: usize) -> ServerBuilder {
+        self.config.max_queued_connections = max;
+        self
+    }
+
+    /// Maximum number of connections the event loop keeps open, see [`Config::max_connections`].
+    /// Must be greater than zero.
+    pub fn max_connections(mut self, max: usize) -> ServerBuilder {
+        self.config.max_connections = max;
+        self
+    }
+
+    /// Maximum combined length of the header lines of a request. Bigger headers are answered with
+    /// `431 Request Header Fields Too Large`.
+    pub fn max_headers_size(mut self, size: usize) -> ServerBuilder {
+        self.config.max_headers_size = size;
+        self
+    }
+
+    /// Maximum size of the body of a request. Bigger requests are answered with
+    /// `413 Payload Too Large`, except for the multipart uploads streamed to the handlers, see
+    /// [`Request::body_reader`](super::request::Request::body_reader).
+    pub fn max_body_size(mut self, size: usize) -> ServerBuilder {
+        self.config.max_body_size = size;
+        self
+    }
+
+    /// Maximum size of the multipart uploads streamed to the handlers, see
+    /// [`Config::max_upload_size`].
+    pub fn max_upload_size(mut self, size: usize) -> ServerBuilder {
+        self.config.max_upload_size = size;
+        self
+    }
+
+    /// Serves HTTPS with the certificate chain and private key of the PEM files. Needs the `tls`
+    /// feature.
+    pub fn tls(mut self, cert: impl Into<PathBuf>, key: impl Into<PathBuf>) -> ServerBuilder {
+        self.config.tls_cert = Some(cert.into());
+        self.config.tls_key = Some(key.into());
+        self
+    }
+
+    /// Loads the options set in the configuration file.
+    pub fn config_file(mut self, path: impl AsRef<Path>) -> Result<ServerBuilder, ConfigError> {
+        self.config.apply_file(path)?;
+        Ok(self)
+    }
+
+    /// Loads the options set in the `WEB_SERVER_*` environment variables.
+    pub fn env(mut self) -> Result<ServerBuilder, ConfigError> {
+        self.config.apply_env()?;
+        Ok(self)
+    }
+
+    /// Metrics where the server keeps the number of open and queued connections. By default the
+    /// server uses its own, available through [`Server::metrics`].
+    pub fn metrics(mut self, metrics: Arc<Metrics>) -> ServerBuilder {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Binds the server to the configured address and port.
+    pub fn build<H: Handler>(self, handler: H) -> Result<Server, ServerError> {
+        let invalid = [
+            ("workers", self.config.workers == 0),
+            (
+                "max_queued_connections",
+                self.config.max_queued_connections == 0,
+            ),
+            ("max_connections", self.config.max_connections == 0),
+            (
+                "keep_alive_timeout",
+                self.config.keep_alive_timeout.is_zero(),
+            ),
+            ("read_timeout", self.config.read_timeout.is_zero()),
+            ("write_timeout", self.config.write_timeout.is_zero()),
+        ];
+        if let Some((key, _)) = invalid.into_iter().find(|(_, invalid)| *invalid) {
+            return Err(ServerError::Config(ConfigError::InvalidValue {
+                key: String::from(key),
+                value: String::from("0"),
+            }));
+        }
+        if tls::enabled(&self.config).map_err(ServerError::Tls)?
+            && self.config.backend == Backend::EventLoop
+        {
+            return Err(ServerError::Tls(TlsError::EventLoop));
+        }
+        #[cfg(feature = "tls")]
+        let tls = tls::load(&self.config).map_err(ServerError::Tls)?;
+
+        let address = (self.config.address.as_str(), self.config.port);
+        let listener = TcpListener::bind(address).map_err(|source| ServerError::Bind {
+            address: format!("{}:{}", self.config.address, self.config.port),
+            source,
+        })?;
+
+        let metrics = self.metrics.unwrap_or_default();
+        #[allow(unused_mut)]
+        let mut server =
+            Server::new(listener, handler, self.config, metrics).map_err(|source| {
+                ServerError::Bind {
+                    address: String::from("listener"),
+                    source,
+                }
+            })?;
+        #[cfg(feature = "tls")]
+        {
+            server.tls = tls;
+        }
+        Ok(server)
+    }
+}
+
+/// Web server accepting connections on a [`TcpListener`] and serving them with a pool of threads.
+/// It is created with a [`ServerBuilder`], or with [`Server::bind`] to use the default options.
+///
+/// With [`Backend::ThreadPool`] every connection keeps a thread until it is closed. With
+/// [`Backend::EventLoop`] the connections are waited on by the thread running the server, and the
+/// threads of the pool are only used while a request is handled and its response written. With
+/// both, connections upgraded to another protocol, like WebSocket, leave the pool and run in a
+/// thread of their own.
+///
+/// The server runs until [`ServerHandle::stop`] or [`ServerHandle::shutdown`] is called. Then it
+/// stops accepting connections, closes the idle ones and gives the in-flight requests up to the
+/// shutdown timeout to finish. After the timeout, the remaining connections are closed and the
+/// server waits for the threads of the pool to finish.
+///
+/// # Example
+///
+/// ```rust
+/// let server = Server::bind("127.0.0.1:0", default_router(&Config::default()))?;
+/// let handle = server.spawn();
+///
+/// println!("Listening on {}", handle.local_addr());
+/// handle.shutdown();
+/// ```
+pub struct Server {
+    listener: TcpListener,
+    handler: Arc<dyn Handler>,
+    config: Arc<Config>,
+    metrics: Arc<Metrics>,
+    handle: ServerHandle,
+    #[cfg(feature = "tls")]
+    tls: Option<Arc<rustls::ServerConfig>>,
+    // Declared last so the listener is already closed when the waiting threads wake up.
+    _stopped: StoppedGuard,
+}
+
+impl Server {
+    /// Binds the server to the address with the default options.
+    pub fn bind<A: ToSocketAddrs, H: Handler>(address: A, handler: H) -> io::Result<Server> {
+        let listener = TcpListener::bind(address)?;
+        Server::new(listener, handler, Config::default(), Arc::default())
+    }
+
+    fn new<H: Handler>(
+        listener: TcpListener,
+        handler: H,
+        config: Config,
+        metrics: Arc<Metrics>,
+    ) -> io::Result<Server> {
+        let handle = ServerHandle::new(listener.local_addr()?);
+
+        Ok(Server {
+            listener,
+            handler: Arc::new(handler),
+            config: Arc::new(config),
+            metrics,
+            handle: handle.clone(),
+            #[cfg(feature = "tls")]
+            tls: None,
+            _stopped: StoppedGuard(handle),
+        })
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    pub fn metrics(&self) -> &Arc<Metrics> {
+        &self.metrics
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.handle.local_addr()
+    }
+
+    /// `https` if the server speaks HTTPS, `http` otherwise.
+    pub fn scheme(&self) -> &'static str {
+        #[cfg(feature = "tls")]
+        if self.tls.is_some() {
+            return "https";
+        }
+        "http"
+    }
+
+    pub fn handle(&self) -> ServerHandle {
+        self.handle.clone()
+    }
+
+    /// Runs the server in a new thread, returning the handle to stop it.
+    pub fn spawn(self) -> ServerHandle {
+        let handle = self.handle();
+        thread::spawn(move || self.run());
+        handle
+    }
+
+    /// Accepts and serves connections until the server is stopped.
+    pub fn run(self) {
+        match self.config.backend {
+            Backend::ThreadPool => self.run_thread_pool(),
+            Backend::EventLoop => {
+                let Server {
+                    listener,
+                    handler,
+                    config,
+                    metrics,
+                    handle,
+                    _stopped,
+                    ..
+                } = self;
+
+                if let Err(err) = event_loop::run(listener, handler, config, metrics, handle) {
+                    eprintln!("Event loop error: {}", err);
+                }
+            }
+        }
+    }
+
+    fn run_thread_pool(self) {
+        let pool = ThreadPool::new(self.config.workers);
+        let connections = Arc::new(Connections::new());
+
+        for stream in self.listener.incoming() {
+            if self.handle.is_shutting_down() {
+                break;
+            }
+
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    eprintln!("Stream error: {}", e);
+                    continue;
+                }
+            };
+
+            if pool.queued_count() >= self.config.max_queued_connections {
+                self.metrics.connection_rejected();
+                close_with(&stream, service_unavailable());
+                continue;
+            }
+
+            let handler = Arc::clone(&self.handler);
+            let connections = Arc::clone(&connections);
+            let config = Arc::clone(&self.config);
+            let metrics = Arc::clone(&self.metrics);
+            #[cfg(feature = "tls")]
+            let tls = self.tls.clone();
+            metrics.connection_queued();
+            pool.execute(move || {
+                metrics.connection_dequeued();
+                let _open = metrics.connection_opened();
+
+                #[cfg(feature = "tls")]
+                if let Some(tls) = tls {
+                    handle_tls_connection(stream, tls, handler.as_ref(), &connections, &config);
+                    return;
+                }
+                handle_connection(stream, handler.as_ref(), &connections, &config);
+            })
+        }
+
+        println!("Shutting down.");
+        connections.shutdown();
+
+        if !join_with_timeout(&pool, self.config.shutdown_timeout) {
+            eprintln!(
+                "Closing {} connections after the shutdown timeout.",
+                connections.len()
+            );
+            connections.close_all();
+            pool.join();
+        }
+    }
+}
+
+/// Wakes up the threads waiting for the server to stop when the server is dropped, which happens
+/// at the end of [`Server::run`] or without running if the server is never started.
+struct StoppedGuard(ServerHandle);
+
+impl Drop for StoppedGuard {
+    fn drop(&mut self) {
+        self.0.mark_stopped();
+    }
+}
+
+/// Response to the connections rejected because the queue of the pool is full.
+pub(super) fn service_unavailable() -> Response {
+    Response::error(StatusCode::SERVICE_UNAVAILABLE)
+        .with_header("Retry-After", RETRY_AFTER.as_secs().to_string())
+}
+
+/// Sends the response and closes the connection, from the thread accepting the connections
+/// (or running the event loop), so the timeouts are short to keep serving the rest.
+pub(super) fn close_with(mut stream: &TcpStream, response: Response) {
+    let response = response.with_header("Connection", "close");
+
+    let _ = stream.set_write_timeout(Some(Duration::from_millis(100)));
+    if response.write_to(&mut stream).is_err() {
+        return;
+    }
+
+    // Closing a socket with unread data resets the connection, which can discard the response
+    // before the client reads it, so the request already received is read first.
+    let _ = stream.shutdown(Shutdown::Write);
+    if stream.set_nonblocking(true).is_ok() {
+        let mut buffer = [0; 4096];
+        while matches!(stream.read(&mut buffer), Ok(read) if read > 0) {}
+    }
+}
+
+/// Waits for all the jobs of the pool to finish. Returns `false` if the timeout was reached.
+fn join_with_timeout(pool: &ThreadPool, timeout: Duration) -> bool {
+    let (sender, receiver) = mpsc::channel();
+    let pool = pool.clone();
+
+    thread::spawn(move || {
+        pool.join();
+        let _ = sender.send(());
+    });
+
+    receiver.recv_timeout(timeout).is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::request::Request;
+    use super::super::response::{Response, StatusCode};
+    use super::super::router::Router;
+    use super::*;
+    use std::io::{Read, Write};
+    use std::time::Instant;
+
+    fn router() -> Router {
+        Router::new()
+            .get("/", |_: &mut Request| {
+                Response::new(StatusCode::OK).with_body("index")
+            })
+            .get("/slow", |_: &mut Request| {
+                thread::sleep(Duration::from_millis(500));
+                Response::new(StatusCode::OK).with_body("slow")
+            })
+            .get("/stuck", |_: &mut Request| {
+                thread::sleep(Duration::from_secs(2));
+                Response::new(StatusCode::OK).with_body("stuck")
+            })
+    }
+
+    fn send(address: SocketAddr, path: &str) -> TcpStream {
+        let mut stream = TcpStream::connect(address).unwrap();
+        let request = format!("GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path);
+        stream.write_all(request.as_bytes()).unwrap();
+        stream
+    }
+
+    fn read_all(mut stream: TcpStream) -> String {
+        let mut response = String::new();
+        let _ = stream.read_to_string(&mut response);
+        response
+    }
+
+    #[test]
+    fn ephemeral_port() {
+        let server = Server::bind("127.0.0.1:0", router()).unwrap();
+        assert_ne!(server.local_addr().port(), 0);
+
+        let handle = server.spawn();
+        let mut stream = send(handle.local_addr(), "/");
+        let mut buffer = [0; 1024];
+        let read = stream.read(&mut buffer).unwrap();
+        assert!(String::from_utf8_lossy(&buffer[..read]).ends_with("index"));
+
+        handle.shutdown();
+        assert!(TcpStream::connect(handle.local_addr()).is_err());
+    }
+
+    #[test]
+    fn finishes_in_flight_requests() {
+        let handle = Server::bind("127.0.0.1:0", router()).unwrap().spawn();
+
+        let slow = send(handle.local_addr(), "/slow");
+        thread::sleep(Duration::from_millis(100));
+        let idle = send(handle.local_addr(), "/");
+        thread::sleep(Duration::from_millis(100));
+
+        let start = Instant::now();
+        handle.shutdown();
+        assert!(start.elapsed() < Duration::from_secs(2));
+
+        let slow = read_all(slow);
+        assert!(slow.contains("Connection: close\r\n"));
+        assert!(slow.ends_with("slow"));
+        // The idle connection is closed without waiting for the keep-alive timeout.
+        assert!(read_all(idle).ends_with("index"));
+    }
+
+    #[test]
+    fn shutdown_timeout() {
+        let handle = ServerBuilder::new()
+            .port(0)
+            .shutdown_timeout(Duration::from_millis(200))
+            .build(router())
+            .unwrap()
+            .spawn();
+
+        let stuck = send(handle.local_addr(), "/stuck");
+        thread::sleep(Duration::from_millis(100));
+        handle.shutdown();
+
+        // The connection was closed before the handler could answer.
+        assert_eq!(read_all(stuck), "");
+    }
+
+    #[test]
+    fn rejects_when_queue_is_full() {
+        let server = ServerBuilder::new()
+            .port(0)
+            .workers(1)
+            .max_queued_connections(1)
+            .build(router())
+            .unwrap();
+        let metrics = Arc::clone(server.metrics());
+        let handle = server.spawn();
+
+        // The first connection keeps the only thread busy and the second one fills the queue.
+        let busy = send(handle.local_addr(), "/slow");
+        thread::sleep(Duration::from_millis(100));
+        let queued = send(handle.local_addr(), "/");
+        thread::sleep(Duration::from_millis(100));
+
+        let rejected = read_all(send(handle.local_addr(), "/"));
+        assert!(rejected.starts_with("HTTP/1.1 503 Service Unavailable\r\n"));
+        assert!(rejected.contains("Retry-After: 1\r\n"));
+        assert_eq!(metrics.rejected_connections(), 1);
+
+        handle.shutdown();
+        assert!(read_all(busy).ends_with("slow"));
+        assert!(read_all(queued).ends_with("index"));
+    }
+
+    #[test]
+    fn builder_errors() {
+        let server = ServerBuilder::new().port(0).build(router()).unwrap();
+        let taken = server.local_addr().port();
+
+        let err = ServerBuilder::new()
+            .port(taken)
+            .build(router())
+            .err()
+            .unwrap();
+        assert!(matches!(err, ServerError::Bind { .. }));
+        assert!(err
+            .to_string()
+            .starts_with(&format!("could not bind to 127.0.0.1:{}", taken)));
+
+        let err = ServerBuilder::new()
+            .workers(0)
+            .build(router())
+            .err()
+            .unwrap();
+        assert!(matches!(err, ServerError::Config(_)));
+        let err = ServerBuilder::new()
+            .read_timeout(Duration::ZERO)
+            .build(router())
+            .err()
+            .unwrap();
+        assert!(matches!(err, ServerError::Config(_)));
+        let err = ServerBuilder::new()
+            .max_queued_connections(0)
+            .build(router())
+            .err()
+            .unwrap();
+        assert_eq!(
+            err.to_string(),
+            "invalid configuration: invalid value '0' for 'max_queued_connections'"
+        );
+
+        // Either the `tls` feature is disabled, or HTTPS is not served by the event loop.
+        let err = ServerBuilder::new()
+            .port(0)
+            .backend(Backend::EventLoop)
+            .tls("tls/localhost.pem", "tls/localhost-key.pem")
+            .build(router())
+            .err()
+            .unwrap();
+        assert!(matches!(
+            err,
+            ServerError::Tls(TlsError::Disabled | TlsError::EventLoop)
+        ));
+    }
+
+    #[test]
+    fn server_never_run() {
+        let server = Server::bind("127.0.0.1:0", router()).unwrap();
+        let handle = server.handle();
+        drop(server);
+
+        handle.shutdown();
+    }
+}
@@ -0,0 +1,619 @@
+use super::cookie::{Cookie, SameSite};
+use super::encoding::{percent_decode, percent_encode_path};
+use super::middleware::{Middleware, Next};
+use super::request::Request;
+use super::response::Response;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use hmac::{Hmac, KeyInit, Mac};
+use sha2::Sha256;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Values stored in a session, by key.
+pub type SessionData = BTreeMap<String, String>;
+
+pub const DEFAULT_COOKIE_NAME: &str = "session";
+/// Time after which a session expires if it is not used.
+pub const DEFAULT_TTL: Duration = Duration::from_secs(24 * 3600);
+/// Minimum length of the key signing the session cookies.
+pub const MIN_KEY_SIZE: usize = 32;
+/// Number of random bytes of the session identifiers, which are written in hexadecimal.
+const ID_SIZE: usize = 16;
+/// Time between two removals of the expired sessions of a [`MemoryStore`].
+const PURGE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Storage of the sessions, shared by all the requests. The identifiers given to the store are
+/// always valid, made of hexadecimal digits.
+pub trait SessionStore: Send + Sync + 'static {
+    /// Returns the data of the session, or `None` if it does not exist or it has expired.
+    fn load(&self, id: &str) -> io::Result<Option<SessionData>>;
+
+    /// Creates or replaces the session, which expires after `ttl` if it is not saved again.
+    fn save(&self, id: &str, data: &SessionData, ttl: Duration) -> io::Result<()>;
+
+    /// Removes the session. Removing a session that does not exist is not an error.
+    fn remove(&self, id: &str) -> io::Result<()>;
+}
+
+/// Sessions kept in memory, lost when the server stops. Expired sessions are removed from time
+/// to time when sessions are saved.
+pub struct MemoryStore {
+    sessions: Mutex<HashMap<String, (SessionData, Instant)>>,
+    last_purge: Mutex<Instant>,
+}
+
+impl MemoryStore {
+    pub fn new() -> MemoryStore {
+        MemoryStore {
+            sessions: Mutex::new(HashMap::new()),
+            last_purge: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Number of sessions stored, including the expired ones not removed yet.
+    pub fn len(&self) -> usize {
+        self.sessions.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Removes the expired sessions.
+    pub fn purge(&self) {
+        let now = Instant::now();
+        self.sessions
+            .lock()
+            .unwrap()
+            .retain(|_, (_, expires)| *expires > now);
+        *self.last_purge.lock().unwrap() = now;
+    }
+}
+
+impl Default for MemoryStore {
+    fn default() -> Self {
+        MemoryStore::new()
+    }
+}
+
+impl SessionStore for MemoryStore {
+    fn load(&self, id: &str) -> io::Result<Option<SessionData>> {
+        let mut sessions = self.sessions.lock().unwrap();
+        match sessions.get(id) {
+            Some((data, expires)) if *expires > Instant::now() => Ok(Some(data.clone())),
+            Some(_) => {
+                sessions.remove(id);
+                Ok(None)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn save(&self, id: &str, data: &SessionData, ttl: Duration) -> io::Result<()> {
+        if self.last_purge.lock().unwrap().elapsed() >= PURGE_INTERVAL {
+            self.purge();
+        }
+        self.sessions
+            .lock()
+            .unwrap()
+            .insert(id.to_string(), (data.clone(), Instant::now() + ttl));
+        Ok(())
+    }
+
+    fn remove(&self, id: &str) -> io::Result<()> {
+        self.sessions.lock().unwrap().remove(id);
+        Ok(())
+    }
+}
+
+/// Sessions stored as files in a directory, so they are kept when the server restarts. Every
+/// session is a `<id>.session` file: its first line is the expiry time in seconds since the UNIX
+/// epoch, followed by a `key=value` line per value, both percent-encoded.
+///
+/// Expired sessions are removed when they are loaded, or by [`FileStore::purge`].
+pub struct FileStore {
+    dir: PathBuf,
+}
+
+impl FileStore {
+    /// Stores the sessions in the directory, which is created if needed.
+    pub fn new(dir: impl Into<PathBuf>) -> io::Result<FileStore> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+        Ok(FileStore { dir })
+    }
+
+    /// Removes the files of the expired sessions, and returns how many were removed.
+    pub fn purge(&self) -> io::Result<usize> {
+        let mut removed = 0;
+        for entry in fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            if path
+                .extension()
+                .is_none_or(|extension| extension != "session")
+            {
+                continue;
+            }
+            if read_session(&path)?.is_none() {
+                remove_file(&path)?;
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+
+    fn path(&self, id: &str) -> PathBuf {
+        self.dir.join(format!("{}.session", id))
+    }
+}
+
+impl SessionStore for FileStore {
+    fn load(&self, id: &str) -> io::Result<Option<SessionData>> {
+        let path = self.path(id);
+        match read_session(&path) {
+            Ok(Some(data)) => Ok(Some(data)),
+            Ok(None) => remove_file(&path).map(|_| None),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    fn save(&self, id: &str, data: &SessionData, ttl: Duration) -> io::Result<()> {
+        static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+        let mut contents = format!("{}\n", (unix_time() + ttl).as_secs());
+        for (key, value) in data {
+            contents.push_str(&format!(
+                "{}={}\n",
+                percent_encode_path(key),
+                percent_encode_path(value)
+            ));
+        }
+
+        // Written to a temporary file first so that a concurrent load never sees half a file.
+        let count = COUNTER.fetch_add(1, Ordering::Relaxed);
+        let temp = self.dir.join(format!("{}.{}.tmp", id, count));
+        let result = fs::File::create(&temp)
+            .and_then(|mut file| file.write_all(contents.as_bytes()))
+            .and_then(|_| fs::rename(&temp, self.path(id)));
+        if result.is_err() {
+            let _ = fs::remove_file(&temp);
+        }
+        result
+    }
+
+    fn remove(&self, id: &str) -> io::Result<()> {
+        remove_file(&self.path(id))
+    }
+}
+
+/// Reads a session file, returns `None` if the session has expired.
+fn read_session(path: &Path) -> io::Result<Option<SessionData>> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "Invalid session file");
+
+    let contents = fs::read_to_string(path)?;
+    let mut lines = contents.lines();
+    let expires: u64 = lines
+        .next()
+        .and_then(|line| line.parse().ok())
+        .ok_or_else(invalid)?;
+    if expires <= unix_time().as_secs() {
+        return Ok(None);
+    }
+
+    let mut data = SessionData::new();
+    for line in lines {
+        let (key, value) = line.split_once('=').ok_or_else(invalid)?;
+        let key = percent_decode(key).ok_or_else(invalid)?;
+        let value = percent_decode(value).ok_or_else(invalid)?;
+        data.insert(key, value);
+    }
+    Ok(Some(data))
+}
+
+/// Removes a file, ignoring that it does not exist.
+fn remove_file(path: &Path) -> io::Result<()> {
+    match fs::remove_file(path) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+        _ => Ok(()),
+    }
+}
+
+fn unix_time() -> Duration {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+}
+
+/// Session of the client that sent a request, available in
+/// [`Request::session`](super::request::Request::session) when the [`Sessions`] middleware is
+/// used. The changes are saved after the handler returns.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Session {
+    /// `None` until the session is saved for the first time.
+    id: Option<String>,
+    data: SessionData,
+    changed: bool,
+    destroyed: bool,
+    regenerate: bool,
+}
+
+impl Session {
+    /// Creates an empty session, which gets an identifier when it is saved.
+    pub fn new() -> Session {
+        Session::default()
+    }
+
+    fn load(id: String, data: SessionData) -> Session {
+        Session {
+            id: Some(id),
+            data,
+            ..Session::default()
+        }
+    }
+
+    /// Identifier of the session, `None` for a new session.
+    pub fn id(&self) -> Option<&str> {
+        self.id.as_deref()
+    }
+
+    /// Whether the session was created by this request.
+    pub fn is_new(&self) -> bool {
+        self.id.is_none()
+    }
+
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.data.get(key).map(String::as_str)
+    }
+
+    pub fn data(&self) -> &SessionData {
+        &self.data
+    }
+
+    /// Sets a value, replacing any previous value with the same key.
+    pub fn insert(&mut self, key: impl Into<String>, value: impl Into<String>) {
+        self.data.insert(key.into(), value.into());
+        self.changed = true;
+    }
+
+    pub fn remove(&mut self, key: &str) -> Option<String> {
+        let value = self.data.remove(key);
+        self.changed |= value.is_some();
+        value
+    }
+
+    /// Removes all the values, but keeps the session.
+    pub fn clear(&mut self) {
+        self.changed |= !self.data.is_empty();
+        self.data.clear();
+    }
+
+    /// Removes the session from the store and the cookie from the client, e.g. on logout.
+    pub fn destroy(&mut self) {
+        self.data.clear();
+        self.destroyed = true;
+    }
+
+    /// Moves the values to a new identifier, e.g. on login, so that an identifier known before
+    /// by an attacker (session fixation) becomes useless.
+    pub fn regenerate(&mut self) {
+        self.regenerate = true;
+        self.changed = true;
+    }
+}
+
+/// Middleware loading the session of the client from a [`SessionStore`] before the handler and
+/// saving it after. The session identifier is sent in a cookie signed with HMAC-SHA256, so a
+/// client cannot forge identifiers; a cookie with an invalid signature gets a new session.
+///
+/// New sessions are only saved, and the cookie only sent, once a value is inserted. Every
+/// request using an existing session extends its expiry.
+///
+/// # Example
+///
+/// ```rust
+/// let handler = Pipeline::new(router).with(Sessions::new(MemoryStore::new(), &key));
+///
+/// fn visits(request: &mut Request) -> Response {
+///     let session = request.session.as_mut().unwrap();
+///     let visits = session.get("visits").and_then(|n| n.parse().ok()).unwrap_or(0) + 1;
+///     session.insert("visits", visits.to_string());
+///     Response::html(format!("{} visits", visits))
+/// }
+/// ```
+pub struct Sessions {
+    store: Box<dyn SessionStore>,
+    key: Vec<u8>,
+    cookie_name: String,
+    ttl: Duration,
+    secure: bool,
+    same_site: SameSite,
+}
+
+impl Sessions {
+    /// Sessions with the default cookie: `session`, `HttpOnly`, `SameSite=Lax`, with the path `/`
+    /// and a `Max-Age` of [`DEFAULT_TTL`].
+    ///
+    /// # Panics
+    ///
+    /// Panics if the key is shorter than [`MIN_KEY_SIZE`] bytes.
+    pub fn new<S: SessionStore>(store: S, key: &[u8]) -> Sessions {
+        assert!(
+            key.len() >= MIN_KEY_SIZE,
+            "The session key must have at least {} bytes",
+            MIN_KEY_SIZE
+        );
+
+        Sessions {
+            store: Box::new(store),
+            key: key.to_vec(),
+            cookie_name: DEFAULT_COOKIE_NAME.to_string(),
+            ttl: DEFAULT_TTL,
+            secure: false,
+            same_site: SameSite::Lax,
+        }
+    }
+
+    /// # Panics
+    ///
+    /// Panics if the name is not a valid cookie name.
+    pub fn cookie_name(mut self, name: impl Into<String>) -> Sessions {
+        self.cookie_name = Cookie::new(name, "").name().to_string();
+        self
+    }
+
+    /// Time after which a session expires if it is not used.
+    pub fn ttl(mut self, ttl: Duration) -> Sessions {
+        self.ttl = ttl;
+        self
+    }
+
+    /// Only sends the cookie over HTTPS, which should be set when the server uses TLS.
+    pub fn secure(mut self, secure: bool) -> Sessions {
+        self.secure = secure;
+        self
+    }
+
+    pub fn same_site(mut self, same_site: SameSite) -> Sessions {
+        self.same_site = same_site;
+        self
+    }
+
+    /// Signs the identifier, the result is the value of the cookie.
+    fn sign(&self, id: &str) -> String {
+        let mut mac = self.mac();
+        mac.update(id.as_bytes());
+        let signature = URL_SAFE_NO_PAD.encode(mac.finalize().into_bytes());
+        format!("{}.{}", id, signature)
+    }
+
+    /// Returns the identifier of the cookie value if its signature is valid.
+    fn verify<'a>(&self, value: &'a str) -> Option<&'a str> {
+        let (id, signature) = value.split_once('.')?;
+        if id.len() != ID_SIZE * 2 || !id.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return None;
+        }
+        let signature = URL_SAFE_NO_PAD.decode(signature).ok()?;
+
+        let mut mac = self.mac();
+        mac.update(id.as_bytes());
+        mac.verify_slice(&signature).ok().map(|_| id)
+    }
+
+    fn mac(&self) -> HmacSha256 {
+        HmacSha256::new_from_slice(&self.key).expect("HMAC accepts keys of any size")
+    }
+
+    fn cookie(&self, value: String) -> Cookie {
+        Cookie::new(&self.cookie_name, value)
+            .path("/")
+            .max_age(self.ttl)
+            .secure(self.secure)
+            .http_only(true)
+            .same_site(self.same_site)
+    }
+
+    fn load(&self, request: &Request) -> Session {
+        let id = request
+            .cookie(&self.cookie_name)
+            .and_then(|value| self.verify(&value).map(str::to_string));
+        let Some(id) = id else {
+            return Session::new();
+        };
+
+        match self.store.load(&id) {
+            Ok(Some(data)) => Session::load(id, data),
+            Ok(None) => Session::new(),
+            Err(err) => {
+                eprintln!("Error loading the session {}: {}", id, err);
+                Session::new()
+            }
+        }
+    }
+
+    /// Saves the session and returns the `Set-Cookie` header to send, if any.
+    fn save(&self, session: Session) -> Option<Cookie> {
+        if session.destroyed {
+            let id = session.id?;
+            if let Err(err) = self.store.remove(&id) {
+                eprintln!("Error removing the session {}: {}", id, err);
+            }
+            return Some(Cookie::removal(&self.cookie_name).path("/"));
+        }
+        if session.is_new() && !session.changed {
+            return None;
+        }
+
+        let id = match session.id {
+            Some(id) if !session.regenerate => id,
+            old => {
+                if let Some(old) = old {
+                    if let Err(err) = self.store.remove(&old) {
+                        eprintln!("Error removing the session {}: {}", old, err);
+                    }
+                }
+                match new_id() {
+                    Ok(id) => id,
+                    Err(err) => {
+                        eprintln!("Error generating a session identifier: {}", err);
+                        return None;
+                    }
+                }
+            }
+        };
+        if let Err(err) = self.store.save(&id, &session.data, self.ttl) {
+            eprintln!("Error saving the session {}: {}", id, err);
+            return None;
+        }
+        Some(self.cookie(self.sign(&id)))
+    }
+}
+
+impl Middleware for Sessions {
+    fn handle(&self, request: &mut Request, next: Next<'_>) -> Response {
+        request.session = Some(self.load(request));
+        let mut response = next.run(request);
+
+        if let Some(cookie) = request.session.take().and_then(|s| self.save(s)) {
+            response.headers.append("Set-Cookie", cookie.to_string());
+        }
+        response
+    }
+}
+
+/// Generates a random session identifier.
+fn new_id() -> io::Result<String> {
+    let mut bytes = [0; ID_SIZE];
+    getrandom::fill(&mut bytes).map_err(|err| io::Error::other(err.to_string()))?;
+    Ok(bytes.iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::middleware::Pipeline;
+    use super::super::request::Method;
+    use super::super::response::StatusCode;
+    use super::super::router::{Handler, Router};
+    use super::*;
+    use std::env;
+
+    const KEY: &[u8] = b"0123456789abcdef0123456789abcdef";
+
+    fn data(pairs: &[(&str, &str)]) -> SessionData {
+        pairs
+            .iter()
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn memory_store() {
+        let store = MemoryStore::new();
+        let values = data(&[("user", "alice")]);
+        store.save("a", &values, DEFAULT_TTL).unwrap();
+        store.save("b", &values, Duration::ZERO).unwrap();
+
+        assert_eq!(store.load("a").unwrap(), Some(values));
+        assert_eq!(store.load("b").unwrap(), None);
+        assert_eq!(store.load("c").unwrap(), None);
+        assert_eq!(store.len(), 1);
+
+        store.remove("a").unwrap();
+        assert!(store.is_empty());
+    }
+
+    #[test]
+    fn file_store() {
+        let dir = env::temp_dir().join(format!("sessions-{}", std::process::id()));
+        let store = FileStore::new(&dir).unwrap();
+        let values = data(&[("user", "alice"), ("note", "a=b\nc d%")]);
+        store.save("a", &values, DEFAULT_TTL).unwrap();
+        store.save("b", &values, Duration::ZERO).unwrap();
+        store.save("c", &values, Duration::ZERO).unwrap();
+
+        assert_eq!(store.load("a").unwrap(), Some(values));
+        assert_eq!(store.load("b").unwrap(), None);
+        assert!(!dir.join("b.session").exists());
+        assert_eq!(store.purge().unwrap(), 1);
+
+        store.remove("a").unwrap();
+        store.remove("a").unwrap();
+        assert_eq!(store.load("a").unwrap(), None);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn signed_cookie() {
+        let sessions = Sessions::new(MemoryStore::new(), KEY);
+        let id = new_id().unwrap();
+        let value = sessions.sign(&id);
+        assert_eq!(sessions.verify(&value), Some(id.as_str()));
+
+        let other = Sessions::new(MemoryStore::new(), b"another key of at least 32 bytes");
+        assert_eq!(other.verify(&value), None);
+        let (_, signature) = value.split_once('.').unwrap();
+        let forged = format!("{}.{}", new_id().unwrap(), signature);
+        assert_eq!(sessions.verify(&forged), None);
+        assert_eq!(sessions.verify(&id), None);
+    }
+
+    #[test]
+    #[should_panic]
+    fn short_key() {
+        Sessions::new(MemoryStore::new(), b"secret");
+    }
+
+    #[test]
+    fn middleware() {
+        let router = Router::new()
+            .get("/", |request: &mut Request| {
+                let start = request.query_param("start").is_some();
+                let session = request.session.as_mut().unwrap();
+                let count: u32 = session.get("count").map_or(0, |n| n.parse().unwrap());
+                if count > 0 || start {
+                    session.insert("count", (count + 1).to_string());
+                }
+                Response::new(StatusCode::OK).with_body(count.to_string())
+            })
+            .get("/logout", |request: &mut Request| {
+                request.session.as_mut().unwrap().destroy();
+                Response::new(StatusCode::NO_CONTENT)
+            });
+        let handler = Pipeline::new(router).with(Sessions::new(MemoryStore::new(), KEY));
+        let send = |target: &str, cookie: Option<&str>| {
+            let mut request = Request::new(Method::Get, target);
+            if let Some(cookie) = cookie {
+                request
+                    .headers
+                    .insert("Cookie", format!("session={}", cookie));
+            }
+            let response = handler.handle(&mut request);
+            let set_cookie = response.headers.get("Set-Cookie").map(str::to_string);
+            (response.body.into_bytes().unwrap(), set_cookie)
+        };
+
+        // An unused session is not saved.
+        assert_eq!(send("/", None), (b"0".to_vec(), None));
+
+        let (_, set_cookie) = send("/?start", None);
+        let set_cookie = set_cookie.unwrap();
+        assert!(set_cookie.ends_with("; Path=/; Max-Age=86400; HttpOnly; SameSite=Lax"));
+        let value = &set_cookie["session=".len()..set_cookie.find(';').unwrap()];
+
+        assert_eq!(send("/", Some(value)).0, b"1");
+        assert_eq!(send("/", Some(value)).0, b"2");
+        assert_eq!(send("/", Some(&value.replace('.', "0."))).0, b"0");
+
+        let (_, removal) = send("/logout", Some(value));
+        assert_eq!(removal.as_deref(), Some("session=; Path=/; Max-Age=0"));
+        assert_eq!(send("/", Some(value)).0, b"0");
+    }
+}
@@ -0,0 +1,313 @@
+use super::body::Body;
+use super::request::Request;
+use super::response::{Response, StatusCode};
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Cursor, Read};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, SyncSender};
+use std::time::Duration;
+
+pub const CONTENT_TYPE: &str = "text/event-stream";
+
+/// Time without events after which a heartbeat comment is sent. It keeps proxies from closing
+/// the connection, and reveals the clients that left, since writing to them fails.
+pub const DEFAULT_HEARTBEAT: Duration = Duration::from_secs(15);
+
+/// Events waiting to be written before [`EventSender::send`] blocks, so a slow client slows
+/// down the producer instead of growing the queue.
+const CAPACITY: usize = 64;
+
+/// Event sent through an [`EventStream`].
+///
+/// ```rust
+/// let event = Event::new("{\"done\": 42}").with_event("progress").with_id("42");
+/// assert_eq!(event.to_string(), "event: progress\nid: 42\ndata: {\"done\": 42}\n\n");
+/// ```
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Event {
+    id: Option<String>,
+    event: Option<String>,
+    data: String,
+    retry: Option<Duration>,
+}
+
+impl Event {
+    /// Creates a `message` event, the type handled by `EventSource.onmessage`. The data can have
+    /// multiple lines.
+    pub fn new(data: impl Into<String>) -> Event {
+        Event {
+            data: data.into(),
+            ..Event::default()
+        }
+    }
+
+    /// Sets the ID the client sends back in `Last-Event-ID` when it reconnects. Line breaks are
+    /// removed, as they would end the field.
+    pub fn with_id(mut self, id: impl Into<String>) -> Event {
+        self.id = Some(single_line(id.into()));
+        self
+    }
+
+    /// Sets the type of the event, for `EventSource.addEventListener`. Line breaks are removed.
+    pub fn with_event(mut self, event: impl Into<String>) -> Event {
+        self.event = Some(single_line(event.into()));
+        self
+    }
+
+    /// Sets the time the client waits before reconnecting, once the connection is lost.
+    pub fn with_retry(mut self, retry: Duration) -> Event {
+        self.retry = Some(retry);
+        self
+    }
+}
+
+fn single_line(value: String) -> String {
+    value.replace(['\r', '\n'], "")
+}
+
+/// Writes the event in the `text/event-stream` format, ending with the blank line that
+/// dispatches it.
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(event) = &self.event {
+            writeln!(f, "event: {}", event)?;
+        }
+        if let Some(id) = &self.id {
+            writeln!(f, "id: {}", id)?;
+        }
+        if let Some(retry) = self.retry {
+            writeln!(f, "retry: {}", retry.as_millis())?;
+        }
+        // Every line of the data gets its own field, which the client joins with `\n`.
+        let data = self.data.replace("\r\n", "\n").replace('\r', "\n");
+        for line in data.split('\n') {
+            writeln!(f, "data: {}", line)?;
+        }
+        writeln!(f)
+    }
+}
+
+/// ID of the last event received by a client reconnecting to a stream, to resume it from there.
+pub fn last_event_id(request: &Request) -> Option<&str> {
+    request.header("Last-Event-ID").map(str::trim)
+}
+
+/// The client left, or the response was dropped before being sent.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Disconnected;
+
+impl fmt::Display for Disconnected {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("the client of the event stream disconnected")
+    }
+}
+
+impl Error for Disconnected {}
+
+/// Sends the events of a stream created with [`EventStream::start`]. It can be cloned to send
+/// from multiple threads, and the stream ends once all the senders are dropped.
+#[derive(Debug, Clone)]
+pub struct EventSender {
+    sender: SyncSender<Vec<u8>>,
+}
+
+impl EventSender {
+    /// Queues the event, waiting if the client is too slow to read the previous ones.
+    pub fn send(&self, event: &Event) -> Result<(), Disconnected> {
+        self.send_bytes(event.to_string().into_bytes())
+    }
+
+    /// Sends a comment, which the client ignores.
+    pub fn comment(&self, text: &str) -> Result<(), Disconnected> {
+        let comment: String = text.lines().map(|line| format!(": {}\n", line)).collect();
+        self.send_bytes(format!("{}\n", comment).into_bytes())
+    }
+
+    fn send_bytes(&self, bytes: Vec<u8>) -> Result<(), Disconnected> {
+        self.sender.send(bytes).map_err(|_| Disconnected)
+    }
+}
+
+/// Server-Sent Events: a `text/event-stream` response held open to push events to the client,
+/// which receives them with `EventSource` in the browser.
+///
+/// The handler returns the response right away and the events are sent from another thread
+/// through the [`EventSender`]. Each event is written and flushed as soon as it is sent. The
+/// stream is written by a thread of the pool like any other response, so every open stream
+/// keeps a thread until it ends or the client leaves: streams should be bounded, like the
+/// progress of a task.
+///
+/// # Example
+///
+/// ```rust
+/// Router::new().get("/countdown", |request: &mut Request| {
+///     let start = match sse::last_event_id(request).and_then(|id| id.parse().ok()) {
+///         Some(id) => id,
+///         None => 10,
+///     };
+///     let (events, response) = EventStream::new().start();
+///     thread::spawn(move || {
+///         for n in (0..start).rev() {
+///             let event = Event::new(n.to_string()).with_id(n.to_string());
+///             if events.send(&event).is_err() {
+///                 break;
+///             }
+///             thread::sleep(Duration::from_secs(1));
+///         }
+///     });
+///     response
+/// })
+/// ```
+#[derive(Debug, Clone)]
+pub struct EventStream {
+    heartbeat: Option<Duration>,
+    retry: Option<Duration>,
+}
+
+impl Default for EventStream {
+    fn default() -> Self {
+        EventStream {
+            heartbeat: Some(DEFAULT_HEARTBEAT),
+            retry: None,
+        }
+    }
+}
+
+impl EventStream {
+    pub fn new() -> EventStream {
+        EventStream::default()
+    }
+
+    /// Time without events after which a heartbeat comment is sent, or `None` to never send one.
+    pub fn heartbeat(mut self, interval: Option<Duration>) -> EventStream {
+        self.heartbeat = interval;
+        self
+    }
+
+    /// Reconnection time sent to the client at the start of the stream.
+    pub fn retry(mut self, retry: Duration) -> EventStream {
+        self.retry = Some(retry);
+        self
+    }
+
+    /// Creates the `200 OK` response of the stream, and the sender of its events.
+    pub fn start(self) -> (EventSender, Response) {
+        let (sender, receiver) = mpsc::sync_channel(CAPACITY);
+        let pending = match self.retry {
+            Some(retry) => format!("retry: {}\n\n", retry.as_millis()).into_bytes(),
+            None => Vec::new(),
+        };
+        let reader = EventReader {
+            receiver,
+            heartbeat: self.heartbeat,
+            pending: Cursor::new(pending),
+        };
+
+        let response = Response::new(StatusCode::OK)
+            .with_header("Content-Type", CONTENT_TYPE)
+            .with_header("Cache-Control", "no-cache")
+            // Tells reverse proxies like nginx not to buffer the events.
+            .with_header("X-Accel-Buffering", "no")
+            .with_body(Body::stream(reader));
+        (EventSender { sender }, response)
+    }
+}
+
+/// Body of an event stream: every read returns the next event, or a heartbeat, and the end of
+/// the body comes when all the senders are dropped.
+struct EventReader {
+    receiver: Receiver<Vec<u8>>,
+    heartbeat: Option<Duration>,
+    /// Event being read.
+    pending: Cursor<Vec<u8>>,
+}
+
+impl Read for EventReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            if (self.pending.position() as usize) < self.pending.get_ref().len() {
+                return self.pending.read(buf);
+            }
+
+            let next = match self.heartbeat {
+                Some(interval) => self.receiver.recv_timeout(interval),
+                None => self
+                    .receiver
+                    .recv()
+                    .map_err(|_| RecvTimeoutError::Disconnected),
+            };
+            let bytes = match next {
+                Ok(bytes) => bytes,
+                Err(RecvTimeoutError::Timeout) => b":\n\n".to_vec(),
+                Err(RecvTimeoutError::Disconnected) => return Ok(0),
+            };
+            self.pending = Cursor::new(bytes);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::request::Method;
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn event_format() {
+        assert_eq!(Event::new("hello").to_string(), "data: hello\n\n");
+        assert_eq!(
+            Event::new("first\nsecond\r\nthird")
+                .with_event("update")
+                .with_id("7\n")
+                .with_retry(Duration::from_secs(3))
+                .to_string(),
+            "event: update\nid: 7\nretry: 3000\ndata: first\ndata: second\ndata: third\n\n"
+        );
+        assert_eq!(Event::new("").to_string(), "data: \n\n");
+    }
+
+    #[test]
+    fn last_event_ids() {
+        let request = Request::new(Method::Get, "/events");
+        assert_eq!(last_event_id(&request), None);
+        let request = request.with_header("Last-Event-ID", " 42 ");
+        assert_eq!(last_event_id(&request), Some("42"));
+    }
+
+    #[test]
+    fn stream() {
+        let (events, response) = EventStream::new().retry(Duration::from_millis(500)).start();
+        assert_eq!(response.headers.get("Content-Type"), Some(CONTENT_TYPE));
+        assert_eq!(response.body.len(), None);
+
+        let sender = thread::spawn(move || {
+            events.send(&Event::new("1").with_id("1")).unwrap();
+            events.comment("still\nthere").unwrap();
+            events.send(&Event::new("2").with_id("2")).unwrap();
+        });
+        let body = response.body.into_bytes().unwrap();
+        sender.join().unwrap();
+
+        assert_eq!(
+            String::from_utf8(body).unwrap(),
+            "retry: 500\n\nid: 1\ndata: 1\n\n: still\n: there\n\nid: 2\ndata: 2\n\n"
+        );
+    }
+
+    #[test]
+    fn heartbeat() {
+        let (events, response) = EventStream::new()
+            .heartbeat(Some(Duration::from_millis(10)))
+            .start();
+        let Body::Stream(mut reader) = response.body else {
+            panic!("not a stream");
+        };
+
+        let mut buffer = [0; 16];
+        let read = reader.read(&mut buffer).unwrap();
+        assert_eq!(&buffer[..read], b":\n\n");
+
+        drop(reader);
+        assert_eq!(events.send(&Event::new("gone")), Err(Disconnected));
+    }
+}
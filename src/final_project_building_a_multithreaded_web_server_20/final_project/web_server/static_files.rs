@@ -0,0 +1,540 @@
+use super::body::Body;
+use super::conditional::{content_range, range_not_satisfiable, Ranges, Validators};
+use super::encoding::{escape_html, percent_decode, percent_encode_path};
+use super::request::{Method, Request};
+use super::response::{Response, StatusCode};
+use super::router::Handler;
+use std::collections::hash_map::RandomState;
+use std::fs::{self, File};
+use std::hash::{BuildHasher, Hasher};
+use std::io::{self, Cursor, Read, Seek, SeekFrom};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+/// Handler serving the files of a document root directory. The path of the request is mapped
+/// onto the directory, so with the root `html` the request `/css/site.css` returns the file
+/// `html/css/site.css`. Usually it is [mounted](super::router::Router::mount) on a router:
+///
+/// ```rust
+/// let router = Router::new().mount("/static", StaticFiles::new("html"));
+/// ```
+///
+/// - Only `GET` and `HEAD` requests are allowed.
+/// - Paths with a `..` segment are rejected with `403 Forbidden`.
+/// - The `Content-Type` is guessed from the extension of the file, see [`content_type`].
+/// - Files are sent with an `ETag` and `Last-Modified`, so clients can revalidate their copy
+///   with `If-None-Match` or `If-Modified-Since` and get `304 Not Modified`.
+/// - `GET` requests with a `Range` header get `206 Partial Content`, as a `multipart/byteranges`
+///   body if there are several ranges. `If-Range` is honored.
+/// - Directories are served through their index file (`index.html` by default). If it does not
+///   exist, a listing of the directory is generated when enabled with
+///   [`with_directory_listing`](StaticFiles::with_directory_listing).
+#[derive(Debug, Clone)]
+pub struct StaticFiles {
+    root: PathBuf,
+    index: String,
+    not_found: Option<String>,
+    directory_listing: bool,
+}
+
+impl StaticFiles {
+    /// Creates a handler serving the files inside `root`.
+    pub fn new(root: impl Into<PathBuf>) -> StaticFiles {
+        StaticFiles {
+            root: root.into(),
+            index: String::from("index.html"),
+            not_found: None,
+            directory_listing: false,
+        }
+    }
+
+    /// Name of the file served when a directory is requested.
+    pub fn with_index(mut self, index: impl Into<String>) -> StaticFiles {
+        self.index = index.into();
+        self
+    }
+
+    /// File, relative to the root, sent with `404 Not Found` when the requested file does not
+    /// exist.
+    pub fn with_not_found(mut self, page: impl Into<String>) -> StaticFiles {
+        self.not_found = Some(page.into());
+        self
+    }
+
+    /// Generates a HTML page with the contents of the directories that do not have an index file.
+    pub fn with_directory_listing(mut self, enabled: bool) -> StaticFiles {
+        self.directory_listing = enabled;
+        self
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Maps the URL path onto the root directory. Returns `None` if the path is not valid or
+    /// tries to leave the root directory.
+    pub fn resolve(&self, url_path: &str) -> Option<PathBuf> {
+        let decoded = percent_decode(url_path)?;
+        let mut path = self.root.clone();
+
+        for segment in decoded.split('/') {
+            match segment {
+                "" | "." => continue,
+                ".." => return None,
+                _ if segment.contains(['\\', '\0']) => return None,
+                _ => path.push(segment),
+            }
+        }
+
+        Some(path)
+    }
+
+    fn serve(&self, request: &Request) -> Response {
+        let path = match self.resolve(&request.path) {
+            Some(path) => path,
+            None => return Response::error(StatusCode::FORBIDDEN),
+        };
+
+        let metadata = match fs::metadata(&path) {
+            Ok(metadata) => metadata,
+            Err(_) => return self.not_found(),
+        };
+
+        if !metadata.is_dir() {
+            return serve_file(request, &path).unwrap_or_else(|| self.not_found());
+        }
+
+        // Relative links inside the page only work if the directory URL ends with a slash.
+        if !request.path.ends_with('/') {
+            let name = request.path.rsplit('/').next().unwrap_or_default();
+            return Response::new(StatusCode::MOVED_PERMANENTLY)
+                .with_header("Location", format!("{}/", name));
+        }
+
+        let index = path.join(&self.index);
+        if index.is_file() {
+            return serve_file(request, &index).unwrap_or_else(|| self.not_found());
+        }
+
+        if self.directory_listing {
+            if let Some(listing) = directory_listing(&path, &request.path) {
+                return Response::html(listing);
+            }
+        }
+
+        self.not_found()
+    }
+
+    fn not_found(&self) -> Response {
+        self.not_found
+            .as_ref()
+            .and_then(|page| {
+                let path = self.root.join(page);
+                let body = Body::file(File::open(&path).ok()?).ok()?;
+                Some(
+                    Response::new(StatusCode::NOT_FOUND)
+                        .with_header("Content-Type", content_type(&path))
+                        .with_body(body),
+                )
+            })
+            .unwrap_or_else(|| Response::error(StatusCode::NOT_FOUND))
+    }
+}
+
+impl Handler for StaticFiles {
+    fn handle(&self, request: &mut Request) -> Response {
+        match request.method {
+            Method::Get | Method::Head => self.serve(request),
+            _ => Response::error(StatusCode::METHOD_NOT_ALLOWED).with_header("Allow", "GET, HEAD"),
+        }
+    }
+}
+
+/// The file is sent in chunks as it is read, so it is never fully loaded into memory. The
+/// conditional headers are evaluated before the `Range` header, as a client with a fresh copy
+/// needs no part of the file.
+fn serve_file(request: &Request, path: &Path) -> Option<Response> {
+    let mut file = File::open(path).ok()?;
+    let metadata = file.metadata().ok()?;
+    let validators = Validators::from_metadata(&metadata);
+    if validators.not_modified(request) {
+        return Some(validators.not_modified_response());
+    }
+
+    let len = metadata.len();
+    let content_type = content_type(path);
+    let ranges = match request.method {
+        Method::Get if validators.range_applies(request) => {
+            Ranges::parse(request.header("Range"), len)
+        }
+        _ => Ranges::Full,
+    };
+
+    let response = match ranges {
+        Ranges::Full => Response::new(StatusCode::OK)
+            .with_header("Content-Type", content_type)
+            .with_body(Body::File { file, len }),
+        Ranges::Unsatisfiable => range_not_satisfiable(len),
+        Ranges::Partial(ranges) => match ranges.as_slice() {
+            [range] => {
+                file.seek(SeekFrom::Start(range.start)).ok()?;
+                Response::new(StatusCode::PARTIAL_CONTENT)
+                    .with_header("Content-Type", content_type)
+                    .with_header("Content-Range", content_range(range, len))
+                    .with_body(Body::File {
+                        file,
+                        len: range.end - range.start,
+                    })
+            }
+            _ => {
+                let boundary = format!("{:016x}", RandomState::new().build_hasher().finish());
+                let body = byte_ranges(file, &ranges, len, content_type, &boundary).ok()?;
+                Response::new(StatusCode::PARTIAL_CONTENT)
+                    .with_header(
+                        "Content-Type",
+                        format!("multipart/byteranges; boundary={}", boundary),
+                    )
+                    .with_body(body)
+            }
+        },
+    };
+
+    Some(validators.apply(response.with_header("Accept-Ranges", "bytes")))
+}
+
+/// Builds the `multipart/byteranges` body with a part for every range of the file. The ranges
+/// are read as the body is sent.
+fn byte_ranges(
+    file: File,
+    ranges: &[Range<u64>],
+    len: u64,
+    content_type: &str,
+    boundary: &str,
+) -> io::Result<Body> {
+    let mut body: Box<dyn Read + Send> = Box::new(io::empty());
+    for range in ranges {
+        let head = format!(
+            "\r\n--{}\r\nContent-Type: {}\r\nContent-Range: {}\r\n\r\n",
+            boundary,
+            content_type,
+            content_range(range, len)
+        );
+        let part = FileRange {
+            file: file.try_clone()?,
+            range: range.clone(),
+            started: false,
+        };
+        body = Box::new(body.chain(Cursor::new(head)).chain(part));
+    }
+    let end = format!("\r\n--{}--\r\n", boundary);
+
+    Ok(Body::stream(body.chain(Cursor::new(end))))
+}
+
+/// Range of a file, which is only seeked to on the first read: the parts of a multipart body
+/// share the position of the same open file, and are read one after the other.
+struct FileRange {
+    file: File,
+    range: Range<u64>,
+    started: bool,
+}
+
+impl Read for FileRange {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if !self.started {
+            self.file.seek(SeekFrom::Start(self.range.start))?;
+            self.started = true;
+        }
+
+        let remaining = self.range.end - self.range.start;
+        let max = buf
+            .len()
+            .min(usize::try_from(remaining).unwrap_or(usize::MAX));
+        let read = self.file.read(&mut buf[..max])?;
+        self.range.start += read as u64;
+        Ok(read)
+    }
+}
+
+/// Generates a HTML page listing the entries of the directory, with the subdirectories first.
+fn directory_listing(directory: &Path, url_path: &str) -> Option<String> {
+    let mut entries: Vec<(bool, String)> = fs::read_dir(directory)
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .map(|entry| {
+            let is_dir = entry.file_type().map(|t| t.is_dir()).unwrap_or(false);
+            (is_dir, entry.file_name().to_string_lossy().into_owned())
+        })
+        .collect();
+    entries.sort_by(|a, b| b.0.cmp(&a.0).then_with(|| a.1.cmp(&b.1)));
+
+    let title = escape_html(&percent_decode(url_path).unwrap_or_else(|| url_path.to_string()));
+    let mut html = format!(
+        "<!DOCTYPE html>\n<html lang=\"en\">\n<head>\n    <meta charset=\"utf-8\">\n    \
+         <title>Index of {0}</title>\n</head>\n<body>\n<h1>Index of {0}</h1>\n<ul>\n",
+        title
+    );
+    if url_path != "/" {
+        html.push_str("    <li><a href=\"../\">../</a></li>\n");
+    }
+    for (is_dir, name) in entries {
+        let suffix = if is_dir { "/" } else { "" };
+        html.push_str(&format!(
+            "    <li><a href=\"{}{}\">{}{}</a></li>\n",
+            percent_encode_path(&name),
+            suffix,
+            escape_html(&name),
+            suffix
+        ));
+    }
+    html.push_str("</ul>\n</body>\n</html>\n");
+
+    Some(html)
+}
+
+/// Guesses the media type of a file from its extension. Unknown extensions are served as
+/// `application/octet-stream`.
+pub fn content_type(path: &Path) -> &'static str {
+    let extension = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .map(|extension| extension.to_ascii_lowercase());
+
+    match extension.as_deref() {
+        Some("html") | Some("htm") => "text/html; charset=utf-8",
+        Some("css") => "text/css; charset=utf-8",
+        Some("js") | Some("mjs") => "text/javascript; charset=utf-8",
+        Some("json") => "application/json",
+        Some("txt") => "text/plain; charset=utf-8",
+        Some("md") => "text/markdown; charset=utf-8",
+        Some("csv") => "text/csv; charset=utf-8",
+        Some("xml") => "application/xml",
+        Some("svg") => "image/svg+xml",
+        Some("png") => "image/png",
+        Some("jpg") | Some("jpeg") => "image/jpeg",
+        Some("gif") => "image/gif",
+        Some("webp") => "image/webp",
+        Some("ico") => "image/x-icon",
+        Some("pdf") => "application/pdf",
+        Some("wasm") => "application/wasm",
+        Some("woff") => "font/woff",
+        Some("woff2") => "font/woff2",
+        Some("ttf") => "font/ttf",
+        Some("mp3") => "audio/mpeg",
+        Some("wav") => "audio/wav",
+        Some("mp4") => "video/mp4",
+        Some("webm") => "video/webm",
+        Some("zip") => "application/zip",
+        Some("gz") => "application/gzip",
+        _ => "application/octet-stream",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::process;
+
+    /// Creates a new directory with some files inside the temporary directory of the system.
+    fn document_root(name: &str) -> PathBuf {
+        let root = env::temp_dir().join(format!("static_files_{}_{}", name, process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("docs/empty")).unwrap();
+        fs::write(root.join("index.html"), "<h1>Index</h1>").unwrap();
+        fs::write(root.join("404.html"), "<h1>Missing</h1>").unwrap();
+        fs::write(root.join("docs/a b.txt"), "text").unwrap();
+        fs::write(root.join("docs/logo.png"), [0x89, b'P', b'N', b'G', 0xff]).unwrap();
+        root
+    }
+
+    fn get(handler: &StaticFiles, path: &str) -> Response {
+        handler.handle(&mut Request::new(Method::Get, path))
+    }
+
+    fn body(response: Response) -> Vec<u8> {
+        response.body.into_bytes().unwrap()
+    }
+
+    #[test]
+    fn serves_files() {
+        let handler = StaticFiles::new(document_root("serves_files"));
+
+        let response = get(&handler, "/docs/a%20b.txt");
+        assert_eq!(response.status, StatusCode::OK);
+        assert_eq!(
+            response.headers.get("Content-Type"),
+            Some("text/plain; charset=utf-8")
+        );
+        assert_eq!(body(response), b"text");
+
+        let response = get(&handler, "/docs/logo.png");
+        assert_eq!(response.headers.get("Content-Type"), Some("image/png"));
+        assert_eq!(response.body.len(), Some(5));
+        assert_eq!(body(response), [0x89, b'P', b'N', b'G', 0xff]);
+    }
+
+    #[test]
+    fn index_and_redirect() {
+        let handler = StaticFiles::new(document_root("index_and_redirect"));
+
+        assert_eq!(body(get(&handler, "/")), b"<h1>Index</h1>");
+
+        let response = get(&handler, "/docs");
+        assert_eq!(response.status, StatusCode::MOVED_PERMANENTLY);
+        assert_eq!(response.headers.get("Location"), Some("docs/"));
+    }
+
+    #[test]
+    fn blocks_traversal() {
+        let handler = StaticFiles::new(document_root("blocks_traversal").join("docs"));
+
+        for path in [
+            "/../index.html",
+            "/%2e%2e/index.html",
+            "/empty/../../404.html",
+        ] {
+            assert_eq!(
+                get(&handler, path).status,
+                StatusCode::FORBIDDEN,
+                "{}",
+                path
+            );
+        }
+    }
+
+    #[test]
+    fn not_found() {
+        let root = document_root("not_found");
+
+        let response = get(&StaticFiles::new(&root), "/missing.html");
+        assert_eq!(response.status, StatusCode::NOT_FOUND);
+
+        let response = get(
+            &StaticFiles::new(&root).with_not_found("404.html"),
+            "/missing",
+        );
+        assert_eq!(response.status, StatusCode::NOT_FOUND);
+        assert_eq!(body(response), b"<h1>Missing</h1>");
+
+        // Directories without index are not listed by default.
+        let response = get(&StaticFiles::new(&root), "/docs/");
+        assert_eq!(response.status, StatusCode::NOT_FOUND);
+    }
+
+    #[test]
+    fn listing() {
+        let handler = StaticFiles::new(document_root("listing")).with_directory_listing(true);
+
+        let response = get(&handler, "/docs/");
+        assert_eq!(response.status, StatusCode::OK);
+        let body = String::from_utf8(body(response)).unwrap();
+        assert!(body.contains("<title>Index of /docs/</title>"));
+        assert!(body.contains("<a href=\"../\">"));
+        assert!(body.contains("<a href=\"empty/\">empty/</a>"));
+        assert!(body.contains("<a href=\"a%20b.txt\">a b.txt</a>"));
+        assert!(body.find("empty/").unwrap() < body.find("a b.txt").unwrap());
+    }
+
+    #[test]
+    fn only_get_and_head() {
+        let handler = StaticFiles::new(document_root("only_get_and_head"));
+
+        let response = handler.handle(&mut Request::new(Method::Post, "/"));
+        assert_eq!(response.status, StatusCode::METHOD_NOT_ALLOWED);
+        let response = handler.handle(&mut Request::new(Method::Head, "/"));
+        assert_eq!(response.status, StatusCode::OK);
+    }
+
+    #[test]
+    fn conditional_requests() {
+        let handler = StaticFiles::new(document_root("conditional_requests"));
+
+        let response = get(&handler, "/docs/a%20b.txt");
+        let etag = response.headers.get("ETag").unwrap().to_string();
+        let last_modified = response.headers.get("Last-Modified").unwrap().to_string();
+        assert_eq!(response.headers.get("Accept-Ranges"), Some("bytes"));
+
+        let mut request =
+            Request::new(Method::Get, "/docs/a%20b.txt").with_header("If-None-Match", &etag);
+        let response = handler.handle(&mut request);
+        assert_eq!(response.status, StatusCode::NOT_MODIFIED);
+        assert_eq!(response.headers.get("ETag"), Some(etag.as_str()));
+        assert!(response.body.is_empty());
+
+        let mut request = Request::new(Method::Head, "/docs/a%20b.txt")
+            .with_header("If-Modified-Since", &last_modified);
+        let response = handler.handle(&mut request);
+        assert_eq!(response.status, StatusCode::NOT_MODIFIED);
+
+        let mut request =
+            Request::new(Method::Get, "/docs/a%20b.txt").with_header("If-None-Match", "\"old\"");
+        assert_eq!(body(handler.handle(&mut request)), b"text");
+    }
+
+    #[test]
+    fn ranges() {
+        let root = document_root("ranges");
+        fs::write(root.join("digits.txt"), "0123456789").unwrap();
+        let handler = StaticFiles::new(root);
+        let range = |value: &str| {
+            let mut request = Request::new(Method::Get, "/digits.txt").with_header("Range", value);
+            handler.handle(&mut request)
+        };
+
+        let response = range("bytes=2-4");
+        assert_eq!(response.status, StatusCode::PARTIAL_CONTENT);
+        assert_eq!(response.headers.get("Content-Range"), Some("bytes 2-4/10"));
+        assert_eq!(response.body.len(), Some(3));
+        assert_eq!(body(response), b"234");
+
+        assert_eq!(body(range("bytes=-3")), b"789");
+
+        let response = range("bytes=10-");
+        assert_eq!(response.status, StatusCode::RANGE_NOT_SATISFIABLE);
+        assert_eq!(response.headers.get("Content-Range"), Some("bytes */10"));
+
+        let response = range("bytes=0-1,8-");
+        assert_eq!(response.status, StatusCode::PARTIAL_CONTENT);
+        let content_type = response.headers.get("Content-Type").unwrap();
+        let boundary = content_type
+            .strip_prefix("multipart/byteranges; boundary=")
+            .unwrap()
+            .to_string();
+        assert_eq!(
+            String::from_utf8(body(response)).unwrap(),
+            format!(
+                "\r\n--{0}\r\nContent-Type: text/plain; charset=utf-8\r\n\
+                 Content-Range: bytes 0-1/10\r\n\r\n01\
+                 \r\n--{0}\r\nContent-Type: text/plain; charset=utf-8\r\n\
+                 Content-Range: bytes 8-9/10\r\n\r\n89\
+                 \r\n--{0}--\r\n",
+                boundary
+            )
+        );
+
+        // An outdated If-Range gets the whole file.
+        let mut request = Request::new(Method::Get, "/digits.txt")
+            .with_header("Range", "bytes=2-4")
+            .with_header("If-Range", "\"old\"");
+        let response = handler.handle(&mut request);
+        assert_eq!(response.status, StatusCode::OK);
+        assert_eq!(body(response), b"0123456789");
+    }
+
+    #[test]
+    fn content_types() {
+        assert_eq!(
+            content_type(Path::new("a/site.CSS")),
+            "text/css; charset=utf-8"
+        );
+        assert_eq!(
+            content_type(Path::new("app.js")),
+            "text/javascript; charset=utf-8"
+        );
+        assert_eq!(
+            content_type(Path::new("README")),
+            "application/octet-stream"
+        );
+    }
+}
@@ -0,0 +1,856 @@
+use super::encoding::escape_html;
+use super::response::{Response, StatusCode};
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::SystemTime;
+
+/// Maximum depth of nested includes, which stops templates that include themselves.
+pub const MAX_INCLUDE_DEPTH: usize = 16;
+
+/// Errors loading or rendering a template.
+#[derive(Debug)]
+pub enum TemplateError {
+    Io(PathBuf, io::Error),
+    Syntax {
+        template: String,
+        line: usize,
+        message: String,
+    },
+    /// A template is included by itself, directly or not.
+    IncludeDepth(String),
+    /// The template includes another one, but it was rendered without [`Templates`].
+    NoTemplates(String),
+}
+
+impl fmt::Display for TemplateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TemplateError::Io(path, err) => write!(f, "could not read {}: {}", path.display(), err),
+            TemplateError::Syntax {
+                template,
+                line,
+                message,
+            } => write!(f, "{}:{}: {}", template, line, message),
+            TemplateError::IncludeDepth(name) => {
+                write!(f, "too many nested includes in '{}'", name)
+            }
+            TemplateError::NoTemplates(name) => {
+                write!(
+                    f,
+                    "'{}' can only be included from a template directory",
+                    name
+                )
+            }
+        }
+    }
+}
+
+impl Error for TemplateError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            TemplateError::Io(_, err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+/// Data rendered by the templates.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum Value {
+    #[default]
+    Null,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    String(String),
+    List(Vec<Value>),
+    Map(BTreeMap<String, Value>),
+}
+
+impl Value {
+    /// `null`, `false`, zero, and the empty strings, lists and maps are false in conditions.
+    pub fn is_truthy(&self) -> bool {
+        match self {
+            Value::Null => false,
+            Value::Bool(value) => *value,
+            Value::Int(value) => *value != 0,
+            Value::Float(value) => *value != 0.0,
+            Value::String(value) => !value.is_empty(),
+            Value::List(items) => !items.is_empty(),
+            Value::Map(entries) => !entries.is_empty(),
+        }
+    }
+
+    /// Looks up the field of a map or, with a numeric name, the item of a list.
+    fn get(&self, name: &str) -> Option<&Value> {
+        match self {
+            Value::Map(entries) => entries.get(name),
+            Value::List(items) => items.get(name.parse::<usize>().ok()?),
+            _ => None,
+        }
+    }
+}
+
+/// Text written by `{{ value }}`. Lists are joined with commas, maps and `null` are empty.
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Value::Null | Value::Map(_) => Ok(()),
+            Value::Bool(value) => write!(f, "{}", value),
+            Value::Int(value) => write!(f, "{}", value),
+            Value::Float(value) => write!(f, "{}", value),
+            Value::String(value) => f.write_str(value),
+            Value::List(items) => {
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(", ")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+macro_rules! value_from {
+    ($variant:ident: $($type:ty),*) => {
+        $(impl From<$type> for Value {
+            fn from(value: $type) -> Value {
+                Value::$variant(value.into())
+            }
+        })*
+    };
+}
+
+value_from!(Bool: bool);
+value_from!(Int: i8, i16, i32, i64, u8, u16, u32);
+value_from!(Float: f32, f64);
+value_from!(String: &str, String, &String);
+
+impl From<usize> for Value {
+    fn from(value: usize) -> Value {
+        i64::try_from(value).map_or(Value::Float(value as f64), Value::Int)
+    }
+}
+
+impl From<u64> for Value {
+    fn from(value: u64) -> Value {
+        i64::try_from(value).map_or(Value::Float(value as f64), Value::Int)
+    }
+}
+
+impl<T: Into<Value>> From<Option<T>> for Value {
+    fn from(value: Option<T>) -> Value {
+        value.map_or(Value::Null, Into::into)
+    }
+}
+
+impl<T: Into<Value>> From<Vec<T>> for Value {
+    fn from(items: Vec<T>) -> Value {
+        Value::List(items.into_iter().map(Into::into).collect())
+    }
+}
+
+impl From<Context> for Value {
+    fn from(context: Context) -> Value {
+        Value::Map(context.0)
+    }
+}
+
+/// Named values available to a template:
+///
+/// ```rust
+/// let context = Context::new()
+///     .with("title", "Files")
+///     .with("files", vec!["a.txt", "b.txt"]);
+/// ```
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Context(BTreeMap<String, Value>);
+
+impl Context {
+    pub fn new() -> Context {
+        Context::default()
+    }
+
+    pub fn with(mut self, name: impl Into<String>, value: impl Into<Value>) -> Context {
+        self.insert(name, value);
+        self
+    }
+
+    pub fn insert(&mut self, name: impl Into<String>, value: impl Into<Value>) {
+        self.0.insert(name.into(), value.into());
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Node {
+    Text(String),
+    /// `{{ path }}`, or `{{ path | raw }}` to skip the HTML escaping.
+    Value {
+        path: Vec<String>,
+        raw: bool,
+    },
+    /// `{% if [not] path %} ... {% else %} ... {% endif %}`.
+    If {
+        negated: bool,
+        path: Vec<String>,
+        then: Vec<Node>,
+        otherwise: Vec<Node>,
+    },
+    /// `{% for name in path %} ... {% else %} ... {% endfor %}`, the `else` block is rendered
+    /// when there is nothing to iterate over.
+    For {
+        name: String,
+        path: Vec<String>,
+        body: Vec<Node>,
+        empty: Vec<Node>,
+    },
+    /// `{% include "name" %}`.
+    Include(String),
+}
+
+/// Parsed template. The syntax is a small subset of Jinja:
+///
+/// - `{{ user.name }}` writes a value of the context, escaped for HTML. Fields of maps and
+///   items of lists are accessed with dots. `{{ html | raw }}` writes the value as it is.
+///   Missing values are empty.
+/// - `{% if user %} ... {% else %} ... {% endif %}` renders a block if the value is truthy (see
+///   [`Value::is_truthy`]), or with `if not` if it is not. The `else` block is optional.
+/// - `{% for file in files %} ... {% endfor %}` renders the block for every item of a list, or
+///   every entry of a map as `file.key` and `file.value`. Inside the block, `loop.index`
+///   counts from 1 and `loop.first` and `loop.last` are booleans. An optional `{% else %}`
+///   block is rendered when there is nothing to iterate over.
+/// - `{% include "header.html" %}` renders another template of the [`Templates`] directory
+///   with the same values.
+/// - `{# comments #}` are not rendered.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Template {
+    name: String,
+    nodes: Vec<Node>,
+}
+
+impl Template {
+    /// Parses the source of a template. The name is only used in the error messages. As in
+    /// Jinja, a single newline at the end of the source is removed, so included templates do
+    /// not add blank lines.
+    pub fn parse(name: &str, source: &str) -> Result<Template, TemplateError> {
+        let source = source
+            .strip_suffix("\r\n")
+            .or_else(|| source.strip_suffix('\n'))
+            .unwrap_or(source);
+        let mut parser = Parser {
+            name,
+            tokens: tokenize(name, source)?.into_iter(),
+            line: 1,
+        };
+
+        match parser.block(&[])? {
+            (nodes, None) => Ok(Template {
+                name: name.to_string(),
+                nodes,
+            }),
+            (_, Some((tag, line))) => Err(parser.error(line, format!("unexpected '{}'", tag))),
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Renders the template. Templates with includes must be rendered through
+    /// [`Templates::render`].
+    pub fn render(&self, context: &Context) -> Result<String, TemplateError> {
+        let mut output = String::new();
+        let mut scope = Scope {
+            context,
+            locals: Vec::new(),
+        };
+        self.render_nodes(&self.nodes, &mut scope, None, 0, &mut output)?;
+        Ok(output)
+    }
+
+    fn render_nodes(
+        &self,
+        nodes: &[Node],
+        scope: &mut Scope,
+        templates: Option<&Templates>,
+        depth: usize,
+        output: &mut String,
+    ) -> Result<(), TemplateError> {
+        for node in nodes {
+            match node {
+                Node::Text(text) => output.push_str(text),
+                Node::Value { path, raw } => {
+                    let value = scope.lookup(path).map(Value::to_string).unwrap_or_default();
+                    match raw {
+                        true => output.push_str(&value),
+                        false => output.push_str(&escape_html(&value)),
+                    }
+                }
+                Node::If {
+                    negated,
+                    path,
+                    then,
+                    otherwise,
+                } => {
+                    let truthy = scope.lookup(path).is_some_and(Value::is_truthy);
+                    let block = if truthy != *negated { then } else { otherwise };
+                    self.render_nodes(block, scope, templates, depth, output)?;
+                }
+                Node::For {
+                    name,
+                    path,
+                    body,
+                    empty,
+                } => {
+                    let items: Vec<Value> = match scope.lookup(path) {
+                        Some(Value::List(items)) => items.clone(),
+                        Some(Value::Map(entries)) => entries
+                            .iter()
+                            .map(|(key, value)| {
+                                Context::new().with("key", key).with("value", value.clone())
+                            })
+                            .map(Value::from)
+                            .collect(),
+                        _ => Vec::new(),
+                    };
+                    if items.is_empty() {
+                        self.render_nodes(empty, scope, templates, depth, output)?;
+                    }
+
+                    let count = items.len();
+                    for (i, item) in items.into_iter().enumerate() {
+                        let info = Context::new()
+                            .with("index", i + 1)
+                            .with("first", i == 0)
+                            .with("last", i + 1 == count);
+                        scope.locals.push((name.clone(), item));
+                        scope.locals.push((String::from("loop"), info.into()));
+                        let result = self.render_nodes(body, scope, templates, depth, output);
+                        scope.locals.truncate(scope.locals.len() - 2);
+                        result?;
+                    }
+                }
+                Node::Include(name) => {
+                    let templates =
+                        templates.ok_or_else(|| TemplateError::NoTemplates(name.clone()))?;
+                    if depth >= MAX_INCLUDE_DEPTH {
+                        return Err(TemplateError::IncludeDepth(self.name.clone()));
+                    }
+                    let included = templates.get(name)?;
+                    included.render_nodes(
+                        &included.nodes,
+                        scope,
+                        Some(templates),
+                        depth + 1,
+                        output,
+                    )?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Values visible while rendering: the variables of the enclosing loops hide the context.
+struct Scope<'a> {
+    context: &'a Context,
+    locals: Vec<(String, Value)>,
+}
+
+impl Scope<'_> {
+    fn lookup(&self, path: &[String]) -> Option<&Value> {
+        let (first, rest) = path.split_first()?;
+        let value = match self.locals.iter().rev().find(|(name, _)| name == first) {
+            Some((_, value)) => value,
+            None => self.context.0.get(first)?,
+        };
+
+        rest.iter().try_fold(value, |value, name| value.get(name))
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Token<'a> {
+    Text(&'a str),
+    Value(&'a str),
+    Tag(&'a str),
+}
+
+/// Splits the source into text, `{{ value }}` and `{% tag %}` tokens with their line numbers.
+/// Comments are dropped.
+fn tokenize<'a>(name: &str, source: &'a str) -> Result<Vec<(Token<'a>, usize)>, TemplateError> {
+    let mut tokens = Vec::new();
+    let mut rest = source;
+    let mut line = 1;
+
+    while let Some(start) = rest.find('{') {
+        let close = match rest[start..].get(..2) {
+            Some("{{") => "}}",
+            Some("{%") => "%}",
+            Some("{#") => "#}",
+            _ => {
+                let text = &rest[..start + 1];
+                tokens.push((Token::Text(text), line));
+                line += text.matches('\n').count();
+                rest = &rest[start + 1..];
+                continue;
+            }
+        };
+
+        if start > 0 {
+            tokens.push((Token::Text(&rest[..start]), line));
+            line += rest[..start].matches('\n').count();
+        }
+        let inner_start = start + 2;
+        let Some(length) = rest[inner_start..].find(close) else {
+            return Err(TemplateError::Syntax {
+                template: name.to_string(),
+                line,
+                message: format!("unclosed '{}'", &rest[start..inner_start]),
+            });
+        };
+        let inner = &rest[inner_start..inner_start + length];
+        match close {
+            "}}" => tokens.push((Token::Value(inner.trim()), line)),
+            "%}" => tokens.push((Token::Tag(inner.trim()), line)),
+            _ => {}
+        }
+        line += inner.matches('\n').count();
+        rest = &rest[inner_start + length + 2..];
+    }
+
+    if !rest.is_empty() {
+        tokens.push((Token::Text(rest), line));
+    }
+    Ok(tokens)
+}
+
+/// Nodes of a block, followed by the tag that ended it and its line.
+type Block = (Vec<Node>, Option<(String, usize)>);
+
+struct Parser<'a> {
+    name: &'a str,
+    tokens: std::vec::IntoIter<(Token<'a>, usize)>,
+    /// Line of the last token, where the missing end tags are reported.
+    line: usize,
+}
+
+impl Parser<'_> {
+    fn error(&self, line: usize, message: impl Into<String>) -> TemplateError {
+        TemplateError::Syntax {
+            template: self.name.to_string(),
+            line,
+            message: message.into(),
+        }
+    }
+
+    /// Parses nodes until one of the `ends` tags, which is returned with its line. The end of
+    /// the template is only valid if `ends` is empty.
+    fn block(
+        &mut self,
+        ends: &[&str],
+    ) -> Result<Block, TemplateError> {
+        let mut nodes = Vec::new();
+
+        while let Some((token, line)) = self.tokens.next() {
+            self.line = line;
+            match token {
+                Token::Text(text) => match nodes.last_mut() {
+                    Some(Node::Text(previous)) => previous.push_str(text),
+                    _ => nodes.push(Node::Text(text.to_string())),
+                },
+                Token::Value(expression) => {
+                    let (path, raw) = match expression.split_once('|') {
+                        Some((path, filter)) if filter.trim() == "raw" => (path, true),
+                        Some((_, filter)) => {
+                            return Err(
+                                self.error(line, format!("unknown filter '{}'", filter.trim()))
+                            )
+                        }
+                        None => (expression, false),
+                    };
+                    nodes.push(Node::Value {
+                        path: self.path(path, line)?,
+                        raw,
+                    });
+                }
+                Token::Tag(tag) => {
+                    let (keyword, arguments) = tag.split_once(' ').unwrap_or((tag, ""));
+                    if ends.contains(&keyword) {
+                        if !arguments.trim().is_empty() {
+                            return Err(self.error(line, format!("'{}' has no arguments", keyword)));
+                        }
+                        return Ok((nodes, Some((keyword.to_string(), line))));
+                    }
+                    nodes.push(self.tag(keyword, arguments.trim(), line)?);
+                }
+            }
+        }
+
+        match ends.last() {
+            Some(end) => Err(self.error(self.line, format!("missing '{}'", end))),
+            None => Ok((nodes, None)),
+        }
+    }
+
+    fn tag(&mut self, keyword: &str, arguments: &str, line: usize) -> Result<Node, TemplateError> {
+        match keyword {
+            "if" => {
+                let (negated, path) = match arguments.strip_prefix("not ") {
+                    Some(path) => (true, path),
+                    None => (false, arguments),
+                };
+                let path = self.path(path, line)?;
+                let (then, end) = self.block(&["else", "endif"])?;
+                let otherwise = match end {
+                    Some((end, _)) if end == "else" => self.block(&["endif"])?.0,
+                    _ => Vec::new(),
+                };
+                Ok(Node::If {
+                    negated,
+                    path,
+                    then,
+                    otherwise,
+                })
+            }
+            "for" => {
+                let Some((name, path)) = arguments.split_once(" in ") else {
+                    return Err(self.error(line, "expected 'for <name> in <value>'"));
+                };
+                let name = name.trim();
+                if !is_name(name) {
+                    return Err(self.error(line, format!("invalid loop variable '{}'", name)));
+                }
+                let path = self.path(path, line)?;
+                let (body, end) = self.block(&["else", "endfor"])?;
+                let empty = match end {
+                    Some((end, _)) if end == "else" => self.block(&["endfor"])?.0,
+                    _ => Vec::new(),
+                };
+                Ok(Node::For {
+                    name: name.to_string(),
+                    path,
+                    body,
+                    empty,
+                })
+            }
+            "include" => {
+                let name = arguments
+                    .strip_prefix('"')
+                    .and_then(|name| name.strip_suffix('"'))
+                    .filter(|name| !name.is_empty())
+                    .ok_or_else(|| self.error(line, "expected 'include \"<name>\"'"))?;
+                Ok(Node::Include(name.to_string()))
+            }
+            "" => Err(self.error(line, "empty tag")),
+            _ => Err(self.error(line, format!("unexpected '{}'", keyword))),
+        }
+    }
+
+    /// Parses a dotted path such as `user.name` or `items.0`.
+    fn path(&self, path: &str, line: usize) -> Result<Vec<String>, TemplateError> {
+        let path = path.trim();
+        if path.split('.').all(is_name) {
+            Ok(path.split('.').map(String::from).collect())
+        } else {
+            Err(self.error(line, format!("invalid value '{}'", path)))
+        }
+    }
+}
+
+fn is_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b == b'_' || b == b'-')
+}
+
+struct Cached {
+    template: Arc<Template>,
+    modified: Option<SystemTime>,
+}
+
+/// Directory of templates. Each template is parsed the first time it is rendered and kept in
+/// memory; with [`with_reload`](Templates::with_reload) the file is checked before every
+/// render and parsed again when it changes, so pages can be edited while the server runs.
+pub struct Templates {
+    dir: PathBuf,
+    reload: bool,
+    cache: RwLock<HashMap<String, Cached>>,
+}
+
+impl Templates {
+    pub fn new(dir: impl Into<PathBuf>) -> Templates {
+        Templates {
+            dir: dir.into(),
+            reload: false,
+            cache: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Reloads the templates when their files change. Meant for development, as every render
+    /// reads the modification time of the files.
+    pub fn with_reload(mut self, reload: bool) -> Templates {
+        self.reload = reload;
+        self
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// Returns the parsed template, from the cache if it is still valid.
+    pub fn get(&self, name: &str) -> Result<Arc<Template>, TemplateError> {
+        let path = self.dir.join(name);
+        let modified = || {
+            fs::metadata(&path)
+                .and_then(|metadata| metadata.modified())
+                .ok()
+        };
+
+        let current = self.reload.then(modified).flatten();
+        if let Some(cached) = self.cache.read().unwrap().get(name) {
+            if !self.reload || cached.modified == current {
+                return Ok(Arc::clone(&cached.template));
+            }
+        }
+
+        let source =
+            fs::read_to_string(&path).map_err(|err| TemplateError::Io(path.clone(), err))?;
+        let template = Arc::new(Template::parse(name, &source)?);
+        self.cache.write().unwrap().insert(
+            name.to_string(),
+            Cached {
+                template: Arc::clone(&template),
+                modified: current.or_else(modified),
+            },
+        );
+
+        Ok(template)
+    }
+
+    pub fn render(&self, name: &str, context: &Context) -> Result<String, TemplateError> {
+        let template = self.get(name)?;
+        let mut output = String::new();
+        let mut scope = Scope {
+            context,
+            locals: Vec::new(),
+        };
+        template.render_nodes(&template.nodes, &mut scope, Some(self), 0, &mut output)?;
+        Ok(output)
+    }
+
+    /// Renders the template as a HTML response with the given status. Errors are logged and
+    /// answered with `500 Internal Server Error`.
+    pub fn response(&self, status: StatusCode, name: &str, context: &Context) -> Response {
+        match self.render(name, context) {
+            Ok(html) => Response::html(html).with_status(status),
+            Err(err) => {
+                eprintln!("Error rendering the template: {}", err);
+                Response::error(StatusCode::INTERNAL_SERVER_ERROR)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::process;
+
+    fn render(source: &str, context: &Context) -> String {
+        Template::parse("test.html", source)
+            .unwrap()
+            .render(context)
+            .unwrap()
+    }
+
+    fn syntax_error(source: &str) -> (usize, String) {
+        match Template::parse("test.html", source) {
+            Err(TemplateError::Syntax { line, message, .. }) => (line, message),
+            result => panic!("expected a syntax error, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn values() {
+        let context = Context::new()
+            .with("name", "<World & Co>")
+            .with("count", 3)
+            .with("user", Context::new().with("name", "Ana"))
+            .with("items", vec!["a", "b"]);
+
+        assert_eq!(
+            render("Hello {{ name }}!", &context),
+            "Hello &lt;World &amp; Co&gt;!"
+        );
+        assert_eq!(render("{{name|raw}}", &context), "<World & Co>");
+        assert_eq!(
+            render("{{ user.name }} {{ items.1 }} {{ count }}", &context),
+            "Ana b 3"
+        );
+        assert_eq!(
+            render("[{{ missing }}{{ user.missing.x }}]", &context),
+            "[]"
+        );
+        assert_eq!(render("{ {# comment #}}", &context), "{ }");
+        assert_eq!(render("line\n\n", &context), "line\n");
+    }
+
+    #[test]
+    fn conditionals() {
+        let context = Context::new()
+            .with("yes", true)
+            .with("empty", Vec::<String>::new())
+            .with("zero", 0);
+        let source = "{% if yes %}a{% endif %}{% if empty %}b{% else %}c{% endif %}\
+                      {% if not zero %}d{% endif %}{% if missing %}e{% endif %}";
+
+        assert_eq!(render(source, &context), "acd");
+    }
+
+    #[test]
+    fn loops() {
+        let context = Context::new()
+            .with("files", vec!["a.txt", "b<.txt"])
+            .with("sizes", Context::new().with("a", 1).with("b", 2))
+            .with("none", Vec::<String>::new());
+
+        assert_eq!(
+            render(
+                "{% for file in files %}{{ loop.index }}={{ file }}{% if not loop.last %},{% endif %}{% endfor %}",
+                &context
+            ),
+            "1=a.txt,2=b&lt;.txt"
+        );
+        assert_eq!(
+            render(
+                "{% for size in sizes %}{{ size.key }}:{{ size.value }} {% endfor %}",
+                &context
+            ),
+            "a:1 b:2 "
+        );
+        assert_eq!(
+            render(
+                "{% for x in none %}{{ x }}{% else %}nothing{% endfor %}",
+                &context
+            ),
+            "nothing"
+        );
+        // The loop variable hides the context only inside the loop.
+        assert_eq!(
+            render(
+                "{% for files in files %}{{ files }}{% endfor %} {{ files }}",
+                &context
+            ),
+            "a.txtb&lt;.txt a.txt, b&lt;.txt"
+        );
+    }
+
+    #[test]
+    fn syntax_errors() {
+        assert_eq!(
+            syntax_error("a\n{{ name"),
+            (2, String::from("unclosed '{{'"))
+        );
+        assert_eq!(
+            syntax_error("\n\n{% if x %}"),
+            (3, String::from("missing 'endif'"))
+        );
+        assert_eq!(
+            syntax_error("{% endfor %}"),
+            (1, String::from("unexpected 'endfor'"))
+        );
+        assert_eq!(
+            syntax_error("{{ a b }}"),
+            (1, String::from("invalid value 'a b'"))
+        );
+        assert_eq!(
+            syntax_error("{{ a | upper }}"),
+            (1, String::from("unknown filter 'upper'"))
+        );
+        assert_eq!(
+            syntax_error("{% for x of y %}{% endfor %}"),
+            (1, String::from("expected 'for <name> in <value>'"))
+        );
+        assert_eq!(
+            syntax_error("{% include header %}"),
+            (1, String::from("expected 'include \"<name>\"'"))
+        );
+    }
+
+    fn template_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("templates_{}_{}", name, process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn includes() {
+        let dir = template_dir("includes");
+        fs::write(
+            dir.join("page.html"),
+            "{% include \"title.html\" %}<p>{{ text }}</p>",
+        )
+        .unwrap();
+        fs::write(dir.join("title.html"), "<h1>{{ title }}</h1>").unwrap();
+        fs::write(dir.join("loop.html"), "{% include \"loop.html\" %}").unwrap();
+        let templates = Templates::new(&dir);
+
+        let context = Context::new().with("title", "Hi").with("text", "there");
+        assert_eq!(
+            templates.render("page.html", &context).unwrap(),
+            "<h1>Hi</h1><p>there</p>"
+        );
+        assert!(matches!(
+            templates.render("loop.html", &context),
+            Err(TemplateError::IncludeDepth(_))
+        ));
+        assert!(matches!(
+            templates.render("missing.html", &context),
+            Err(TemplateError::Io(..))
+        ));
+        assert!(matches!(
+            templates.get("page.html").unwrap().render(&context),
+            Err(TemplateError::NoTemplates(_))
+        ));
+
+        let response = templates.response(StatusCode::NOT_FOUND, "title.html", &context);
+        assert_eq!(response.status, StatusCode::NOT_FOUND);
+        assert_eq!(
+            response.headers.get("Content-Type"),
+            Some("text/html; charset=utf-8")
+        );
+    }
+
+    #[test]
+    fn cache_and_reload() {
+        let dir = template_dir("cache_and_reload");
+        let path = dir.join("page.html");
+        fs::write(&path, "old").unwrap();
+        let cached = Templates::new(&dir);
+        let reloaded = Templates::new(&dir).with_reload(true);
+        let context = Context::new();
+        assert_eq!(cached.render("page.html", &context).unwrap(), "old");
+        assert_eq!(reloaded.render("page.html", &context).unwrap(), "old");
+
+        fs::write(&path, "new").unwrap();
+        // Makes sure the modification time changes even on coarse file systems.
+        let file = fs::File::options().write(true).open(&path).unwrap();
+        file.set_modified(SystemTime::now() + std::time::Duration::from_secs(10))
+            .unwrap();
+
+        assert_eq!(cached.render("page.html", &context).unwrap(), "old");
+        assert_eq!(reloaded.render("page.html", &context).unwrap(), "new");
+    }
+}
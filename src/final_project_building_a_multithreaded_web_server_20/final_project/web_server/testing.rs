@@ -0,0 +1,288 @@
+use super::config::Config;
+use super::headers::Headers;
+use super::request::{self, Limits, Method, Request};
+use super::response::{self, StatusCode};
+use super::router::Handler;
+use super::server::{ServerBuilder, ServerHandle};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::time::Duration;
+
+/// Time a [`Client`] waits for a response before failing, so a broken server fails the test
+/// instead of hanging it.
+pub const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Server started in the process of the test, on an ephemeral port of localhost. It runs in a
+/// thread of its own and is shut down when dropped.
+///
+/// # Example
+///
+/// ```rust
+/// let server = TestServer::start(Router::new().get("/", |_: &mut Request| Response::html("Hi")));
+///
+/// let response = server.get("/").unwrap();
+/// assert_eq!(response.status, StatusCode::OK);
+/// assert_eq!(response.text(), "Hi");
+/// ```
+pub struct TestServer {
+    handle: ServerHandle,
+    config: Config,
+}
+
+impl TestServer {
+    /// Starts the server with the default options.
+    pub fn start<H: Handler>(handler: H) -> TestServer {
+        TestServer::with_builder(ServerBuilder::new(), handler)
+    }
+
+    /// Starts the server with the options of the builder, except for the address and the port.
+    ///
+    /// # Panics
+    ///
+    /// If the server cannot be built, like with an invalid option.
+    pub fn with_builder<H: Handler>(builder: ServerBuilder, handler: H) -> TestServer {
+        let server = builder
+            .address("127.0.0.1")
+            .port(0)
+            .build(handler)
+            .expect("Could not start the test server");
+        let config = server.config().clone();
+
+        TestServer {
+            handle: server.spawn(),
+            config,
+        }
+    }
+
+    pub fn address(&self) -> SocketAddr {
+        self.handle.local_addr()
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Opens a new connection to the server.
+    pub fn client(&self) -> io::Result<Client> {
+        Client::connect(self.address())
+    }
+
+    /// Sends a `GET` request through a new connection.
+    pub fn get(&self, target: &str) -> io::Result<TestResponse> {
+        self.client()?.get(target)
+    }
+
+    /// Sends the request through a new connection.
+    pub fn send(&self, request: &Request) -> io::Result<TestResponse> {
+        self.client()?.send(request)
+    }
+
+    /// Stops the server and waits until it has stopped.
+    pub fn shutdown(self) {
+        // Done by `drop`.
+    }
+}
+
+impl Drop for TestServer {
+    fn drop(&mut self) {
+        self.handle.shutdown();
+    }
+}
+
+/// Minimal HTTP/1.1 client over a persistent connection, to send requests one after another and
+/// read their responses.
+pub struct Client {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+    host: String,
+}
+
+impl Client {
+    pub fn connect(address: SocketAddr) -> io::Result<Client> {
+        let stream = TcpStream::connect(address)?;
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        stream.set_write_timeout(Some(TIMEOUT))?;
+
+        Ok(Client {
+            reader: BufReader::new(stream.try_clone()?),
+            writer: stream,
+            host: address.to_string(),
+        })
+    }
+
+    pub fn get(&mut self, target: &str) -> io::Result<TestResponse> {
+        self.send(&Request::new(Method::Get, target))
+    }
+
+    /// Sends the request and reads its response. The `Host` header is added if the request does
+    /// not have it, and `Content-Length` if it has a body.
+    pub fn send(&mut self, request: &Request) -> io::Result<TestResponse> {
+        self.write_request(request)?;
+        self.read_response(request.method)
+    }
+
+    /// Writes the request without waiting for the response, to test pipelining or requests sent
+    /// in parts, for example.
+    pub fn write_request(&mut self, request: &Request) -> io::Result<()> {
+        let mut headers = request.headers.clone();
+        if !headers.contains("Host") {
+            headers.insert("Host", self.host.clone());
+        }
+        if !request.body.is_empty() && !headers.contains("Transfer-Encoding") {
+            headers.insert("Content-Length", request.body.len().to_string());
+        }
+
+        let mut bytes = match &request.query {
+            Some(query) => format!("{} {}?{} ", request.method, request.path, query),
+            None => format!("{} {} ", request.method, request.path),
+        }
+        .into_bytes();
+        bytes.extend_from_slice(format!("{}\r\n{}\r\n", request.version, headers).as_bytes());
+        bytes.extend_from_slice(&request.body);
+        self.write_raw(&bytes)
+    }
+
+    /// Writes the bytes as they are, to send invalid requests.
+    pub fn write_raw(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.writer.write_all(bytes)?;
+        self.writer.flush()
+    }
+
+    /// Reads the next response, sent for a request with the method.
+    pub fn read_response(&mut self, method: Method) -> io::Result<TestResponse> {
+        let reader = &mut self.reader;
+        let limits = Limits {
+            max_body_size: usize::MAX,
+            ..Limits::default()
+        };
+
+        let (status, headers) = response::read_head(reader, &limits)?;
+        let body = if method == Method::Head || !status.allows_body() {
+            Vec::new()
+        } else if request::is_chunked(&headers) {
+            request::read_chunked_body(reader, &limits)?
+        } else {
+            match request::content_length(&headers)? {
+                Some(length) => {
+                    let mut body = vec![0; length];
+                    reader.read_exact(&mut body)?;
+                    body
+                }
+                // The end of the body is the end of the connection.
+                None => {
+                    let mut body = Vec::new();
+                    reader.read_to_end(&mut body)?;
+                    body
+                }
+            }
+        };
+
+        Ok(TestResponse {
+            status,
+            headers,
+            body,
+        })
+    }
+
+    /// Returns `true` if the server closed the connection, waiting up to the [`TIMEOUT`].
+    pub fn is_closed(&mut self) -> bool {
+        matches!(self.reader.fill_buf(), Ok([]) | Err(_))
+    }
+}
+
+/// Response received by a [`Client`], with the body already decoded from the chunked coding.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TestResponse {
+    pub status: StatusCode,
+    pub headers: Headers,
+    pub body: Vec<u8>,
+}
+
+impl TestResponse {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.get(name)
+    }
+
+    /// The body as text. Invalid UTF-8 sequences are replaced.
+    pub fn text(&self) -> String {
+        String::from_utf8_lossy(&self.body).into_owned()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::body::Body;
+    use super::super::response::Response;
+    use super::super::router::Router;
+    use super::*;
+
+    fn router() -> Router {
+        Router::new()
+            .get("/hello/:name", |request: &mut Request| {
+                let name = request.param("name").unwrap_or_default().to_string();
+                Response::new(StatusCode::OK)
+                    .with_header("Content-Type", "text/plain")
+                    .with_body(format!("Hello, {}!", name))
+            })
+            .get("/stream", |_: &mut Request| {
+                let reader = (&b"first "[..]).chain(&b"second"[..]);
+                Response::new(StatusCode::OK).with_body(Body::stream(reader))
+            })
+            .post("/echo", |request: &mut Request| {
+                Response::new(StatusCode::OK).with_body(request.body.clone())
+            })
+    }
+
+    #[test]
+    fn requests() {
+        let server = TestServer::start(router());
+        assert_eq!(server.address().ip().to_string(), "127.0.0.1");
+        assert_ne!(server.address().port(), 0);
+
+        let response = server.get("/hello/world").unwrap();
+        assert_eq!(response.status, StatusCode::OK);
+        assert_eq!(response.header("Content-Type"), Some("text/plain"));
+        assert_eq!(response.text(), "Hello, world!");
+
+        let response = server
+            .send(&Request::new(Method::Post, "/echo").with_body("ping"))
+            .unwrap();
+        assert_eq!(response.text(), "ping");
+
+        assert_eq!(
+            server.get("/missing").unwrap().status,
+            StatusCode::NOT_FOUND
+        );
+    }
+
+    #[test]
+    fn persistent_connection() {
+        let server = TestServer::start(router());
+        let mut client = server.client().unwrap();
+
+        let response = client.get("/stream").unwrap();
+        assert_eq!(response.header("Transfer-Encoding"), Some("chunked"));
+        assert_eq!(response.text(), "first second");
+
+        let response = client
+            .send(&Request::new(Method::Head, "/hello/head"))
+            .unwrap();
+        assert_eq!(response.header("Content-Length"), Some("12"));
+        assert!(response.body.is_empty());
+
+        let response = client
+            .send(&Request::new(Method::Get, "/hello/bye").with_header("Connection", "close"))
+            .unwrap();
+        assert_eq!(response.text(), "Hello, bye!");
+        assert!(client.is_closed());
+    }
+
+    #[test]
+    fn shutdown() {
+        let server = TestServer::start(router());
+        let address = server.address();
+
+        server.shutdown();
+        assert!(TcpStream::connect(address).is_err());
+    }
+}
@@ -0,0 +1,266 @@
+use super::config::Config;
+#[cfg(feature = "tls")]
+use rustls::{ServerConfig, ServerConnection, StreamOwned};
+#[cfg(feature = "tls")]
+use std::cell::RefCell;
+use std::error::Error;
+use std::fmt;
+#[cfg(feature = "tls")]
+use std::fs::File;
+use std::io;
+#[cfg(feature = "tls")]
+use std::io::{BufReader, Read, Write};
+#[cfg(feature = "tls")]
+use std::net::TcpStream;
+#[cfg(feature = "tls")]
+use std::path::Path;
+use std::path::PathBuf;
+#[cfg(feature = "tls")]
+use std::sync::Arc;
+
+/// Errors setting up HTTPS.
+#[derive(Debug)]
+pub enum TlsError {
+    /// The configuration sets a certificate or key, but the crate was built without the `tls`
+    /// feature.
+    Disabled,
+    /// Only one of the certificate and the key is set.
+    Incomplete,
+    /// HTTPS is only served by the [`Backend::ThreadPool`](super::config::Backend::ThreadPool).
+    EventLoop,
+    Io(PathBuf, io::Error),
+    NoCertificate(PathBuf),
+    NoPrivateKey(PathBuf),
+    /// The key does not match the certificate, or one of them is not supported.
+    #[cfg(feature = "tls")]
+    Rustls(rustls::Error),
+}
+
+impl fmt::Display for TlsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TlsError::Disabled => f.write_str("HTTPS needs the 'tls' feature"),
+            TlsError::Incomplete => f.write_str("both 'tls_cert' and 'tls_key' must be set"),
+            TlsError::EventLoop => f.write_str("HTTPS is not supported by the event loop backend"),
+            TlsError::Io(path, err) => write!(f, "could not read {}: {}", path.display(), err),
+            TlsError::NoCertificate(path) => write!(f, "no certificate in {}", path.display()),
+            TlsError::NoPrivateKey(path) => write!(f, "no private key in {}", path.display()),
+            #[cfg(feature = "tls")]
+            TlsError::Rustls(err) => write!(f, "invalid certificate or key: {}", err),
+        }
+    }
+}
+
+impl Error for TlsError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            TlsError::Io(_, err) => Some(err),
+            #[cfg(feature = "tls")]
+            TlsError::Rustls(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+/// Checks that HTTPS can be served with the configuration. Returns `false` if the configuration
+/// does not ask for it.
+pub fn enabled(config: &Config) -> Result<bool, TlsError> {
+    match (&config.tls_cert, &config.tls_key) {
+        (None, None) => Ok(false),
+        (Some(_), Some(_)) if cfg!(not(feature = "tls")) => Err(TlsError::Disabled),
+        (Some(_), Some(_)) => Ok(true),
+        _ => Err(TlsError::Incomplete),
+    }
+}
+
+/// Loads the certificate chain and private key set in the configuration, or returns `None` if
+/// they are not set.
+#[cfg(feature = "tls")]
+pub fn load(config: &Config) -> Result<Option<Arc<ServerConfig>>, TlsError> {
+    let (Some(cert), Some(key)) = (&config.tls_cert, &config.tls_key) else {
+        return enabled(config).map(|_| None);
+    };
+
+    let certs = rustls_pemfile::certs(&mut open(cert)?)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|err| TlsError::Io(cert.clone(), err))?;
+    if certs.is_empty() {
+        return Err(TlsError::NoCertificate(cert.clone()));
+    }
+    let private_key = rustls_pemfile::private_key(&mut open(key)?)
+        .map_err(|err| TlsError::Io(key.clone(), err))?
+        .ok_or_else(|| TlsError::NoPrivateKey(key.clone()))?;
+
+    let mut tls = ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(certs, private_key)
+        .map_err(TlsError::Rustls)?;
+    tls.alpn_protocols = vec![b"http/1.1".to_vec()];
+
+    Ok(Some(Arc::new(tls)))
+}
+
+#[cfg(feature = "tls")]
+fn open(path: &Path) -> Result<BufReader<File>, TlsError> {
+    File::open(path)
+        .map(BufReader::new)
+        .map_err(|err| TlsError::Io(path.to_path_buf(), err))
+}
+
+/// TLS session over a connection. Like [`TcpStream`], it is read and written through shared
+/// references, so a connection can use a buffered reader and writer at the same time.
+///
+/// The handshake happens with the first read or write, using the timeouts of the socket.
+#[cfg(feature = "tls")]
+pub struct TlsStream<'a> {
+    inner: RefCell<StreamOwned<ServerConnection, &'a TcpStream>>,
+}
+
+#[cfg(feature = "tls")]
+impl<'a> TlsStream<'a> {
+    pub fn new(stream: &'a TcpStream, config: Arc<ServerConfig>) -> io::Result<TlsStream<'a>> {
+        let connection = ServerConnection::new(config).map_err(io::Error::other)?;
+
+        Ok(TlsStream {
+            inner: RefCell::new(StreamOwned::new(connection, stream)),
+        })
+    }
+
+    /// Tells the client that nothing else will be sent, so it can tell a complete response from
+    /// a truncated one.
+    pub fn close(&self) -> io::Result<()> {
+        let mut inner = self.inner.borrow_mut();
+        inner.conn.send_close_notify();
+        inner.flush()
+    }
+
+    /// Moves the session to a socket owned by the stream, for a connection that outlives the
+    /// borrowed one, like an upgraded connection.
+    pub fn into_owned(self, socket: TcpStream) -> StreamOwned<ServerConnection, TcpStream> {
+        StreamOwned::new(self.inner.into_inner().conn, socket)
+    }
+}
+
+#[cfg(feature = "tls")]
+impl Read for &TlsStream<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.inner.borrow_mut().read(buf)
+    }
+}
+
+#[cfg(feature = "tls")]
+impl Write for &TlsStream<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.borrow_mut().flush()
+    }
+}
+
+#[cfg(all(test, feature = "tls"))]
+mod tests {
+    use super::super::request::Request;
+    use super::super::response::{Response, StatusCode};
+    use super::super::router::Router;
+    use super::super::server::ServerBuilder;
+    use super::*;
+    use rustls::pki_types::ServerName;
+    use rustls::{ClientConfig, ClientConnection, RootCertStore};
+    use std::time::Duration;
+
+    const CERT: &str = "tls/localhost.pem";
+    const KEY: &str = "tls/localhost-key.pem";
+
+    fn client_config() -> Arc<ClientConfig> {
+        let mut roots = RootCertStore::empty();
+        for cert in rustls_pemfile::certs(&mut open(Path::new(CERT)).unwrap()) {
+            roots.add(cert.unwrap()).unwrap();
+        }
+
+        Arc::new(
+            ClientConfig::builder()
+                .with_root_certificates(roots)
+                .with_no_client_auth(),
+        )
+    }
+
+    #[test]
+    fn https_on_localhost() {
+        let router = Router::new().get("/*", |request: &mut Request| {
+            Response::new(StatusCode::OK).with_body(format!("secure {}", request.path))
+        });
+        let server = ServerBuilder::new()
+            .port(0)
+            .tls(CERT, KEY)
+            .build(router)
+            .unwrap();
+        assert_eq!(server.scheme(), "https");
+        let handle = server.spawn();
+
+        let name = ServerName::try_from("localhost").unwrap();
+        let connection = ClientConnection::new(client_config(), name).unwrap();
+        let socket = TcpStream::connect(handle.local_addr()).unwrap();
+        let mut stream = StreamOwned::new(connection, socket);
+
+        stream
+            .write_all(b"GET /hello HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n\r\n")
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.ends_with("\r\n\r\nsecure /hello"));
+        handle.shutdown();
+    }
+
+    #[test]
+    fn plain_http_is_refused() {
+        // The server waits for the rest of what it takes as a TLS record until the read timeout.
+        let server = ServerBuilder::new()
+            .port(0)
+            .read_timeout(Duration::from_millis(200))
+            .tls(CERT, KEY)
+            .build(Router::new())
+            .unwrap();
+        let handle = server.spawn();
+
+        let mut stream = TcpStream::connect(handle.local_addr()).unwrap();
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .unwrap();
+        let mut response = Vec::new();
+        let _ = stream.read_to_end(&mut response);
+
+        assert!(!response.starts_with(b"HTTP/1.1"));
+        handle.shutdown();
+    }
+
+    #[test]
+    fn invalid_configurations() {
+        let config = |cert: Option<&str>, key: Option<&str>| Config {
+            tls_cert: cert.map(PathBuf::from),
+            tls_key: key.map(PathBuf::from),
+            ..Config::default()
+        };
+
+        assert!(load(&config(None, None)).unwrap().is_none());
+        assert!(matches!(
+            load(&config(Some(CERT), None)),
+            Err(TlsError::Incomplete)
+        ));
+        assert!(matches!(
+            load(&config(Some(KEY), Some(KEY))),
+            Err(TlsError::NoCertificate(_))
+        ));
+        assert!(matches!(
+            load(&config(Some(CERT), Some(CERT))),
+            Err(TlsError::NoPrivateKey(_))
+        ));
+        assert!(matches!(
+            load(&config(Some("missing.pem"), Some(KEY))),
+            Err(TlsError::Io(..))
+        ));
+    }
+}
@@ -0,0 +1,130 @@
+use super::connection::Connections;
+use std::fmt;
+use std::io::{self, Cursor, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpStream};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+/// Stream of an upgraded connection: the socket itself or a TLS session over it.
+pub trait Io: Read + Write + Send {}
+
+impl<T: Read + Write + Send> Io for T {}
+
+/// Connection taken over by another protocol after a `101 Switching Protocols` response.
+///
+/// The bytes the client sent right after its request may already be buffered by the server, so
+/// they are read first, before the ones still in the socket.
+pub struct Upgraded {
+    io: Box<dyn Io>,
+    /// Clone of the socket, to set its timeouts and shut it down.
+    socket: TcpStream,
+    buffered: Cursor<Vec<u8>>,
+}
+
+impl Upgraded {
+    fn new(io: impl Io + 'static, socket: TcpStream, buffered: Vec<u8>) -> Upgraded {
+        Upgraded {
+            io: Box::new(io),
+            socket,
+            buffered: Cursor::new(buffered),
+        }
+    }
+
+    pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.peer_addr()
+    }
+
+    /// Maximum time a read waits for the client. There is no timeout by default, as upgraded
+    /// connections usually stay open while nothing happens.
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.socket.set_read_timeout(timeout)
+    }
+
+    /// Closes both directions of the connection, waking up a thread blocked reading it.
+    pub fn shutdown(&self) -> io::Result<()> {
+        self.socket.shutdown(Shutdown::Both)
+    }
+}
+
+impl Read for Upgraded {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if (self.buffered.position() as usize) < self.buffered.get_ref().len() {
+            self.buffered.read(buf)
+        } else {
+            self.io.read(buf)
+        }
+    }
+}
+
+impl Write for Upgraded {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.io.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.io.flush()
+    }
+}
+
+/// Protocol taking over a connection, set on a response with [`Response::with_upgrade`].
+///
+/// Once the response is sent, the function runs with the connection in a thread of its own: a
+/// long-lived connection never keeps a thread of the pool, whichever the backend. The connection
+/// stays registered as idle while the function runs, so the shutdown of the server closes it.
+///
+/// [`Response::with_upgrade`]: super::response::Response::with_upgrade
+pub struct Upgrade(Box<dyn FnOnce(Upgraded) + Send>);
+
+impl Upgrade {
+    pub fn new<F: FnOnce(Upgraded) + Send + 'static>(function: F) -> Upgrade {
+        Upgrade(Box::new(function))
+    }
+
+    /// Runs the upgrade in a new thread with the connection read and written through `io`, then
+    /// unregisters the connection `id`. Nothing runs if the shutdown of the server has already
+    /// started.
+    pub(super) fn spawn(
+        self,
+        io: impl Io + 'static,
+        socket: &TcpStream,
+        buffered: Vec<u8>,
+        connections: Arc<Connections>,
+        id: usize,
+    ) {
+        let socket = match socket
+            .try_clone()
+            .and_then(|socket| socket.set_read_timeout(None).map(|_| socket))
+        {
+            Ok(socket) => socket,
+            Err(err) => {
+                eprintln!("Error upgrading the connection: {}", err);
+                connections.unregister(id);
+                return;
+            }
+        };
+        if !connections.set_idle(id, true) {
+            connections.unregister(id);
+            return;
+        }
+
+        let upgraded = Upgraded::new(io, socket, buffered);
+        let registered = Arc::clone(&connections);
+        let result = thread::Builder::new()
+            .name(String::from("upgraded"))
+            .spawn(move || {
+                (self.0)(upgraded);
+                registered.unregister(id);
+            });
+        if let Err(err) = result {
+            eprintln!("Error starting the upgraded connection: {}", err);
+            connections.unregister(id);
+        }
+    }
+}
+
+impl fmt::Debug for Upgrade {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Upgrade")
+    }
+}
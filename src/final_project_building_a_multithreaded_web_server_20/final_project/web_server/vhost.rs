@@ -0,0 +1,255 @@
+use super::request::Request;
+use super::response::{Response, StatusCode};
+use super::router::Handler;
+use std::collections::HashMap;
+use std::str::FromStr;
+
+/// What happens to the requests for a host that is not served, see
+/// [`VirtualHosts::unknown_hosts`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum UnknownHosts {
+    /// Served by the fallback handler, or answered with `421 Misdirected Request` without one.
+    #[default]
+    Fallback,
+    /// Answered with `421 Misdirected Request`, which tells the client that this server does not
+    /// serve the host, even if there is a fallback handler.
+    Misdirected,
+    /// Answered with `404 Not Found`, which does not reveal that the host is unknown.
+    NotFound,
+}
+
+impl FromStr for UnknownHosts {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "fallback" | "default" => Ok(UnknownHosts::Fallback),
+            "misdirected" | "421" => Ok(UnknownHosts::Misdirected),
+            "not_found" | "404" => Ok(UnknownHosts::NotFound),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Returns `true` if the pattern is a valid host name for [`VirtualHosts::host`]: a name or an
+/// IP address, optionally starting with `*.` to match all the subdomains of the name.
+pub fn is_valid_pattern(pattern: &str) -> bool {
+    let name = pattern.strip_prefix("*.").unwrap_or(pattern);
+    let name = name.strip_suffix('.').unwrap_or(name);
+
+    if let Some(address) = name
+        .strip_prefix('[')
+        .and_then(|name| name.strip_suffix(']'))
+    {
+        return address.parse::<std::net::Ipv6Addr>().is_ok();
+    }
+    !name.is_empty()
+        && name.split('.').all(|label| {
+            !label.is_empty()
+                && label
+                    .bytes()
+                    .all(|b| b.is_ascii_alphanumeric() || b == b'-')
+        })
+}
+
+/// Lowercase host name of the `Host` header, without the port nor the trailing dot.
+fn host_name(host: &str) -> String {
+    let host = host.trim();
+    let name = if host.starts_with('[') {
+        // IPv6 address, whose colons are not a port.
+        host.find(']').map_or(host, |end| &host[..=end])
+    } else {
+        host.rsplit_once(':').map_or(host, |(name, _)| name)
+    };
+    name.trim_end_matches('.').to_ascii_lowercase()
+}
+
+/// Serves several sites from one server, choosing the handler of each request by the host name of
+/// its `Host` header.
+///
+/// The host names match without case, and without the port and the trailing dot of the header. A
+/// name starting with `*.` matches all the subdomains of the rest of the name, at any depth, but
+/// not the name itself. Exact names are tried first, then the wildcards from the longest to the
+/// shortest.
+///
+/// The requests for other hosts, and the HTTP/1.0 requests without `Host`, go to the handler set
+/// with [`VirtualHosts::fallback`], depending on [`VirtualHosts::unknown_hosts`].
+///
+/// # Example
+///
+/// ```rust
+/// let hosts = VirtualHosts::new()
+///     .host("example.com", StaticFiles::new("sites/example"))
+///     .host("*.blog.example.com", blog_router)
+///     .fallback(default_router(&config));
+/// ```
+#[derive(Default)]
+pub struct VirtualHosts {
+    hosts: HashMap<String, Box<dyn Handler>>,
+    /// Patterns starting with `*.`, stored as the suffix after the `*`, longest first.
+    wildcards: Vec<(String, Box<dyn Handler>)>,
+    fallback: Option<Box<dyn Handler>>,
+    unknown_hosts: UnknownHosts,
+}
+
+impl VirtualHosts {
+    pub fn new() -> VirtualHosts {
+        VirtualHosts::default()
+    }
+
+    /// Serves the host with the handler. A later handler for the same host replaces the previous
+    /// one.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the pattern is not valid, see [`is_valid_pattern`].
+    pub fn host<H: Handler>(mut self, pattern: &str, handler: H) -> VirtualHosts {
+        assert!(is_valid_pattern(pattern), "Invalid host name: {}", pattern);
+
+        let pattern = pattern.trim_end_matches('.').to_ascii_lowercase();
+        match pattern.strip_prefix('*') {
+            Some(suffix) => {
+                self.wildcards.retain(|(other, _)| other != suffix);
+                self.wildcards.push((suffix.to_string(), Box::new(handler)));
+                self.wildcards
+                    .sort_by_key(|(suffix, _)| std::cmp::Reverse(suffix.len()));
+            }
+            None => {
+                self.hosts.insert(pattern, Box::new(handler));
+            }
+        }
+        self
+    }
+
+    /// Sets the handler of the requests for the hosts that are not served.
+    pub fn fallback<H: Handler>(mut self, handler: H) -> VirtualHosts {
+        self.fallback = Some(Box::new(handler));
+        self
+    }
+
+    /// Sets what happens to the requests for the hosts that are not served. The requests without
+    /// `Host` always go to the fallback handler, if there is one.
+    pub fn unknown_hosts(mut self, unknown_hosts: UnknownHosts) -> VirtualHosts {
+        self.unknown_hosts = unknown_hosts;
+        self
+    }
+
+    /// Returns the handler serving the host name.
+    fn find(&self, name: &str) -> Option<&dyn Handler> {
+        if let Some(handler) = self.hosts.get(name) {
+            return Some(handler.as_ref());
+        }
+        self.wildcards
+            .iter()
+            .find(|(suffix, _)| name.len() > suffix.len() && name.ends_with(suffix.as_str()))
+            .map(|(_, handler)| handler.as_ref())
+    }
+}
+
+impl Handler for VirtualHosts {
+    fn handle(&self, request: &mut Request) -> Response {
+        let handler = match request.header("Host").map(host_name) {
+            Some(name) => match (self.find(&name), self.unknown_hosts) {
+                (Some(handler), _) => Some(handler),
+                (None, UnknownHosts::Fallback) => self.fallback.as_deref(),
+                (None, UnknownHosts::Misdirected) => None,
+                (None, UnknownHosts::NotFound) => {
+                    return Response::error(StatusCode::NOT_FOUND);
+                }
+            },
+            None => self.fallback.as_deref(),
+        };
+
+        match handler {
+            Some(handler) => handler.handle(request),
+            None => Response::error(StatusCode::MISDIRECTED_REQUEST),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::request::Method;
+    use super::*;
+
+    fn text(value: &'static str) -> impl Handler {
+        move |_: &mut Request| Response::new(StatusCode::OK).with_body(value)
+    }
+
+    fn body(hosts: &VirtualHosts, host: Option<&str>) -> (StatusCode, String) {
+        let mut request = Request::new(Method::Get, "/");
+        if let Some(host) = host {
+            request = request.with_header("Host", host);
+        }
+        let response = hosts.handle(&mut request);
+        (
+            response.status,
+            String::from_utf8(response.body.into_bytes().unwrap()).unwrap(),
+        )
+    }
+
+    #[test]
+    fn host_names() {
+        assert_eq!(host_name("Example.COM:8080"), "example.com");
+        assert_eq!(host_name("example.com."), "example.com");
+        assert_eq!(host_name("127.0.0.1:7878"), "127.0.0.1");
+        assert_eq!(host_name("[::1]:7878"), "[::1]");
+        assert_eq!(host_name("[::1]"), "[::1]");
+
+        assert!(is_valid_pattern("example.com"));
+        assert!(is_valid_pattern("*.example.com"));
+        assert!(is_valid_pattern("localhost."));
+        assert!(is_valid_pattern("[::1]"));
+        assert!(!is_valid_pattern(""));
+        assert!(!is_valid_pattern("*"));
+        assert!(!is_valid_pattern("a.*.com"));
+        assert!(!is_valid_pattern("example.com:80"));
+        assert!(!is_valid_pattern("a..com"));
+    }
+
+    #[test]
+    fn dispatch() {
+        let hosts = VirtualHosts::new()
+            .host("example.com", text("example"))
+            .host("*.example.com", text("subdomain"))
+            .host("*.blog.example.com", text("blog"))
+            .host("api.example.com", text("api"))
+            .fallback(text("default"));
+
+        let ok = |body: &str| (StatusCode::OK, body.to_string());
+        assert_eq!(body(&hosts, Some("EXAMPLE.com:7878")), ok("example"));
+        assert_eq!(body(&hosts, Some("www.example.com")), ok("subdomain"));
+        assert_eq!(body(&hosts, Some("a.b.example.com")), ok("subdomain"));
+        assert_eq!(body(&hosts, Some("me.blog.example.com")), ok("blog"));
+        assert_eq!(body(&hosts, Some("api.example.com.")), ok("api"));
+        assert_eq!(body(&hosts, Some("notexample.com")), ok("default"));
+        assert_eq!(body(&hosts, None), ok("default"));
+    }
+
+    #[test]
+    fn unknown_hosts() {
+        let hosts = VirtualHosts::new().host("example.com", text("example"));
+        assert_eq!(
+            body(&hosts, Some("other.com")).0,
+            StatusCode::MISDIRECTED_REQUEST
+        );
+        assert_eq!(body(&hosts, None).0, StatusCode::MISDIRECTED_REQUEST);
+
+        let hosts = hosts
+            .fallback(text("default"))
+            .unknown_hosts(UnknownHosts::Misdirected);
+        assert_eq!(
+            body(&hosts, Some("other.com")).0,
+            StatusCode::MISDIRECTED_REQUEST
+        );
+        assert_eq!(body(&hosts, None).1, "default");
+
+        let hosts = hosts.unknown_hosts(UnknownHosts::NotFound);
+        assert_eq!(body(&hosts, Some("other.com")).0, StatusCode::NOT_FOUND);
+        assert_eq!(body(&hosts, Some("example.com")).1, "example");
+
+        assert_eq!("421".parse(), Ok(UnknownHosts::Misdirected));
+        assert_eq!("not_found".parse(), Ok(UnknownHosts::NotFound));
+        assert_eq!("other".parse::<UnknownHosts>(), Err(()));
+    }
+}
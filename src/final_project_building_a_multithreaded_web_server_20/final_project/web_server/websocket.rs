@@ -0,0 +1,1031 @@
+use super::request::{Method, Request, Version};
+use super::response::{Response, StatusCode};
+use super::router::Handler;
+use super::server::service_unavailable;
+use super::upgrade::{Upgrade, Upgraded};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use sha1::{Digest, Sha1};
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Version of the protocol sent in `Sec-WebSocket-Version`, the only one defined by RFC 6455.
+pub const VERSION: &str = "13";
+
+/// Appended to the key of the client to compute `Sec-WebSocket-Accept`.
+const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+pub const DEFAULT_MAX_MESSAGE_SIZE: usize = 1024 * 1024;
+
+pub const DEFAULT_MAX_CONNECTIONS: usize = 256;
+
+/// Time given to the client to answer the close frame of the server.
+pub const CLOSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+const CONTINUATION: u8 = 0x0;
+const TEXT: u8 = 0x1;
+const BINARY: u8 = 0x2;
+const CLOSE: u8 = 0x8;
+const PING: u8 = 0x9;
+const PONG: u8 = 0xA;
+
+/// Maximum payload of the control frames: close, ping and pong.
+const MAX_CONTROL_PAYLOAD: usize = 125;
+
+/// Status code of a close frame, telling why the connection is closed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CloseCode(pub u16);
+
+impl CloseCode {
+    pub const NORMAL: CloseCode = CloseCode(1000);
+    pub const GOING_AWAY: CloseCode = CloseCode(1001);
+    pub const PROTOCOL_ERROR: CloseCode = CloseCode(1002);
+    pub const UNSUPPORTED_DATA: CloseCode = CloseCode(1003);
+    pub const INVALID_PAYLOAD: CloseCode = CloseCode(1007);
+    pub const POLICY_VIOLATION: CloseCode = CloseCode(1008);
+    pub const MESSAGE_TOO_BIG: CloseCode = CloseCode(1009);
+    pub const INTERNAL_ERROR: CloseCode = CloseCode(1011);
+
+    /// Returns `true` for the codes that can be sent in a close frame: the ones defined by the
+    /// RFC and the ranges left to libraries and applications.
+    pub fn is_valid(&self) -> bool {
+        matches!(self.0, 1000..=1003 | 1007..=1011 | 3000..=4999)
+    }
+}
+
+/// Status code and reason of a close frame.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CloseFrame {
+    pub code: CloseCode,
+    pub reason: String,
+}
+
+/// Message received or sent through a [`WebSocket`]. Fragmented messages are only received
+/// once all their fragments have arrived.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+    Text(String),
+    Binary(Vec<u8>),
+    /// Already answered with a pong when received.
+    Ping(Vec<u8>),
+    Pong(Vec<u8>),
+    /// The client closes the connection, optionally telling why.
+    Close(Option<CloseFrame>),
+}
+
+impl From<String> for Message {
+    fn from(text: String) -> Message {
+        Message::Text(text)
+    }
+}
+
+impl From<&str> for Message {
+    fn from(text: &str) -> Message {
+        Message::Text(String::from(text))
+    }
+}
+
+impl From<Vec<u8>> for Message {
+    fn from(data: Vec<u8>) -> Message {
+        Message::Binary(data)
+    }
+}
+
+/// Errors receiving or sending messages.
+#[derive(Debug)]
+pub enum WebSocketError {
+    /// The connection failed, or a read timed out (see [`WebSocketError::is_timeout`]).
+    Io(io::Error),
+    /// The client broke the protocol. The connection is closed with
+    /// [`CloseCode::PROTOCOL_ERROR`].
+    Protocol(&'static str),
+    /// A text message or a close reason is not valid UTF-8. The connection is closed with
+    /// [`CloseCode::INVALID_PAYLOAD`].
+    InvalidUtf8,
+    /// A message is bigger than the maximum size. The connection is closed with
+    /// [`CloseCode::MESSAGE_TOO_BIG`].
+    TooBig,
+    /// The closing handshake is done or the client disconnected: nothing else can be received.
+    Closed,
+}
+
+impl WebSocketError {
+    /// Returns `true` if a read timed out, after
+    /// [`WebSocket::set_read_timeout`]. The connection can still be used.
+    pub fn is_timeout(&self) -> bool {
+        match self {
+            WebSocketError::Io(err) => matches!(
+                err.kind(),
+                io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+            ),
+            _ => false,
+        }
+    }
+
+    /// Code of the close frame sent when the connection fails with this error.
+    fn close_code(&self) -> Option<CloseCode> {
+        match self {
+            WebSocketError::Protocol(_) => Some(CloseCode::PROTOCOL_ERROR),
+            WebSocketError::InvalidUtf8 => Some(CloseCode::INVALID_PAYLOAD),
+            WebSocketError::TooBig => Some(CloseCode::MESSAGE_TOO_BIG),
+            // The socket was shut down for reading, by the client or by the shutdown of the
+            // server.
+            WebSocketError::Closed => Some(CloseCode::GOING_AWAY),
+            WebSocketError::Io(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for WebSocketError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WebSocketError::Io(err) => write!(f, "I/O error: {}", err),
+            WebSocketError::Protocol(message) => write!(f, "protocol error: {}", message),
+            WebSocketError::InvalidUtf8 => f.write_str("text is not valid UTF-8"),
+            WebSocketError::TooBig => f.write_str("message too big"),
+            WebSocketError::Closed => f.write_str("connection closed"),
+        }
+    }
+}
+
+impl Error for WebSocketError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            WebSocketError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for WebSocketError {
+    fn from(err: io::Error) -> Self {
+        WebSocketError::Io(err)
+    }
+}
+
+/// Computes the `Sec-WebSocket-Accept` header answering the `Sec-WebSocket-Key` of a client.
+pub fn accept_key(key: &str) -> String {
+    let mut sha1 = Sha1::new();
+    sha1.update(key.as_bytes());
+    sha1.update(GUID.as_bytes());
+    BASE64.encode(sha1.finalize())
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct Frame {
+    fin: bool,
+    opcode: u8,
+    payload: Vec<u8>,
+}
+
+/// Parses the first frame of the buffer, unmasking its payload. Returns the frame and its length
+/// in the buffer, or `None` if the buffer does not hold the complete frame yet.
+///
+/// The header is checked as soon as it is received, so a frame bigger than `max_size` is
+/// rejected before its payload arrives.
+fn parse_frame(buffer: &[u8], max_size: usize) -> Result<Option<(Frame, usize)>, WebSocketError> {
+    let [first, second, ..] = *buffer else {
+        return Ok(None);
+    };
+    let fin = first & 0x80 != 0;
+    let opcode = first & 0x0F;
+
+    if first & 0x70 != 0 {
+        return Err(WebSocketError::Protocol("reserved bits are set"));
+    }
+    match opcode {
+        CONTINUATION | TEXT | BINARY => {}
+        CLOSE | PING | PONG if !fin => {
+            return Err(WebSocketError::Protocol("fragmented control frame"))
+        }
+        CLOSE | PING | PONG => {}
+        _ => return Err(WebSocketError::Protocol("unknown opcode")),
+    }
+    if second & 0x80 == 0 {
+        return Err(WebSocketError::Protocol(
+            "frames from the client must be masked",
+        ));
+    }
+
+    let (len, header) = match second & 0x7F {
+        126 => match buffer.get(2..4) {
+            Some(bytes) => (u16::from_be_bytes([bytes[0], bytes[1]]) as u64, 4),
+            None => return Ok(None),
+        },
+        127 => match buffer.get(2..10) {
+            Some(bytes) => (u64::from_be_bytes(bytes.try_into().unwrap()), 10),
+            None => return Ok(None),
+        },
+        len => (len as u64, 2),
+    };
+    if opcode >= CLOSE && len > MAX_CONTROL_PAYLOAD as u64 {
+        return Err(WebSocketError::Protocol("control frame too long"));
+    }
+    if len > max_size as u64 {
+        return Err(WebSocketError::TooBig);
+    }
+
+    let start = header + 4;
+    let end = start + len as usize;
+    if buffer.len() < end {
+        return Ok(None);
+    }
+    let mask = &buffer[header..start];
+    let payload = buffer[start..end]
+        .iter()
+        .enumerate()
+        .map(|(i, byte)| byte ^ mask[i % 4])
+        .collect();
+
+    Ok(Some((
+        Frame {
+            fin,
+            opcode,
+            payload,
+        },
+        end,
+    )))
+}
+
+/// Writes an unfragmented and unmasked frame, as sent by servers, and flushes it.
+fn write_frame<W: Write>(writer: &mut W, opcode: u8, payload: &[u8]) -> io::Result<()> {
+    let mut header = Vec::with_capacity(10);
+    header.push(0x80 | opcode);
+    match payload.len() {
+        len @ 0..=125 => header.push(len as u8),
+        len @ 126..=0xFFFF => {
+            header.push(126);
+            header.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        len => {
+            header.push(127);
+            header.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+    }
+
+    writer.write_all(&header)?;
+    writer.write_all(payload)?;
+    writer.flush()
+}
+
+/// Payload of a close frame: the code followed by the reason, cut to fit in a control frame.
+fn close_payload(code: CloseCode, reason: &str) -> Vec<u8> {
+    let mut end = reason.len().min(MAX_CONTROL_PAYLOAD - 2);
+    while !reason.is_char_boundary(end) {
+        end -= 1;
+    }
+
+    let mut payload = code.0.to_be_bytes().to_vec();
+    payload.extend_from_slice(&reason.as_bytes()[..end]);
+    payload
+}
+
+fn parse_close(payload: &[u8]) -> Result<Option<CloseFrame>, WebSocketError> {
+    match payload {
+        [] => Ok(None),
+        [_] => Err(WebSocketError::Protocol(
+            "close frame without a complete code",
+        )),
+        [high, low, reason @ ..] => {
+            let code = CloseCode(u16::from_be_bytes([*high, *low]));
+            if !code.is_valid() {
+                return Err(WebSocketError::Protocol("invalid close code"));
+            }
+            let reason =
+                String::from_utf8(reason.to_vec()).map_err(|_| WebSocketError::InvalidUtf8)?;
+            Ok(Some(CloseFrame { code, reason }))
+        }
+    }
+}
+
+/// WebSocket connection with a client, given to the function of a [`handler`].
+///
+/// Messages are received one at a time with [`WebSocket::recv`], which answers the pings and
+/// the close frame of the client by itself, and sent with [`WebSocket::send`]. A handler that
+/// also sends messages on its own, like notifications, can poll with a read timeout:
+///
+/// ```rust
+/// socket.set_read_timeout(Some(Duration::from_millis(100)))?;
+/// loop {
+///     match socket.recv() {
+///         Ok(Message::Text(text)) => socket.send(text)?,
+///         Ok(Message::Close(_)) => break,
+///         Ok(_) => {}
+///         Err(err) if err.is_timeout() => {}
+///         Err(err) => return Err(err),
+///     }
+///     while let Ok(notification) = notifications.try_recv() {
+///         socket.send(notification)?;
+///     }
+/// }
+/// ```
+///
+/// When the socket is dropped without completing the closing handshake, a close frame with
+/// [`CloseCode::NORMAL`] is sent before the connection is closed. When the server shuts down,
+/// [`WebSocket::recv`] sends a close frame with [`CloseCode::GOING_AWAY`] and returns
+/// [`WebSocketError::Closed`].
+pub struct WebSocket {
+    stream: Upgraded,
+    /// Bytes received but not parsed yet, kept when a read times out in the middle of a frame.
+    buffer: Vec<u8>,
+    /// Opcode and payload of the fragmented message being received.
+    fragments: Option<(u8, Vec<u8>)>,
+    max_message_size: usize,
+    protocol: Option<String>,
+    /// A close frame has been sent, so nothing else can be.
+    close_sent: bool,
+    /// The close frame of the client has been received, or the connection failed.
+    closed: bool,
+}
+
+impl WebSocket {
+    fn new(stream: Upgraded, max_message_size: usize, protocol: Option<String>) -> WebSocket {
+        WebSocket {
+            stream,
+            buffer: Vec::new(),
+            fragments: None,
+            max_message_size,
+            protocol,
+            close_sent: false,
+            closed: false,
+        }
+    }
+
+    /// Subprotocol chosen during the handshake, see [`WebSocketHandler::protocols`].
+    pub fn protocol(&self) -> Option<&str> {
+        self.protocol.as_deref()
+    }
+
+    pub fn peer_addr(&self) -> Option<SocketAddr> {
+        self.stream.peer_addr().ok()
+    }
+
+    /// Maximum time [`WebSocket::recv`] waits for a message. There is no timeout by default.
+    pub fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        self.stream.set_read_timeout(timeout)
+    }
+
+    /// Waits for the next message. Pings are answered with a pong before being returned, and a
+    /// close frame of the client with a close frame, after which the connection is closed.
+    ///
+    /// If the client breaks the protocol, the connection is closed with the matching
+    /// [`CloseCode`] and the error is returned.
+    pub fn recv(&mut self) -> Result<Message, WebSocketError> {
+        if self.closed {
+            return Err(WebSocketError::Closed);
+        }
+
+        loop {
+            let result = self
+                .read_frame()
+                .and_then(|frame| self.process_frame(frame));
+            match result {
+                Ok(Some(message)) => return Ok(message),
+                Ok(None) => {}
+                Err(err) => return Err(self.fail(err)),
+            }
+        }
+    }
+
+    /// Sends a message in a single frame. Strings are sent as text and byte vectors as binary
+    /// messages. Sending [`Message::Close`] is the same as calling [`WebSocket::close`].
+    pub fn send(&mut self, message: impl Into<Message>) -> Result<(), WebSocketError> {
+        if self.close_sent {
+            return Err(WebSocketError::Closed);
+        }
+
+        let (opcode, payload) = match message.into() {
+            Message::Text(text) => (TEXT, text.into_bytes()),
+            Message::Binary(data) => (BINARY, data),
+            Message::Ping(data) => (PING, data),
+            Message::Pong(data) => (PONG, data),
+            Message::Close(frame) => {
+                let frame = frame.unwrap_or(CloseFrame {
+                    code: CloseCode::NORMAL,
+                    reason: String::new(),
+                });
+                return self.close(frame.code, &frame.reason);
+            }
+        };
+        if opcode >= CLOSE && payload.len() > MAX_CONTROL_PAYLOAD {
+            return Err(WebSocketError::Io(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "control frame payload longer than 125 bytes",
+            )));
+        }
+
+        write_frame(&mut self.stream, opcode, &payload)?;
+        Ok(())
+    }
+
+    /// Starts the closing handshake: sends a close frame and waits up to the
+    /// [`CLOSE_TIMEOUT`] for the close frame of the client, discarding the messages received in
+    /// the meantime. Then the connection is closed.
+    pub fn close(&mut self, code: CloseCode, reason: &str) -> Result<(), WebSocketError> {
+        if !self.close_sent {
+            self.close_sent = true;
+            write_frame(&mut self.stream, CLOSE, &close_payload(code, reason))?;
+        }
+
+        if !self.closed {
+            self.stream.set_read_timeout(Some(CLOSE_TIMEOUT))?;
+            while self.recv().is_ok() {}
+        }
+        self.closed = true;
+        let _ = self.stream.shutdown();
+        Ok(())
+    }
+
+    fn read_frame(&mut self) -> Result<Frame, WebSocketError> {
+        let mut chunk = [0; 4096];
+
+        loop {
+            if let Some((frame, len)) = parse_frame(&self.buffer, self.max_message_size)? {
+                self.buffer.drain(..len);
+                return Ok(frame);
+            }
+
+            match self.stream.read(&mut chunk) {
+                Ok(0) => return Err(WebSocketError::Closed),
+                Ok(read) => self.buffer.extend_from_slice(&chunk[..read]),
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => return Err(err.into()),
+            }
+        }
+    }
+
+    /// Handles a frame, returning the message it completes if any.
+    fn process_frame(&mut self, frame: Frame) -> Result<Option<Message>, WebSocketError> {
+        let Frame {
+            fin,
+            opcode,
+            payload,
+        } = frame;
+
+        match opcode {
+            PING => {
+                if !self.close_sent {
+                    write_frame(&mut self.stream, PONG, &payload)?;
+                }
+                Ok(Some(Message::Ping(payload)))
+            }
+            PONG => Ok(Some(Message::Pong(payload))),
+            CLOSE => {
+                let close = parse_close(&payload)?;
+                if !self.close_sent {
+                    self.close_sent = true;
+                    let payload = match &close {
+                        Some(frame) => frame.code.0.to_be_bytes().to_vec(),
+                        None => Vec::new(),
+                    };
+                    write_frame(&mut self.stream, CLOSE, &payload)?;
+                }
+                self.closed = true;
+                let _ = self.stream.shutdown();
+                Ok(Some(Message::Close(close)))
+            }
+            TEXT | BINARY if self.fragments.is_some() => Err(WebSocketError::Protocol(
+                "new message before the end of a fragmented one",
+            )),
+            TEXT | BINARY if !fin => {
+                self.fragments = Some((opcode, payload));
+                Ok(None)
+            }
+            TEXT | BINARY => message(opcode, payload).map(Some),
+            _ => {
+                let Some((opcode, mut data)) = self.fragments.take() else {
+                    return Err(WebSocketError::Protocol(
+                        "continuation frame without a message",
+                    ));
+                };
+                if data.len() + payload.len() > self.max_message_size {
+                    return Err(WebSocketError::TooBig);
+                }
+                data.extend_from_slice(&payload);
+
+                if fin {
+                    message(opcode, data).map(Some)
+                } else {
+                    self.fragments = Some((opcode, data));
+                    Ok(None)
+                }
+            }
+        }
+    }
+
+    /// Fails the connection after an error: sends the matching close frame, if any, and closes
+    /// it. Timeouts leave the connection as it is, so it can still be used.
+    fn fail(&mut self, err: WebSocketError) -> WebSocketError {
+        if err.is_timeout() {
+            return err;
+        }
+
+        if let Some(code) = err.close_code() {
+            if !self.close_sent {
+                self.close_sent = true;
+                let _ = write_frame(&mut self.stream, CLOSE, &close_payload(code, ""));
+            }
+        }
+        self.closed = true;
+        let _ = self.stream.shutdown();
+        err
+    }
+}
+
+impl Drop for WebSocket {
+    fn drop(&mut self) {
+        if !self.close_sent && !self.closed {
+            let _ = write_frame(
+                &mut self.stream,
+                CLOSE,
+                &close_payload(CloseCode::NORMAL, ""),
+            );
+        }
+    }
+}
+
+fn message(opcode: u8, data: Vec<u8>) -> Result<Message, WebSocketError> {
+    match opcode {
+        TEXT => String::from_utf8(data)
+            .map(Message::Text)
+            .map_err(|_| WebSocketError::InvalidUtf8),
+        _ => Ok(Message::Binary(data)),
+    }
+}
+
+/// Checks the handshake of the client (RFC 6455, section 4.2.1), returning the value of the
+/// `Sec-WebSocket-Accept` header, or the error response.
+fn handshake(request: &Request) -> Result<String, Response> {
+    if request.method != Method::Get {
+        return Err(Response::error(StatusCode::METHOD_NOT_ALLOWED).with_header("Allow", "GET"));
+    }
+    if !request.headers.contains_token("Upgrade", "websocket") {
+        return Err(upgrade_required());
+    }
+    if request.version != Version::Http11
+        || !request.headers.contains_token("Connection", "upgrade")
+    {
+        return Err(Response::error(StatusCode::BAD_REQUEST));
+    }
+    if request.header("Sec-WebSocket-Version").map(str::trim) != Some(VERSION) {
+        return Err(upgrade_required());
+    }
+
+    // The key is a random 16 bytes value, encoded in base64.
+    match request.header("Sec-WebSocket-Key").map(str::trim) {
+        Some(key) if BASE64.decode(key).is_ok_and(|key| key.len() == 16) => Ok(accept_key(key)),
+        _ => Err(Response::error(StatusCode::BAD_REQUEST)),
+    }
+}
+
+/// `426 Upgrade Required` response to the requests that are not a WebSocket handshake, or ask
+/// for another version of the protocol.
+fn upgrade_required() -> Response {
+    Response::error(StatusCode::UPGRADE_REQUIRED)
+        .with_header("Upgrade", "websocket")
+        .with_header("Sec-WebSocket-Version", VERSION)
+}
+
+/// Decrements the number of open WebSocket connections of a handler when dropped.
+struct Open(Arc<AtomicUsize>);
+
+impl Drop for Open {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Handler created by [`handler`].
+pub struct WebSocketHandler<F> {
+    function: Arc<F>,
+    protocols: Vec<String>,
+    max_message_size: usize,
+    max_connections: usize,
+    open: Arc<AtomicUsize>,
+}
+
+/// Creates a handler accepting WebSocket connections. Once the handshake is done, the function
+/// runs with the connection and a copy of the request, in a thread of its own so that the
+/// threads of the server are not kept by long-lived connections:
+///
+/// ```rust
+/// Router::new().get("/ws/echo", websocket::handler(|mut socket: WebSocket, _: Request| {
+///     while let Ok(message) = socket.recv() {
+///         if let Message::Text(text) = message {
+///             let _ = socket.send(text);
+///         }
+///     }
+/// }))
+/// ```
+///
+/// Requests that are not a valid handshake are answered with `426 Upgrade Required` or
+/// `400 Bad Request`, and handshakes above the maximum number of connections with
+/// `503 Service Unavailable`.
+pub fn handler<F>(function: F) -> WebSocketHandler<F>
+where
+    F: Fn(WebSocket, Request) + Send + Sync + 'static,
+{
+    WebSocketHandler {
+        function: Arc::new(function),
+        protocols: Vec::new(),
+        max_message_size: DEFAULT_MAX_MESSAGE_SIZE,
+        max_connections: DEFAULT_MAX_CONNECTIONS,
+        open: Arc::default(),
+    }
+}
+
+impl<F> WebSocketHandler<F> {
+    /// Subprotocols supported by the handler. The first one the client asks for in
+    /// `Sec-WebSocket-Protocol` is chosen, see [`WebSocket::protocol`].
+    pub fn protocols<I, S>(mut self, protocols: I) -> WebSocketHandler<F>
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.protocols = protocols.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Maximum size of a message received, fragments included. Bigger messages close the
+    /// connection with [`CloseCode::MESSAGE_TOO_BIG`].
+    pub fn max_message_size(mut self, size: usize) -> WebSocketHandler<F> {
+        self.max_message_size = size;
+        self
+    }
+
+    /// Maximum number of connections open at the same time, each keeping a thread.
+    pub fn max_connections(mut self, max: usize) -> WebSocketHandler<F> {
+        self.max_connections = max;
+        self
+    }
+
+    /// Number of connections currently open.
+    pub fn open_connections(&self) -> usize {
+        self.open.load(Ordering::SeqCst)
+    }
+
+    fn choose_protocol(&self, request: &Request) -> Option<String> {
+        let offered = request.header("Sec-WebSocket-Protocol")?;
+        offered
+            .split(',')
+            .map(str::trim)
+            .find(|protocol| self.protocols.iter().any(|p| p == protocol))
+            .map(String::from)
+    }
+}
+
+impl<F> Handler for WebSocketHandler<F>
+where
+    F: Fn(WebSocket, Request) + Send + Sync + 'static,
+{
+    fn handle(&self, request: &mut Request) -> Response {
+        let accept = match handshake(request) {
+            Ok(accept) => accept,
+            Err(response) => return response,
+        };
+
+        if self.open.fetch_add(1, Ordering::SeqCst) >= self.max_connections {
+            self.open.fetch_sub(1, Ordering::SeqCst);
+            return service_unavailable();
+        }
+        // Counted from now on, until the function returns or the upgrade is dropped because the
+        // response could not be sent.
+        let open = Open(Arc::clone(&self.open));
+
+        let protocol = self.choose_protocol(request);
+        let mut response = Response::new(StatusCode::SWITCHING_PROTOCOLS)
+            .with_header("Upgrade", "websocket")
+            .with_header("Connection", "Upgrade")
+            .with_header("Sec-WebSocket-Accept", accept);
+        if let Some(protocol) = &protocol {
+            response = response.with_header("Sec-WebSocket-Protocol", protocol.clone());
+        }
+
+        let function = Arc::clone(&self.function);
+        let request = request.clone();
+        let max_message_size = self.max_message_size;
+        response.with_upgrade(Upgrade::new(move |upgraded| {
+            let _open = open;
+            function(
+                WebSocket::new(upgraded, max_message_size, protocol),
+                request,
+            );
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::config::Backend;
+    use super::super::router::Router;
+    use super::super::server::{ServerBuilder, ServerHandle};
+    use super::*;
+    use std::net::TcpStream;
+
+    const KEY: &str = "dGhlIHNhbXBsZSBub25jZQ==";
+
+    /// Frame as sent by a client, masked.
+    fn client_frame(fin: bool, opcode: u8, payload: &[u8]) -> Vec<u8> {
+        let mask = [0x12, 0x34, 0x56, 0x78];
+        let mut frame = vec![if fin { 0x80 } else { 0 } | opcode];
+        match payload.len() {
+            len @ 0..=125 => frame.push(0x80 | len as u8),
+            len => {
+                frame.push(0x80 | 126);
+                frame.extend_from_slice(&(len as u16).to_be_bytes());
+            }
+        }
+        frame.extend_from_slice(&mask);
+        frame.extend(payload.iter().enumerate().map(|(i, b)| b ^ mask[i % 4]));
+        frame
+    }
+
+    fn handshake_request(headers: &[(&str, &str)]) -> Request {
+        let mut request = Request::new(Method::Get, "/ws")
+            .with_header("Upgrade", "websocket")
+            .with_header("Connection", "keep-alive, Upgrade")
+            .with_header("Sec-WebSocket-Version", VERSION)
+            .with_header("Sec-WebSocket-Key", KEY);
+        for (name, value) in headers {
+            request.headers.insert(*name, *value);
+        }
+        request
+    }
+
+    #[test]
+    fn accept() {
+        // Example of RFC 6455, section 1.3.
+        assert_eq!(accept_key(KEY), "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=");
+    }
+
+    #[test]
+    fn frames() {
+        let frame = client_frame(true, TEXT, b"Hello");
+        assert_eq!(
+            parse_frame(&frame, 1024).unwrap(),
+            Some((
+                Frame {
+                    fin: true,
+                    opcode: TEXT,
+                    payload: b"Hello".to_vec()
+                },
+                frame.len()
+            ))
+        );
+        assert_eq!(parse_frame(&frame[..frame.len() - 1], 1024).unwrap(), None);
+        assert_eq!(parse_frame(&frame[..1], 1024).unwrap(), None);
+
+        let long = client_frame(false, BINARY, &[7; 300]);
+        let (parsed, len) = parse_frame(&long, 1024).unwrap().unwrap();
+        assert!(!parsed.fin);
+        assert_eq!((parsed.payload.len(), len), (300, long.len()));
+        // Too big is known from the header alone.
+        assert!(matches!(
+            parse_frame(&long[..4], 100),
+            Err(WebSocketError::TooBig)
+        ));
+
+        let mut unmasked = Vec::new();
+        write_frame(&mut unmasked, TEXT, b"Hello").unwrap();
+        assert_eq!(unmasked, b"\x81\x05Hello");
+        let mut errors = vec![
+            unmasked,
+            client_frame(false, PING, b""),
+            client_frame(true, PING, &[0; 126]),
+            client_frame(true, 0x3, b""),
+        ];
+        errors[3][0] |= 0x40;
+        for frame in errors {
+            assert!(matches!(
+                parse_frame(&frame, 1024),
+                Err(WebSocketError::Protocol(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn close_frames() {
+        assert_eq!(parse_close(b"").unwrap(), None);
+        assert_eq!(
+            parse_close(b"\x03\xe8bye").unwrap(),
+            Some(CloseFrame {
+                code: CloseCode::NORMAL,
+                reason: String::from("bye")
+            })
+        );
+        assert!(matches!(
+            parse_close(b"\x03"),
+            Err(WebSocketError::Protocol(_))
+        ));
+        assert!(matches!(
+            parse_close(b"\x03\xed"),
+            Err(WebSocketError::Protocol(_))
+        ));
+        assert!(matches!(
+            parse_close(b"\x03\xe8\xff"),
+            Err(WebSocketError::InvalidUtf8)
+        ));
+
+        let payload = close_payload(CloseCode::GOING_AWAY, &"é".repeat(100));
+        assert_eq!(payload.len(), 124);
+        assert_eq!(&payload[..2], b"\x03\xe9");
+    }
+
+    #[test]
+    fn handshakes() {
+        let handler = handler(|_: WebSocket, _: Request| {}).protocols(["chat", "json"]);
+
+        let response = handler.handle(&mut handshake_request(&[(
+            "Sec-WebSocket-Protocol",
+            "xml, json, chat",
+        )]));
+        assert_eq!(response.status, StatusCode::SWITCHING_PROTOCOLS);
+        assert_eq!(
+            response.headers.get("Sec-WebSocket-Accept"),
+            Some("s3pPLMBiTxaQ9kYGzzhZRbK+xOo=")
+        );
+        assert_eq!(response.headers.get("Sec-WebSocket-Protocol"), Some("json"));
+        assert!(response.upgrade.is_some());
+        assert_eq!(handler.open_connections(), 1);
+        drop(response);
+        assert_eq!(handler.open_connections(), 0);
+
+        let mut plain = Request::new(Method::Get, "/ws");
+        let response = handler.handle(&mut plain);
+        assert_eq!(response.status, StatusCode::UPGRADE_REQUIRED);
+        assert_eq!(response.headers.get("Sec-WebSocket-Version"), Some(VERSION));
+
+        let status =
+            |headers: &[(&str, &str)]| handler.handle(&mut handshake_request(headers)).status;
+        assert_eq!(
+            status(&[("Sec-WebSocket-Version", "8")]),
+            StatusCode::UPGRADE_REQUIRED
+        );
+        assert_eq!(
+            status(&[("Sec-WebSocket-Key", "c2hvcnQ=")]),
+            StatusCode::BAD_REQUEST
+        );
+        assert_eq!(status(&[("Connection", "close")]), StatusCode::BAD_REQUEST);
+    }
+
+    #[test]
+    fn connection_limit() {
+        let handler = handler(|_: WebSocket, _: Request| {}).max_connections(1);
+
+        let first = handler.handle(&mut handshake_request(&[]));
+        assert_eq!(first.status, StatusCode::SWITCHING_PROTOCOLS);
+        let second = handler.handle(&mut handshake_request(&[]));
+        assert_eq!(second.status, StatusCode::SERVICE_UNAVAILABLE);
+        drop(first);
+        let third = handler.handle(&mut handshake_request(&[]));
+        assert_eq!(third.status, StatusCode::SWITCHING_PROTOCOLS);
+    }
+
+    fn start(backend: Backend) -> ServerHandle {
+        let echo = handler(|mut socket: WebSocket, request: Request| {
+            socket.send(format!("hello {}", request.path)).unwrap();
+            loop {
+                let result = match socket.recv() {
+                    Ok(Message::Text(text)) => socket.send(text),
+                    Ok(Message::Binary(data)) => socket.send(data),
+                    Ok(_) => Ok(()),
+                    Err(_) => break,
+                };
+                if result.is_err() {
+                    break;
+                }
+            }
+        });
+
+        ServerBuilder::new()
+            .port(0)
+            .workers(1)
+            .backend(backend)
+            .build(Router::new().get("/ws/*", echo))
+            .unwrap()
+            .spawn()
+    }
+
+    /// Reads an unfragmented frame of the server, with a short payload.
+    fn read_frame(stream: &mut TcpStream) -> (u8, Vec<u8>) {
+        let mut header = [0; 2];
+        stream.read_exact(&mut header).unwrap();
+        assert_eq!(header[0] & 0x80, 0x80, "fragmented frame");
+        assert_eq!(header[1] & 0x80, 0, "masked frame");
+
+        let mut payload = vec![0; header[1] as usize];
+        stream.read_exact(&mut payload).unwrap();
+        (header[0] & 0x0F, payload)
+    }
+
+    fn connect(handle: &ServerHandle, first_frame: &[u8]) -> TcpStream {
+        let mut stream = TcpStream::connect(handle.local_addr()).unwrap();
+        stream
+            .set_read_timeout(Some(Duration::from_secs(2)))
+            .unwrap();
+
+        let mut handshake = format!(
+            "GET /ws/echo HTTP/1.1\r\nHost: a\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+             Sec-WebSocket-Version: 13\r\nSec-WebSocket-Key: {}\r\n\r\n",
+            KEY
+        )
+        .into_bytes();
+        // A frame sent right after the handshake is buffered with the request.
+        handshake.extend_from_slice(first_frame);
+        stream.write_all(&handshake).unwrap();
+
+        let mut head = Vec::new();
+        let mut byte = [0];
+        while !head.ends_with(b"\r\n\r\n") {
+            stream.read_exact(&mut byte).unwrap();
+            head.push(byte[0]);
+        }
+        let head = String::from_utf8(head).unwrap();
+        assert!(head.starts_with("HTTP/1.1 101 Switching Protocols\r\n"));
+        assert!(head.contains("Connection: Upgrade\r\n"));
+        assert!(head.contains("Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n"));
+
+        assert_eq!(read_frame(&mut stream), (TEXT, b"hello /ws/echo".to_vec()));
+        stream
+    }
+
+    fn echo_session(backend: Backend) {
+        let handle = start(backend);
+        let mut stream = connect(&handle, &client_frame(true, TEXT, b"early"));
+        assert_eq!(read_frame(&mut stream), (TEXT, b"early".to_vec()));
+
+        // A fragmented message, with a ping in the middle.
+        let mut frames = client_frame(false, TEXT, b"Hel");
+        frames.extend(client_frame(true, PING, b"?"));
+        frames.extend(client_frame(false, CONTINUATION, b"lo "));
+        frames.extend(client_frame(true, CONTINUATION, "wörld".as_bytes()));
+        frames.extend(client_frame(true, BINARY, &[1, 2, 3]));
+        stream.write_all(&frames).unwrap();
+        assert_eq!(read_frame(&mut stream), (PONG, b"?".to_vec()));
+        assert_eq!(
+            read_frame(&mut stream),
+            (TEXT, "Hello wörld".as_bytes().to_vec())
+        );
+        assert_eq!(read_frame(&mut stream), (BINARY, vec![1, 2, 3]));
+
+        // The server stays responsive while the socket is open.
+        let mut other = TcpStream::connect(handle.local_addr()).unwrap();
+        other
+            .write_all(b"GET /missing HTTP/1.1\r\nHost: a\r\nConnection: close\r\n\r\n")
+            .unwrap();
+        let mut response = String::new();
+        other.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+
+        stream
+            .write_all(&client_frame(true, CLOSE, b"\x03\xe8bye"))
+            .unwrap();
+        assert_eq!(read_frame(&mut stream), (CLOSE, b"\x03\xe8".to_vec()));
+        assert_eq!(stream.read(&mut [0]).unwrap(), 0);
+
+        handle.shutdown();
+    }
+
+    #[test]
+    fn echo_thread_pool() {
+        echo_session(Backend::ThreadPool);
+    }
+
+    #[test]
+    fn echo_event_loop() {
+        echo_session(Backend::EventLoop);
+    }
+
+    #[test]
+    fn protocol_errors() {
+        let handle = start(Backend::ThreadPool);
+
+        let mut stream = connect(&handle, &[]);
+        let mut unmasked = Vec::new();
+        write_frame(&mut unmasked, TEXT, b"Hello").unwrap();
+        stream.write_all(&unmasked).unwrap();
+        assert_eq!(read_frame(&mut stream), (CLOSE, b"\x03\xea".to_vec()));
+
+        let mut stream = connect(&handle, &client_frame(true, TEXT, b"\xff"));
+        assert_eq!(read_frame(&mut stream), (CLOSE, b"\x03\xef".to_vec()));
+
+        let mut stream = connect(&handle, &client_frame(true, CONTINUATION, b"x"));
+        assert_eq!(read_frame(&mut stream), (CLOSE, b"\x03\xea".to_vec()));
+
+        handle.shutdown();
+    }
+
+    #[test]
+    fn shutdown_closes_sockets() {
+        let handle = start(Backend::ThreadPool);
+        let mut stream = connect(&handle, &[]);
+
+        handle.shutdown();
+        assert_eq!(read_frame(&mut stream), (CLOSE, b"\x03\xe9".to_vec()));
+    }
+}
@@ -8,8 +8,12 @@ mod object_oriented_programming_features_of_rust_17;
 mod final_project_building_a_multithreaded_web_server_20;
 
 use final_project_building_a_multithreaded_web_server_20::final_project::web_server;
+use std::process;
 
 
 fn main() {
-    web_server::run_server()
+    if let Err(err) = web_server::run_server(web_server::default_handler) {
+        eprintln!("Server error: {}", err);
+        process::exit(1);
+    }
 }